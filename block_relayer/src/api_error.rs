@@ -0,0 +1,184 @@
+use crate::deposit_status::DepositStatusError;
+use crate::relay_program_interaction::RelayTxError;
+use crate::store::StoreError;
+use crate::yona::client_relay_error;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use anchor_client::solana_sdk::signature::Signature;
+use anchor_client::ClientError as AnchorClientError;
+use btc_relay::errors::RelayErrorCode;
+use serde::Serialize;
+use std::fmt;
+use tokio::task::JoinError;
+
+/// Stable error codes of the HTTP API, documented in docs/swagger/tx_relay.yml
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ApiErrorCode {
+    InvalidTxId,
+    InvalidYonaAddress,
+    /// The Bitcoin node doesn't know the transaction
+    TxNotFound,
+    /// The transaction is only in the mempool
+    TxNotConfirmed,
+    /// The relay program doesn't have the block including the transaction yet
+    BlockNotRelayedYet,
+    NotEnoughConfirmations,
+    /// The transaction doesn't pay the deposit address of the Yona address
+    NoDepositOutputs,
+    /// The deposit is below the minimum deposit or doesn't cover the deposit fee
+    DepositTooSmall,
+    /// The deposit is already minted to the Yona address
+    AlreadyClaimed,
+    /// A verification of the deposit was sent, but its outcome isn't known yet
+    VerificationPending,
+    /// The relayer's hourly fee budget is spent, deposit verifications are paused
+    FeeBudgetExhausted,
+    /// The signer nodes haven't registered the group key deposit addresses are derived from
+    BridgeKeyNotRegistered,
+    /// The relay program rejected the verification with another error
+    RelayProgramError,
+    /// Neither the relayer nor the Bitcoin node know the deposit
+    UnknownDeposit,
+    /// Another request is relaying the deposit
+    RelayInProgress,
+    /// The hourly limit of registered deposit addresses or the limit of pending relay requests is
+    /// reached
+    RateLimited,
+    BitcoinNodeError,
+    YonaError,
+    InternalError,
+}
+
+impl ApiErrorCode {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiErrorCode::InvalidTxId | ApiErrorCode::InvalidYonaAddress => StatusCode::BAD_REQUEST,
+            ApiErrorCode::TxNotFound | ApiErrorCode::UnknownDeposit => StatusCode::NOT_FOUND,
+            ApiErrorCode::TxNotConfirmed
+            | ApiErrorCode::BlockNotRelayedYet
+            | ApiErrorCode::NotEnoughConfirmations
+            | ApiErrorCode::AlreadyClaimed
+            | ApiErrorCode::VerificationPending
+            | ApiErrorCode::RelayInProgress => StatusCode::CONFLICT,
+            ApiErrorCode::NoDepositOutputs
+            | ApiErrorCode::DepositTooSmall
+            | ApiErrorCode::RelayProgramError => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiErrorCode::BitcoinNodeError | ApiErrorCode::YonaError => StatusCode::BAD_GATEWAY,
+            ApiErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ApiErrorCode::FeeBudgetExhausted | ApiErrorCode::BridgeKeyNotRegistered => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ApiErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// JSON body of the HTTP API's error responses
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ApiError {
+    pub code: ApiErrorCode,
+    pub message: String,
+    /// Yona transaction the error refers to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(code: ApiErrorCode, message: impl Into<String>) -> ApiError {
+        ApiError {
+            code,
+            message: message.into(),
+            signature: None,
+        }
+    }
+
+    pub fn with_signature(mut self, signature: &Signature) -> ApiError {
+        self.signature = Some(signature.to_string());
+        self
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.code.status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(self)
+    }
+}
+
+impl From<&AnchorClientError> for ApiError {
+    fn from(error: &AnchorClientError) -> Self {
+        match client_relay_error(error) {
+            Some(relay_error) => {
+                let code = match relay_error {
+                    RelayErrorCode::NoDepositOutputs => ApiErrorCode::NoDepositOutputs,
+                    RelayErrorCode::DepositTooSmall => ApiErrorCode::DepositTooSmall,
+                    RelayErrorCode::DepositAlreadyClaimed => ApiErrorCode::AlreadyClaimed,
+                    RelayErrorCode::BlockConfirmations => ApiErrorCode::NotEnoughConfirmations,
+                    _ => ApiErrorCode::RelayProgramError,
+                };
+                ApiError::new(code, format!("{}: {relay_error}", relay_error.name()))
+            }
+            None => ApiError::new(ApiErrorCode::YonaError, error.to_string()),
+        }
+    }
+}
+
+// A blocking handler panicked or was cancelled
+impl From<JoinError> for ApiError {
+    fn from(error: JoinError) -> Self {
+        ApiError::new(ApiErrorCode::InternalError, error.to_string())
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(error: StoreError) -> Self {
+        ApiError::new(ApiErrorCode::InternalError, error.to_string())
+    }
+}
+
+impl From<RelayTxError> for ApiError {
+    fn from(error: RelayTxError) -> Self {
+        let code = match &error {
+            RelayTxError::Anchor(e) => return e.into(),
+            RelayTxError::Store(e) => {
+                return ApiError::new(ApiErrorCode::InternalError, e.to_string())
+            }
+            RelayTxError::VerificationPending(signature) => {
+                return ApiError::new(ApiErrorCode::VerificationPending, error.to_string())
+                    .with_signature(signature)
+            }
+            RelayTxError::BitcoinRpc(_) | RelayTxError::CouldNotFindTxidInBlock => {
+                ApiErrorCode::BitcoinNodeError
+            }
+            RelayTxError::TxNotFound => ApiErrorCode::TxNotFound,
+            RelayTxError::TxIsNotIncludedToBlock => ApiErrorCode::TxNotConfirmed,
+            RelayTxError::BlockNotRelayed { .. } => ApiErrorCode::BlockNotRelayedYet,
+            RelayTxError::NotEnoughConfirmations { .. } => ApiErrorCode::NotEnoughConfirmations,
+            RelayTxError::FeeBudgetExhausted { .. } => ApiErrorCode::FeeBudgetExhausted,
+            RelayTxError::RelayInProgress => ApiErrorCode::RelayInProgress,
+            RelayTxError::TooManyPendingRequests { .. } => ApiErrorCode::RateLimited,
+        };
+        ApiError::new(code, error.to_string())
+    }
+}
+
+impl From<DepositStatusError> for ApiError {
+    fn from(error: DepositStatusError) -> Self {
+        let code = match &error {
+            DepositStatusError::Anchor(_) => ApiErrorCode::YonaError,
+            DepositStatusError::Bitcoin(_) => ApiErrorCode::BitcoinNodeError,
+            DepositStatusError::Store(_) => ApiErrorCode::InternalError,
+        };
+        ApiError::new(code, error.to_string())
+    }
+}
@@ -1,10 +1,13 @@
+use crate::deposit_watcher::DepositAddressPolicy;
+use crate::fees::FeePolicy;
+use crate::store::RelayRequestPolicy;
 use bitcoincore_rpc::Auth;
 use serde::Deserialize;
 use std::fs;
 use std::io;
 use toml;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 #[serde(rename_all = "lowercase")]
 pub enum BitcoinAuth {
@@ -21,13 +24,95 @@ impl From<BitcoinAuth> for Auth {
     }
 }
 
+/// Yona RPC node the relayer fails over to
+#[derive(Deserialize, Debug, Clone)]
+pub struct YonaEndpoint {
+    pub http: String,
+    pub ws: String,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct RelayConfig {
     pub bitcoind_url: String,
+    /// Nodes the block relayer fails over to, in order, using the same `bitcoin_auth`
+    #[serde(default)]
+    pub bitcoind_fallback_urls: Vec<String>,
     pub bitcoin_auth: BitcoinAuth,
+    /// The node's `zmqpubhashblock` endpoint, like `tcp://127.0.0.1:28332`, announcing new blocks
+    /// as they arrive rather than on the next poll
+    pub bitcoind_zmq_url: Option<String>,
     pub yona_http: String,
     pub yona_ws: String,
+    /// Yona RPC nodes the block relayer fails over to, in order
+    #[serde(default)]
+    pub yona_fallbacks: Vec<YonaEndpoint>,
     pub yona_keipair: String,
+    #[serde(default = "default_store_path")]
+    pub store_path: String,
+    #[serde(default = "default_deposit_confirmations")]
+    pub deposit_confirmations: u32,
+    /// Registration limit and expiry of the addresses the deposit watcher scans for
+    #[serde(default)]
+    pub deposit_addresses: DepositAddressPolicy,
+    /// Limit and expiry of the relay requests waiting for headers, confirmations or fees
+    #[serde(default)]
+    pub relay_requests: RelayRequestPolicy,
+    /// Where the relay-blocks and watch-deposits modes serve `/metrics`, `/healthz` and `/readyz`,
+    /// the transaction relay server serves them along with its API
+    #[serde(default = "default_metrics_address")]
+    pub metrics_address: String,
+    /// Signer balance in lamports below which the health endpoints report the relayer degraded
+    #[serde(default = "default_min_signer_balance")]
+    pub min_signer_balance: u64,
+    /// Blocks the relay's tip may be behind the node's before the relayer is reported degraded
+    #[serde(default = "default_max_header_lag")]
+    pub max_header_lag: u32,
+    /// Compute budget, priority fees and fee budget of the relayer's Yona transactions
+    #[serde(default)]
+    pub fees: FeePolicy,
+}
+
+impl RelayConfig {
+    /// The primary Yona endpoint followed by the fallbacks
+    pub fn yona_endpoints(&self) -> Vec<YonaEndpoint> {
+        let primary = YonaEndpoint {
+            http: self.yona_http.clone(),
+            ws: self.yona_ws.clone(),
+        };
+        [primary]
+            .into_iter()
+            .chain(self.yona_fallbacks.iter().cloned())
+            .collect()
+    }
+
+    /// The primary Bitcoin node URL followed by the fallbacks
+    pub fn bitcoind_urls(&self) -> Vec<String> {
+        [self.bitcoind_url.clone()]
+            .into_iter()
+            .chain(self.bitcoind_fallback_urls.iter().cloned())
+            .collect()
+    }
+}
+
+fn default_store_path() -> String {
+    "relayer.sqlite".into()
+}
+
+fn default_deposit_confirmations() -> u32 {
+    1
+}
+
+fn default_metrics_address() -> String {
+    "0.0.0.0:9184".into()
+}
+
+fn default_min_signer_balance() -> u64 {
+    // 0.1 SOL pays for about 20 000 transactions
+    100_000_000
+}
+
+fn default_max_header_lag() -> u32 {
+    3
 }
 
 #[derive(Debug)]
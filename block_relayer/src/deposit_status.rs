@@ -0,0 +1,285 @@
+use crate::deposit_watcher::{deposit_address, registered_scripts};
+use crate::relay_program_interaction::is_unknown_transaction;
+use crate::store::{relay_tx_key, RelayerStore, StoreError, TxKind, TxStatus};
+use crate::yona::YonaClient;
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use anchor_client::ClientError as AnchorClientError;
+use bitcoin::{Transaction, Txid};
+use bitcoincore_rpc::{Error as BtcRpcError, RpcApi};
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// Where a deposit is on its way to be minted on Yona
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum DepositState {
+    /// The transaction is in the node's mempool
+    SeenInMempool,
+    /// The transaction is in a block, but the relay program doesn't have the required
+    /// confirmations for it yet
+    Confirming {
+        /// Confirmations on the Bitcoin node
+        confirmations: u32,
+        /// Confirmations in the headers submitted to the relay program
+        relayed_confirmations: u32,
+        required_confirmations: u32,
+    },
+    /// The relay program has the required confirmations, the verification isn't submitted yet
+    HeaderRelayed,
+    /// The verification is sent, but its outcome isn't known yet
+    VerificationSubmitted {
+        signature: String,
+    },
+    Minted {
+        signature: String,
+    },
+    Failed {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DepositStatus {
+    pub txid: Txid,
+    pub yona_address: String,
+    /// Satoshis paid to the deposit address of `yona_address`, if the node knows the transaction
+    pub amount: Option<u64>,
+    /// Height of the block including the transaction
+    pub block_height: Option<u32>,
+    #[serde(flatten)]
+    pub state: DepositState,
+}
+
+#[derive(Debug)]
+pub enum DepositStatusError {
+    Anchor(AnchorClientError),
+    Bitcoin(BtcRpcError),
+    Store(StoreError),
+}
+
+impl fmt::Display for DepositStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DepositStatusError::Anchor(e) => write!(f, "{e}"),
+            DepositStatusError::Bitcoin(e) => write!(f, "{e}"),
+            DepositStatusError::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<AnchorClientError> for DepositStatusError {
+    fn from(error: AnchorClientError) -> Self {
+        DepositStatusError::Anchor(error)
+    }
+}
+
+impl From<BtcRpcError> for DepositStatusError {
+    fn from(error: BtcRpcError) -> Self {
+        DepositStatusError::Bitcoin(error)
+    }
+}
+
+impl From<StoreError> for DepositStatusError {
+    fn from(error: StoreError) -> Self {
+        DepositStatusError::Store(error)
+    }
+}
+
+/// The deposit transaction as the Bitcoin node knows it
+struct NodeTransaction {
+    transaction: Transaction,
+    block_height: Option<u32>,
+    confirmations: u32,
+}
+
+fn node_transaction(
+    bitcoind_client: &impl RpcApi,
+    txid: &Txid,
+) -> Result<Option<NodeTransaction>, BtcRpcError> {
+    let info = match bitcoind_client.get_raw_transaction_info(txid, None) {
+        Ok(info) => info,
+        Err(e) if is_unknown_transaction(&e) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let block_height = match info.blockhash {
+        Some(hash) => Some(bitcoind_client.get_block_info(&hash)?.height as u32),
+        None => None,
+    };
+
+    Ok(Some(NodeTransaction {
+        // bitcoind returned hex which isn't a transaction
+        transaction: info
+            .transaction()
+            .map_err(|_| BtcRpcError::UnexpectedStructure)?,
+        block_height,
+        confirmations: info.confirmations.unwrap_or(0),
+    }))
+}
+
+// Group key of the relay program's bridge key, no output pays a deposit address while it has none
+fn group_key(yona: &impl YonaClient) -> Result<Option<[u8; 32]>, AnchorClientError> {
+    match yona.bridge_key() {
+        Ok(bridge_key) => Ok(Some(bridge_key.group_key)),
+        Err(AnchorClientError::AccountNotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn deposit_status(
+    store: &RelayerStore,
+    group_key: Option<[u8; 32]>,
+    relay_height: u32,
+    required_confirmations: u32,
+    txid: Txid,
+    node_transaction: Option<&NodeTransaction>,
+    yona_address: String,
+) -> Result<DepositStatus, StoreError> {
+    let amount = node_transaction.map(|node_transaction| {
+        let script_pubkey = Pubkey::from_str(&yona_address)
+            .ok()
+            .zip(group_key)
+            .and_then(|(pubkey, group_key)| deposit_address(&pubkey, group_key))
+            .map(|address| address.script_pubkey());
+        node_transaction
+            .transaction
+            .output
+            .iter()
+            .filter(|output| Some(&output.script_pubkey) == script_pubkey.as_ref())
+            .map(|output| output.value.to_sat())
+            .sum()
+    });
+    let block_height = node_transaction.and_then(|node_transaction| node_transaction.block_height);
+
+    let transactions = store.transactions(TxKind::RelayTx, &relay_tx_key(&txid, &yona_address))?;
+    let latest = |status: TxStatus| transactions.iter().find(|tx| tx.status == status);
+
+    let state = if let Some(minted) = latest(TxStatus::Confirmed) {
+        DepositState::Minted {
+            signature: minted.signature.to_string(),
+        }
+    } else if let Some(pending) = latest(TxStatus::Pending) {
+        DepositState::VerificationSubmitted {
+            signature: pending.signature.to_string(),
+        }
+    } else if let Some(failed) = transactions.first() {
+        DepositState::Failed {
+            reason: failed
+                .error
+                .clone()
+                .unwrap_or_else(|| "Verification failed".into()),
+        }
+    } else {
+        match (node_transaction, amount, block_height) {
+            (None, _, _) => DepositState::Failed {
+                reason: "The Bitcoin node doesn't know the transaction".into(),
+            },
+            (_, Some(0), _) => DepositState::Failed {
+                reason: format!("No outputs sending to the deposit address of {yona_address}"),
+            },
+            (Some(_), _, None) => DepositState::SeenInMempool,
+            (Some(node_transaction), _, Some(height)) => {
+                // The relay program counts confirmations from its own tip
+                let relayed_confirmations = (relay_height + 1).saturating_sub(height);
+                if relayed_confirmations >= required_confirmations {
+                    DepositState::HeaderRelayed
+                } else {
+                    DepositState::Confirming {
+                        confirmations: node_transaction.confirmations,
+                        relayed_confirmations,
+                        required_confirmations,
+                    }
+                }
+            }
+        }
+    };
+
+    Ok(DepositStatus {
+        txid,
+        yona_address,
+        amount,
+        block_height,
+        state,
+    })
+}
+
+/// Statuses of the deposits made by the transaction, to the Yona addresses it was relayed or
+/// requested for and to the registered deposit addresses it pays.
+///
+/// Empty when neither the relayer nor the node know the transaction.
+pub fn deposits_of_tx(
+    yona: &impl YonaClient,
+    bitcoind_client: &impl RpcApi,
+    store: &RelayerStore,
+    txid: Txid,
+    required_confirmations: u32,
+) -> Result<Vec<DepositStatus>, DepositStatusError> {
+    let node_transaction = node_transaction(bitcoind_client, &txid)?;
+    let group_key = group_key(yona)?;
+
+    let mut yona_addresses = store.deposit_addresses_of_tx(&txid)?;
+    if let Some((node_transaction, group_key)) = node_transaction.as_ref().zip(group_key) {
+        let registered = registered_scripts(store, group_key)?;
+        for output in node_transaction.transaction.output.iter() {
+            if let Some(yona_address) = registered.get(&output.script_pubkey) {
+                if !yona_addresses.contains(yona_address) {
+                    yona_addresses.push(yona_address.clone());
+                }
+            }
+        }
+    }
+    if yona_addresses.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let relay_height = yona.main_state()?.block_height;
+    yona_addresses
+        .into_iter()
+        .map(|yona_address| {
+            Ok(deposit_status(
+                store,
+                group_key,
+                relay_height,
+                required_confirmations,
+                txid,
+                node_transaction.as_ref(),
+                yona_address,
+            )?)
+        })
+        .collect()
+}
+
+/// Statuses of the deposits relayed, requested or found for the Yona address, oldest first.
+///
+/// Deposits only in the mempool are known once their relay is requested.
+pub fn deposits_of_address(
+    yona: &impl YonaClient,
+    bitcoind_client: &impl RpcApi,
+    store: &RelayerStore,
+    yona_address: &Pubkey,
+    required_confirmations: u32,
+) -> Result<Vec<DepositStatus>, DepositStatusError> {
+    let txids = store.deposit_txids_of(&yona_address.to_string())?;
+    if txids.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let group_key = group_key(yona)?;
+    let relay_height = yona.main_state()?.block_height;
+    txids
+        .into_iter()
+        .map(|txid| {
+            let node_transaction = node_transaction(bitcoind_client, &txid)?;
+            Ok(deposit_status(
+                store,
+                group_key,
+                relay_height,
+                required_confirmations,
+                txid,
+                node_transaction.as_ref(),
+                yona_address.to_string(),
+            )?)
+        })
+        .collect()
+}
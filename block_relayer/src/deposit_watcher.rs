@@ -0,0 +1,214 @@
+use crate::relay_program_interaction::{relay_tx, RelayTxError};
+use crate::store::{Deposit, RelayerStore, StoreError};
+use crate::yona::YonaClient;
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use anchor_client::ClientError as AnchorClientError;
+use bitcoin::{Address, Network, ScriptBuf};
+use bitcoincore_rpc::{Error as BtcRpcError, RpcApi};
+use btc_relay::utils::bridge_deposit_script_pubkey;
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// How many deposit addresses can be registered and how long the deposit watcher scans for them
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DepositAddressPolicy {
+    /// Seconds an address is scanned for after its latest registration
+    pub ttl_secs: u64,
+    /// New addresses registered per hour, registering an already registered one is always allowed
+    pub max_registrations_per_hour: u64,
+}
+
+impl Default for DepositAddressPolicy {
+    fn default() -> DepositAddressPolicy {
+        DepositAddressPolicy {
+            ttl_secs: 7 * 24 * 60 * 60,
+            max_registrations_per_hour: 600,
+        }
+    }
+}
+
+/// Bridge deposit address of the Yona account, derived from the group key of the relay program's
+/// bridge key. `None` if the group key isn't a valid x-only key
+pub fn deposit_address(yona_address: &Pubkey, group_key: [u8; 32]) -> Option<Address> {
+    let script_pubkey = bridge_deposit_script_pubkey(yona_address.to_bytes(), group_key).ok()?;
+    Address::from_script(&script_pubkey, Network::Regtest).ok()
+}
+
+/// Deposit address scripts of the registered Yona addresses
+pub(crate) fn registered_scripts(
+    store: &RelayerStore,
+    group_key: [u8; 32],
+) -> Result<HashMap<ScriptBuf, String>, StoreError> {
+    Ok(store
+        .deposit_addresses()?
+        .into_iter()
+        .filter_map(|yona_address| {
+            let pubkey = Pubkey::from_str(&yona_address).ok()?;
+            let script_pubkey = bridge_deposit_script_pubkey(pubkey.to_bytes(), group_key).ok()?;
+            Some((script_pubkey, yona_address))
+        })
+        .collect())
+}
+
+#[derive(Debug)]
+pub enum WatchDepositsError {
+    Anchor(AnchorClientError),
+    Bitcoin(BtcRpcError),
+    Store(StoreError),
+}
+
+impl fmt::Display for WatchDepositsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchDepositsError::Anchor(e) => write!(f, "{e}"),
+            WatchDepositsError::Bitcoin(e) => write!(f, "{e}"),
+            WatchDepositsError::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<AnchorClientError> for WatchDepositsError {
+    fn from(error: AnchorClientError) -> Self {
+        WatchDepositsError::Anchor(error)
+    }
+}
+
+impl From<BtcRpcError> for WatchDepositsError {
+    fn from(error: BtcRpcError) -> Self {
+        WatchDepositsError::Bitcoin(error)
+    }
+}
+
+impl From<StoreError> for WatchDepositsError {
+    fn from(error: StoreError) -> Self {
+        WatchDepositsError::Store(error)
+    }
+}
+
+/// Records the deposits to the registered addresses in the blocks the watcher hasn't scanned yet.
+///
+/// On the first run scanning starts at the relay's tip, as older deposits were relayed by request.
+fn scan_blocks(
+    bitcoind_client: &impl RpcApi,
+    store: &RelayerStore,
+    confirmations: u32,
+    relay_height: u32,
+    group_key: [u8; 32],
+) -> Result<(), WatchDepositsError> {
+    let best_height = bitcoind_client.get_block_count()? as u32;
+
+    let addresses = registered_scripts(store, group_key)?;
+    if addresses.is_empty() {
+        return Ok(store.set_scanned_height(best_height)?);
+    }
+
+    // A reorg shallower than the required confirmations can replace scanned blocks before their
+    // deposits are relayed, so these blocks are scanned again
+    let start = match store.scanned_height()? {
+        Some(scanned) => (scanned + 1).saturating_sub(confirmations),
+        None => relay_height,
+    };
+
+    for height in start..=best_height {
+        let block_hash = bitcoind_client.get_block_hash(height as u64)?;
+        let block = bitcoind_client.get_block(&block_hash)?;
+
+        for tx in block.txdata.iter() {
+            let mut amounts: HashMap<&String, u64> = HashMap::new();
+            for output in tx.output.iter() {
+                if let Some(yona_address) = addresses.get(&output.script_pubkey) {
+                    *amounts.entry(yona_address).or_default() += output.value.to_sat();
+                }
+            }
+
+            for (yona_address, amount) in amounts {
+                let deposit = Deposit {
+                    txid: tx.compute_txid(),
+                    yona_address: yona_address.clone(),
+                    amount,
+                    height,
+                };
+                info!("Found deposit {deposit:?}");
+                store.insert_deposit(&deposit)?;
+            }
+        }
+        store.set_scanned_height(height)?;
+    }
+    Ok(())
+}
+
+/// Scans new blocks for deposits to the registered addresses and relays the found deposits once
+/// the relay program has `confirmations` blocks on top of them.
+///
+/// Returns the number of deposits minted. Deposits failing to relay are retried on the next call.
+pub fn watch_deposits(
+    yona: &impl YonaClient,
+    bitcoind_client: &impl RpcApi,
+    store: &RelayerStore,
+    confirmations: u32,
+) -> Result<usize, WatchDepositsError> {
+    let main_state_data = yona.main_state()?;
+    // No deposit address exists before the signer nodes register their group key
+    let bridge_key = match yona.bridge_key() {
+        Ok(bridge_key) => bridge_key,
+        Err(AnchorClientError::AccountNotFound) => {
+            info!("The relay program has no bridge key yet, not scanning for deposits");
+            return Ok(0);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    scan_blocks(
+        bitcoind_client,
+        store,
+        confirmations,
+        main_state_data.block_height,
+        bridge_key.group_key,
+    )?;
+
+    let (main_state, _) = Pubkey::find_program_address(&[b"state"], &yona.id());
+    let mut minted = 0;
+    for deposit in store.pending_deposits()? {
+        // Not waiting for the confirmations in relay_tx, to not record the wait as a failure
+        if main_state_data.block_height + 1 < deposit.height + confirmations {
+            continue;
+        }
+
+        let mint_receiver = match Pubkey::from_str(&deposit.yona_address) {
+            Ok(pubkey) => pubkey,
+            Err(e) => {
+                error!("Deposit {} has invalid yona_address: {e}", deposit.txid);
+                continue;
+            }
+        };
+
+        match relay_tx(
+            yona,
+            store,
+            main_state,
+            bitcoind_client,
+            deposit.txid,
+            mint_receiver,
+            confirmations,
+        ) {
+            Ok(sig) => {
+                info!("Relayed deposit {}, Yona tx {sig}", deposit.txid);
+                store.set_deposit_result(&deposit, Ok(&sig))?;
+                minted += 1;
+            }
+            // The other deposits would be paused too
+            Err(e @ RelayTxError::FeeBudgetExhausted { .. }) => {
+                info!("Pausing deposit relays: {e}");
+                break;
+            }
+            Err(e) => {
+                warn!("Error {e:?} on relaying deposit {}", deposit.txid);
+                store.set_deposit_result(&deposit, Err(&format!("{e:?}")))?;
+            }
+        }
+    }
+    Ok(minted)
+}
@@ -0,0 +1,135 @@
+use crate::yona::YonaClient;
+use anchor_client::solana_sdk::compute_budget::ComputeBudgetInstruction;
+use anchor_client::solana_sdk::instruction::Instruction;
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use log::warn;
+use serde::Deserialize;
+
+// Priority fee a retry escalates from when the recent fees are all zero, in micro-lamports per
+// compute unit
+const RETRY_BASE_PRIORITY_FEE: u64 = 1_000;
+
+/// How the relayer prices its Yona transactions.
+///
+/// Priority fees are in micro-lamports per compute unit.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct FeePolicy {
+    /// Compute units requested per transaction, `None` keeps Yona's default
+    pub compute_unit_limit: Option<u32>,
+    /// Percentile of the recent prioritization fees paid for the accounts the transaction writes
+    pub priority_fee_percentile: u8,
+    pub min_priority_fee: u64,
+    pub max_priority_fee: u64,
+    /// Factor the priority fee grows by with each earlier attempt of the transaction
+    pub retry_fee_multiplier: f64,
+    /// Lamports the relayer may pay per hour before deposit verifications are paused. Header
+    /// submissions are always sent, as the deposits wait for them.
+    pub hourly_fee_budget: Option<u64>,
+}
+
+impl Default for FeePolicy {
+    fn default() -> FeePolicy {
+        FeePolicy {
+            compute_unit_limit: None,
+            priority_fee_percentile: 75,
+            min_priority_fee: 0,
+            max_priority_fee: 100_000,
+            retry_fee_multiplier: 2.0,
+            hourly_fee_budget: None,
+        }
+    }
+}
+
+/// The value at the percentile of the fees, 0 without fees
+fn percentile(fees: &[u64], percentile: u8) -> u64 {
+    let mut fees = fees.to_vec();
+    fees.sort_unstable();
+    let Some(last) = fees.len().checked_sub(1) else {
+        return 0;
+    };
+    let index = (last * usize::from(percentile.min(100))).div_ceil(100);
+    fees[index]
+}
+
+impl FeePolicy {
+    /// Priority fee of a transaction attempted `retries` times before, given the recent
+    /// prioritization fees
+    pub fn priority_fee(&self, recent_fees: &[u64], retries: usize) -> u64 {
+        let mut fee =
+            percentile(recent_fees, self.priority_fee_percentile).max(self.min_priority_fee);
+        if retries > 0 {
+            let escalation = self
+                .retry_fee_multiplier
+                .powi(retries.try_into().unwrap_or(i32::MAX));
+            fee = (fee.max(RETRY_BASE_PRIORITY_FEE) as f64 * escalation) as u64;
+        }
+        fee.min(self.max_priority_fee)
+    }
+
+    /// Compute budget instructions to prepend to the instruction, pricing it from the fees
+    /// recently paid for the accounts it writes
+    pub fn compute_budget_instructions(
+        &self,
+        yona: &impl YonaClient,
+        instruction: &Instruction,
+        retries: usize,
+    ) -> Vec<Instruction> {
+        let writable: Vec<Pubkey> = instruction
+            .accounts
+            .iter()
+            .filter(|account| account.is_writable)
+            .map(|account| account.pubkey)
+            .collect();
+        // Without recent fees the transaction is priced from the minimum
+        let recent_fees = yona
+            .recent_prioritization_fees(&writable)
+            .unwrap_or_else(|e| {
+                warn!("Error {e} on getting the recent prioritization fees");
+                vec![]
+            });
+
+        let mut instructions = vec![];
+        if let Some(units) = self.compute_unit_limit {
+            instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(units));
+        }
+        let priority_fee = self.priority_fee(&recent_fees, retries);
+        if priority_fee > 0 {
+            instructions.push(ComputeBudgetInstruction::set_compute_unit_price(
+                priority_fee,
+            ));
+        }
+        instructions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prices_from_the_recent_fees_percentile() {
+        let policy = FeePolicy::default();
+        assert_eq!(policy.priority_fee(&[], 0), 0);
+        assert_eq!(policy.priority_fee(&[40, 10, 30, 20, 50], 0), 40);
+
+        let policy = FeePolicy {
+            min_priority_fee: 45,
+            max_priority_fee: 1_000,
+            ..FeePolicy::default()
+        };
+        assert_eq!(policy.priority_fee(&[40, 10, 30, 20, 50], 0), 45);
+        assert_eq!(policy.priority_fee(&[5_000], 0), 1_000);
+    }
+
+    #[test]
+    fn escalates_retries_up_to_the_maximum() {
+        let policy = FeePolicy {
+            max_priority_fee: 10_000,
+            ..FeePolicy::default()
+        };
+        assert_eq!(policy.priority_fee(&[], 1), 2_000);
+        assert_eq!(policy.priority_fee(&[3_000], 1), 6_000);
+        assert_eq!(policy.priority_fee(&[3_000], 2), 10_000);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::api_error::ApiError;
+use crate::yona::YonaClient;
+use actix_web::{web, HttpResponse};
+use anchor_client::solana_sdk::signature::Keypair;
+use anchor_client::Program;
+use bitcoincore_rpc::{Client as BitcoinRpcClient, RpcApi};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::task::spawn_blocking;
+
+/// Limits beyond which the relayer reports itself degraded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthThresholds {
+    /// Lowest balance of the signer paying for the relayer's transactions, in lamports
+    pub min_signer_balance: u64,
+    /// Most blocks the relay program's tip may be behind the node's best block
+    pub max_header_lag: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    Degraded,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthCheck {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+/// Body of the `/healthz` and `/readyz` responses
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    pub fn is_ok(&self) -> bool {
+        self.status == HealthStatus::Ok
+    }
+}
+
+fn check(name: &'static str, ok: bool, detail: impl Into<String>) -> HealthCheck {
+    HealthCheck {
+        name,
+        ok,
+        detail: detail.into(),
+    }
+}
+
+/// Checks the connectivity to the Bitcoin node and Yona, the signer balance and the header lag
+pub fn check_health(
+    yona: &impl YonaClient,
+    bitcoind_client: &impl RpcApi,
+    thresholds: &HealthThresholds,
+) -> HealthReport {
+    let mut checks = vec![];
+
+    let node_height = match bitcoind_client.get_block_count() {
+        Ok(height) => {
+            checks.push(check("bitcoind", true, format!("Best block {height}")));
+            Some(height as u32)
+        }
+        Err(e) => {
+            checks.push(check("bitcoind", false, e.to_string()));
+            None
+        }
+    };
+
+    let relay_height = match yona.main_state() {
+        Ok(main_state) => {
+            checks.push(check(
+                "yona",
+                true,
+                format!("Relay tip {}", main_state.block_height),
+            ));
+            Some(main_state.block_height)
+        }
+        Err(e) => {
+            checks.push(check("yona", false, e.to_string()));
+            None
+        }
+    };
+
+    checks.push(match yona.get_account(&yona.payer()) {
+        Ok(account) if account.lamports >= thresholds.min_signer_balance => check(
+            "signer_balance",
+            true,
+            format!("{} lamports", account.lamports),
+        ),
+        Ok(account) => check(
+            "signer_balance",
+            false,
+            format!(
+                "{} lamports, below the {} floor",
+                account.lamports, thresholds.min_signer_balance
+            ),
+        ),
+        Err(e) => check("signer_balance", false, e.to_string()),
+    });
+
+    checks.push(match (node_height, relay_height) {
+        (Some(node_height), Some(relay_height)) => {
+            let lag = node_height.saturating_sub(relay_height);
+            if lag <= thresholds.max_header_lag {
+                check("header_lag", true, format!("{lag} blocks"))
+            } else {
+                check(
+                    "header_lag",
+                    false,
+                    format!(
+                        "{lag} blocks, above the limit of {}",
+                        thresholds.max_header_lag
+                    ),
+                )
+            }
+        }
+        _ => check(
+            "header_lag",
+            false,
+            "Unknown, the Bitcoin node or Yona is unreachable",
+        ),
+    });
+
+    let status = if checks.iter().all(|check| check.ok) {
+        HealthStatus::Ok
+    } else {
+        HealthStatus::Degraded
+    };
+    HealthReport { status, checks }
+}
+
+/// Clients the health endpoints check with, separate from the relayer's own
+pub(crate) struct HealthState {
+    pub(crate) yona: Program<Arc<Keypair>>,
+    pub(crate) bitcoind_client: BitcoinRpcClient,
+    pub(crate) thresholds: HealthThresholds,
+}
+
+async fn health_report(data: web::Data<HealthState>) -> Result<HealthReport, ApiError> {
+    Ok(
+        spawn_blocking(move || check_health(&data.yona, &data.bitcoind_client, &data.thresholds))
+            .await?,
+    )
+}
+
+/// Liveness: answers as long as the relayer serves, reporting the checks
+pub(crate) async fn healthz_web_api(
+    data: web::Data<HealthState>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(health_report(data).await?))
+}
+
+/// Readiness: `503 Service Unavailable` while a check fails
+pub(crate) async fn readyz_web_api(data: web::Data<HealthState>) -> Result<HttpResponse, ApiError> {
+    let report = health_report(data).await?;
+    if report.is_ok() {
+        Ok(HttpResponse::Ok().json(report))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(report))
+    }
+}
@@ -1,47 +1,78 @@
+pub mod api_error;
 pub mod config;
+pub mod deposit_status;
+pub mod deposit_watcher;
+pub mod fees;
+pub mod health;
 mod merkle;
+pub mod metrics;
+pub mod notifications;
+pub mod relay_loop;
 mod relay_program_interaction;
-
-use crate::config::RelayConfig;
-use crate::relay_program_interaction::{
-    init_program, reconstruct_commited_header, relay_tx, submit_block,
+pub mod shutdown;
+pub mod store;
+pub mod yona;
+
+use crate::api_error::{ApiError, ApiErrorCode};
+use crate::config::{RelayConfig, YonaEndpoint};
+use crate::deposit_status::{deposits_of_address, deposits_of_tx};
+use crate::deposit_watcher::{deposit_address, watch_deposits};
+use crate::health::{healthz_web_api, readyz_web_api, HealthState, HealthThresholds};
+use crate::metrics::{
+    error_label, metrics_web_api, record_signer_balance, track_http_request, METRICS,
+};
+use crate::notifications::{subscribe_to_blocks, subscribe_to_relay_tip, Wakeup};
+use crate::relay_loop::{BlockRelayer, Failover, RelayError};
+pub use crate::relay_program_interaction::{
+    init_deposit, init_deposit_fees, init_fee_pool, relay_tx, RelayTxError, SubmitBlockError,
 };
+use crate::relay_program_interaction::{init_program, reconstruct_commited_header, submit_block};
+use crate::shutdown::Shutdown;
+use crate::store::{relay_tx_key, RelayerStore, StoreError, TxKind, TxStatus};
+use crate::yona::YonaClient;
 use actix_cors::Cors;
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use anchor_client::anchor_lang::{AccountDeserialize, AnchorDeserialize, Id};
+use actix_web::{web, App, HttpResponse, HttpServer};
+use anchor_client::anchor_lang::Id;
 use anchor_client::solana_sdk::commitment_config::CommitmentConfig;
 use anchor_client::solana_sdk::pubkey::Pubkey;
 use anchor_client::solana_sdk::signature::{read_keypair_file, Keypair, Signature};
 use anchor_client::{Client as AnchorClient, ClientError as AnchorClientError, Cluster, Program};
-use base64::prelude::BASE64_STANDARD;
-use base64::Engine;
 use bitcoin::hashes::Hash;
 use bitcoin::hex::DisplayHex;
-use bitcoin::{Address, BlockHash, Network, PublicKey, Txid};
+use bitcoin::{Address, BlockHash, Txid};
 use bitcoincore_rpc::{Client as BitcoinRpcClient, Error as BtcError, RpcApi};
-use btc_relay::events::StoreHeader;
 use btc_relay::program::BtcRelay;
-use btc_relay::state::MainState;
-use btc_relay::utils::{bridge_deposit_script, BITCOIN_DEPOSIT_PUBKEY};
-use log::{debug, error, info};
-use serde::Deserialize;
-use solana_transaction_status::UiTransactionEncoding;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{env, error, thread};
 use tokio::task::spawn_blocking;
 
 fn get_yona_client(
     config: &RelayConfig,
 ) -> Result<AnchorClient<Arc<Keypair>>, Box<dyn error::Error>> {
-    let mut keypair_path = env::home_dir().expect("to get the home dir");
+    let endpoint = YonaEndpoint {
+        http: config.yona_http.clone(),
+        ws: config.yona_ws.clone(),
+    };
+    get_yona_endpoint_client(config, &endpoint)
+}
+
+fn get_yona_endpoint_client(
+    config: &RelayConfig,
+    endpoint: &YonaEndpoint,
+) -> Result<AnchorClient<Arc<Keypair>>, Box<dyn error::Error>> {
+    let mut keypair_path = env::home_dir().ok_or("Couldn't get the home dir")?;
     keypair_path.push(&config.yona_keipair);
     // Set up sender and recipient keypairs
     let sender = read_keypair_file(keypair_path)?;
 
     let signer = Arc::new(sender);
-    let cluster = Cluster::Custom(config.yona_http.clone(), config.yona_ws.clone());
+    let cluster = Cluster::Custom(endpoint.http.clone(), endpoint.ws.clone());
     Ok(AnchorClient::new_with_options(
         cluster,
         signer,
@@ -49,133 +80,259 @@ fn get_yona_client(
     ))
 }
 
-pub fn relay_blocks_from_full_node(config: RelayConfig) {
-    let yona_client = get_yona_client(&config).expect("Couldn't create Yona client");
+fn main_state_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"state"], &BtcRelay::id()).0
+}
+
+/// Wakes the loop on the node's new blocks, when its ZMQ endpoint is configured
+fn subscribe_to_node_blocks(config: &RelayConfig, wakeup: &Wakeup) {
+    if let Some(endpoint) = &config.bitcoind_zmq_url {
+        subscribe_to_blocks(endpoint.clone(), wakeup.clone());
+    }
+}
+
+/// Relays the node's blocks until SIGTERM or SIGINT, failing over between the configured
+/// endpoints
+pub fn relay_blocks_from_full_node(config: RelayConfig) -> Result<(), RelayError> {
+    let shutdown = Shutdown::on_termination_signals().map_err(RelayError::CouldNotHandleSignals)?;
+    serve_ops(&config)?;
+
+    let mut yona = vec![];
+    for endpoint in config.yona_endpoints() {
+        let yona_client = get_yona_endpoint_client(&config, &endpoint)
+            .map_err(RelayError::CouldNotInitYonaClient)?;
+        yona.push((endpoint.http, yona_client.program(BtcRelay::id())?));
+    }
 
-    let bitcoind_client = BitcoinRpcClient::new(&config.bitcoind_url, config.bitcoin_auth.into())
-        .expect("Couldn't create Bitcoin client");
+    let mut bitcoind = vec![];
+    for url in config.bitcoind_urls() {
+        let bitcoind_client = BitcoinRpcClient::new(&url, config.bitcoin_auth.clone().into())?;
+        bitcoind.push((url, bitcoind_client));
+    }
 
-    let relay_program = BtcRelay::id();
-    let program = yona_client
-        .program(relay_program)
-        .expect("Couldn't create relay program instance");
+    let store = RelayerStore::open(&config.store_path)?.with_fee_policy(config.fees.clone());
 
-    let (main_state, _) = Pubkey::find_program_address(&[b"state"], &relay_program);
+    // Blocks relayed by other relayers move the tip too
+    subscribe_to_node_blocks(&config, &shutdown.wakeup());
+    subscribe_to_relay_tip(
+        config.yona_ws.clone(),
+        main_state_address(),
+        shutdown.wakeup(),
+    );
 
-    loop {
-        let raw_account = match program.rpc().get_account(&main_state) {
-            Ok(acc) => acc,
+    BlockRelayer::new(Failover::new(yona), Failover::new(bitcoind), store).run(&shutdown);
+    Ok(())
+}
+
+fn health_state(config: &RelayConfig) -> Result<HealthState, RelayError> {
+    let yona_client = get_yona_client(config).map_err(RelayError::CouldNotInitYonaClient)?;
+    Ok(HealthState {
+        yona: yona_client.program(BtcRelay::id())?,
+        bitcoind_client: BitcoinRpcClient::new(
+            &config.bitcoind_url,
+            config.bitcoin_auth.clone().into(),
+        )?,
+        thresholds: HealthThresholds {
+            min_signer_balance: config.min_signer_balance,
+            max_header_lag: config.max_header_lag,
+        },
+    })
+}
+
+/// Serves `/metrics`, `/healthz` and `/readyz` in a background thread, for the modes without an
+/// HTTP API
+fn serve_ops(config: &RelayConfig) -> Result<(), RelayError> {
+    let address = config.metrics_address.clone();
+    let health_state = web::Data::new(health_state(config)?);
+    thread::spawn(move || {
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(health_state.clone())
+                .route("/metrics", web::get().to(metrics_web_api))
+                .route("/healthz", web::get().to(healthz_web_api))
+                .route("/readyz", web::get().to(readyz_web_api))
+        })
+        .workers(1)
+        .bind(&address);
+        // The relayer keeps working without metrics and health endpoints
+        let server = match server {
+            Ok(server) => server,
             Err(e) => {
-                error!("Error {e} on get_account(main_state)");
-                thread::sleep(Duration::from_secs(10));
-                continue;
+                error!("Error {e} on binding the metrics server to {address}");
+                return;
             }
         };
+        if let Err(e) = actix_web::rt::System::new().block_on(server.run()) {
+            error!("Metrics server stopped: {e}");
+        }
+    });
+    Ok(())
+}
 
-        // TODO there seems to be an allocation of 8 unneeded bytes, which makes deserialization fail
-        let main_state_data =
-            match MainState::try_deserialize_unchecked(&mut &raw_account.data[..8128]) {
-                Ok(data) => data,
-                Err(e) => {
-                    error!("Error {e} on main_state deserialization attempt");
-                    thread::sleep(Duration::from_secs(10));
-                    continue;
-                }
-            };
+/// Relays the deposits to the addresses handed out by the web API, without waiting for the user
+/// to request it
+pub fn watch_deposits_from_full_node(config: RelayConfig) -> Result<(), RelayError> {
+    serve_ops(&config)?;
+
+    // Deposits show up in new blocks and get relayable as the relay's tip catches up
+    let wakeup = Wakeup::default();
+    subscribe_to_node_blocks(&config, &wakeup);
+    subscribe_to_relay_tip(config.yona_ws.clone(), main_state_address(), wakeup.clone());
+
+    let yona_client = get_yona_client(&config).map_err(RelayError::CouldNotInitYonaClient)?;
+
+    let bitcoind_client = BitcoinRpcClient::new(&config.bitcoind_url, config.bitcoin_auth.into())?;
+
+    let program = yona_client.program(BtcRelay::id())?;
 
-        let mut block_hash = main_state_data.tip_block_hash;
-        let commited_header = match reconstruct_commited_header(
+    let store = RelayerStore::open(&config.store_path)?
+        .with_fee_policy(config.fees.clone())
+        .with_deposit_address_policy(config.deposit_addresses.clone());
+
+    loop {
+        record_signer_balance(&program);
+        match watch_deposits(
+            &program,
             &bitcoind_client,
-            &BlockHash::from_byte_array(block_hash),
-            main_state_data.block_height,
-            main_state_data.last_diff_adjustment,
+            &store,
+            config.deposit_confirmations,
         ) {
-            Ok(header) => header,
-            Err(e) => {
-                error!("Error {e} on reconstruct_commited_header");
-                thread::sleep(Duration::from_secs(10));
-                continue;
+            Ok(minted) => {
+                if minted > 0 {
+                    info!("Relayed {minted} deposits");
+                }
+                wakeup.wait(Duration::from_secs(30));
             }
-        };
-        block_hash.reverse();
-
-        info!(
-            "Last stored block hash {} and height {}",
-            block_hash.to_lower_hex_string(),
-            main_state_data.block_height
-        );
-
-        let stored_header = StoreHeader {
-            block_hash,
-            commit_hash: main_state_data.tip_commit_hash,
-            header: commited_header,
-        };
-
-        let last_submitted_height = stored_header.header.blockheight;
-
-        let best_block_hash = match bitcoind_client.get_best_block_hash() {
-            Ok(hash) => hash,
             Err(e) => {
-                error!("Error {e} on Bitcoin's get_best_block_hash");
-                thread::sleep(Duration::from_secs(10));
-                continue;
+                error!("Error {e} on watching deposits");
+                wakeup.wait(Duration::from_secs(10));
             }
-        };
+        }
+    }
+}
 
-        let best_block_height = match bitcoind_client.get_block_info(&best_block_hash) {
-            Ok(info) => info.height as u32,
-            Err(e) => {
-                error!("Error {e} on Bitcoin's get_block_info({best_block_hash:02x})");
-                thread::sleep(Duration::from_secs(10));
-                continue;
-            }
-        };
+#[derive(Debug)]
+pub enum RelayBlockError {
+    Anchor(AnchorClientError),
+    Bitcoin(BtcError),
+    Submit(SubmitBlockError),
+    Store(StoreError),
+}
 
-        if last_submitted_height >= best_block_height {
-            info!("Latest BTC block {best_block_height} is already submitted to Yona. Waiting for a new one.");
-            thread::sleep(Duration::from_secs(30));
-            continue;
+impl fmt::Display for RelayBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelayBlockError::Anchor(e) => write!(f, "{e}"),
+            RelayBlockError::Bitcoin(e) => write!(f, "{e}"),
+            RelayBlockError::Submit(e) => write!(f, "{e}"),
+            RelayBlockError::Store(e) => write!(f, "{e}"),
         }
+    }
+}
 
-        let new_height = last_submitted_height + 1;
+impl From<AnchorClientError> for RelayBlockError {
+    fn from(error: AnchorClientError) -> Self {
+        RelayBlockError::Anchor(error)
+    }
+}
 
-        let block_hash_to_submit = match bitcoind_client.get_block_hash(new_height as u64) {
-            Ok(hash) => hash,
-            Err(e) => {
-                error!("Error {e} on Bitcoin's get_block_hash({new_height})");
-                thread::sleep(Duration::from_secs(10));
-                continue;
-            }
-        };
+impl From<BtcError> for RelayBlockError {
+    fn from(error: BtcError) -> Self {
+        RelayBlockError::Bitcoin(error)
+    }
+}
 
-        let block_to_submit = match bitcoind_client.get_block(&block_hash_to_submit) {
-            Ok(block) => block,
-            Err(e) => {
-                error!("Error {e} on Bitcoin's get_block({block_hash_to_submit:02x})");
-                thread::sleep(Duration::from_secs(10));
-                continue;
-            }
-        };
+impl From<SubmitBlockError> for RelayBlockError {
+    fn from(error: SubmitBlockError) -> Self {
+        RelayBlockError::Submit(error)
+    }
+}
 
-        if let Err(e) = submit_block(
-            &program,
-            main_state,
-            block_to_submit,
-            new_height,
-            stored_header.header,
-        ) {
-            error!("Error {e} on block submit attempt");
-            thread::sleep(Duration::from_secs(10));
-            continue;
-        }
+impl From<StoreError> for RelayBlockError {
+    fn from(error: StoreError) -> Self {
+        RelayBlockError::Store(error)
     }
+}
+
+/// Submits the block following the relay's tip.
+///
+/// Returns the height of the submitted block, or `None` when the relay already has the node's best block.
+pub fn relay_next_block(
+    yona: &impl YonaClient,
+    bitcoind_client: &impl RpcApi,
+    store: &RelayerStore,
+) -> Result<Option<u32>, RelayBlockError> {
+    // Headers sent before a restart may have landed since, or still need to be broadcast
+    store.resume_pending(yona, TxKind::Header)?;
+
+    let main_state_data = yona.main_state()?;
+
+    let mut block_hash = main_state_data.tip_block_hash;
+    let commited_header = reconstruct_commited_header(
+        bitcoind_client,
+        &BlockHash::from_byte_array(block_hash),
+        main_state_data.block_height,
+        &main_state_data,
+    )?;
+    block_hash.reverse();
+
+    info!(
+        "Last stored block hash {} and height {}",
+        block_hash.to_lower_hex_string(),
+        main_state_data.block_height
+    );
+
+    let last_submitted_height = commited_header.blockheight;
 
-    /*
-    if env::var("INIT_DEPOSIT").is_ok() {
-        init_deposit(&program, 100 * LAMPORTS_PER_SOL);
+    let best_block_hash = bitcoind_client.get_best_block_hash()?;
+    let best_block_height = bitcoind_client.get_block_info(&best_block_hash)?.height as u32;
+
+    METRICS
+        .relay_tip_height
+        .set(main_state_data.block_height as i64);
+    METRICS.bitcoind_tip_height.set(best_block_height as i64);
+
+    if last_submitted_height >= best_block_height {
+        info!("Latest BTC block {best_block_height} is already submitted to Yona. Waiting for a new one.");
+        return Ok(None);
+    }
+
+    let new_height = last_submitted_height + 1;
+    let block_hash_to_submit = bitcoind_client.get_block_hash(new_height as u64)?;
+    let block_to_submit = bitcoind_client.get_block(&block_hash_to_submit)?;
+
+    let (main_state, _) = Pubkey::find_program_address(&[b"state"], &yona.id());
+    let started = Instant::now();
+    match submit_block(
+        yona,
+        store,
+        main_state,
+        block_to_submit,
+        new_height,
+        commited_header,
+    ) {
+        Ok(_) => {
+            METRICS
+                .header_submission_seconds
+                .observe(started.elapsed().as_secs_f64());
+            METRICS.relay_tip_height.set(new_height as i64);
+        }
+        Err(e) => {
+            let error = match &e {
+                SubmitBlockError::Anchor(e) => error_label(e),
+                // Headers the relay program would reject aren't sent
+                SubmitBlockError::Rejected(_) => "rejected".into(),
+                SubmitBlockError::Store(_) => "store".into(),
+            };
+            METRICS
+                .header_submission_failures
+                .with_label_values(&[&error])
+                .inc();
+            return Err(e.into());
+        }
     }
 
-     */
+    Ok(Some(new_height))
 }
 
 #[derive(Debug)]
@@ -203,22 +360,182 @@ pub fn run_init_program(config: RelayConfig) -> Result<Signature, InitProgramErr
 
     let bitcoind_client = BitcoinRpcClient::new(&config.bitcoind_url, config.bitcoin_auth.into())?;
 
-    let relay_program = BtcRelay::id();
-    let program = yona_client.program(relay_program)?;
+    let program = yona_client.program(BtcRelay::id())?;
 
+    init_program_at_tip(&program, &bitcoind_client)
+}
+
+/// Creates the fees deducted from the deposits the relay program mints and paid on top of
+/// withdrawals
+pub fn run_init_deposit_fees(
+    config: RelayConfig,
+    treasury: Pubkey,
+    fixed_fee: u64,
+    fee_bps: u16,
+    min_deposit: u64,
+    withdrawal_fee: u64,
+    pool_share_bps: u16,
+) -> Result<Signature, InitProgramError> {
+    let yona_client = get_yona_client(&config).map_err(InitProgramError::CouldNotInitYonaClient)?;
+
+    let program = yona_client.program(BtcRelay::id())?;
+
+    Ok(init_deposit_fees(
+        &program,
+        treasury,
+        fixed_fee,
+        fee_bps,
+        min_deposit,
+        withdrawal_fee,
+        pool_share_bps,
+    )?)
+}
+
+/// Creates and funds the fee pool rewarding the relayers submitting headers, the Yona keypair has
+/// to be the relay program's upgrade authority
+pub fn run_init_fee_pool(
+    config: RelayConfig,
+    header_reward: u64,
+    funding: u64,
+) -> Result<Signature, InitProgramError> {
+    let yona_client = get_yona_client(&config).map_err(InitProgramError::CouldNotInitYonaClient)?;
+
+    let program = yona_client.program(BtcRelay::id())?;
+
+    Ok(init_fee_pool(&program, header_reward, funding)?)
+}
+
+/// Initializes BTC relay program with the node's current tip
+pub fn init_program_at_tip(
+    yona: &impl YonaClient,
+    bitcoind_client: &impl RpcApi,
+) -> Result<Signature, InitProgramError> {
     let tip = bitcoind_client.get_chain_tips()?.remove(0);
     debug!("Current bitcoin tip {tip:?}");
 
     let last_block = bitcoind_client.get_block(&tip.hash)?;
     debug!("Bitcoin last block {last_block:?}");
 
-    Ok(init_program(&program, last_block, tip.height as u32)?)
+    init_program(yona, bitcoind_client, last_block, tip.height as u32)
 }
 
 struct RelayTransactionsState {
     relay_program: Program<Arc<Keypair>>,
     bitcoin_rpc_client: BitcoinRpcClient,
     main_state: Pubkey,
+    store: RelayerStore,
+    deposit_confirmations: u32,
+    /// Deposits being relayed, keyed as [`relay_tx_key`]
+    relaying: Mutex<HashSet<String>>,
+}
+
+/// Marks a deposit as being relayed until dropped
+struct RelayingGuard<'a> {
+    relaying: &'a Mutex<HashSet<String>>,
+    key: String,
+}
+
+impl Drop for RelayingGuard<'_> {
+    fn drop(&mut self) {
+        self.relaying
+            .lock()
+            .expect("relaying mutex not to be poisoned")
+            .remove(&self.key);
+    }
+}
+
+impl RelayTransactionsState {
+    /// Relays the deposit, tracking the request in the store so it's resumed after a restart.
+    ///
+    /// A request waiting for headers or confirmations stays pending, to be relayed by
+    /// [`RelayTransactionsState::process_relay_requests`] until it expires. A new one fails
+    /// instead once the queue is full, while requests relayed right away are never refused.
+    fn relay_request(&self, tx_id: Txid, mint_receiver: Pubkey) -> Result<Signature, RelayTxError> {
+        let yona_address = mint_receiver.to_string();
+        // Two concurrent relays of a deposit could both send a verification. The set is only locked
+        // to mark the deposit, so other deposits aren't held up by this one's RPC calls.
+        let key = relay_tx_key(&tx_id, &yona_address);
+        if !self
+            .relaying
+            .lock()
+            .expect("relaying mutex not to be poisoned")
+            .insert(key.clone())
+        {
+            return Err(RelayTxError::RelayInProgress);
+        }
+        let _relaying = RelayingGuard {
+            relaying: &self.relaying,
+            key,
+        };
+
+        let inserted = self.store.insert_relay_request(&tx_id, &yona_address)?;
+        let result = match relay_tx(
+            &self.relay_program,
+            &self.store,
+            self.main_state,
+            &self.bitcoin_rpc_client,
+            tx_id,
+            mint_receiver,
+            self.deposit_confirmations,
+        ) {
+            // Only new requests which have to wait count against the queue's limit
+            Err(e) if e.is_waiting() && inserted => {
+                if self.store.relay_requests_full(&tx_id, &yona_address)? {
+                    Err(RelayTxError::TooManyPendingRequests {
+                        max_pending: self.store.relay_request_policy().max_pending,
+                    })
+                } else {
+                    Err(e)
+                }
+            }
+            result => result,
+        };
+
+        match &result {
+            Ok(sig) => self.store.set_relay_request_status(
+                &tx_id,
+                &yona_address,
+                TxStatus::Confirmed,
+                Some(sig),
+                None,
+            )?,
+            Err(e) => self.store.set_relay_request_status(
+                &tx_id,
+                &yona_address,
+                if e.is_waiting() {
+                    TxStatus::Pending
+                } else {
+                    TxStatus::Failed
+                },
+                None,
+                Some(&e.to_string()),
+            )?,
+        }
+        result
+    }
+
+    /// Relays the queued requests and the ones accepted before a restart, which didn't get a result
+    fn process_relay_requests(&self) -> Result<(), StoreError> {
+        let expired = self.store.expire_relay_requests()?;
+        if expired > 0 {
+            warn!("{expired} relay requests expired while waiting");
+        }
+        for request in self.store.pending_relay_requests()? {
+            let mint_receiver = match Pubkey::from_str(&request.yona_address) {
+                Ok(pubkey) => pubkey,
+                Err(e) => {
+                    error!("Stored relay request has invalid yona_address: {e}");
+                    continue;
+                }
+            };
+            match self.relay_request(request.txid, mint_receiver) {
+                Ok(sig) => info!("Relayed queued {}, Yona tx {sig}", request.txid),
+                Err(e) if e.is_waiting() => debug!("Relay of {} is waiting: {e}", request.txid),
+                Err(e) => error!("Relay of queued {} failed: {e}", request.txid),
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Deserialize)]
@@ -227,36 +544,68 @@ struct RelayTxRequest {
     yona_address: String,
 }
 
+fn parse_txid(txid: &str) -> Result<Txid, ApiError> {
+    Txid::from_str(txid).map_err(|_| ApiError::new(ApiErrorCode::InvalidTxId, "tx_id is not valid"))
+}
+
+fn parse_yona_address(yona_address: &str) -> Result<Pubkey, ApiError> {
+    Pubkey::from_str(yona_address).map_err(|_| {
+        ApiError::new(
+            ApiErrorCode::InvalidYonaAddress,
+            "yona_address is not valid",
+        )
+    })
+}
+
+/// Response to a relay request waiting for headers
+#[derive(Serialize)]
+struct QueuedRelay {
+    /// Key of the deposit's verification, `txid:yona_address`
+    tracking_id: String,
+    status_url: String,
+    /// What the request waits for
+    code: ApiErrorCode,
+    message: String,
+}
+
 async fn relay_tx_web_api(
     data: web::Data<RelayTransactionsState>,
     req: web::Json<RelayTxRequest>,
-) -> impl Responder {
-    let tx_id = match Txid::from_str(&req.tx_id) {
-        Ok(tx_id) => tx_id,
-        Err(_) => return HttpResponse::BadRequest().json("tx_id is not valid"),
-    };
-    let mint_receiver = match Pubkey::from_str(&req.yona_address) {
-        Ok(pubkey) => pubkey,
-        Err(_) => return HttpResponse::BadRequest().json("yona_address is not valid"),
-    };
-
-    let relay_tx_res = spawn_blocking(move || {
-        relay_tx(
-            &data.relay_program,
-            data.main_state,
-            &data.bitcoin_rpc_client,
-            tx_id,
-            mint_receiver,
+) -> Result<HttpResponse, ApiError> {
+    let tx_id = parse_txid(&req.tx_id)?;
+    let mint_receiver = parse_yona_address(&req.yona_address)?;
+
+    let key = relay_tx_key(&tx_id, &mint_receiver.to_string());
+    if let Some(signature) = data.store.confirmed_transaction(TxKind::RelayTx, &key)? {
+        return Err(ApiError::new(
+            ApiErrorCode::AlreadyClaimed,
+            format!("Deposit {tx_id} is already minted to {mint_receiver}"),
         )
-    })
-    .await
-    .expect("relay_tx to not panic");
+        .with_signature(&signature));
+    }
 
-    match relay_tx_res {
-        Ok(sig) => HttpResponse::Ok().json(format!("{sig}")),
+    match spawn_blocking(move || data.relay_request(tx_id, mint_receiver)).await? {
+        Ok(signature) => Ok(HttpResponse::Ok().json(signature.to_string())),
+        // The request stays queued until the block relayer submits the deposit's headers, the fee
+        // budget frees up or the concurrent relay of the deposit ends
+        Err(
+            e @ (RelayTxError::BlockNotRelayed { .. }
+            | RelayTxError::NotEnoughConfirmations { .. }
+            | RelayTxError::FeeBudgetExhausted { .. }
+            | RelayTxError::RelayInProgress),
+        ) => {
+            info!("Queued relay of {tx_id}: {e}");
+            let reason = ApiError::from(e);
+            Ok(HttpResponse::Accepted().json(QueuedRelay {
+                tracking_id: key,
+                status_url: format!("/deposits/{tx_id}"),
+                code: reason.code,
+                message: reason.message,
+            }))
+        }
         Err(e) => {
-            error!("{e:?}");
-            HttpResponse::InternalServerError().json("Failed to relay bitcoin tx")
+            error!("Error {e} on relaying {tx_id}");
+            Err(e.into())
         }
     }
 }
@@ -266,57 +615,163 @@ struct GetDepositAddrReq {
     yona_address: String,
 }
 
-async fn get_deposit_address(req: web::Query<GetDepositAddrReq>) -> impl Responder {
-    let yona_address = match Pubkey::from_str(&req.yona_address) {
-        Ok(pubkey) => pubkey,
-        Err(_) => return HttpResponse::BadRequest().body("yona_address is not valid"),
-    };
+// Deposit address of the Yona address, derived from the relay program's bridge key
+async fn bridge_deposit_address(
+    data: web::Data<RelayTransactionsState>,
+    yona_address: Pubkey,
+) -> Result<Address, ApiError> {
+    let bridge_key = spawn_blocking(move || data.relay_program.bridge_key()).await?;
+    match bridge_key {
+        Ok(bridge_key) => deposit_address(&yona_address, bridge_key.group_key).ok_or_else(|| {
+            ApiError::new(
+                ApiErrorCode::InternalError,
+                "The relay program's bridge key isn't a valid x-only key",
+            )
+        }),
+        Err(AnchorClientError::AccountNotFound) => Err(ApiError::new(
+            ApiErrorCode::BridgeKeyNotRegistered,
+            "The signer nodes haven't registered their group key in the relay program yet",
+        )),
+        Err(e) => {
+            error!("Error {e} on getting the bridge key");
+            Err(ApiError::from(&e))
+        }
+    }
+}
 
-    let bitcoin_pubkey =
-        PublicKey::from_str(BITCOIN_DEPOSIT_PUBKEY).expect("Valid bitcoin public key");
+async fn register_deposit_address(
+    data: web::Data<RelayTransactionsState>,
+    req: web::Json<GetDepositAddrReq>,
+) -> Result<HttpResponse, ApiError> {
+    let yona_address = parse_yona_address(&req.yona_address)?;
+    let address = bridge_deposit_address(data.clone(), yona_address).await?;
+
+    // Deposits to the returned address are relayed by the deposit watcher until the registration
+    // expires, registering it again keeps it
+    if !data
+        .store
+        .register_deposit_address(&yona_address.to_string())?
+    {
+        return Err(ApiError::new(
+            ApiErrorCode::RateLimited,
+            "Too many deposit addresses were registered in the last hour, try again later",
+        ));
+    }
 
-    let script = bridge_deposit_script(
-        yona_address.to_bytes(),
-        bitcoin_pubkey.pubkey_hash().to_byte_array(),
-    );
+    Ok(HttpResponse::Ok().body(address.to_string()))
+}
 
-    info!("{script:?}");
+async fn get_deposit(
+    data: web::Data<RelayTransactionsState>,
+    txid: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let txid = parse_txid(&txid)?;
 
-    let deposit_address = Address::p2wsh(script.as_script(), Network::Regtest);
+    let deposits = spawn_blocking(move || {
+        deposits_of_tx(
+            &data.relay_program,
+            &data.bitcoin_rpc_client,
+            &data.store,
+            txid,
+            data.deposit_confirmations,
+        )
+    })
+    .await?
+    .map_err(|e| {
+        error!("Error {e} on getting status of deposit {txid}");
+        ApiError::from(e)
+    })?;
+
+    if deposits.is_empty() {
+        return Err(ApiError::new(
+            ApiErrorCode::UnknownDeposit,
+            format!("Neither the relayer nor the Bitcoin node know deposit {txid}"),
+        ));
+    }
+    Ok(HttpResponse::Ok().json(deposits))
+}
 
-    HttpResponse::Ok().body(deposit_address.to_string())
+async fn get_deposits(
+    data: web::Data<RelayTransactionsState>,
+    req: web::Query<GetDepositAddrReq>,
+) -> Result<HttpResponse, ApiError> {
+    let yona_address = parse_yona_address(&req.yona_address)?;
+
+    let deposits = spawn_blocking(move || {
+        deposits_of_address(
+            &data.relay_program,
+            &data.bitcoin_rpc_client,
+            &data.store,
+            &yona_address,
+            data.deposit_confirmations,
+        )
+    })
+    .await?
+    .map_err(|e| {
+        error!("Error {e} on getting deposits of {yona_address}");
+        ApiError::from(e)
+    })?;
+
+    Ok(HttpResponse::Ok().json(deposits))
 }
 
-pub async fn relay_transactions(config: RelayConfig) {
-    let yona_client = get_yona_client(&config).expect("Couldn't create Yona client");
+pub async fn relay_transactions(config: RelayConfig) -> Result<(), RelayError> {
+    let health_state = web::Data::new(health_state(&config)?);
+    let yona_client = get_yona_client(&config).map_err(RelayError::CouldNotInitYonaClient)?;
 
     let bitcoin_rpc_client =
-        BitcoinRpcClient::new(&config.bitcoind_url, config.bitcoin_auth.into())
-            .expect("Couldn't create Bitcoin client");
+        BitcoinRpcClient::new(&config.bitcoind_url, config.bitcoin_auth.into())?;
+
+    let main_state = main_state_address();
+    let relay_program = yona_client.program(BtcRelay::id())?;
 
-    let relay_program = BtcRelay::id();
-    let (main_state, _) = Pubkey::find_program_address(&[b"state"], &relay_program);
-    let relay_program = yona_client
-        .program(relay_program)
-        .expect("Couldn't create relay program instance");
+    let store = RelayerStore::open(&config.store_path)?
+        .with_fee_policy(config.fees.clone())
+        .with_deposit_address_policy(config.deposit_addresses.clone())
+        .with_relay_request_policy(config.relay_requests.clone());
 
     let app_state = web::Data::new(RelayTransactionsState {
         relay_program,
         bitcoin_rpc_client,
         main_state,
+        store,
+        deposit_confirmations: config.deposit_confirmations,
+        relaying: Mutex::default(),
+    });
+
+    // Relays the requests accepted before a restart, then the queued ones as headers arrive
+    let queue_state = app_state.clone();
+    let wakeup = Wakeup::default();
+    subscribe_to_relay_tip(config.yona_ws.clone(), main_state, wakeup.clone());
+    thread::spawn(move || loop {
+        record_signer_balance(&queue_state.relay_program);
+        if let Err(e) = queue_state.process_relay_requests() {
+            error!("Error {e} on processing relay requests");
+        }
+        wakeup.wait(Duration::from_secs(30));
     });
 
     // Start HTTP server
     HttpServer::new(move || {
         App::new()
             .wrap(Cors::permissive())
+            .wrap_fn(track_http_request)
             .app_data(app_state.clone())
+            .app_data(health_state.clone())
             .route("/relay_tx", web::post().to(relay_tx_web_api))
-            .route("/get_deposit_address", web::get().to(get_deposit_address))
+            .route(
+                "/deposit_addresses",
+                web::post().to(register_deposit_address),
+            )
+            .route("/deposits", web::get().to(get_deposits))
+            .route("/deposits/{txid}", web::get().to(get_deposit))
+            .route("/metrics", web::get().to(metrics_web_api))
+            .route("/healthz", web::get().to(healthz_web_api))
+            .route("/readyz", web::get().to(readyz_web_api))
     })
     .bind("0.0.0.0:8199")
-    .expect("Couldn't bind to 0.0.0.0:8199")
+    .map_err(RelayError::CouldNotServeApi)?
     .run()
     .await
-    .expect("HTTP server hasn't gracefully stop");
+    .map_err(RelayError::CouldNotServeApi)
 }
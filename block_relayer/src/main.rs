@@ -1,14 +1,52 @@
+use anchor_client::solana_sdk::pubkey::Pubkey;
 use block_relayer_lib::config::read_config;
-use block_relayer_lib::{relay_blocks_from_full_node, relay_transactions, run_init_program};
+use block_relayer_lib::{
+    relay_blocks_from_full_node, relay_transactions, run_init_deposit_fees, run_init_fee_pool,
+    run_init_program, watch_deposits_from_full_node,
+};
 use clap::{Parser, Subcommand};
+use std::process;
 use tokio::runtime::Runtime;
 
 #[derive(Subcommand)]
 enum RelayerCommand {
     InitDeposit,
+    /// Create the fees deducted from minted deposits and paid on top of withdrawals, the keypair
+    /// becomes their authority
+    InitDepositFees {
+        /// Yona account receiving the fees
+        #[arg(long)]
+        treasury: Pubkey,
+        /// Satoshis deducted from every deposit
+        #[arg(long, default_value_t = 0)]
+        fixed_fee: u64,
+        /// Basis points of the deposited amount deducted on top of the fixed fee
+        #[arg(long, default_value_t = 0)]
+        fee_bps: u16,
+        /// Satoshis a deposit has to pay at least to be minted
+        #[arg(long, default_value_t = 0)]
+        min_deposit: u64,
+        /// Satoshis paid on top of every withdrawal
+        #[arg(long, default_value_t = 0)]
+        withdrawal_fee: u64,
+        /// Basis points of the deposit and withdrawal fees paid into the fee pool
+        #[arg(long, default_value_t = 0)]
+        pool_share_bps: u16,
+    },
+    /// Create the fee pool rewarding relayers per submitted header. The keypair has to be the relay
+    /// program's upgrade authority and becomes the pool's authority
+    InitFeePool {
+        /// Lamports paid per header
+        #[arg(long)]
+        header_reward: u64,
+        /// Lamports transferred to the pool
+        #[arg(long, default_value_t = 0)]
+        funding: u64,
+    },
     InitProgram,
     RelayBlocks,
     RelayTransactions,
+    WatchDeposits,
 }
 
 #[derive(Parser)]
@@ -26,14 +64,56 @@ fn main() {
 
     match cli.command {
         RelayerCommand::InitDeposit => unimplemented!(),
+        RelayerCommand::InitDepositFees {
+            treasury,
+            fixed_fee,
+            fee_bps,
+            min_deposit,
+            withdrawal_fee,
+            pool_share_bps,
+        } => {
+            let result = run_init_deposit_fees(
+                config,
+                treasury,
+                fixed_fee,
+                fee_bps,
+                min_deposit,
+                withdrawal_fee,
+                pool_share_bps,
+            )
+            .expect("Deposit fees initialization failed");
+            println!("Deposit fees initialization tx signature {}", result);
+        }
+        RelayerCommand::InitFeePool {
+            header_reward,
+            funding,
+        } => {
+            let result = run_init_fee_pool(config, header_reward, funding)
+                .expect("Fee pool initialization failed");
+            println!("Fee pool initialization tx signature {}", result);
+        }
         RelayerCommand::InitProgram => {
             let result = run_init_program(config).expect("Relay program initialization failed");
             println!("Initialization tx signature {}", result);
         }
-        RelayerCommand::RelayBlocks => relay_blocks_from_full_node(config),
+        RelayerCommand::RelayBlocks => {
+            if let Err(e) = relay_blocks_from_full_node(config) {
+                eprintln!("Block relayer failed: {e}");
+                process::exit(1);
+            }
+        }
         RelayerCommand::RelayTransactions => {
             let runtime = Runtime::new().expect("tokio runtime to be created");
-            runtime.block_on(relay_transactions(config));
+            if let Err(e) = runtime.block_on(relay_transactions(config)) {
+                eprintln!("Transaction relayer failed: {e}");
+                process::exit(1);
+            }
+        }
+        RelayerCommand::WatchDeposits => {
+            if let Err(e) = watch_deposits_from_full_node(config) {
+                eprintln!("Deposit watcher failed: {e}");
+                process::exit(1);
+            }
         }
     }
 }
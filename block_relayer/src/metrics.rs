@@ -0,0 +1,201 @@
+use crate::store::TxKind;
+use crate::yona::{client_relay_error, YonaClient};
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::ContentType;
+use actix_web::{Error as ActixError, HttpResponse};
+use anchor_client::solana_sdk::transaction::Transaction;
+use anchor_client::ClientError as AnchorClientError;
+use log::{error, warn};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Instant;
+
+/// Prometheus metrics of the relayer process, served at `/metrics`
+pub struct Metrics {
+    registry: Registry,
+    /// Height of the relay program's tip
+    pub relay_tip_height: IntGauge,
+    /// Height of the Bitcoin node's best block
+    pub bitcoind_tip_height: IntGauge,
+    /// Time to get a header submission confirmed, including the relayer's checks
+    pub header_submission_seconds: Histogram,
+    /// Failed header submissions, by relay program error name
+    pub header_submission_failures: IntCounterVec,
+    /// Fees paid by the relayer's transactions, by transaction kind
+    pub fees_lamports: IntCounterVec,
+    pub signer_balance_lamports: IntGauge,
+    /// Deposit verifications minting on Yona
+    pub deposits_relayed: IntCounter,
+    /// Deposit verifications rejected or failed, by relay program error name
+    pub deposits_failed: IntCounterVec,
+    /// HTTP API requests, by method, route and status
+    pub http_requests: IntCounterVec,
+    pub http_request_seconds: HistogramVec,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+// Label of failures which aren't relay program errors, like connectivity ones
+const OTHER_ERROR: &str = "other";
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry =
+            Registry::new_custom(Some("relayer".into()), None).expect("metrics prefix to be valid");
+
+        macro_rules! register {
+            ($metric:expr) => {{
+                let metric = $metric.expect("metric options to be valid");
+                registry
+                    .register(Box::new(metric.clone()))
+                    .expect("metric to be registered once");
+                metric
+            }};
+        }
+
+        Metrics {
+            relay_tip_height: register!(IntGauge::new(
+                "relay_tip_height",
+                "Height of the relay program's tip"
+            )),
+            bitcoind_tip_height: register!(IntGauge::new(
+                "bitcoind_tip_height",
+                "Height of the Bitcoin node's best block"
+            )),
+            header_submission_seconds: register!(Histogram::with_opts(
+                HistogramOpts::new(
+                    "header_submission_seconds",
+                    "Time to get a block header submission confirmed"
+                )
+                .buckets(vec![0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0])
+            )),
+            header_submission_failures: register!(IntCounterVec::new(
+                Opts::new(
+                    "header_submission_failures_total",
+                    "Failed block header submissions by relay program error"
+                ),
+                &["error"]
+            )),
+            fees_lamports: register!(IntCounterVec::new(
+                Opts::new(
+                    "fees_lamports_total",
+                    "Fees paid by the relayer's Yona transactions"
+                ),
+                &["kind"]
+            )),
+            signer_balance_lamports: register!(IntGauge::new(
+                "signer_balance_lamports",
+                "Balance of the account signing the relayer's transactions"
+            )),
+            deposits_relayed: register!(IntCounter::new(
+                "deposits_relayed_total",
+                "Deposit verifications minting on Yona"
+            )),
+            deposits_failed: register!(IntCounterVec::new(
+                Opts::new(
+                    "deposits_failed_total",
+                    "Failed deposit verifications by relay program error"
+                ),
+                &["error"]
+            )),
+            http_requests: register!(IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP API requests"),
+                &["method", "route", "status"]
+            )),
+            http_request_seconds: register!(HistogramVec::new(
+                HistogramOpts::new("http_request_seconds", "HTTP API request durations"),
+                &["method", "route"]
+            )),
+            registry,
+        }
+    }
+
+    /// Metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics to be encoded");
+        String::from_utf8(buffer).expect("metrics to be valid UTF-8")
+    }
+}
+
+/// Label of a failed Yona request: the relay program error name, or `other`
+pub(crate) fn error_label(error: &AnchorClientError) -> String {
+    client_relay_error(error)
+        .map(|relay_error| relay_error.name())
+        .unwrap_or_else(|| OTHER_ERROR.into())
+}
+
+/// Adds the fee of a transaction Yona processed, returning it
+pub(crate) fn record_fee(
+    yona: &impl YonaClient,
+    kind: TxKind,
+    transaction: &Transaction,
+) -> Option<u64> {
+    match yona.transaction_fee(transaction) {
+        Ok(fee) => {
+            METRICS
+                .fees_lamports
+                .with_label_values(&[kind.as_str()])
+                .inc_by(fee);
+            Some(fee)
+        }
+        Err(e) => {
+            warn!(
+                "Error {e} on getting the fee of {}",
+                transaction.signatures[0]
+            );
+            None
+        }
+    }
+}
+
+pub(crate) fn record_signer_balance(yona: &impl YonaClient) {
+    match yona.get_account(&yona.payer()) {
+        Ok(account) => METRICS.signer_balance_lamports.set(account.lamports as i64),
+        Err(e) => error!("Error {e} on getting the signer balance"),
+    }
+}
+
+pub(crate) async fn metrics_web_api() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(METRICS.render())
+}
+
+/// `wrap_fn` middleware counting and timing the HTTP API's requests
+pub(crate) fn track_http_request<S, B>(
+    req: ServiceRequest,
+    service: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, ActixError>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    B: MessageBody,
+{
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let response = service.call(req);
+    async move {
+        let response = response.await?;
+        // Routes rather than paths keep the label values bounded
+        let route = response
+            .request()
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".into());
+        METRICS
+            .http_requests
+            .with_label_values(&[&method, &route, response.status().as_str()])
+            .inc();
+        METRICS
+            .http_request_seconds
+            .with_label_values(&[&method, &route])
+            .observe(start.elapsed().as_secs_f64());
+        Ok(response)
+    }
+}
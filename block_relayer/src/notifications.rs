@@ -0,0 +1,159 @@
+use crate::relay_loop::Backoff;
+use anchor_client::solana_client::pubsub_client::PubsubClient;
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use bitcoin::hashes::Hash;
+use bitcoin::BlockHash;
+use log::{debug, info, warn};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Builder as RuntimeBuilder;
+use zeromq::{Socket, SocketRecv, SubSocket, ZmqMessage};
+
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Wakes a relay loop waiting for its next poll, latching a wake-up which happens while the loop
+/// is busy so it isn't missed
+#[derive(Clone, Default)]
+pub struct Wakeup {
+    woken: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Wakeup {
+    pub fn wake(&self) {
+        let (woken, condvar) = &*self.woken;
+        *woken.lock().expect("wakeup mutex not to be poisoned") = true;
+        condvar.notify_all();
+    }
+
+    /// Waits for a wake-up for up to the timeout. Returns whether the loop was woken up.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let (woken, condvar) = &*self.woken;
+        let woken = woken.lock().expect("wakeup mutex not to be poisoned");
+        let (mut woken, _) = condvar
+            .wait_timeout_while(woken, timeout, |woken| !*woken)
+            .expect("wakeup mutex not to be poisoned");
+        std::mem::take(&mut *woken)
+    }
+}
+
+/// Hash of the block announced by a `hashblock` ZMQ notification, made of the topic, the hash and
+/// a sequence number
+pub fn notified_block_hash(message: &ZmqMessage) -> Option<BlockHash> {
+    if message.get(0)?.as_ref() != b"hashblock" {
+        return None;
+    }
+    // The hash is sent in the byte order Bitcoin Core displays
+    let mut hash: [u8; 32] = message.get(1)?.as_ref().try_into().ok()?;
+    hash.reverse();
+    Some(BlockHash::from_byte_array(hash))
+}
+
+async fn receive_block_notifications(endpoint: &str, wakeup: &Wakeup, backoff: &mut Backoff) {
+    let mut socket = SubSocket::new();
+    if let Err(e) = socket.connect(endpoint).await {
+        warn!("Error {e} on connecting to ZMQ {endpoint}");
+        return;
+    }
+    if let Err(e) = socket.subscribe("hashblock").await {
+        warn!("Error {e} on subscribing to ZMQ {endpoint}");
+        return;
+    }
+    info!("Subscribed to block notifications from {endpoint}");
+
+    loop {
+        match socket.recv().await {
+            Ok(message) => {
+                backoff.reset();
+                if let Some(hash) = notified_block_hash(&message) {
+                    debug!("New block {hash}");
+                    wakeup.wake();
+                }
+            }
+            Err(e) => {
+                warn!("Error {e} on receiving from ZMQ {endpoint}");
+                return;
+            }
+        }
+    }
+}
+
+/// Wakes the loop on the new blocks the Bitcoin node announces on its `zmqpubhashblock`
+/// endpoint, resubscribing when the connection is lost
+pub fn subscribe_to_blocks(endpoint: String, wakeup: Wakeup) {
+    thread::spawn(move || {
+        let runtime = RuntimeBuilder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("tokio runtime to be created");
+        let mut backoff = Backoff::new(RECONNECT_INITIAL_DELAY, RECONNECT_MAX_DELAY);
+        loop {
+            runtime.block_on(receive_block_notifications(
+                &endpoint,
+                &wakeup,
+                &mut backoff,
+            ));
+            // New blocks are still found by polling meanwhile
+            thread::sleep(backoff.next_delay());
+        }
+    });
+}
+
+/// Wakes the loop when the relay program's main state changes, as headers are submitted,
+/// resubscribing when the connection is lost
+pub fn subscribe_to_relay_tip(ws_url: String, main_state: Pubkey, wakeup: Wakeup) {
+    thread::spawn(move || {
+        let mut backoff = Backoff::new(RECONNECT_INITIAL_DELAY, RECONNECT_MAX_DELAY);
+        loop {
+            match PubsubClient::account_subscribe(&ws_url, &main_state, None) {
+                Ok((_subscription, updates)) => {
+                    info!("Subscribed to relay tip updates from {ws_url}");
+                    for _ in updates.iter() {
+                        backoff.reset();
+                        debug!("Relay tip updated");
+                        wakeup.wake();
+                    }
+                    warn!("Relay tip subscription to {ws_url} closed");
+                }
+                Err(e) => warn!("Error {e} on subscribing to relay tip updates from {ws_url}"),
+            }
+            // The relay tip is still polled meanwhile
+            thread::sleep(backoff.next_delay());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn wakeup_is_latched_until_waited_for() {
+        let wakeup = Wakeup::default();
+        assert!(!wakeup.wait(Duration::from_millis(1)));
+
+        wakeup.wake();
+        assert!(wakeup.wait(Duration::from_secs(10)));
+        assert!(!wakeup.wait(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn parses_hashblock_notifications() {
+        let hash =
+            BlockHash::from_str("0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206")
+                .unwrap();
+        let mut displayed = hash.to_byte_array();
+        displayed.reverse();
+
+        let mut message = ZmqMessage::from("hashblock");
+        message.push_back(displayed.to_vec().into());
+        message.push_back(vec![0; 4].into());
+        assert_eq!(notified_block_hash(&message), Some(hash));
+
+        let mut message = ZmqMessage::from("hashtx");
+        message.push_back(displayed.to_vec().into());
+        assert_eq!(notified_block_hash(&message), None);
+    }
+}
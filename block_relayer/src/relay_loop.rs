@@ -0,0 +1,278 @@
+use crate::metrics::record_signer_balance;
+use crate::relay_program_interaction::is_unreachable_node;
+use crate::shutdown::Shutdown;
+use crate::store::{RelayerStore, StoreError};
+use crate::yona::{client_relay_error, is_unreachable, YonaClient};
+use crate::{relay_next_block, RelayBlockError, SubmitBlockError};
+use anchor_client::ClientError as AnchorClientError;
+use bitcoincore_rpc::{Error as BtcError, RpcApi};
+use btc_relay::errors::RelayErrorCode;
+use header_chain::SimulationError;
+use log::{error, info, warn};
+use rand::Rng;
+use std::time::Duration;
+use std::{error, fmt, io};
+
+// Wait for a new block once the relay has the node's best block
+const IDLE_DELAY: Duration = Duration::from_secs(30);
+const RETRY_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// Failure of the block relayer, classified by how the relay loop recovers from it
+#[derive(Debug)]
+pub enum RelayError {
+    CouldNotInitYonaClient(Box<dyn error::Error>),
+    CouldNotHandleSignals(io::Error),
+    /// The HTTP API couldn't be bound or stopped with an error
+    CouldNotServeApi(io::Error),
+    /// The Bitcoin node can't be reached, the next endpoint is used
+    BitcoinUnreachable(BtcError),
+    /// The Bitcoin node failed the request
+    Bitcoin(BtcError),
+    /// Yona can't be reached, the next endpoint is used
+    YonaUnreachable(AnchorClientError),
+    /// The relay program rejected the header submission
+    Program(RelayErrorCode),
+    /// Yona failed the request
+    Yona(AnchorClientError),
+    /// The header chain simulator rejected the header, so it wasn't sent
+    HeaderRejected(SimulationError),
+    Store(StoreError),
+}
+
+impl fmt::Display for RelayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelayError::CouldNotInitYonaClient(e) => write!(f, "Couldn't create Yona client: {e}"),
+            RelayError::CouldNotHandleSignals(e) => write!(f, "Couldn't handle signals: {e}"),
+            RelayError::CouldNotServeApi(e) => write!(f, "Couldn't serve the HTTP API: {e}"),
+            RelayError::BitcoinUnreachable(e) => write!(f, "Bitcoin node unreachable: {e}"),
+            RelayError::Bitcoin(e) => write!(f, "{e}"),
+            RelayError::YonaUnreachable(e) => write!(f, "Yona unreachable: {e}"),
+            RelayError::Program(e) => write!(f, "{}: {e}", e.name()),
+            RelayError::Yona(e) => write!(f, "{e}"),
+            RelayError::HeaderRejected(e) => write!(f, "{e}"),
+            RelayError::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<AnchorClientError> for RelayError {
+    fn from(error: AnchorClientError) -> Self {
+        if is_unreachable(&error) {
+            return RelayError::YonaUnreachable(error);
+        }
+        match client_relay_error(&error) {
+            Some(relay_error) => RelayError::Program(relay_error),
+            None => RelayError::Yona(error),
+        }
+    }
+}
+
+impl From<BtcError> for RelayError {
+    fn from(error: BtcError) -> Self {
+        if is_unreachable_node(&error) {
+            RelayError::BitcoinUnreachable(error)
+        } else {
+            RelayError::Bitcoin(error)
+        }
+    }
+}
+
+impl From<StoreError> for RelayError {
+    fn from(error: StoreError) -> Self {
+        RelayError::Store(error)
+    }
+}
+
+impl From<RelayBlockError> for RelayError {
+    fn from(error: RelayBlockError) -> Self {
+        match error {
+            RelayBlockError::Anchor(e) | RelayBlockError::Submit(SubmitBlockError::Anchor(e)) => {
+                e.into()
+            }
+            RelayBlockError::Bitcoin(e) => e.into(),
+            RelayBlockError::Submit(SubmitBlockError::Rejected(e)) => RelayError::HeaderRejected(e),
+            RelayBlockError::Store(e) | RelayBlockError::Submit(SubmitBlockError::Store(e)) => {
+                RelayError::Store(e)
+            }
+        }
+    }
+}
+
+/// Jittered exponential backoff between the retries of a failing operation
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max,
+            failures: 0,
+        }
+    }
+
+    /// Delay before the next retry, doubling with each consecutive failure up to the maximum
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self
+            .initial
+            .saturating_mul(2u32.saturating_pow(self.failures))
+            .min(self.max);
+        self.failures = self.failures.saturating_add(1);
+        // Relayers failing together, like on a node restart, don't retry in lockstep
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+/// Clients of the same service at several endpoints, one of them used at a time
+pub struct Failover<T> {
+    endpoints: Vec<(String, T)>,
+    current: usize,
+}
+
+impl<T> Failover<T> {
+    /// Starts with the first of the endpoints, named by their URLs
+    pub fn new(endpoints: Vec<(String, T)>) -> Failover<T> {
+        assert!(!endpoints.is_empty(), "Failover needs an endpoint");
+        Failover {
+            endpoints,
+            current: 0,
+        }
+    }
+
+    pub fn current(&self) -> &T {
+        &self.endpoints[self.current].1
+    }
+
+    pub fn current_url(&self) -> &str {
+        &self.endpoints[self.current].0
+    }
+
+    /// Switches to the next endpoint, after the last one back to the first
+    pub fn fail_over(&mut self) {
+        if self.endpoints.len() == 1 {
+            return;
+        }
+        let failed = self.current;
+        self.current = (self.current + 1) % self.endpoints.len();
+        warn!(
+            "Failing over from {} to {}",
+            self.endpoints[failed].0,
+            self.current_url()
+        );
+    }
+}
+
+/// Relays the node's blocks to the relay program, failing over from unreachable endpoints and
+/// backing off on errors
+pub struct BlockRelayer<Y, B> {
+    yona: Failover<Y>,
+    bitcoind: Failover<B>,
+    store: RelayerStore,
+    backoff: Backoff,
+}
+
+impl<Y: YonaClient, B: RpcApi> BlockRelayer<Y, B> {
+    pub fn new(
+        yona: Failover<Y>,
+        bitcoind: Failover<B>,
+        store: RelayerStore,
+    ) -> BlockRelayer<Y, B> {
+        BlockRelayer {
+            yona,
+            bitcoind,
+            store,
+            backoff: Backoff::new(RETRY_INITIAL_DELAY, RETRY_MAX_DELAY),
+        }
+    }
+
+    pub fn yona(&self) -> &Failover<Y> {
+        &self.yona
+    }
+
+    pub fn bitcoind(&self) -> &Failover<B> {
+        &self.bitcoind
+    }
+
+    /// Submits the block following the relay's tip, switching to the next endpoint of a service
+    /// which couldn't be reached
+    pub fn relay_once(&mut self) -> Result<Option<u32>, RelayError> {
+        let result = relay_next_block(self.yona.current(), self.bitcoind.current(), &self.store)
+            .map_err(RelayError::from);
+        match &result {
+            Err(RelayError::YonaUnreachable(_)) => self.yona.fail_over(),
+            Err(RelayError::BitcoinUnreachable(_)) => self.bitcoind.fail_over(),
+            _ => {}
+        }
+        result
+    }
+
+    /// Relays blocks until the shutdown is requested. A submission in flight is finished first, so
+    /// its outcome is stored.
+    pub fn run(&mut self, shutdown: &Shutdown) {
+        while !shutdown.is_requested() {
+            record_signer_balance(self.yona.current());
+            let delay = match self.relay_once() {
+                Ok(Some(_)) => {
+                    self.backoff.reset();
+                    continue;
+                }
+                Ok(None) => {
+                    self.backoff.reset();
+                    IDLE_DELAY
+                }
+                // Another relayer submitted the header first, the next one follows its tip
+                Err(RelayError::Program(RelayErrorCode::TipAlreadyExtended)) => {
+                    info!("Another relayer extended the relay's tip first");
+                    self.backoff.reset();
+                    continue;
+                }
+                Err(e) => {
+                    let delay = self.backoff.next_delay();
+                    error!("Error {e} on relaying the next block, retrying in {delay:?}");
+                    delay
+                }
+            };
+            shutdown.sleep(delay);
+        }
+        info!("Block relayer stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum_with_jitter() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+        for ceiling in [1, 2, 4, 8, 10, 10] {
+            let delay = backoff.next_delay();
+            let ceiling = Duration::from_secs(ceiling);
+            assert!(delay <= ceiling && delay >= ceiling / 2, "{delay:?}");
+        }
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn failover_cycles_through_the_endpoints() {
+        let mut failover = Failover::new(vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+        assert_eq!(*failover.current(), 1);
+        failover.fail_over();
+        assert_eq!(*failover.current(), 2);
+        assert_eq!(failover.current_url(), "b");
+        failover.fail_over();
+        assert_eq!(*failover.current(), 1);
+    }
+}
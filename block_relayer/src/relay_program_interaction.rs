@@ -1,41 +1,106 @@
 use crate::merkle::Proof;
-use anchor_client::anchor_lang::prelude::{AccountDeserialize, AccountMeta};
+use crate::metrics::{error_label, METRICS};
+use crate::store::{relay_tx_key, RelayerStore, StoreError, TxKind, TxStatus};
+use crate::yona::{client_transaction_error, YonaClient};
+use crate::InitProgramError;
+use anchor_client::anchor_lang::prelude::AccountMeta;
+use anchor_client::solana_sdk::bpf_loader_upgradeable;
 use anchor_client::solana_sdk::pubkey::Pubkey;
-use anchor_client::solana_sdk::signature::{Keypair, Signature};
+use anchor_client::solana_sdk::signature::Signature;
 use anchor_client::ClientError as AnchorClientError;
-use anchor_client::Program;
 use bitcoin::hashes::Hash;
 use bitcoin::hex::DisplayHex;
 use bitcoin::{Block, BlockHash, Txid};
-use bitcoincore_rpc::{Client as BitcoinRpcClient, Error as BtcRpcError, RpcApi};
-use btc_relay::accounts::{Deposit, Initialize, SubmitBlockHeaders, VerifyTransaction};
+use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+use bitcoincore_rpc::{Error as BtcRpcError, RpcApi};
+use btc_relay::accounts::{
+    Deposit, FundFeePool, Initialize, InitializeDepositFees, InitializeFeePool, SubmitBlockHeaders,
+    VerifyTransaction,
+};
 use btc_relay::instruction::{
-    Deposit as DepositInstruction, Initialize as InitializeInstruction,
+    Deposit as DepositInstruction, FundFeePool as FundFeePoolInstruction,
+    Initialize as InitializeInstruction, InitializeDepositFees as InitializeDepositFeesInstruction,
+    InitializeFeePool as InitializeFeePoolInstruction,
     SubmitBlockHeaders as SubmitBlockHeadersInstruction, VerifySmallTx as VerifySmallTxInstruction,
 };
 use btc_relay::state::MainState;
 use btc_relay::structs::{BlockHeader, CommittedBlockHeader};
+use btc_relay::u256::U256;
+use header_chain::{HeaderChain, SimulationError};
 use log::{debug, info};
-use std::sync::Arc;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub(crate) fn reconstruct_commited_header(
-    bitcoind_client: &BitcoinRpcClient,
+// Bitcoin Core's error code for a transaction it doesn't know
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+pub(crate) fn is_unknown_transaction(error: &BtcRpcError) -> bool {
+    matches!(error, BtcRpcError::JsonRpc(JsonRpcError::Rpc(e)) if e.code == RPC_INVALID_ADDRESS_OR_KEY)
+}
+
+/// Whether the request failed because the Bitcoin node couldn't be reached
+pub(crate) fn is_unreachable_node(error: &BtcRpcError) -> bool {
+    matches!(
+        error,
+        BtcRpcError::JsonRpc(JsonRpcError::Transport(_)) | BtcRpcError::Io(_)
+    )
+}
+
+/// Returns the chain work the relay program stored for the block.
+///
+/// The relay's chain work can differ from Bitcoin Core's by a constant, depending on the chain work
+/// the program was initialized with, so the difference is taken from the relay's tip.
+fn relay_chain_work(
+    bitcoind_client: &impl RpcApi,
     hash: &BlockHash,
-    height: u32,
-    last_diff_adjustment: u32,
-) -> Result<CommittedBlockHeader, BtcRpcError> {
-    let header = bitcoind_client.get_block_header(hash)?;
-    debug!("Got header {header:?}");
+    main_state: &MainState,
+) -> Result<[u8; 32], BtcRpcError> {
+    let tip_hash = BlockHash::from_byte_array(main_state.tip_block_hash);
+    if *hash == tip_hash {
+        return Ok(main_state.chain_work);
+    }
 
+    let core_chain_work = |hash: &BlockHash| -> Result<U256, BtcRpcError> {
+        let chainwork = bitcoind_client.get_block_header_info(hash)?.chainwork;
+        let mut bytes = [0; 32];
+        bytes[32 - chainwork.len()..].copy_from_slice(&chainwork);
+        Ok(U256::from_be_bytes(bytes))
+    };
+
+    let (offset, _) =
+        U256::from_be_bytes(main_state.chain_work).overflowing_sub(core_chain_work(&tip_hash)?);
+    Ok(core_chain_work(hash)?.wrapping_add(offset).to_be_bytes())
+}
+
+/// Returns the timestamps of the 10 blocks preceding the block at `height`, oldest first
+fn prev_block_timestamps(
+    bitcoind_client: &impl RpcApi,
+    height: u32,
+) -> Result<[u32; 10], BtcRpcError> {
     let mut prev_block_timestamps = [0; 10];
     for i in 0..10 {
         let prev_block_hash = bitcoind_client.get_block_hash(height as u64 - i as u64 - 1)?;
         let block = bitcoind_client.get_block(&prev_block_hash)?;
         prev_block_timestamps[9 - i] = block.header.time;
     }
+    Ok(prev_block_timestamps)
+}
+
+pub(crate) fn reconstruct_commited_header(
+    bitcoind_client: &impl RpcApi,
+    hash: &BlockHash,
+    height: u32,
+    main_state: &MainState,
+) -> Result<CommittedBlockHeader, BtcRpcError> {
+    let header = bitcoind_client.get_block_header(hash)?;
+    debug!("Got header {header:?}");
+
+    let chain_work = relay_chain_work(bitcoind_client, hash, main_state)?;
+
+    let prev_block_timestamps = prev_block_timestamps(bitcoind_client, height)?;
 
     Ok(CommittedBlockHeader {
-        chain_work: [0; 32],
+        chain_work,
         header: BlockHeader {
             version: header.version.to_consensus() as u32,
             reversed_prev_blockhash: header.prev_blockhash.to_byte_array(),
@@ -44,35 +109,106 @@ pub(crate) fn reconstruct_commited_header(
             nbits: header.bits.to_consensus(),
             nonce: header.nonce,
         },
-        last_diff_adjustment,
+        last_diff_adjustment: main_state.last_diff_adjustment,
         blockheight: height,
         prev_block_timestamps,
     })
 }
 
-pub(crate) fn init_deposit(
-    program: &Program<Arc<Keypair>>,
-    amount: u64,
-) -> Result<Signature, AnchorClientError> {
-    let (deposit_account, _) = Pubkey::find_program_address(&[b"solana_deposit"], &program.id());
+pub fn init_deposit(yona: &impl YonaClient, amount: u64) -> Result<Signature, AnchorClientError> {
+    let (deposit_account, _) = Pubkey::find_program_address(&[b"solana_deposit"], &yona.id());
 
-    program
-        .request()
-        .accounts(Deposit {
-            signer: program.payer(),
+    yona.send(
+        Deposit {
+            signer: yona.payer(),
             deposit_account,
             system_program: anchor_client::solana_sdk::system_program::ID,
-        })
-        .args(DepositInstruction { amount })
-        .send()
+        },
+        vec![],
+        DepositInstruction { amount },
+    )
+}
+
+/// Creates the fees deducted from minted deposits and paid on top of withdrawals, in satoshis,
+/// `pool_share_bps` of which go to the fee pool and the rest to the treasury. The payer has to be
+/// the relay program's upgrade authority and becomes their authority
+pub fn init_deposit_fees(
+    yona: &impl YonaClient,
+    treasury: Pubkey,
+    fixed_fee: u64,
+    fee_bps: u16,
+    min_deposit: u64,
+    withdrawal_fee: u64,
+    pool_share_bps: u16,
+) -> Result<Signature, AnchorClientError> {
+    let (deposit_fees, _) = Pubkey::find_program_address(&[b"deposit_fees"], &yona.id());
+    let (program_data, _) =
+        Pubkey::find_program_address(&[yona.id().as_ref()], &bpf_loader_upgradeable::ID);
+
+    yona.send(
+        InitializeDepositFees {
+            signer: yona.payer(),
+            deposit_fees,
+            program_data,
+            system_program: anchor_client::solana_sdk::system_program::ID,
+        },
+        vec![],
+        InitializeDepositFeesInstruction {
+            treasury,
+            fixed_fee,
+            fee_bps,
+            min_deposit,
+            withdrawal_fee,
+            pool_share_bps,
+        },
+    )
+}
+
+/// Creates the fee pool rewarding header submitters with `header_reward` lamports per header and
+/// funds it. The payer has to be the relay program's upgrade authority and becomes the pool's
+/// authority
+pub fn init_fee_pool(
+    yona: &impl YonaClient,
+    header_reward: u64,
+    funding: u64,
+) -> Result<Signature, AnchorClientError> {
+    let (fee_pool, _) = Pubkey::find_program_address(&[b"fee_pool"], &yona.id());
+    let (program_data, _) =
+        Pubkey::find_program_address(&[yona.id().as_ref()], &bpf_loader_upgradeable::ID);
+
+    let mut instructions = vec![yona.instruction(
+        InitializeFeePool {
+            signer: yona.payer(),
+            fee_pool,
+            program_data,
+            system_program: anchor_client::solana_sdk::system_program::ID,
+        },
+        vec![],
+        InitializeFeePoolInstruction { header_reward },
+    )];
+    if funding > 0 {
+        instructions.push(yona.instruction(
+            FundFeePool {
+                signer: yona.payer(),
+                fee_pool,
+                system_program: anchor_client::solana_sdk::system_program::ID,
+            },
+            vec![],
+            FundFeePoolInstruction { amount: funding },
+        ));
+    }
+
+    let transaction = yona.sign_transaction(&instructions)?;
+    yona.send_transaction(&transaction)
 }
 
 pub fn init_program(
-    program: &Program<Arc<Keypair>>,
+    yona: &impl YonaClient,
+    bitcoind_client: &impl RpcApi,
     block: Block,
     block_height: u32,
-) -> Result<Signature, AnchorClientError> {
-    let (main_state, _) = Pubkey::find_program_address(&[b"state"], &program.id());
+) -> Result<Signature, InitProgramError> {
+    let (main_state, _) = Pubkey::find_program_address(&[b"state"], &yona.id());
 
     let yona_block_header = BlockHeader {
         version: block.header.version.to_consensus() as u32,
@@ -83,27 +219,31 @@ pub fn init_program(
         nonce: block.header.nonce,
     };
 
-    let block_hash = yona_block_header.get_block_hash()?;
+    let block_hash = yona_block_header.get_block_hash();
 
     let (header_topic, _) =
-        Pubkey::find_program_address(&[b"header", block_hash.as_slice()], &program.id());
+        Pubkey::find_program_address(&[b"header", block_hash.as_slice()], &yona.id());
+
+    // Committed headers are reconstructed from the node's timestamps, so the relay has to start
+    // with the same ones
+    let prev_block_timestamps = prev_block_timestamps(bitcoind_client, block_height)?;
 
-    let res = program
-        .request()
-        .accounts(Initialize {
-            signer: program.payer(),
+    let res = yona.send(
+        Initialize {
+            signer: yona.payer(),
             main_state,
             header_topic,
             system_program: anchor_client::solana_sdk::system_program::ID,
-        })
-        .args(InitializeInstruction {
+        },
+        vec![],
+        InitializeInstruction {
             data: yona_block_header,
             block_height,
             chain_work: [0; 32],
             last_diff_adjustment: yona_block_header.timestamp,
-            prev_block_timestamps: [yona_block_header.timestamp; 10],
-        })
-        .send()?;
+            prev_block_timestamps,
+        },
+    )?;
 
     info!(
         "Submitted block {}, tx sig {res}",
@@ -113,13 +253,44 @@ pub fn init_program(
     Ok(res)
 }
 
+#[derive(Debug)]
+pub enum SubmitBlockError {
+    Anchor(AnchorClientError),
+    /// The relay program would reject the header, so it isn't sent
+    Rejected(SimulationError),
+    Store(StoreError),
+}
+
+impl fmt::Display for SubmitBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitBlockError::Anchor(e) => write!(f, "{e}"),
+            SubmitBlockError::Rejected(e) => write!(f, "{e}"),
+            SubmitBlockError::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<AnchorClientError> for SubmitBlockError {
+    fn from(error: AnchorClientError) -> Self {
+        SubmitBlockError::Anchor(error)
+    }
+}
+
+impl From<StoreError> for SubmitBlockError {
+    fn from(error: StoreError) -> Self {
+        SubmitBlockError::Store(error)
+    }
+}
+
 pub(crate) fn submit_block(
-    program: &Program<Arc<Keypair>>,
+    yona: &impl YonaClient,
+    store: &RelayerStore,
     main_state: Pubkey,
     block: Block,
     height: u32,
     commited_header: CommittedBlockHeader,
-) -> Result<Signature, AnchorClientError> {
+) -> Result<Signature, SubmitBlockError> {
     let yona_block_header = BlockHeader {
         version: block.header.version.to_consensus() as u32,
         reversed_prev_blockhash: block.header.prev_blockhash.to_byte_array(),
@@ -129,26 +300,51 @@ pub(crate) fn submit_block(
         nonce: block.header.nonce,
     };
 
-    let mut block_hash = yona_block_header.get_block_hash()?;
-    let (header_topic, _) =
-        Pubkey::find_program_address(&[b"header", block_hash.as_slice()], &program.id());
+    // Pre-flight the header with the relay's verification rules to not pay for a failing transaction
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time to be after unix epoch")
+        .as_secs() as u32;
+    HeaderChain::new(commited_header)
+        .simulate(&[yona_block_header], now)
+        .map_err(SubmitBlockError::Rejected)?;
+
+    let mut block_hash = yona_block_header.get_block_hash();
+    let (header_topic, header_topic_bump) =
+        Pubkey::find_program_address(&[b"header", block_hash.as_slice()], &yona.id());
 
     let header_account = AccountMeta::new(header_topic, false);
 
-    let res = program
-        .request()
-        .accounts(SubmitBlockHeaders {
-            signer: program.payer(),
+    // The fee pool rewards the submission when the relay program has one
+    let (fee_pool, _) = Pubkey::find_program_address(&[b"fee_pool"], &yona.id());
+    let fee_pool = match yona.get_account(&fee_pool) {
+        Ok(_) => Some(fee_pool),
+        Err(AnchorClientError::AccountNotFound) => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let instruction = yona.instruction(
+        SubmitBlockHeaders {
+            signer: yona.payer(),
             main_state,
-        })
-        .accounts(vec![header_account])
-        .args(SubmitBlockHeadersInstruction {
+            fee_pool,
+        },
+        vec![header_account],
+        SubmitBlockHeadersInstruction {
             data: vec![yona_block_header],
             commited_header,
-        })
-        .send()?;
+            header_topic_bumps: vec![header_topic_bump],
+        },
+    );
 
     block_hash.reverse();
+    let res = store.send::<SubmitBlockError>(
+        yona,
+        TxKind::Header,
+        &block_hash.to_lower_hex_string(),
+        instruction,
+    )?;
+
     info!(
         "Submitted block header. Hash {}, height {height}, Yona tx {res}",
         block_hash.to_lower_hex_string()
@@ -158,11 +354,97 @@ pub(crate) fn submit_block(
 }
 
 #[derive(Debug)]
-pub(crate) enum RelayTxError {
+pub enum RelayTxError {
     Anchor(AnchorClientError),
     BitcoinRpc(BtcRpcError),
+    Store(StoreError),
+    /// The Bitcoin node doesn't know the transaction
+    TxNotFound,
     TxIsNotIncludedToBlock,
     CouldNotFindTxidInBlock,
+    /// The relay program doesn't have the block including the transaction yet
+    BlockNotRelayed {
+        block_height: u32,
+        relay_height: u32,
+    },
+    /// The relay program has the block, but not enough blocks on top of it
+    NotEnoughConfirmations {
+        confirmations: u32,
+        required: u32,
+    },
+    /// A verification of the deposit was sent, but its outcome couldn't be checked
+    VerificationPending(Signature),
+    /// The fees paid over the last hour reached the budget, pausing deposit verifications
+    FeeBudgetExhausted {
+        spent: u64,
+        budget: u64,
+    },
+    /// Another request is relaying the same deposit
+    RelayInProgress,
+    /// The relayer refuses new requests while this many wait to be relayed
+    TooManyPendingRequests {
+        max_pending: u64,
+    },
+}
+
+impl fmt::Display for RelayTxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelayTxError::Anchor(e) => write!(f, "{e}"),
+            RelayTxError::BitcoinRpc(e) => write!(f, "{e}"),
+            RelayTxError::Store(e) => write!(f, "{e}"),
+            RelayTxError::TxNotFound => write!(f, "The Bitcoin node doesn't know the transaction"),
+            RelayTxError::TxIsNotIncludedToBlock => write!(f, "The transaction isn't in a block"),
+            RelayTxError::CouldNotFindTxidInBlock => {
+                write!(f, "The transaction isn't in the block the node reports")
+            }
+            RelayTxError::BlockNotRelayed {
+                block_height,
+                relay_height,
+            } => write!(
+                f,
+                "The transaction's block {block_height} is above the relay's tip {relay_height}"
+            ),
+            RelayTxError::NotEnoughConfirmations {
+                confirmations,
+                required,
+            } => write!(
+                f,
+                "The relay has {confirmations} of the {required} required confirmations"
+            ),
+            RelayTxError::VerificationPending(signature) => {
+                write!(f, "The outcome of verification {signature} isn't known yet")
+            }
+            RelayTxError::FeeBudgetExhausted { spent, budget } => write!(
+                f,
+                "The relayer paid {spent} lamports of fees over the last hour, reaching its \
+                 budget of {budget}"
+            ),
+            RelayTxError::RelayInProgress => {
+                write!(f, "The deposit is being relayed by another request")
+            }
+            RelayTxError::TooManyPendingRequests { max_pending } => write!(
+                f,
+                "The relayer has {max_pending} pending requests, try again later"
+            ),
+        }
+    }
+}
+
+impl RelayTxError {
+    /// Whether relaying the deposit can succeed later without a change in the request, once the
+    /// block relayer submits more headers, the sent verification is settled, the fee budget frees
+    /// up or the concurrent relay of the deposit ends
+    pub fn is_waiting(&self) -> bool {
+        matches!(
+            self,
+            RelayTxError::BlockNotRelayed { .. }
+                | RelayTxError::NotEnoughConfirmations { .. }
+                | RelayTxError::VerificationPending(_)
+                | RelayTxError::FeeBudgetExhausted { .. }
+                | RelayTxError::RelayInProgress
+        )
+    }
 }
 
 impl From<AnchorClientError> for RelayTxError {
@@ -177,21 +459,55 @@ impl From<BtcRpcError> for RelayTxError {
     }
 }
 
-pub(crate) fn relay_tx(
-    program: &Program<Arc<Keypair>>,
+impl From<StoreError> for RelayTxError {
+    fn from(error: StoreError) -> Self {
+        RelayTxError::Store(error)
+    }
+}
+
+/// Verifies the deposit transaction on Yona, minting to `mint_receiver`, once the relay program
+/// has `confirmations` blocks on top of the deposit, counting its own block.
+///
+/// A deposit already relayed to the receiver isn't relayed again, the signature of the first
+/// verification is returned instead.
+pub fn relay_tx(
+    yona: &impl YonaClient,
+    store: &RelayerStore,
     main_state: Pubkey,
-    bitcoind_client: &BitcoinRpcClient,
+    bitcoind_client: &impl RpcApi,
     tx_id: Txid,
     mint_receiver: Pubkey,
+    confirmations: u32,
 ) -> Result<Signature, RelayTxError> {
-    let raw_account = program
-        .rpc()
-        .get_account(&main_state)
-        .map_err(AnchorClientError::from)?;
-    let main_state_data = MainState::try_deserialize_unchecked(&mut &raw_account.data[..8128])
-        .map_err(AnchorClientError::from)?;
+    // Settle verifications whose outcome is unknown first, one of them could be for this deposit
+    store.resume_pending(yona, TxKind::RelayTx)?;
+    let key = relay_tx_key(&tx_id, &mint_receiver.to_string());
+    if let Some(signature) = store.confirmed_transaction(TxKind::RelayTx, &key)? {
+        info!("Deposit {tx_id} is already relayed to {mint_receiver}, Yona tx {signature}");
+        return Ok(signature);
+    }
+    // A verification with an unknown outcome could still mint, so another one isn't sent
+    if let Some(pending) = store
+        .transactions(TxKind::RelayTx, &key)?
+        .into_iter()
+        .find(|tx| tx.status == TxStatus::Pending)
+    {
+        return Err(RelayTxError::VerificationPending(pending.signature));
+    }
+    // Unlike the headers, which the deposits wait for, verifications can be paused
+    if let Some(budget) = store.fee_policy().hourly_fee_budget {
+        let spent = store.hourly_fees()?;
+        if spent >= budget {
+            return Err(RelayTxError::FeeBudgetExhausted { spent, budget });
+        }
+    }
+
+    let main_state_data = yona.main_state()?;
 
-    let transaction = bitcoind_client.get_raw_transaction_info(&tx_id, None)?;
+    let transaction = match bitcoind_client.get_raw_transaction_info(&tx_id, None) {
+        Err(e) if is_unknown_transaction(&e) => return Err(RelayTxError::TxNotFound),
+        result => result?,
+    };
 
     let block_hash = match transaction.blockhash {
         Some(hash) => hash,
@@ -199,13 +515,25 @@ pub(crate) fn relay_tx(
     };
 
     let block_info = bitcoind_client.get_block_info(&block_hash)?;
+    let block_height = block_info.height as u32;
+    // The relay program would fail, or underflow computing the confirmations, so the request waits
+    // for the block relayer instead
+    if block_height > main_state_data.block_height {
+        return Err(RelayTxError::BlockNotRelayed {
+            block_height,
+            relay_height: main_state_data.block_height,
+        });
+    }
+    let relayed_confirmations = main_state_data.block_height - block_height + 1;
+    if relayed_confirmations < confirmations {
+        return Err(RelayTxError::NotEnoughConfirmations {
+            confirmations: relayed_confirmations,
+            required: confirmations,
+        });
+    }
 
-    let commited_header = reconstruct_commited_header(
-        &bitcoind_client,
-        &block_hash,
-        block_info.height as u32,
-        main_state_data.last_diff_adjustment,
-    )?;
+    let commited_header =
+        reconstruct_commited_header(bitcoind_client, &block_hash, block_height, &main_state_data)?;
 
     let tx_pos = block_info
         .tx
@@ -214,24 +542,63 @@ pub(crate) fn relay_tx(
         .ok_or(RelayTxError::CouldNotFindTxidInBlock)?;
     let proof = Proof::create(&block_info.tx, tx_pos);
 
-    let (deposit_account, _) = Pubkey::find_program_address(&[b"solana_deposit"], &program.id());
+    let (deposit_account, _) = Pubkey::find_program_address(&[b"solana_deposit"], &yona.id());
+    let (bridge_key, _) = Pubkey::find_program_address(&[b"bridge_key"], &yona.id());
+    // Deposits are minted without fees while the relay program has none
+    let (deposit_fees, _) = Pubkey::find_program_address(&[b"deposit_fees"], &yona.id());
+    let fees = match yona.deposit_fees() {
+        Ok(fees) => Some(fees),
+        Err(AnchorClientError::AccountNotFound) => None,
+        Err(e) => return Err(e.into()),
+    };
+    // The fee pool receives its share of the deposit fee
+    let (fee_pool, _) = Pubkey::find_program_address(&[b"fee_pool"], &yona.id());
+    let fee_pool = fees
+        .as_ref()
+        .is_some_and(|fees| fees.pool_share_bps > 0)
+        .then_some(fee_pool);
+    let reversed_txid = tx_id.to_byte_array();
+    let (deposit_claim, _) = Pubkey::find_program_address(
+        &[b"deposit_claim", &reversed_txid, mint_receiver.as_ref()],
+        &yona.id(),
+    );
 
-    let res = program
-        .request()
-        .accounts(VerifyTransaction {
-            signer: program.payer(),
+    let instruction = yona.instruction(
+        VerifyTransaction {
+            signer: yona.payer(),
             main_state,
             deposit_account,
             mint_receiver,
-        })
-        .args(VerifySmallTxInstruction {
+            bridge_key,
+            deposit_fees,
+            treasury: fees.map(|fees| fees.treasury),
+            fee_pool,
+            deposit_claim,
+            system_program: anchor_client::solana_sdk::system_program::ID,
+        },
+        vec![],
+        VerifySmallTxInstruction {
             tx_bytes: transaction.hex,
-            confirmations: 1,
-            tx_index: tx_pos as u32,
+            confirmations,
+            tx_index: proof.position() as u32,
             commited_header,
             reversed_merkle_proof: proof.to_reversed_vec(),
-        })
-        .send()?;
+        },
+    );
 
-    Ok(res)
+    match store.send::<RelayTxError>(yona, TxKind::RelayTx, &key, instruction) {
+        Ok(signature) => {
+            METRICS.deposits_relayed.inc();
+            Ok(signature)
+        }
+        // Verifications with an unknown outcome are resumed, they didn't fail yet
+        Err(RelayTxError::Anchor(e)) if client_transaction_error(&e).is_some() => {
+            METRICS
+                .deposits_failed
+                .with_label_values(&[&error_label(&e)])
+                .inc();
+            Err(RelayTxError::Anchor(e))
+        }
+        Err(e) => Err(e),
+    }
 }
@@ -0,0 +1,62 @@
+use crate::notifications::Wakeup;
+use log::{info, warn};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::io;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Shutdown request shared between a relay loop and the signal handler, letting the loop finish
+/// the work in flight instead of being killed in the middle of it
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    wakeup: Wakeup,
+}
+
+impl Shutdown {
+    /// Requests the shutdown on the first SIGTERM or SIGINT, a second one exits right away
+    pub fn on_termination_signals() -> io::Result<Shutdown> {
+        let shutdown = Shutdown::default();
+        let mut signals = Signals::new([SIGTERM, SIGINT])?;
+        let handler = shutdown.clone();
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                if handler.is_requested() {
+                    warn!(
+                        "Got signal {signal} again, exiting without finishing the work in flight"
+                    );
+                    process::exit(1);
+                }
+                info!("Got signal {signal}, stopping after the work in flight");
+                handler.request();
+            }
+        });
+        Ok(shutdown)
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.wakeup.wake();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Wakeup ending the loop's sleep early, also woken up by the shutdown request
+    pub fn wakeup(&self) -> Wakeup {
+        self.wakeup.clone()
+    }
+
+    /// Sleeps for the duration, waking up early when the shutdown is requested or the loop is
+    /// notified of new work
+    pub fn sleep(&self, duration: Duration) {
+        if !self.is_requested() {
+            self.wakeup.wait(duration);
+        }
+    }
+}
@@ -0,0 +1,744 @@
+use crate::deposit_watcher::DepositAddressPolicy;
+use crate::fees::FeePolicy;
+use crate::metrics::record_fee;
+use crate::yona::{
+    client_transaction_error, is_preflight_failure, transaction_error_message, YonaClient,
+};
+use anchor_client::solana_sdk::instruction::Instruction;
+use anchor_client::solana_sdk::signature::Signature;
+use anchor_client::solana_sdk::transaction::Transaction;
+use anchor_client::ClientError as AnchorClientError;
+use bitcoin::Txid;
+use log::{info, warn};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Deserialize;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub type StoreError = rusqlite::Error;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS yona_transactions (
+        signature TEXT PRIMARY KEY,
+        kind TEXT NOT NULL,
+        key TEXT NOT NULL,
+        raw BLOB NOT NULL,
+        status TEXT NOT NULL,
+        error TEXT,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS yona_transactions_key ON yona_transactions (kind, key);
+    CREATE TABLE IF NOT EXISTS relay_requests (
+        txid TEXT NOT NULL,
+        yona_address TEXT NOT NULL,
+        status TEXT NOT NULL,
+        signature TEXT,
+        error TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (txid, yona_address)
+    );
+    CREATE TABLE IF NOT EXISTS deposit_addresses (
+        yona_address TEXT PRIMARY KEY,
+        created_at INTEGER NOT NULL,
+        refreshed_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS deposits (
+        txid TEXT NOT NULL,
+        yona_address TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        height INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        signature TEXT,
+        error TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (txid, yona_address)
+    );
+    CREATE TABLE IF NOT EXISTS watcher_state (
+        key TEXT PRIMARY KEY,
+        value INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS yona_fees (
+        signature TEXT PRIMARY KEY,
+        kind TEXT NOT NULL,
+        lamports INTEGER NOT NULL,
+        paid_at INTEGER NOT NULL
+    );
+";
+
+/// What a Yona transaction sent by the relayer does, along with the key it's tracked by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxKind {
+    /// Block header submission, keyed by the block hash
+    Header,
+    /// Deposit verification, keyed by `txid:yona_address`
+    RelayTx,
+}
+
+/// Key of the verification of the deposit to the Yona address
+pub fn relay_tx_key(txid: &Txid, yona_address: &str) -> String {
+    format!("{txid}:{yona_address}")
+}
+
+impl TxKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            TxKind::Header => "header",
+            TxKind::RelayTx => "relay_tx",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatus {
+    /// Signed and stored, but not known to be processed
+    Pending,
+    Confirmed,
+    Failed,
+}
+
+impl TxStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TxStatus::Pending => "pending",
+            TxStatus::Confirmed => "confirmed",
+            TxStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(status: &str) -> Option<TxStatus> {
+        match status {
+            "pending" => Some(TxStatus::Pending),
+            "confirmed" => Some(TxStatus::Confirmed),
+            "failed" => Some(TxStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// Stored Yona transaction, without its content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionRecord {
+    pub signature: Signature,
+    pub status: TxStatus,
+    /// Why the transaction failed
+    pub error: Option<String>,
+}
+
+/// Relay request accepted by the transaction relay server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayRequest {
+    pub txid: Txid,
+    pub yona_address: String,
+}
+
+/// How many relay requests can wait for headers, confirmations or fees, and for how long
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RelayRequestPolicy {
+    /// Pending requests after which new ones are refused
+    pub max_pending: u64,
+    /// Seconds a request stays pending before it fails as expired
+    pub expiry_secs: u64,
+}
+
+impl Default for RelayRequestPolicy {
+    fn default() -> RelayRequestPolicy {
+        RelayRequestPolicy {
+            max_pending: 1000,
+            expiry_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+/// Bridge deposit found by the deposit watcher
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deposit {
+    pub txid: Txid,
+    pub yona_address: String,
+    /// Deposited satoshis
+    pub amount: u64,
+    /// Height of the block including the transaction when it was found
+    pub height: u32,
+}
+
+/// Embedded SQLite store keeping the relayer's in-flight work across restarts.
+///
+/// Yona transactions are stored signed before they are sent, so after a crash they can be
+/// confirmed or re-broadcast with the same signature instead of being submitted again.
+pub struct RelayerStore {
+    connection: Mutex<Connection>,
+    fee_policy: FeePolicy,
+    deposit_address_policy: DepositAddressPolicy,
+    relay_request_policy: RelayRequestPolicy,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time to be after unix epoch")
+        .as_secs() as i64
+}
+
+fn to_sql_error(error: impl std::error::Error + Send + Sync + 'static) -> StoreError {
+    rusqlite::Error::ToSqlConversionFailure(Box::new(error))
+}
+
+impl RelayerStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<RelayerStore, StoreError> {
+        let connection = Connection::open(path)?;
+        // The block and transaction relayers can share the file
+        connection.busy_timeout(Duration::from_secs(5))?;
+        Self::with_connection(connection)
+    }
+
+    pub fn in_memory() -> Result<RelayerStore, StoreError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<RelayerStore, StoreError> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(RelayerStore {
+            connection: Mutex::new(connection),
+            fee_policy: FeePolicy::default(),
+            deposit_address_policy: DepositAddressPolicy::default(),
+            relay_request_policy: RelayRequestPolicy::default(),
+        })
+    }
+
+    /// Prices the transactions sent through [`RelayerStore::send`] with the policy
+    pub fn with_fee_policy(mut self, fee_policy: FeePolicy) -> RelayerStore {
+        self.fee_policy = fee_policy;
+        self
+    }
+
+    /// Limits and expires the registrations of deposit addresses with the policy
+    pub fn with_deposit_address_policy(mut self, policy: DepositAddressPolicy) -> RelayerStore {
+        self.deposit_address_policy = policy;
+        self
+    }
+
+    /// Limits and expires the pending relay requests with the policy
+    pub fn with_relay_request_policy(mut self, policy: RelayRequestPolicy) -> RelayerStore {
+        self.relay_request_policy = policy;
+        self
+    }
+
+    pub fn fee_policy(&self) -> &FeePolicy {
+        &self.fee_policy
+    }
+
+    pub fn relay_request_policy(&self) -> &RelayRequestPolicy {
+        &self.relay_request_policy
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .expect("store mutex not to be poisoned")
+    }
+
+    pub fn insert_transaction(
+        &self,
+        kind: TxKind,
+        key: &str,
+        transaction: &Transaction,
+    ) -> Result<(), StoreError> {
+        let raw = bincode::serialize(transaction).map_err(to_sql_error)?;
+        self.connection().execute(
+            "INSERT OR REPLACE INTO yona_transactions (signature, kind, key, raw, status, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                transaction.signatures[0].to_string(),
+                kind.as_str(),
+                key,
+                raw,
+                TxStatus::Pending.as_str(),
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_transaction_status(
+        &self,
+        signature: &Signature,
+        status: TxStatus,
+        error: Option<&str>,
+    ) -> Result<(), StoreError> {
+        self.connection().execute(
+            "UPDATE yona_transactions SET status = ?2, error = ?3 WHERE signature = ?1",
+            params![signature.to_string(), status.as_str(), error],
+        )?;
+        Ok(())
+    }
+
+    /// Signature of the confirmed transaction of the kind and key, if any
+    pub fn confirmed_transaction(
+        &self,
+        kind: TxKind,
+        key: &str,
+    ) -> Result<Option<Signature>, StoreError> {
+        let signature: Option<String> = self
+            .connection()
+            .query_row(
+                "SELECT signature FROM yona_transactions WHERE kind = ?1 AND key = ?2 AND status = ?3",
+                params![kind.as_str(), key, TxStatus::Confirmed.as_str()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        signature
+            .map(|signature| Signature::from_str(&signature).map_err(to_sql_error))
+            .transpose()
+    }
+
+    pub fn pending_transactions(&self, kind: TxKind) -> Result<Vec<Transaction>, StoreError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT raw FROM yona_transactions WHERE kind = ?1 AND status = ?2 ORDER BY created_at",
+        )?;
+        let rows = statement
+            .query_map(params![kind.as_str(), TxStatus::Pending.as_str()], |row| {
+                row.get::<_, Vec<u8>>(0)
+            })?;
+        rows.map(|raw| bincode::deserialize(&raw?).map_err(to_sql_error))
+            .collect()
+    }
+
+    /// Transactions of the kind and key, newest first
+    pub fn transactions(
+        &self,
+        kind: TxKind,
+        key: &str,
+    ) -> Result<Vec<TransactionRecord>, StoreError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT signature, status, error FROM yona_transactions WHERE kind = ?1 AND key = ?2
+             ORDER BY created_at DESC, rowid DESC",
+        )?;
+        let rows = statement.query_map(params![kind.as_str(), key], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get(2)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (signature, status, error) = row?;
+            Ok(TransactionRecord {
+                signature: Signature::from_str(&signature).map_err(to_sql_error)?,
+                status: TxStatus::parse(&status).ok_or_else(|| {
+                    rusqlite::Error::InvalidColumnType(1, status, rusqlite::types::Type::Text)
+                })?,
+                error,
+            })
+        })
+        .collect()
+    }
+
+    /// Records the relay request as pending, keeping the state of a pending or confirmed one and
+    /// retrying a failed one. Returns whether the request was recorded.
+    pub fn insert_relay_request(
+        &self,
+        txid: &Txid,
+        yona_address: &str,
+    ) -> Result<bool, StoreError> {
+        let connection = self.connection();
+        let status: Option<String> = connection
+            .query_row(
+                "SELECT status FROM relay_requests WHERE txid = ?1 AND yona_address = ?2",
+                params![txid.to_string(), yona_address],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if status.is_some_and(|status| status != TxStatus::Failed.as_str()) {
+            return Ok(false);
+        }
+        // A retried request waits for the policy's expiry again
+        connection.execute(
+            "INSERT INTO relay_requests (txid, yona_address, status, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?4)
+             ON CONFLICT (txid, yona_address) DO UPDATE SET status = excluded.status,
+                signature = NULL, error = NULL, created_at = excluded.created_at,
+                updated_at = excluded.updated_at",
+            params![
+                txid.to_string(),
+                yona_address,
+                TxStatus::Pending.as_str(),
+                now()
+            ],
+        )?;
+        Ok(true)
+    }
+
+    /// Whether the pending relay requests other than this one reach the policy's maximum
+    pub fn relay_requests_full(&self, txid: &Txid, yona_address: &str) -> Result<bool, StoreError> {
+        let pending: u64 = self.connection().query_row(
+            "SELECT COUNT(*) FROM relay_requests
+             WHERE status = ?1 AND NOT (txid = ?2 AND yona_address = ?3)",
+            params![TxStatus::Pending.as_str(), txid.to_string(), yona_address],
+            |row| row.get(0),
+        )?;
+        Ok(pending >= self.relay_request_policy.max_pending)
+    }
+
+    /// Fails the requests pending for longer than the policy's expiry, returning how many expired
+    pub fn expire_relay_requests(&self) -> Result<usize, StoreError> {
+        let expiry_secs = self.relay_request_policy.expiry_secs;
+        let now = now();
+        self.connection().execute(
+            "UPDATE relay_requests SET status = ?1, error = ?2, updated_at = ?3
+             WHERE status = ?4 AND created_at <= ?5",
+            params![
+                TxStatus::Failed.as_str(),
+                format!("The request expired after waiting {expiry_secs} seconds"),
+                now,
+                TxStatus::Pending.as_str(),
+                now - expiry_secs as i64
+            ],
+        )
+    }
+
+    pub fn set_relay_request_status(
+        &self,
+        txid: &Txid,
+        yona_address: &str,
+        status: TxStatus,
+        signature: Option<&Signature>,
+        error: Option<&str>,
+    ) -> Result<(), StoreError> {
+        self.connection().execute(
+            "UPDATE relay_requests SET status = ?3, signature = ?4, error = ?5, updated_at = ?6
+             WHERE txid = ?1 AND yona_address = ?2",
+            params![
+                txid.to_string(),
+                yona_address,
+                status.as_str(),
+                signature.map(|signature| signature.to_string()),
+                error,
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Relay requests accepted but not yet relayed, oldest first
+    pub fn pending_relay_requests(&self) -> Result<Vec<RelayRequest>, StoreError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT txid, yona_address FROM relay_requests WHERE status = ?1 ORDER BY created_at",
+        )?;
+        let rows = statement.query_map(params![TxStatus::Pending.as_str()], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        rows.map(|row| {
+            let (txid, yona_address) = row?;
+            Ok(RelayRequest {
+                txid: Txid::from_str(&txid).map_err(to_sql_error)?,
+                yona_address,
+            })
+        })
+        .collect()
+    }
+
+    /// Registers the Yona address for the deposit watcher, or keeps an already registered one from
+    /// expiring. Returns false without registering the address once the registrations of the last
+    /// hour reach the policy's limit.
+    pub fn register_deposit_address(&self, yona_address: &str) -> Result<bool, StoreError> {
+        let policy = &self.deposit_address_policy;
+        let connection = self.connection();
+        let now = now();
+        connection.execute(
+            "DELETE FROM deposit_addresses WHERE refreshed_at <= ?1",
+            params![now - policy.ttl_secs as i64],
+        )?;
+
+        let refreshed = connection.execute(
+            "UPDATE deposit_addresses SET refreshed_at = ?2 WHERE yona_address = ?1",
+            params![yona_address, now],
+        )?;
+        if refreshed > 0 {
+            return Ok(true);
+        }
+
+        let registered_last_hour: u64 = connection.query_row(
+            "SELECT COUNT(*) FROM deposit_addresses WHERE created_at > ?1",
+            params![now - 3600],
+            |row| row.get(0),
+        )?;
+        if registered_last_hour >= policy.max_registrations_per_hour {
+            return Ok(false);
+        }
+        connection.execute(
+            "INSERT INTO deposit_addresses (yona_address, created_at, refreshed_at) VALUES (?1, ?2, ?2)",
+            params![yona_address, now],
+        )?;
+        Ok(true)
+    }
+
+    /// Yona addresses registered or refreshed within the policy's time to live
+    pub fn deposit_addresses(&self) -> Result<Vec<String>, StoreError> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare("SELECT yona_address FROM deposit_addresses WHERE refreshed_at > ?1")?;
+        let rows = statement.query_map(
+            params![now() - self.deposit_address_policy.ttl_secs as i64],
+            |row| row.get(0),
+        )?;
+        rows.collect()
+    }
+
+    /// Records the deposit, keeping the state of an already known one
+    pub fn insert_deposit(&self, deposit: &Deposit) -> Result<(), StoreError> {
+        let now = now();
+        self.connection().execute(
+            "INSERT OR IGNORE INTO deposits (txid, yona_address, amount, height, status, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)",
+            params![
+                deposit.txid.to_string(),
+                deposit.yona_address,
+                deposit.amount,
+                deposit.height,
+                TxStatus::Pending.as_str(),
+                now
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Deposits which aren't minted yet, oldest first
+    pub fn pending_deposits(&self) -> Result<Vec<Deposit>, StoreError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT txid, yona_address, amount, height FROM deposits WHERE status = ?1 ORDER BY height",
+        )?;
+        let rows = statement.query_map(params![TxStatus::Pending.as_str()], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (txid, yona_address, amount, height) = row?;
+            Ok(Deposit {
+                txid: Txid::from_str(&txid).map_err(to_sql_error)?,
+                yona_address,
+                amount,
+                height,
+            })
+        })
+        .collect()
+    }
+
+    /// Marks the deposit minted, or records why relaying it failed, keeping it pending to retry
+    pub fn set_deposit_result(
+        &self,
+        deposit: &Deposit,
+        result: Result<&Signature, &str>,
+    ) -> Result<(), StoreError> {
+        let (status, signature, error) = match result {
+            Ok(signature) => (TxStatus::Confirmed, Some(signature.to_string()), None),
+            Err(error) => (TxStatus::Pending, None, Some(error)),
+        };
+        self.connection().execute(
+            "UPDATE deposits SET status = ?3, signature = ?4, error = ?5, updated_at = ?6
+             WHERE txid = ?1 AND yona_address = ?2",
+            params![
+                deposit.txid.to_string(),
+                deposit.yona_address,
+                status.as_str(),
+                signature,
+                error,
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Yona addresses the transaction was requested, found or verified for
+    pub fn deposit_addresses_of_tx(&self, txid: &Txid) -> Result<Vec<String>, StoreError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT yona_address FROM relay_requests WHERE txid = ?1
+             UNION SELECT yona_address FROM deposits WHERE txid = ?1
+             UNION SELECT substr(key, 66) FROM yona_transactions
+                 WHERE kind = ?2 AND substr(key, 1, 64) = ?1",
+        )?;
+        let rows = statement
+            .query_map(params![txid.to_string(), TxKind::RelayTx.as_str()], |row| {
+                row.get(0)
+            })?;
+        rows.collect()
+    }
+
+    /// Transactions requested, found or verified for the Yona address, oldest first
+    pub fn deposit_txids_of(&self, yona_address: &str) -> Result<Vec<Txid>, StoreError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT txid, MIN(created_at) FROM (
+                 SELECT txid, created_at FROM relay_requests WHERE yona_address = ?1
+                 UNION ALL SELECT txid, created_at FROM deposits WHERE yona_address = ?1
+                 UNION ALL SELECT substr(key, 1, 64), created_at FROM yona_transactions
+                     WHERE kind = ?2 AND substr(key, 66) = ?1
+             ) GROUP BY txid ORDER BY MIN(created_at), txid",
+        )?;
+        let rows = statement.query_map(params![yona_address, TxKind::RelayTx.as_str()], |row| {
+            row.get::<_, String>(0)
+        })?;
+        rows.map(|txid| Txid::from_str(&txid?).map_err(to_sql_error))
+            .collect()
+    }
+
+    /// Height of the last block scanned by the deposit watcher
+    pub fn scanned_height(&self) -> Result<Option<u32>, StoreError> {
+        self.connection()
+            .query_row(
+                "SELECT value FROM watcher_state WHERE key = 'scanned_height'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    pub fn set_scanned_height(&self, height: u32) -> Result<(), StoreError> {
+        self.connection().execute(
+            "INSERT OR REPLACE INTO watcher_state (key, value) VALUES ('scanned_height', ?1)",
+            params![height],
+        )?;
+        Ok(())
+    }
+
+    /// Lamports paid by the relayer's transactions over the last hour
+    pub fn hourly_fees(&self) -> Result<u64, StoreError> {
+        self.connection().query_row(
+            "SELECT COALESCE(SUM(lamports), 0) FROM yona_fees WHERE paid_at > ?1",
+            params![now() - 3600],
+            |row| row.get(0),
+        )
+    }
+
+    fn record_fee(
+        &self,
+        yona: &impl YonaClient,
+        kind: TxKind,
+        transaction: &Transaction,
+    ) -> Result<(), StoreError> {
+        let Some(fee) = record_fee(yona, kind, transaction) else {
+            return Ok(());
+        };
+        self.connection().execute(
+            "INSERT OR REPLACE INTO yona_fees (signature, kind, lamports, paid_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                transaction.signatures[0].to_string(),
+                kind.as_str(),
+                fee,
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Signs the instruction with a priority fee following the fee policy, stores the transaction
+    /// and sends it. Each earlier attempt of the transaction raises the priority fee.
+    ///
+    /// A transaction whose outcome is unknown after sending stays pending, to be resolved by
+    /// [`RelayerStore::resume_pending`].
+    pub fn send<E: From<AnchorClientError> + From<StoreError>>(
+        &self,
+        yona: &impl YonaClient,
+        kind: TxKind,
+        key: &str,
+        instruction: Instruction,
+    ) -> Result<Signature, E> {
+        let retries = self.transactions(kind, key)?.len();
+        let mut instructions =
+            self.fee_policy
+                .compute_budget_instructions(yona, &instruction, retries);
+        instructions.push(instruction);
+        let transaction = yona.sign_transaction(&instructions)?;
+        let signature = transaction.signatures[0];
+        self.insert_transaction(kind, key, &transaction)?;
+
+        match yona.send_transaction(&transaction) {
+            Ok(_) => {
+                self.set_transaction_status(&signature, TxStatus::Confirmed, None)?;
+                self.record_fee(yona, kind, &transaction)?;
+                Ok(signature)
+            }
+            Err(e) => {
+                // A transaction failing the preflight simulation isn't broadcast
+                let status = match client_transaction_error(&e) {
+                    Some(tx_error) => Ok(Some(Err(tx_error))),
+                    None => yona.signature_status(&signature),
+                };
+                match status {
+                    Ok(Some(Ok(()))) => {
+                        self.set_transaction_status(&signature, TxStatus::Confirmed, None)?;
+                        self.record_fee(yona, kind, &transaction)?;
+                        return Ok(signature);
+                    }
+                    Ok(Some(Err(tx_error))) => {
+                        self.set_transaction_status(
+                            &signature,
+                            TxStatus::Failed,
+                            Some(&transaction_error_message(&tx_error)),
+                        )?;
+                        // Only processed transactions are paid for
+                        if !is_preflight_failure(&e) {
+                            self.record_fee(yona, kind, &transaction)?;
+                        }
+                    }
+                    _ => {}
+                }
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Confirms the pending transactions of the kind, re-broadcasting the ones Yona doesn't know.
+    ///
+    /// A re-broadcast transaction keeps its signature, so it can't be processed twice. It's only
+    /// marked failed on a transaction error, a transport error leaves it pending to retry.
+    pub fn resume_pending(&self, yona: &impl YonaClient, kind: TxKind) -> Result<(), StoreError> {
+        for transaction in self.pending_transactions(kind)? {
+            let signature = transaction.signatures[0];
+            let status = match yona.signature_status(&signature) {
+                Ok(status) => status,
+                Err(e) => {
+                    warn!("Error {e} on signature_status({signature}), will retry");
+                    continue;
+                }
+            };
+
+            let (status, error) = match status {
+                Some(Ok(())) => (TxStatus::Confirmed, None),
+                Some(Err(e)) => (TxStatus::Failed, Some(transaction_error_message(&e))),
+                None => match yona.send_transaction(&transaction) {
+                    Ok(_) => (TxStatus::Confirmed, None),
+                    Err(e) => match client_transaction_error(&e) {
+                        Some(tx_error) => {
+                            (TxStatus::Failed, Some(transaction_error_message(&tx_error)))
+                        }
+                        // The transaction may still land, so it isn't replaced
+                        None => {
+                            warn!("Error {e} re-broadcasting {signature}, will retry");
+                            continue;
+                        }
+                    },
+                },
+            };
+            info!(
+                "Resumed {} transaction {signature}: {status:?}",
+                kind.as_str()
+            );
+            self.set_transaction_status(&signature, status, error.as_deref())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+use anchor_client::anchor_lang::prelude::AccountMeta;
+use anchor_client::anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use anchor_client::solana_client::client_error::ClientErrorKind;
+use anchor_client::solana_client::rpc_request::{RpcError, RpcResponseErrorData};
+use anchor_client::solana_client::rpc_response::RpcSimulateTransactionResult;
+use anchor_client::solana_sdk::account::Account;
+use anchor_client::solana_sdk::instruction::{Instruction, InstructionError};
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use anchor_client::solana_sdk::signature::{Signature, Signer};
+use anchor_client::solana_sdk::transaction::{Transaction, TransactionError};
+use anchor_client::{ClientError as AnchorClientError, Program};
+use btc_relay::errors::RelayErrorCode;
+use btc_relay::state::{BridgeKey, DepositFees, MainState};
+use std::ops::Deref;
+
+/// The subset of Yona's RPC the relayer needs, implemented by the anchor client and by
+/// in-process test environments
+pub trait YonaClient {
+    /// Relay program id
+    fn id(&self) -> Pubkey;
+
+    /// Account paying for and signing the relayer's transactions
+    fn payer(&self) -> Pubkey;
+
+    /// Fails with [`AnchorClientError::AccountNotFound`] when the account doesn't exist
+    fn get_account(&self, address: &Pubkey) -> Result<Account, AnchorClientError>;
+
+    /// Signs a transaction with the instructions by the payer, using the latest blockhash
+    fn sign_transaction(
+        &self,
+        instructions: &[Instruction],
+    ) -> Result<Transaction, AnchorClientError>;
+
+    /// Sends the transaction and waits for its confirmation
+    fn send_transaction(&self, transaction: &Transaction) -> Result<Signature, AnchorClientError>;
+
+    /// Processing result of the transaction, `None` if it isn't known to Yona
+    fn signature_status(
+        &self,
+        signature: &Signature,
+    ) -> Result<Option<Result<(), TransactionError>>, AnchorClientError>;
+
+    /// Lamports the transaction pays for being processed
+    fn transaction_fee(&self, transaction: &Transaction) -> Result<u64, AnchorClientError>;
+
+    /// Prioritization fees of the recent slots' transactions writing the accounts, in
+    /// micro-lamports per compute unit
+    fn recent_prioritization_fees(
+        &self,
+        accounts: &[Pubkey],
+    ) -> Result<Vec<u64>, AnchorClientError>;
+
+    fn send_instruction(&self, instruction: Instruction) -> Result<Signature, AnchorClientError> {
+        self.send_transaction(&self.sign_transaction(&[instruction])?)
+    }
+
+    /// Builds a relay program instruction
+    fn instruction(
+        &self,
+        accounts: impl ToAccountMetas,
+        remaining_accounts: Vec<AccountMeta>,
+        args: impl InstructionData,
+    ) -> Instruction {
+        let mut account_metas = accounts.to_account_metas(None);
+        account_metas.extend(remaining_accounts);
+        Instruction {
+            program_id: self.id(),
+            accounts: account_metas,
+            data: args.data(),
+        }
+    }
+
+    /// Sends a relay program instruction
+    fn send(
+        &self,
+        accounts: impl ToAccountMetas,
+        remaining_accounts: Vec<AccountMeta>,
+        args: impl InstructionData,
+    ) -> Result<Signature, AnchorClientError> {
+        self.send_instruction(self.instruction(accounts, remaining_accounts, args))
+    }
+
+    fn main_state(&self) -> Result<MainState, AnchorClientError> {
+        let (main_state, _) = Pubkey::find_program_address(&[b"state"], &self.id());
+        let raw_account = self.get_account(&main_state)?;
+
+        // TODO there seems to be an allocation of 8 unneeded bytes, which makes deserialization fail
+        Ok(MainState::try_deserialize_unchecked(
+            &mut &raw_account.data[..8128],
+        )?)
+    }
+
+    /// Fees deducted from the deposits the relay program mints
+    fn deposit_fees(&self) -> Result<DepositFees, AnchorClientError> {
+        let (deposit_fees, _) = Pubkey::find_program_address(&[b"deposit_fees"], &self.id());
+        let raw_account = self.get_account(&deposit_fees)?;
+        Ok(DepositFees::try_deserialize(
+            &mut raw_account.data.as_slice(),
+        )?)
+    }
+
+    /// Group key of the signer nodes the deposit addresses are derived from
+    fn bridge_key(&self) -> Result<BridgeKey, AnchorClientError> {
+        let (bridge_key, _) = Pubkey::find_program_address(&[b"bridge_key"], &self.id());
+        let raw_account = self.get_account(&bridge_key)?;
+        Ok(BridgeKey::try_deserialize(
+            &mut raw_account.data.as_slice(),
+        )?)
+    }
+}
+
+impl<C: Deref<Target = impl Signer> + Clone> YonaClient for Program<C> {
+    fn id(&self) -> Pubkey {
+        Program::id(self)
+    }
+
+    fn payer(&self) -> Pubkey {
+        Program::payer(self)
+    }
+
+    fn get_account(&self, address: &Pubkey) -> Result<Account, AnchorClientError> {
+        // Missing accounts are told apart from the RPC failing, like the in-process clients do
+        let rpc = self.rpc();
+        rpc.get_account_with_commitment(address, rpc.commitment())?
+            .value
+            .ok_or(AnchorClientError::AccountNotFound)
+    }
+
+    fn sign_transaction(
+        &self,
+        instructions: &[Instruction],
+    ) -> Result<Transaction, AnchorClientError> {
+        instructions
+            .iter()
+            .fold(self.request(), |request, instruction| {
+                request.instruction(instruction.clone())
+            })
+            .signed_transaction()
+    }
+
+    fn send_transaction(&self, transaction: &Transaction) -> Result<Signature, AnchorClientError> {
+        Ok(self.rpc().send_and_confirm_transaction(transaction)?)
+    }
+
+    fn signature_status(
+        &self,
+        signature: &Signature,
+    ) -> Result<Option<Result<(), TransactionError>>, AnchorClientError> {
+        Ok(self.rpc().get_signature_status(signature)?)
+    }
+
+    fn transaction_fee(&self, transaction: &Transaction) -> Result<u64, AnchorClientError> {
+        Ok(self.rpc().get_fee_for_message(&transaction.message)?)
+    }
+
+    fn recent_prioritization_fees(
+        &self,
+        accounts: &[Pubkey],
+    ) -> Result<Vec<u64>, AnchorClientError> {
+        let fees = self.rpc().get_recent_prioritization_fees(accounts)?;
+        Ok(fees.into_iter().map(|fee| fee.prioritization_fee).collect())
+    }
+}
+
+impl<T: YonaClient> YonaClient for &T {
+    fn id(&self) -> Pubkey {
+        (*self).id()
+    }
+
+    fn payer(&self) -> Pubkey {
+        (*self).payer()
+    }
+
+    fn get_account(&self, address: &Pubkey) -> Result<Account, AnchorClientError> {
+        (*self).get_account(address)
+    }
+
+    fn sign_transaction(
+        &self,
+        instructions: &[Instruction],
+    ) -> Result<Transaction, AnchorClientError> {
+        (*self).sign_transaction(instructions)
+    }
+
+    fn send_transaction(&self, transaction: &Transaction) -> Result<Signature, AnchorClientError> {
+        (*self).send_transaction(transaction)
+    }
+
+    fn signature_status(
+        &self,
+        signature: &Signature,
+    ) -> Result<Option<Result<(), TransactionError>>, AnchorClientError> {
+        (*self).signature_status(signature)
+    }
+
+    fn transaction_fee(&self, transaction: &Transaction) -> Result<u64, AnchorClientError> {
+        (*self).transaction_fee(transaction)
+    }
+
+    fn recent_prioritization_fees(
+        &self,
+        accounts: &[Pubkey],
+    ) -> Result<Vec<u64>, AnchorClientError> {
+        (*self).recent_prioritization_fees(accounts)
+    }
+}
+
+// Anchor numbers the program errors in declaration order, starting at ERROR_CODE_OFFSET
+const RELAY_ERRORS: [RelayErrorCode; 33] = [
+    RelayErrorCode::PrevBlockCommitment,
+    RelayErrorCode::PrevBlock,
+    RelayErrorCode::ErrDiffTarget,
+    RelayErrorCode::ErrPowToolow,
+    RelayErrorCode::ErrTimestampToolow,
+    RelayErrorCode::ErrTimestampTooHigh,
+    RelayErrorCode::InvalidHeaderTopic,
+    RelayErrorCode::NoHeaders,
+    RelayErrorCode::ForkTooShort,
+    RelayErrorCode::ErrInit,
+    RelayErrorCode::BlockConfirmations,
+    RelayErrorCode::MerkleRoot,
+    RelayErrorCode::InvalidBlockheight,
+    RelayErrorCode::InvalidForkId,
+    RelayErrorCode::InvalidRemainingAccounts,
+    RelayErrorCode::NoDepositOutputs,
+    RelayErrorCode::InvalidCoinbase,
+    RelayErrorCode::NoWitnessCommitment,
+    RelayErrorCode::WitnessCommitment,
+    RelayErrorCode::InvalidTransaction,
+    RelayErrorCode::InvalidTxId,
+    RelayErrorCode::ChainWorkOverflow,
+    RelayErrorCode::InvalidNbits,
+    RelayErrorCode::TipAlreadyExtended,
+    RelayErrorCode::DepositTooSmall,
+    RelayErrorCode::InvalidDepositFees,
+    RelayErrorCode::InvalidWithdrawal,
+    RelayErrorCode::InvalidBridgeKey,
+    RelayErrorCode::Unauthorized,
+    RelayErrorCode::MissingFeePool,
+    RelayErrorCode::InvalidTreasury,
+    RelayErrorCode::DepositAlreadyClaimed,
+    RelayErrorCode::AmountOverflow,
+];
+
+/// The relay program error with the custom error number
+pub fn relay_error(code: u32) -> Option<RelayErrorCode> {
+    RELAY_ERRORS.into_iter().find(|e| u32::from(*e) == code)
+}
+
+/// The relay program error the transaction failed with
+pub fn transaction_relay_error(error: &TransactionError) -> Option<RelayErrorCode> {
+    match error {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => relay_error(*code),
+        _ => None,
+    }
+}
+
+/// Describes why the transaction failed, naming the relay program error if it failed with one
+pub fn transaction_error_message(error: &TransactionError) -> String {
+    match transaction_relay_error(error) {
+        Some(relay_error) => format!("{}: {relay_error}", relay_error.name()),
+        None => error.to_string(),
+    }
+}
+
+/// Whether the request failed because Yona couldn't be reached
+pub fn is_unreachable(error: &AnchorClientError) -> bool {
+    match error {
+        AnchorClientError::SolanaClientError(e) => {
+            matches!(e.kind(), ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_))
+        }
+        AnchorClientError::IOError(_) => true,
+        _ => false,
+    }
+}
+
+/// The error the transaction failed with, if Yona processed or simulated it
+pub fn client_transaction_error(error: &AnchorClientError) -> Option<TransactionError> {
+    match error {
+        AnchorClientError::SolanaClientError(e) => e.get_transaction_error(),
+        _ => None,
+    }
+}
+
+fn preflight_simulation(error: &AnchorClientError) -> Option<&RpcSimulateTransactionResult> {
+    let AnchorClientError::SolanaClientError(e) = error else {
+        return None;
+    };
+    match e.kind() {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            data: RpcResponseErrorData::SendTransactionPreflightFailure(simulation),
+            ..
+        }) => Some(simulation),
+        _ => None,
+    }
+}
+
+/// Whether the transaction was rejected by the preflight simulation, so it wasn't broadcast
+pub fn is_preflight_failure(error: &AnchorClientError) -> bool {
+    preflight_simulation(error).is_some()
+}
+
+/// The relay program error the request failed with.
+///
+/// Taken from the transaction error, or from the `Error Number` Anchor logs in a failed preflight
+/// simulation when the error isn't a custom one.
+pub fn client_relay_error(error: &AnchorClientError) -> Option<RelayErrorCode> {
+    if let Some(relay_error) = client_transaction_error(error)
+        .as_ref()
+        .and_then(transaction_relay_error)
+    {
+        return Some(relay_error);
+    }
+
+    let simulation = preflight_simulation(error)?;
+    simulation.logs.iter().flatten().find_map(|log| {
+        let (_, number) = log.split_once("Error Number: ")?;
+        relay_error(number.trim_end_matches('.').parse().ok()?)
+    })
+}
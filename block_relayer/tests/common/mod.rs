@@ -0,0 +1,586 @@
+// In-process test environment: the relay program runs in solana-program-test and a mock
+// Bitcoin node mines regtest difficulty blocks in memory, so the relayer can be tested end to end
+// without Docker, bitcoind or a validator
+use anchor_client::anchor_lang::prelude::{AccountInfo, Pubkey};
+use anchor_client::anchor_lang::solana_program::entrypoint::ProgramResult;
+use anchor_client::solana_client::client_error::ClientError as SolanaClientError;
+use anchor_client::solana_sdk::account::{Account, AccountSharedData};
+use anchor_client::solana_sdk::bpf_loader_upgradeable::{self, UpgradeableLoaderState};
+use anchor_client::solana_sdk::instruction::Instruction;
+use anchor_client::solana_sdk::signature::{Keypair, Signature, Signer};
+use anchor_client::solana_sdk::transaction::{Transaction as YonaTransaction, TransactionError};
+use anchor_client::solana_sdk::transport::TransportError;
+use anchor_client::ClientError as AnchorClientError;
+use bitcoin::absolute::LockTime;
+use bitcoin::block::{Header, Version};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::hashes::Hash;
+use bitcoin::hex::FromHex;
+use bitcoin::script::Builder;
+use bitcoin::transaction::Version as TxVersion;
+use bitcoin::{
+    Amount, Block, BlockHash, CompactTarget, Network, OutPoint, ScriptBuf, Sequence, Transaction,
+    TxIn, TxMerkleNode, TxOut, Witness,
+};
+use bitcoincore_rpc::json::{
+    GetBlockHeaderResult, GetBlockResult, GetChainTipsResultStatus, GetChainTipsResultTip,
+    GetRawTransactionResult,
+};
+use bitcoincore_rpc::jsonrpc::error::{Error as JsonRpcError, RpcError};
+use bitcoincore_rpc::{Error as BtcRpcError, RpcApi};
+use block_relayer_lib::yona::YonaClient;
+use btc_relay::utils::{bridge_deposit_script_pubkey, legacy_deposit_script_pubkey};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use solana_program_test::{
+    processor, BanksClient, BanksClientError, ProgramTest, ProgramTestBanksClientExt,
+    ProgramTestContext,
+};
+use std::cell::{Cell, RefCell};
+use std::io;
+use tokio::runtime::Runtime;
+
+// Lowest regtest difficulty, every block adds 2 to the chain work
+const REGTEST_NBITS: u32 = 0x207fffff;
+const BLOCK_INTERVAL: u32 = 600;
+// x-only key of the generator point, registered as the signer nodes' group key
+const GROUP_KEY: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+pub fn group_key() -> [u8; 32] {
+    FromHex::from_hex(GROUP_KEY).expect("group key to be hex")
+}
+
+// Anchor's entrypoint needs the accounts to live as long as the account infos they contain
+fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    btc_relay::entry(program_id, accounts, data)
+}
+
+/// Yona client backed by the relay program running in solana-program-test
+pub struct InProcessYona {
+    runtime: Runtime,
+    context: RefCell<ProgramTestContext>,
+    banks_client: BanksClient,
+    payer: Keypair,
+    recent_prioritization_fee: Cell<u64>,
+}
+
+impl InProcessYona {
+    pub fn start() -> InProcessYona {
+        let runtime = Runtime::new().expect("tokio runtime to be created");
+        let program_test =
+            ProgramTest::new("btc_relay", btc_relay::ID, processor!(process_instruction));
+        let context = runtime.block_on(program_test.start_with_context());
+        let yona = InProcessYona {
+            runtime,
+            banks_client: context.banks_client.clone(),
+            payer: context.payer.insecure_clone(),
+            context: RefCell::new(context),
+            recent_prioritization_fee: Cell::new(0),
+        };
+        yona.set_upgrade_authority(Some(yona.payer.pubkey()));
+        yona
+    }
+
+    /// The program isn't deployed with the upgradeable loader, so its program data account is
+    /// created with the upgrade authority gating the program's configuration
+    pub fn set_upgrade_authority(&self, upgrade_authority_address: Option<Pubkey>) {
+        let program_data = UpgradeableLoaderState::ProgramData {
+            slot: 0,
+            upgrade_authority_address,
+        };
+        let address =
+            Pubkey::find_program_address(&[btc_relay::ID.as_ref()], &bpf_loader_upgradeable::ID).0;
+        let account =
+            AccountSharedData::new_data(1_000_000_000, &program_data, &bpf_loader_upgradeable::ID)
+                .expect("program data to be serialized");
+        self.context.borrow_mut().set_account(&address, &account);
+        // A transaction rejected under the previous authority would otherwise be answered from the
+        // status cache when signed again with the same blockhash
+        let mut banks_client = self.banks_client.clone();
+        self.runtime
+            .block_on(async {
+                let blockhash = banks_client.get_latest_blockhash().await?;
+                banks_client.get_new_latest_blockhash(&blockhash).await
+            })
+            .expect("new blockhash");
+    }
+
+    /// Registers the group key deposit addresses are derived from, signed by the payer as the
+    /// upgrade authority like the signer nodes' `register-group-key`
+    pub fn register_bridge_key(&self) -> Result<Signature, AnchorClientError> {
+        let program_data =
+            Pubkey::find_program_address(&[btc_relay::ID.as_ref()], &bpf_loader_upgradeable::ID).0;
+        self.send(
+            btc_relay::accounts::InitializeBridgeKey {
+                signer: self.payer(),
+                bridge_key: Pubkey::find_program_address(&[b"bridge_key"], &btc_relay::ID).0,
+                program_data,
+                system_program: anchor_client::solana_sdk::system_program::ID,
+            },
+            vec![],
+            btc_relay::instruction::InitializeBridgeKey {
+                group_key: group_key(),
+            },
+        )
+    }
+
+    /// Stops the relay program from minting legacy deposits, signed by the payer as the bridge key's
+    /// authority
+    pub fn end_legacy_deposits(&self) -> Result<Signature, AnchorClientError> {
+        self.send(
+            btc_relay::accounts::SetBridgeKey {
+                authority: self.payer(),
+                bridge_key: Pubkey::find_program_address(&[b"bridge_key"], &btc_relay::ID).0,
+            },
+            vec![],
+            btc_relay::instruction::EndLegacyDeposits {},
+        )
+    }
+
+    /// Prioritization fee reported for the recent slots, in micro-lamports per compute unit
+    pub fn set_recent_prioritization_fee(&self, fee: u64) {
+        self.recent_prioritization_fee.set(fee);
+    }
+
+    pub fn balance(&self, address: &Pubkey) -> u64 {
+        let mut banks_client = self.banks_client.clone();
+        self.runtime
+            .block_on(banks_client.get_balance(*address))
+            .expect("balance to be fetched")
+    }
+}
+
+// Keeps transaction errors typed, like the RPC client reports them
+fn client_error(error: BanksClientError) -> AnchorClientError {
+    SolanaClientError::from(TransportError::from(error)).into()
+}
+
+impl YonaClient for InProcessYona {
+    fn id(&self) -> Pubkey {
+        btc_relay::ID
+    }
+
+    fn payer(&self) -> Pubkey {
+        self.payer.pubkey()
+    }
+
+    fn get_account(&self, address: &Pubkey) -> Result<Account, AnchorClientError> {
+        let mut banks_client = self.banks_client.clone();
+        self.runtime
+            .block_on(banks_client.get_account(*address))
+            .map_err(client_error)?
+            .ok_or(AnchorClientError::AccountNotFound)
+    }
+
+    fn sign_transaction(
+        &self,
+        instructions: &[Instruction],
+    ) -> Result<YonaTransaction, AnchorClientError> {
+        let mut banks_client = self.banks_client.clone();
+        let blockhash = self
+            .runtime
+            .block_on(banks_client.get_latest_blockhash())
+            .map_err(client_error)?;
+        Ok(YonaTransaction::new_signed_with_payer(
+            instructions,
+            Some(&self.payer.pubkey()),
+            &[&self.payer],
+            blockhash,
+        ))
+    }
+
+    fn send_transaction(
+        &self,
+        transaction: &YonaTransaction,
+    ) -> Result<Signature, AnchorClientError> {
+        let mut banks_client = self.banks_client.clone();
+        self.runtime
+            .block_on(banks_client.process_transaction(transaction.clone()))
+            .map_err(client_error)?;
+        Ok(transaction.signatures[0])
+    }
+
+    fn signature_status(
+        &self,
+        signature: &Signature,
+    ) -> Result<Option<Result<(), TransactionError>>, AnchorClientError> {
+        let mut banks_client = self.banks_client.clone();
+        let status = self
+            .runtime
+            .block_on(banks_client.get_transaction_status(*signature))
+            .map_err(client_error)?;
+        Ok(status.map(|status| match status.err {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }))
+    }
+    fn transaction_fee(&self, transaction: &YonaTransaction) -> Result<u64, AnchorClientError> {
+        let mut banks_client = self.banks_client.clone();
+        self.runtime
+            .block_on(banks_client.get_fee_for_message(transaction.message.clone()))
+            .map_err(client_error)?
+            .ok_or(AnchorClientError::AccountNotFound)
+    }
+
+    fn recent_prioritization_fees(&self, _: &[Pubkey]) -> Result<Vec<u64>, AnchorClientError> {
+        Ok(vec![self.recent_prioritization_fee.get()])
+    }
+}
+
+/// Yona client whose transactions never reach the cluster, as if the relayer crashed after
+/// signing them
+pub struct UnreachableYona<'a>(pub &'a InProcessYona);
+
+impl YonaClient for UnreachableYona<'_> {
+    fn id(&self) -> Pubkey {
+        self.0.id()
+    }
+
+    fn payer(&self) -> Pubkey {
+        self.0.payer()
+    }
+
+    fn get_account(&self, address: &Pubkey) -> Result<Account, AnchorClientError> {
+        self.0.get_account(address)
+    }
+
+    fn sign_transaction(
+        &self,
+        instructions: &[Instruction],
+    ) -> Result<YonaTransaction, AnchorClientError> {
+        self.0.sign_transaction(instructions)
+    }
+
+    fn send_transaction(&self, _: &YonaTransaction) -> Result<Signature, AnchorClientError> {
+        Err(io::Error::other("connection lost").into())
+    }
+
+    fn signature_status(
+        &self,
+        signature: &Signature,
+    ) -> Result<Option<Result<(), TransactionError>>, AnchorClientError> {
+        self.0.signature_status(signature)
+    }
+    fn transaction_fee(&self, transaction: &YonaTransaction) -> Result<u64, AnchorClientError> {
+        self.0.transaction_fee(transaction)
+    }
+
+    fn recent_prioritization_fees(
+        &self,
+        accounts: &[Pubkey],
+    ) -> Result<Vec<u64>, AnchorClientError> {
+        self.0.recent_prioritization_fees(accounts)
+    }
+}
+
+/// Bitcoin node mock, mining regtest blocks in memory on top of the regtest genesis block.
+///
+/// Answers the RPC calls the relayer makes, filling only the result fields the relayer reads.
+pub struct MockBitcoind {
+    blocks: RefCell<Vec<Block>>,
+    mempool: RefCell<Vec<Transaction>>,
+}
+
+impl MockBitcoind {
+    pub fn new() -> MockBitcoind {
+        MockBitcoind {
+            blocks: RefCell::new(vec![genesis_block(Network::Regtest)]),
+            mempool: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn tip_height(&self) -> u32 {
+        self.blocks.borrow().len() as u32 - 1
+    }
+
+    /// Adds the transaction to the mempool, until a block mines it
+    pub fn broadcast(&self, transaction: Transaction) {
+        self.mempool.borrow_mut().push(transaction);
+    }
+
+    /// Mines a block with the transactions after the coinbase, returning its hash
+    pub fn mine_block(&self, transactions: Vec<Transaction>) -> BlockHash {
+        self.mempool
+            .borrow_mut()
+            .retain(|in_mempool| !transactions.contains(in_mempool));
+
+        let height = self.tip_height() + 1;
+        let prev = self.blocks.borrow().last().unwrap().header;
+
+        // The height in the coinbase (BIP34) keeps coinbase txids unique
+        let coinbase = Transaction {
+            version: TxVersion::ONE,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(height as i64).into_script(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_btc(50.0).unwrap(),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+
+        let mut block = Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash: prev.block_hash(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: prev.time + BLOCK_INTERVAL,
+                bits: CompactTarget::from_consensus(REGTEST_NBITS),
+                nonce: 0,
+            },
+            txdata: [vec![coinbase], transactions].concat(),
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        while block.header.validate_pow(block.header.target()).is_err() {
+            block.header.nonce += 1;
+        }
+
+        let hash = block.block_hash();
+        self.blocks.borrow_mut().push(block);
+        hash
+    }
+
+    pub fn mine_blocks(&self, count: u32) {
+        for _ in 0..count {
+            self.mine_block(vec![]);
+        }
+    }
+
+    fn block_height(&self, hash: &BlockHash) -> Option<usize> {
+        self.blocks
+            .borrow()
+            .iter()
+            .position(|block| block.block_hash() == *hash)
+    }
+
+    fn chain_work(height: usize) -> Vec<u8> {
+        let mut chain_work = vec![0; 32];
+        chain_work[24..].copy_from_slice(&(2 * (height as u64 + 1)).to_be_bytes());
+        chain_work
+    }
+
+    fn block_result(&self, height: usize) -> GetBlockResult {
+        let blocks = self.blocks.borrow();
+        let block = &blocks[height];
+        GetBlockResult {
+            hash: block.block_hash(),
+            confirmations: (blocks.len() - height) as i32,
+            size: block.total_size(),
+            strippedsize: None,
+            weight: block.weight().to_wu() as usize,
+            height,
+            version: block.header.version.to_consensus(),
+            version_hex: Some(block.header.version.to_consensus().to_be_bytes().to_vec()),
+            merkleroot: block.header.merkle_root,
+            tx: block.txdata.iter().map(|tx| tx.compute_txid()).collect(),
+            time: block.header.time as usize,
+            mediantime: None,
+            nonce: block.header.nonce,
+            bits: format!("{:08x}", block.header.bits.to_consensus()),
+            difficulty: 0.0,
+            chainwork: Self::chain_work(height),
+            n_tx: block.txdata.len(),
+            previousblockhash: height.checked_sub(1).map(|prev| blocks[prev].block_hash()),
+            nextblockhash: blocks.get(height + 1).map(|next| next.block_hash()),
+        }
+    }
+
+    fn block_header_result(&self, height: usize) -> GetBlockHeaderResult {
+        let block = self.block_result(height);
+        GetBlockHeaderResult {
+            hash: block.hash,
+            confirmations: block.confirmations,
+            height,
+            version: Version::from_consensus(block.version),
+            version_hex: block.version_hex,
+            merkle_root: block.merkleroot,
+            time: block.time,
+            median_time: None,
+            nonce: block.nonce,
+            bits: block.bits,
+            difficulty: block.difficulty,
+            chainwork: block.chainwork,
+            n_tx: block.n_tx,
+            previous_block_hash: block.previousblockhash,
+            next_block_hash: block.nextblockhash,
+        }
+    }
+
+    fn raw_transaction_result(&self, txid: &bitcoin::Txid) -> Option<GetRawTransactionResult> {
+        let blocks = self.blocks.borrow();
+        let in_block = blocks.iter().enumerate().find_map(|(height, block)| {
+            let tx = block.txdata.iter().find(|tx| tx.compute_txid() == *txid)?;
+            Some(GetRawTransactionResult {
+                in_active_chain: Some(true),
+                hex: bitcoin::consensus::serialize(tx),
+                txid: *txid,
+                hash: tx.compute_wtxid(),
+                size: tx.total_size(),
+                vsize: tx.vsize(),
+                version: tx.version.0 as u32,
+                locktime: tx.lock_time.to_consensus_u32(),
+                vin: vec![],
+                vout: vec![],
+                blockhash: Some(block.block_hash()),
+                confirmations: Some((blocks.len() - height) as u32),
+                time: Some(block.header.time as usize),
+                blocktime: Some(block.header.time as usize),
+            })
+        });
+        in_block.or_else(|| {
+            let mempool = self.mempool.borrow();
+            let tx = mempool.iter().find(|tx| tx.compute_txid() == *txid)?;
+            Some(GetRawTransactionResult {
+                in_active_chain: None,
+                hex: bitcoin::consensus::serialize(tx),
+                txid: *txid,
+                hash: tx.compute_wtxid(),
+                size: tx.total_size(),
+                vsize: tx.vsize(),
+                version: tx.version.0 as u32,
+                locktime: tx.lock_time.to_consensus_u32(),
+                vin: vec![],
+                vout: vec![],
+                blockhash: None,
+                confirmations: None,
+                time: None,
+                blocktime: None,
+            })
+        })
+    }
+
+    fn handle(&self, cmd: &str, args: &[Value]) -> Result<Value, BtcRpcError> {
+        let not_found = || BtcRpcError::ReturnedError(format!("{cmd} {args:?}: not found"));
+        let block_height = |arg: &Value| -> Result<usize, BtcRpcError> {
+            let hash: BlockHash = serde_json::from_value(arg.clone())?;
+            self.block_height(&hash).ok_or_else(not_found)
+        };
+
+        let result = match cmd {
+            "getbestblockhash" => {
+                serde_json::to_value(self.blocks.borrow().last().unwrap().block_hash())?
+            }
+            "getblockcount" => serde_json::to_value(self.tip_height())?,
+            "getblockhash" => {
+                let height = args[0].as_u64().ok_or_else(not_found)? as usize;
+                let hash = self
+                    .blocks
+                    .borrow()
+                    .get(height)
+                    .ok_or_else(not_found)?
+                    .block_hash();
+                serde_json::to_value(hash)?
+            }
+            "getblock" => {
+                let height = block_height(&args[0])?;
+                match args[1].as_u64() {
+                    Some(0) => Value::from(serialize_hex(&self.blocks.borrow()[height])),
+                    _ => serde_json::to_value(self.block_result(height))?,
+                }
+            }
+            "getblockheader" => {
+                let height = block_height(&args[0])?;
+                match args[1].as_bool() {
+                    Some(false) => Value::from(serialize_hex(&self.blocks.borrow()[height].header)),
+                    _ => serde_json::to_value(self.block_header_result(height))?,
+                }
+            }
+            "getchaintips" => {
+                let tip_height = self.tip_height();
+                serde_json::to_value(vec![GetChainTipsResultTip {
+                    height: tip_height as u64,
+                    hash: self.blocks.borrow()[tip_height as usize].block_hash(),
+                    branch_length: 0,
+                    status: GetChainTipsResultStatus::Active,
+                }])?
+            }
+            "getrawtransaction" => {
+                let txid = serde_json::from_value(args[0].clone())?;
+                // Bitcoin Core answers unknown transactions with RPC_INVALID_ADDRESS_OR_KEY
+                let result = self.raw_transaction_result(&txid).ok_or_else(|| {
+                    BtcRpcError::JsonRpc(JsonRpcError::Rpc(RpcError {
+                        code: -5,
+                        message: "No such mempool or blockchain transaction".into(),
+                        data: None,
+                    }))
+                })?;
+                serde_json::to_value(result)?
+            }
+            _ => {
+                return Err(BtcRpcError::ReturnedError(format!(
+                    "{cmd} is not supported by the mock"
+                )))
+            }
+        };
+        Ok(result)
+    }
+}
+
+impl RpcApi for MockBitcoind {
+    fn call<T: DeserializeOwned>(&self, cmd: &str, args: &[Value]) -> Result<T, BtcRpcError> {
+        Ok(serde_json::from_value(self.handle(cmd, args)?)?)
+    }
+}
+
+/// Endpoint of the mock node, failing every request like an unreachable node while it's down
+pub struct NodeEndpoint<'a> {
+    pub node: &'a MockBitcoind,
+    pub up: bool,
+}
+
+impl RpcApi for NodeEndpoint<'_> {
+    fn call<T: DeserializeOwned>(&self, cmd: &str, args: &[Value]) -> Result<T, BtcRpcError> {
+        if !self.up {
+            return Err(BtcRpcError::JsonRpc(JsonRpcError::Transport(
+                "connection refused".into(),
+            )));
+        }
+        self.node.call(cmd, args)
+    }
+}
+
+/// Bitcoin transaction paying `amount` sats to the bridge deposit address of the Yona account
+pub fn deposit_transaction(yona_address: Pubkey, amount: u64) -> Transaction {
+    let script_pubkey = bridge_deposit_script_pubkey(yona_address.to_bytes(), group_key())
+        .expect("group key to be a valid x-only key");
+    transaction_paying(script_pubkey, amount)
+}
+
+/// Bitcoin transaction paying `amount` sats to the legacy P2WSH deposit address of the Yona account
+pub fn legacy_deposit_transaction(yona_address: Pubkey, amount: u64) -> Transaction {
+    transaction_paying(
+        legacy_deposit_script_pubkey(yona_address.to_bytes()),
+        amount,
+    )
+}
+
+fn transaction_paying(script_pubkey: ScriptBuf, amount: u64) -> Transaction {
+    // The mock doesn't track UTXOs, so the spent output is made up
+    Transaction {
+        version: TxVersion::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: bitcoin::Txid::from_byte_array([1; 32]),
+                vout: 0,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(amount),
+            script_pubkey,
+        }],
+    }
+}
@@ -0,0 +1,944 @@
+// End to end relayer tests against the in-process environment, the mock node mines regtest blocks
+// which only pass the relay program's checks when it's built for regtest
+#![cfg(feature = "bitcoin_regtest")]
+
+mod common;
+
+use anchor_client::anchor_lang::AccountDeserialize;
+use anchor_client::solana_sdk::native_token::LAMPORTS_PER_SOL;
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use bitcoin::hashes::Hash;
+use bitcoin::Txid;
+use block_relayer_lib::api_error::{ApiError, ApiErrorCode};
+use block_relayer_lib::deposit_status::{deposits_of_address, deposits_of_tx, DepositState};
+use block_relayer_lib::deposit_watcher::{watch_deposits, DepositAddressPolicy};
+use block_relayer_lib::fees::FeePolicy;
+use block_relayer_lib::health::{check_health, HealthStatus, HealthThresholds};
+use block_relayer_lib::metrics::METRICS;
+use block_relayer_lib::relay_loop::{BlockRelayer, Failover, RelayError};
+use block_relayer_lib::shutdown::Shutdown;
+use block_relayer_lib::store::{RelayRequestPolicy, RelayerStore, TxKind, TxStatus};
+use block_relayer_lib::yona::YonaClient;
+use block_relayer_lib::{
+    init_deposit, init_deposit_fees, init_fee_pool, init_program_at_tip, relay_next_block,
+    relay_tx, RelayTxError,
+};
+use btc_relay::state::FeePool;
+use common::{
+    deposit_transaction, legacy_deposit_transaction, InProcessYona, MockBitcoind, NodeEndpoint,
+    UnreachableYona,
+};
+use std::fs;
+
+// The relay can only be initialized once the node has the 10 blocks preceding the tip
+const INIT_HEIGHT: u32 = 10;
+
+fn main_state_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"state"], &btc_relay::ID).0
+}
+
+fn initialized_relay_without_bridge_key() -> (InProcessYona, MockBitcoind) {
+    let yona = InProcessYona::start();
+    let bitcoind = MockBitcoind::new();
+    bitcoind.mine_blocks(INIT_HEIGHT);
+
+    init_program_at_tip(&yona, &bitcoind).expect("relay program to be initialized");
+    let main_state = yona.main_state().unwrap();
+    assert_eq!(main_state.block_height, INIT_HEIGHT);
+    (yona, bitcoind)
+}
+
+// Deposits are minted without fees until the relay program's deposit fees are initialized
+fn initialized_relay() -> (InProcessYona, MockBitcoind) {
+    let (yona, bitcoind) = initialized_relay_without_bridge_key();
+    yona.register_bridge_key()
+        .expect("bridge key to be registered");
+    (yona, bitcoind)
+}
+
+fn initialized_relay_with_deposit_fees(
+    treasury: Pubkey,
+    fixed_fee: u64,
+    fee_bps: u16,
+    min_deposit: u64,
+    pool_share_bps: u16,
+) -> (InProcessYona, MockBitcoind) {
+    let (yona, bitcoind) = initialized_relay();
+    init_deposit_fees(
+        &yona,
+        treasury,
+        fixed_fee,
+        fee_bps,
+        min_deposit,
+        0,
+        pool_share_bps,
+    )
+    .expect("deposit fees to be initialized");
+    (yona, bitcoind)
+}
+
+#[test]
+fn relays_blocks_as_they_are_mined() {
+    let (yona, bitcoind) = initialized_relay();
+    let store = RelayerStore::in_memory().unwrap();
+    assert_eq!(relay_next_block(&yona, &bitcoind, &store).unwrap(), None);
+
+    bitcoind.mine_blocks(3);
+    for height in INIT_HEIGHT + 1..=INIT_HEIGHT + 3 {
+        assert_eq!(
+            relay_next_block(&yona, &bitcoind, &store).unwrap(),
+            Some(height)
+        );
+    }
+    assert_eq!(relay_next_block(&yona, &bitcoind, &store).unwrap(), None);
+
+    // Past the 10 blocks the relay was initialized with, the committed headers only depend on
+    // the submitted ones
+    bitcoind.mine_blocks(10);
+    while relay_next_block(&yona, &bitcoind, &store)
+        .unwrap()
+        .is_some()
+    {}
+
+    let main_state = yona.main_state().unwrap();
+    assert_eq!(main_state.block_height, bitcoind.tip_height());
+    assert!(store
+        .pending_transactions(TxKind::Header)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn mints_relayed_deposits() {
+    let (yona, bitcoind) = initialized_relay();
+    let store = RelayerStore::in_memory().unwrap();
+    init_deposit(&yona, LAMPORTS_PER_SOL).unwrap();
+
+    let receiver = Pubkey::new_unique();
+    let deposit = deposit_transaction(receiver, 200_000);
+    bitcoind.mine_block(vec![deposit.clone()]);
+    bitcoind.mine_blocks(1);
+
+    while relay_next_block(&yona, &bitcoind, &store)
+        .unwrap()
+        .is_some()
+    {}
+
+    let signature = relay_tx(
+        &yona,
+        &store,
+        main_state_address(),
+        &bitcoind,
+        deposit.compute_txid(),
+        receiver,
+        1,
+    )
+    .expect("deposit to be relayed");
+
+    // 10 lamports are minted per deposited satoshi
+    assert_eq!(yona.balance(&receiver), 2_000_000);
+
+    // Relaying the deposit again returns the first verification instead of minting twice
+    let second_signature = relay_tx(
+        &yona,
+        &store,
+        main_state_address(),
+        &bitcoind,
+        deposit.compute_txid(),
+        receiver,
+        1,
+    )
+    .unwrap();
+    assert_eq!(second_signature, signature);
+    assert_eq!(yona.balance(&receiver), 2_000_000);
+
+    // Another relayer can't mint it again either, the relay program keeps a claim of the deposit
+    let error = relay_tx(
+        &yona,
+        &RelayerStore::in_memory().unwrap(),
+        main_state_address(),
+        &bitcoind,
+        deposit.compute_txid(),
+        receiver,
+        1,
+    )
+    .unwrap_err();
+    assert_eq!(ApiError::from(error).code, ApiErrorCode::AlreadyClaimed);
+    assert_eq!(yona.balance(&receiver), 2_000_000);
+
+    // Another receiver can't claim the deposit
+    let other_receiver = Pubkey::new_unique();
+    assert!(relay_tx(
+        &yona,
+        &store,
+        main_state_address(),
+        &bitcoind,
+        deposit.compute_txid(),
+        other_receiver,
+        1,
+    )
+    .is_err());
+    assert_eq!(yona.balance(&other_receiver), 0);
+}
+
+#[test]
+fn mints_legacy_deposits_until_they_are_ended() {
+    let (yona, bitcoind) = initialized_relay_without_bridge_key();
+    let store = RelayerStore::in_memory().unwrap();
+    init_deposit(&yona, LAMPORTS_PER_SOL).unwrap();
+
+    let receiver = Pubkey::new_unique();
+    let before_bridge_key = legacy_deposit_transaction(receiver, 100_000);
+    bitcoind.mine_block(vec![before_bridge_key.clone()]);
+    let after_bridge_key = legacy_deposit_transaction(receiver, 20_000);
+    bitcoind.mine_block(vec![after_bridge_key.clone()]);
+    let after_cutover = legacy_deposit_transaction(receiver, 3_000);
+    let taproot = deposit_transaction(receiver, 4_000);
+    bitcoind.mine_block(vec![after_cutover.clone(), taproot.clone()]);
+    bitcoind.mine_blocks(1);
+
+    while relay_next_block(&yona, &bitcoind, &store)
+        .unwrap()
+        .is_some()
+    {}
+    let relay = |deposit: &bitcoin::Transaction| {
+        relay_tx(
+            &yona,
+            &store,
+            main_state_address(),
+            &bitcoind,
+            deposit.compute_txid(),
+            receiver,
+            1,
+        )
+    };
+
+    // Legacy deposits are minted before the bridge key is registered, and after until they're ended
+    relay(&before_bridge_key).expect("legacy deposit to be minted without a bridge key");
+    assert_eq!(yona.balance(&receiver), 1_000_000);
+    yona.register_bridge_key()
+        .expect("bridge key to be registered");
+    relay(&after_bridge_key).expect("legacy deposit to be minted with a bridge key");
+    assert_eq!(yona.balance(&receiver), 1_200_000);
+
+    yona.end_legacy_deposits()
+        .expect("legacy deposits to be ended");
+    let error = relay(&after_cutover).unwrap_err();
+    assert_eq!(ApiError::from(error).code, ApiErrorCode::NoDepositOutputs);
+    relay(&taproot).expect("taproot deposit to be minted");
+    assert_eq!(yona.balance(&receiver), 1_240_000);
+}
+
+#[test]
+fn deducts_deposit_fees_for_the_treasury() {
+    let treasury = Pubkey::new_unique();
+    let (yona, bitcoind) = initialized_relay_with_deposit_fees(treasury, 100_000, 50, 200_000, 0);
+    let store = RelayerStore::in_memory().unwrap();
+    init_deposit(&yona, LAMPORTS_PER_SOL).unwrap();
+
+    let receiver = Pubkey::new_unique();
+    let deposit = deposit_transaction(receiver, 1_000_000);
+    let small_receiver = Pubkey::new_unique();
+    let small_deposit = deposit_transaction(small_receiver, 150_000);
+    bitcoind.mine_block(vec![deposit.clone(), small_deposit.clone()]);
+    while relay_next_block(&yona, &bitcoind, &store)
+        .unwrap()
+        .is_some()
+    {}
+
+    let relay = |txid, receiver| {
+        relay_tx(
+            &yona,
+            &store,
+            main_state_address(),
+            &bitcoind,
+            txid,
+            receiver,
+            1,
+        )
+    };
+
+    // 100k satoshis plus 0.5% of the deposit go to the treasury, at 10 lamports per satoshi
+    relay(deposit.compute_txid(), receiver).expect("deposit to be relayed");
+    assert_eq!(yona.balance(&receiver), (1_000_000 - 105_000) * 10);
+    assert_eq!(yona.balance(&treasury), 105_000 * 10);
+
+    // Deposits below the minimum aren't minted
+    let error = ApiError::from(relay(small_deposit.compute_txid(), small_receiver).unwrap_err());
+    assert_eq!(error.code, ApiErrorCode::DepositTooSmall);
+    assert_eq!(yona.balance(&small_receiver), 0);
+    assert_eq!(yona.balance(&treasury), 105_000 * 10);
+}
+
+#[test]
+fn pays_the_fee_pool_its_share_of_deposit_fees() {
+    let treasury = Pubkey::new_unique();
+    let (yona, bitcoind) = initialized_relay_with_deposit_fees(treasury, 200_000, 0, 0, 2_000);
+    let store = RelayerStore::in_memory().unwrap();
+    init_deposit(&yona, LAMPORTS_PER_SOL).unwrap();
+    init_fee_pool(&yona, 0, 0).unwrap();
+    let (fee_pool, _) = Pubkey::find_program_address(&[b"fee_pool"], &btc_relay::ID);
+    let pool_balance = yona.balance(&fee_pool);
+
+    let receiver = Pubkey::new_unique();
+    let deposit = deposit_transaction(receiver, 1_000_000);
+    bitcoind.mine_block(vec![deposit.clone()]);
+    while relay_next_block(&yona, &bitcoind, &store)
+        .unwrap()
+        .is_some()
+    {}
+    relay_tx(
+        &yona,
+        &store,
+        main_state_address(),
+        &bitcoind,
+        deposit.compute_txid(),
+        receiver,
+        1,
+    )
+    .expect("deposit to be relayed");
+
+    // 20% of the fee goes to the fee pool, at 10 lamports per satoshi
+    assert_eq!(yona.balance(&receiver), (1_000_000 - 200_000) * 10);
+    assert_eq!(yona.balance(&treasury), 160_000 * 10);
+    assert_eq!(yona.balance(&fee_pool), pool_balance + 40_000 * 10);
+}
+
+#[test]
+fn pays_priority_fees_within_the_hourly_budget() {
+    let (yona, bitcoind) = initialized_relay();
+    init_deposit(&yona, LAMPORTS_PER_SOL).unwrap();
+    let store = RelayerStore::in_memory()
+        .unwrap()
+        .with_fee_policy(FeePolicy {
+            compute_unit_limit: Some(400_000),
+            hourly_fee_budget: Some(1),
+            ..FeePolicy::default()
+        });
+    yona.set_recent_prioritization_fee(10_000);
+
+    let receiver = Pubkey::new_unique();
+    let deposit = deposit_transaction(receiver, 200_000);
+    bitcoind.mine_block(vec![deposit.clone()]);
+
+    // Headers are sent past the budget, paying 400k compute units at 10k micro-lamports on top of
+    // the signature fee
+    assert_eq!(
+        relay_next_block(&yona, &bitcoind, &store).unwrap(),
+        Some(INIT_HEIGHT + 1)
+    );
+    assert_eq!(store.hourly_fees().unwrap(), 5_000 + 4_000);
+
+    // Deposit verifications wait for the budget instead
+    let result = relay_tx(
+        &yona,
+        &store,
+        main_state_address(),
+        &bitcoind,
+        deposit.compute_txid(),
+        receiver,
+        1,
+    );
+    assert!(matches!(
+        result,
+        Err(RelayTxError::FeeBudgetExhausted {
+            spent: 9_000,
+            budget: 1
+        })
+    ));
+    assert_eq!(yona.balance(&receiver), 0);
+}
+
+#[test]
+fn rewards_header_submissions_from_the_fee_pool() {
+    let (yona, bitcoind) = initialized_relay();
+    // Only the relay program's upgrade authority can create the pool
+    yona.set_upgrade_authority(Some(Pubkey::new_unique()));
+    assert!(init_fee_pool(&yona, 1_000, LAMPORTS_PER_SOL).is_err());
+    yona.set_upgrade_authority(Some(yona.payer()));
+    init_fee_pool(&yona, 1_000, LAMPORTS_PER_SOL).unwrap();
+    let store = RelayerStore::in_memory().unwrap();
+    let balance = yona.balance(&yona.payer());
+
+    bitcoind.mine_blocks(2);
+    while relay_next_block(&yona, &bitcoind, &store)
+        .unwrap()
+        .is_some()
+    {}
+
+    // Each header earns the reward on top of covering its fee
+    assert_eq!(store.hourly_fees().unwrap(), 2 * 5_000);
+    assert_eq!(yona.balance(&yona.payer()), balance - 2 * 5_000 + 2 * 1_000);
+
+    let (fee_pool, _) = Pubkey::find_program_address(&[b"fee_pool"], &btc_relay::ID);
+    let fee_pool =
+        FeePool::try_deserialize(&mut &yona.get_account(&fee_pool).unwrap().data[..]).unwrap();
+    assert_eq!(fee_pool.total_rewards, 2_000);
+}
+
+#[test]
+fn competing_relayer_extending_the_tip_first() {
+    let (yona, bitcoind) = initialized_relay();
+    let store = RelayerStore::in_memory().unwrap();
+    let competitor_store = RelayerStore::in_memory().unwrap();
+    let block_hash = bitcoind.mine_block(vec![]);
+
+    // The header is signed and stored, but another relayer submits it first
+    assert!(relay_next_block(&UnreachableYona(&yona), &bitcoind, &store).is_err());
+    assert_eq!(
+        relay_next_block(&yona, &bitcoind, &competitor_store).unwrap(),
+        Some(INIT_HEIGHT + 1)
+    );
+
+    // The stored submission fails instead of extending the tip twice, and the relayer follows the
+    // competitor's tip
+    assert_eq!(relay_next_block(&yona, &bitcoind, &store).unwrap(), None);
+    let header = store
+        .transactions(TxKind::Header, &block_hash.to_string())
+        .unwrap();
+    assert_eq!(header.len(), 1);
+    assert_eq!(header[0].status, TxStatus::Failed);
+    assert!(header[0]
+        .error
+        .as_deref()
+        .unwrap()
+        .starts_with("TipAlreadyExtended"));
+
+    bitcoind.mine_blocks(1);
+    assert_eq!(
+        relay_next_block(&yona, &bitcoind, &store).unwrap(),
+        Some(INIT_HEIGHT + 2)
+    );
+}
+
+#[test]
+fn exports_relay_metrics() {
+    let (yona, bitcoind) = initialized_relay();
+    let store = RelayerStore::in_memory().unwrap();
+    init_deposit(&yona, LAMPORTS_PER_SOL).unwrap();
+
+    // Other tests run in the same process, so only the increments are checked
+    let header_fees = METRICS.fees_lamports.with_label_values(&["header"]);
+    let fees_before = header_fees.get();
+    let submissions_before = METRICS.header_submission_seconds.get_sample_count();
+    let relayed_before = METRICS.deposits_relayed.get();
+    let no_outputs = METRICS
+        .deposits_failed
+        .with_label_values(&["NoDepositOutputs"]);
+    let no_outputs_before = no_outputs.get();
+
+    let receiver = Pubkey::new_unique();
+    let deposit = deposit_transaction(receiver, 100_000);
+    bitcoind.mine_block(vec![deposit.clone()]);
+    while relay_next_block(&yona, &bitcoind, &store)
+        .unwrap()
+        .is_some()
+    {}
+
+    assert!(METRICS.header_submission_seconds.get_sample_count() > submissions_before);
+    assert!(header_fees.get() > fees_before);
+
+    relay_tx(
+        &yona,
+        &store,
+        main_state_address(),
+        &bitcoind,
+        deposit.compute_txid(),
+        receiver,
+        1,
+    )
+    .unwrap();
+    assert!(METRICS.deposits_relayed.get() > relayed_before);
+
+    relay_tx(
+        &yona,
+        &store,
+        main_state_address(),
+        &bitcoind,
+        deposit.compute_txid(),
+        Pubkey::new_unique(),
+        1,
+    )
+    .unwrap_err();
+    assert!(no_outputs.get() > no_outputs_before);
+
+    let rendered = METRICS.render();
+    for metric in [
+        "relayer_relay_tip_height",
+        "relayer_bitcoind_tip_height",
+        "relayer_header_submission_seconds_bucket",
+        "relayer_fees_lamports_total{kind=\"relay_tx\"}",
+        "relayer_deposits_relayed_total",
+        "relayer_deposits_failed_total{error=\"NoDepositOutputs\"}",
+    ] {
+        assert!(
+            rendered.contains(metric),
+            "{metric} missing from {rendered}"
+        );
+    }
+}
+
+#[test]
+fn reports_degraded_health_past_thresholds() {
+    let (yona, bitcoind) = initialized_relay();
+    let store = RelayerStore::in_memory().unwrap();
+    let balance = yona.balance(&yona.payer());
+    let thresholds = HealthThresholds {
+        min_signer_balance: balance,
+        max_header_lag: 1,
+    };
+
+    let report = check_health(&yona, &bitcoind, &thresholds);
+    assert_eq!(report.status, HealthStatus::Ok, "{report:?}");
+    assert!(report.is_ok());
+
+    bitcoind.mine_blocks(2);
+    let report = check_health(&yona, &bitcoind, &thresholds);
+    assert_eq!(report.status, HealthStatus::Degraded);
+    let failed: Vec<_> = report.checks.iter().filter(|check| !check.ok).collect();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].name, "header_lag");
+    assert_eq!(failed[0].detail, "2 blocks, above the limit of 1");
+
+    relay_next_block(&yona, &bitcoind, &store).unwrap();
+    let report = check_health(&yona, &bitcoind, &thresholds);
+    // The header submission's fee took the balance below the floor
+    let failed: Vec<_> = report.checks.iter().filter(|check| !check.ok).collect();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].name, "signer_balance");
+
+    let body = serde_json::to_value(&report).unwrap();
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["checks"][3]["name"], "header_lag");
+    assert_eq!(body["checks"][3]["ok"], true);
+}
+
+#[test]
+fn block_relayer_fails_over_from_unreachable_endpoints() {
+    let (yona, bitcoind) = initialized_relay();
+    bitcoind.mine_blocks(2);
+
+    let mut unreachable_yona = BlockRelayer::new(
+        Failover::new(vec![("unreachable".into(), UnreachableYona(&yona))]),
+        Failover::new(vec![(
+            "up".into(),
+            NodeEndpoint {
+                node: &bitcoind,
+                up: true,
+            },
+        )]),
+        RelayerStore::in_memory().unwrap(),
+    );
+    assert!(matches!(
+        unreachable_yona.relay_once(),
+        Err(RelayError::YonaUnreachable(_))
+    ));
+
+    let mut relayer = BlockRelayer::new(
+        Failover::new(vec![("in-process".into(), &yona)]),
+        Failover::new(vec![
+            (
+                "down".into(),
+                NodeEndpoint {
+                    node: &bitcoind,
+                    up: false,
+                },
+            ),
+            (
+                "up".into(),
+                NodeEndpoint {
+                    node: &bitcoind,
+                    up: true,
+                },
+            ),
+        ]),
+        RelayerStore::in_memory().unwrap(),
+    );
+    assert!(matches!(
+        relayer.relay_once(),
+        Err(RelayError::BitcoinUnreachable(_))
+    ));
+    assert_eq!(relayer.bitcoind().current_url(), "up");
+    assert_eq!(relayer.relay_once().unwrap(), Some(INIT_HEIGHT + 1));
+
+    // The loop doesn't start another submission once the shutdown is requested
+    let shutdown = Shutdown::default();
+    shutdown.request();
+    relayer.run(&shutdown);
+    assert_eq!(yona.main_state().unwrap().block_height, INIT_HEIGHT + 1);
+}
+
+#[test]
+fn watcher_mints_deposits_to_registered_addresses() {
+    let (yona, bitcoind) = initialized_relay_without_bridge_key();
+    let store = RelayerStore::in_memory().unwrap();
+    init_deposit(&yona, LAMPORTS_PER_SOL).unwrap();
+
+    // No deposit address exists until the signer nodes register their group key
+    assert_eq!(watch_deposits(&yona, &bitcoind, &store, 1).unwrap(), 0);
+    assert_eq!(store.scanned_height().unwrap(), None);
+    yona.register_bridge_key().unwrap();
+
+    let receiver = Pubkey::new_unique();
+    let unregistered = Pubkey::new_unique();
+    store
+        .register_deposit_address(&receiver.to_string())
+        .unwrap();
+
+    bitcoind.mine_block(vec![
+        deposit_transaction(receiver, 300_000),
+        deposit_transaction(unregistered, 100_000),
+    ]);
+    bitcoind.mine_blocks(1);
+
+    // The relay doesn't have the deposit's block yet
+    let confirmations = 2;
+    assert_eq!(
+        watch_deposits(&yona, &bitcoind, &store, confirmations).unwrap(),
+        0
+    );
+    assert_eq!(store.pending_deposits().unwrap().len(), 1);
+
+    // With only the deposit's block the deposit lacks a confirmation
+    relay_next_block(&yona, &bitcoind, &store).unwrap();
+    assert_eq!(
+        watch_deposits(&yona, &bitcoind, &store, confirmations).unwrap(),
+        0
+    );
+    assert_eq!(yona.balance(&receiver), 0);
+
+    relay_next_block(&yona, &bitcoind, &store).unwrap();
+    assert_eq!(
+        watch_deposits(&yona, &bitcoind, &store, confirmations).unwrap(),
+        1
+    );
+    assert_eq!(yona.balance(&receiver), 3_000_000);
+    assert_eq!(yona.balance(&unregistered), 0);
+    assert!(store.pending_deposits().unwrap().is_empty());
+
+    // Rescanned blocks don't bring the minted deposit back
+    bitcoind.mine_blocks(1);
+    assert_eq!(
+        watch_deposits(&yona, &bitcoind, &store, confirmations).unwrap(),
+        0
+    );
+    assert!(store.pending_deposits().unwrap().is_empty());
+}
+
+#[test]
+fn limits_and_expires_deposit_address_registrations() {
+    let store = RelayerStore::in_memory()
+        .unwrap()
+        .with_deposit_address_policy(DepositAddressPolicy {
+            ttl_secs: 3600,
+            max_registrations_per_hour: 2,
+        });
+    let addresses: Vec<String> = (0..3).map(|_| Pubkey::new_unique().to_string()).collect();
+
+    assert!(store.register_deposit_address(&addresses[0]).unwrap());
+    assert!(store.register_deposit_address(&addresses[1]).unwrap());
+    assert!(!store.register_deposit_address(&addresses[2]).unwrap());
+    // Registering an address again only keeps it from expiring
+    assert!(store.register_deposit_address(&addresses[0]).unwrap());
+    let mut registered = store.deposit_addresses().unwrap();
+    registered.sort();
+    let mut expected = addresses[..2].to_vec();
+    expected.sort();
+    assert_eq!(registered, expected);
+
+    // Expired addresses aren't scanned for
+    let expiring = RelayerStore::in_memory()
+        .unwrap()
+        .with_deposit_address_policy(DepositAddressPolicy {
+            ttl_secs: 0,
+            ..DepositAddressPolicy::default()
+        });
+    assert!(expiring.register_deposit_address(&addresses[0]).unwrap());
+    assert!(expiring.deposit_addresses().unwrap().is_empty());
+}
+
+#[test]
+fn limits_and_expires_pending_relay_requests() {
+    let store = RelayerStore::in_memory()
+        .unwrap()
+        .with_relay_request_policy(RelayRequestPolicy {
+            max_pending: 2,
+            expiry_secs: 3600,
+        });
+    let requests: Vec<_> = (0..3u8)
+        .map(|i| {
+            (
+                Txid::from_byte_array([i; 32]),
+                Pubkey::new_unique().to_string(),
+            )
+        })
+        .collect();
+    let pending = |store: &RelayerStore| store.pending_relay_requests().unwrap().len();
+    let full = |store: &RelayerStore, i: usize| {
+        store
+            .relay_requests_full(&requests[i].0, &requests[i].1)
+            .unwrap()
+    };
+
+    assert!(store
+        .insert_relay_request(&requests[0].0, &requests[0].1)
+        .unwrap());
+    assert!(store
+        .insert_relay_request(&requests[1].0, &requests[1].1)
+        .unwrap());
+    assert!(!full(&store, 0));
+    assert!(full(&store, 2));
+    // A known request is kept, and recorded once
+    assert!(!store
+        .insert_relay_request(&requests[0].0, &requests[0].1)
+        .unwrap());
+    assert_eq!(pending(&store), 2);
+
+    // A settled request frees a slot
+    store
+        .set_relay_request_status(&requests[1].0, &requests[1].1, TxStatus::Failed, None, None)
+        .unwrap();
+    assert!(!full(&store, 2));
+    assert!(store
+        .insert_relay_request(&requests[2].0, &requests[2].1)
+        .unwrap());
+    assert_eq!(store.expire_relay_requests().unwrap(), 0);
+    assert_eq!(pending(&store), 2);
+
+    // Requests waiting past the expiry fail, and can be retried
+    let expiring = RelayerStore::in_memory()
+        .unwrap()
+        .with_relay_request_policy(RelayRequestPolicy {
+            expiry_secs: 0,
+            ..RelayRequestPolicy::default()
+        });
+    assert!(expiring
+        .insert_relay_request(&requests[0].0, &requests[0].1)
+        .unwrap());
+    assert_eq!(expiring.expire_relay_requests().unwrap(), 1);
+    assert_eq!(pending(&expiring), 0);
+    assert!(expiring
+        .insert_relay_request(&requests[0].0, &requests[0].1)
+        .unwrap());
+    assert_eq!(pending(&expiring), 1);
+}
+
+#[test]
+fn tracks_deposit_lifecycle() {
+    let (yona, bitcoind) = initialized_relay();
+    let store = RelayerStore::in_memory().unwrap();
+    init_deposit(&yona, LAMPORTS_PER_SOL).unwrap();
+    let confirmations = 2;
+
+    let receiver = Pubkey::new_unique();
+    store
+        .register_deposit_address(&receiver.to_string())
+        .unwrap();
+    let deposit = deposit_transaction(receiver, 150_000);
+    let txid = deposit.compute_txid();
+    let state = |txid| {
+        let mut deposits = deposits_of_tx(&yona, &bitcoind, &store, txid, confirmations).unwrap();
+        assert_eq!(deposits.len(), 1);
+        deposits.remove(0).state
+    };
+
+    assert!(
+        deposits_of_tx(&yona, &bitcoind, &store, txid, confirmations)
+            .unwrap()
+            .is_empty()
+    );
+
+    bitcoind.broadcast(deposit.clone());
+    assert_eq!(state(txid), DepositState::SeenInMempool);
+
+    bitcoind.mine_block(vec![deposit.clone()]);
+    bitcoind.mine_blocks(1);
+    assert_eq!(
+        state(txid),
+        DepositState::Confirming {
+            confirmations: 2,
+            relayed_confirmations: 0,
+            required_confirmations: confirmations,
+        }
+    );
+
+    while relay_next_block(&yona, &bitcoind, &store)
+        .unwrap()
+        .is_some()
+    {}
+    assert_eq!(state(txid), DepositState::HeaderRelayed);
+
+    let signature = relay_tx(
+        &yona,
+        &store,
+        main_state_address(),
+        &bitcoind,
+        txid,
+        receiver,
+        1,
+    )
+    .unwrap();
+    assert_eq!(
+        state(txid),
+        DepositState::Minted {
+            signature: signature.to_string()
+        }
+    );
+
+    // A relay request for a receiver the transaction doesn't pay fails with the program's error
+    let other_receiver = Pubkey::new_unique();
+    assert!(relay_tx(
+        &yona,
+        &store,
+        main_state_address(),
+        &bitcoind,
+        txid,
+        other_receiver,
+        1,
+    )
+    .is_err());
+    let deposits =
+        deposits_of_address(&yona, &bitcoind, &store, &other_receiver, confirmations).unwrap();
+    assert_eq!(deposits.len(), 1);
+    assert_eq!(deposits[0].amount, Some(0));
+    match &deposits[0].state {
+        DepositState::Failed { reason } => {
+            assert!(reason.starts_with("NoDepositOutputs"), "{reason}")
+        }
+        state => panic!("unexpected state {state:?}"),
+    }
+
+    let deposits = deposits_of_address(&yona, &bitcoind, &store, &receiver, confirmations).unwrap();
+    assert_eq!(deposits.len(), 1);
+    assert_eq!(deposits[0].amount, Some(150_000));
+    assert_eq!(deposits[0].block_height, Some(INIT_HEIGHT + 1));
+    assert_eq!(
+        deposits_of_tx(&yona, &bitcoind, &store, txid, confirmations)
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[test]
+fn maps_relay_failures_to_api_error_codes() {
+    let (yona, bitcoind) = initialized_relay();
+    let store = RelayerStore::in_memory().unwrap();
+    init_deposit(&yona, LAMPORTS_PER_SOL).unwrap();
+    let receiver = Pubkey::new_unique();
+    let relay_with_confirmations = |txid, receiver, confirmations| {
+        relay_tx(
+            &yona,
+            &store,
+            main_state_address(),
+            &bitcoind,
+            txid,
+            receiver,
+            confirmations,
+        )
+    };
+    let relay = |txid, receiver| relay_with_confirmations(txid, receiver, 1);
+    let error_code = |result: Result<_, _>| ApiError::from(result.unwrap_err()).code;
+
+    let deposit = deposit_transaction(receiver, 100_000);
+    let txid = deposit.compute_txid();
+    assert_eq!(error_code(relay(txid, receiver)), ApiErrorCode::TxNotFound);
+
+    bitcoind.broadcast(deposit.clone());
+    assert_eq!(
+        error_code(relay(txid, receiver)),
+        ApiErrorCode::TxNotConfirmed
+    );
+
+    // Requests waiting for the block relayer are queued by the server
+    bitcoind.mine_block(vec![deposit]);
+    let error = relay(txid, receiver).unwrap_err();
+    assert!(error.is_waiting());
+    assert_eq!(ApiError::from(error).code, ApiErrorCode::BlockNotRelayedYet);
+
+    relay_next_block(&yona, &bitcoind, &store).unwrap();
+    let error = relay_with_confirmations(txid, receiver, 2).unwrap_err();
+    assert!(error.is_waiting());
+    assert_eq!(
+        ApiError::from(error).code,
+        ApiErrorCode::NotEnoughConfirmations
+    );
+    let error = ApiError::from(relay(txid, Pubkey::new_unique()).unwrap_err());
+    assert_eq!(error.code, ApiErrorCode::NoDepositOutputs);
+    assert_eq!(
+        error.message,
+        "NoDepositOutputs: No outputs sending to expected deposit address!"
+    );
+
+    relay(txid, receiver).unwrap();
+    assert_eq!(yona.balance(&receiver), 1_000_000);
+}
+
+#[test]
+fn resumes_transactions_sent_before_a_restart() {
+    let (yona, bitcoind) = initialized_relay();
+    init_deposit(&yona, LAMPORTS_PER_SOL).unwrap();
+    let store_path =
+        std::env::temp_dir().join(format!("relayer-resume-test-{}.sqlite", std::process::id()));
+    let _ = fs::remove_file(&store_path);
+
+    let receiver = Pubkey::new_unique();
+    let deposit = deposit_transaction(receiver, 100_000);
+    bitcoind.mine_block(vec![deposit.clone()]);
+
+    // The header and the verification are signed and stored, but never reach Yona
+    let pending = {
+        let store = RelayerStore::open(&store_path).unwrap();
+        assert!(relay_next_block(&UnreachableYona(&yona), &bitcoind, &store).is_err());
+        assert_eq!(yona.main_state().unwrap().block_height, INIT_HEIGHT);
+
+        let header = store.pending_transactions(TxKind::Header).unwrap();
+        assert_eq!(header.len(), 1);
+        header[0].signatures[0]
+    };
+
+    // After the restart the stored header transaction is broadcast instead of a new one, and kept
+    // pending while it can't be
+    let store = RelayerStore::open(&store_path).unwrap();
+    store
+        .resume_pending(&UnreachableYona(&yona), TxKind::Header)
+        .unwrap();
+    assert_eq!(store.pending_transactions(TxKind::Header).unwrap().len(), 1);
+    assert_eq!(
+        relay_next_block(&yona, &bitcoind, &store).unwrap(),
+        None,
+        "the resumed header should bring the relay to the tip"
+    );
+    assert_eq!(yona.main_state().unwrap().block_height, INIT_HEIGHT + 1);
+    assert!(yona.signature_status(&pending).unwrap().is_some());
+    assert!(store
+        .pending_transactions(TxKind::Header)
+        .unwrap()
+        .is_empty());
+
+    assert!(relay_tx(
+        &UnreachableYona(&yona),
+        &store,
+        main_state_address(),
+        &bitcoind,
+        deposit.compute_txid(),
+        receiver,
+        1,
+    )
+    .is_err());
+    assert_eq!(yona.balance(&receiver), 0);
+
+    relay_tx(
+        &yona,
+        &store,
+        main_state_address(),
+        &bitcoind,
+        deposit.compute_txid(),
+        receiver,
+        1,
+    )
+    .unwrap();
+    assert_eq!(yona.balance(&receiver), 1_000_000);
+
+    fs::remove_file(&store_path).unwrap();
+}
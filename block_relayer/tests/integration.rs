@@ -1,5 +1,8 @@
 use block_relayer_lib::config::{BitcoinAuth, RelayConfig};
+use block_relayer_lib::deposit_watcher::DepositAddressPolicy;
+use block_relayer_lib::fees::FeePolicy;
 use block_relayer_lib::run_init_program;
+use block_relayer_lib::store::RelayRequestPolicy;
 use bollard::container::RemoveContainerOptions;
 use bollard::Docker;
 use once_cell::sync::Lazy;
@@ -17,6 +20,8 @@ use tokio::runtime::Runtime;
 
 const ESPLORA_CONTAINER: &str = "esplora_for_bridge_tests";
 
+// The container and the localnet run as long as their handles are held
+#[allow(dead_code)]
 struct TestCtx {
     docker: Docker,
     esplora_container: ContainerAsync<GenericImage>,
@@ -37,11 +42,8 @@ static TEST_CTX: Lazy<TestCtx> = Lazy::new(|| {
         link: false,
     };
 
-    if let Err(_) =
-        TEST_RUNTIME.block_on(docker.remove_container(ESPLORA_CONTAINER, Some(rm_options)))
-    {
-        // just do nothing here
-    };
+    // there's only a container to remove if an earlier run left it behind
+    let _ = TEST_RUNTIME.block_on(docker.remove_container(ESPLORA_CONTAINER, Some(rm_options)));
 
     let current_dir = std::env::current_dir().unwrap();
 
@@ -97,6 +99,7 @@ static TEST_CTX: Lazy<TestCtx> = Lazy::new(|| {
 });
 
 #[test]
+#[ignore = "requires Docker and anchor localnet, run with --ignored"]
 fn init_program() {
     let bitcoind_url = match env::var("GITHUB_ACTIONS") {
         Ok(_) => "http://172.17.0.1:18443".into(),
@@ -105,17 +108,28 @@ fn init_program() {
 
     let relay_config = RelayConfig {
         bitcoind_url,
+        bitcoind_fallback_urls: vec![],
         bitcoin_auth: BitcoinAuth::UserPass {
             user: "test".into(),
             password: "test".into(),
         },
+        bitcoind_zmq_url: None,
         yona_http: "http://127.0.0.1:8899".into(),
         yona_ws: "ws://127.0.0.1:8900/".into(),
+        yona_fallbacks: vec![],
         yona_keipair: TEST_CTX
             .current_dir
             .join("../anchor.json")
             .display()
             .to_string(),
+        store_path: "relayer.sqlite".into(),
+        deposit_confirmations: 1,
+        deposit_addresses: DepositAddressPolicy::default(),
+        relay_requests: RelayRequestPolicy::default(),
+        metrics_address: "127.0.0.1:9184".into(),
+        min_signer_balance: 0,
+        max_header_lag: 3,
+        fees: FeePolicy::default(),
     };
 
     let init_result = run_init_program(relay_config).expect("run_init_program");
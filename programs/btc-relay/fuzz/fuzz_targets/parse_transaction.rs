@@ -0,0 +1,46 @@
+#![no_main]
+
+// Differential fuzzing of the on-chain transaction parser against the bitcoin crate's decoder,
+// run with: cargo +nightly fuzz run parse_transaction
+use bitcoin::consensus::deserialize;
+use bitcoin::hashes::Hash;
+use bitcoin::Transaction;
+use btc_relay::txutils::ParsedTransaction;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let expected: Result<Transaction, _> = deserialize(data);
+    let parsed = ParsedTransaction::parse(data);
+
+    let (tx, parsed) = match (expected, parsed) {
+        (Ok(tx), Ok(parsed)) => (tx, parsed),
+        (Err(_), Err(_)) => return,
+        (expected, parsed) => panic!(
+            "decoders disagree, bitcoin: {:?}, parsed: {:?}",
+            expected.map(|tx| tx.compute_txid()),
+            parsed.map(|tx| tx.compute_txid())
+        ),
+    };
+
+    assert_eq!(parsed.version, tx.version.0);
+    assert_eq!(parsed.locktime, tx.lock_time.to_consensus_u32());
+    assert_eq!(parsed.compute_txid(), tx.compute_txid().to_byte_array());
+    assert_eq!(parsed.compute_wtxid(), tx.compute_wtxid().to_byte_array());
+
+    assert_eq!(parsed.inputs().count(), tx.input.len());
+    for (parsed, expected) in parsed.inputs().zip(tx.input.iter()) {
+        assert_eq!(
+            parsed.reversed_prev_txid,
+            expected.previous_output.txid.as_byte_array()
+        );
+        assert_eq!(parsed.prev_vout, expected.previous_output.vout);
+        assert_eq!(parsed.script_sig, expected.script_sig.as_bytes());
+        assert_eq!(parsed.sequence, expected.sequence.0);
+    }
+
+    assert_eq!(parsed.outputs().count(), tx.output.len());
+    for (parsed, expected) in parsed.outputs().zip(tx.output.iter()) {
+        assert_eq!(parsed.value, expected.value.to_sat());
+        assert_eq!(parsed.script_pubkey, expected.script_pubkey.as_bytes());
+    }
+});
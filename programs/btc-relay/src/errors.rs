@@ -34,4 +34,40 @@ pub enum RelayErrorCode {
     InvalidRemainingAccounts,
     #[msg("No outputs sending to expected deposit address!")]
     NoDepositOutputs,
+    #[msg("Invalid coinbase transaction")]
+    InvalidCoinbase,
+    #[msg("Coinbase transaction doesn't contain witness commitment")]
+    NoWitnessCommitment,
+    #[msg("Invalid witness commitment")]
+    WitnessCommitment,
+    #[msg("Transaction can't be parsed")]
+    InvalidTransaction,
+    #[msg("Transaction id doesn't match")]
+    InvalidTxId,
+    #[msg("Chain work overflow")]
+    ChainWorkOverflow,
+    #[msg("nBits encode negative, overflowing, zero or too large target")]
+    InvalidNbits,
+    #[msg("Tip was already extended by another submission")]
+    TipAlreadyExtended,
+    #[msg("Deposit is below the minimum or doesn't cover the deposit fee")]
+    DepositTooSmall,
+    #[msg("Deposit fee can't exceed 1000 and fee pool share 10000 basis points")]
+    InvalidDepositFees,
+    #[msg("Withdrawal has to pay a standard script pubkey at least its dust limit")]
+    InvalidWithdrawal,
+    #[msg("Bridge key isn't a valid x-only public key")]
+    InvalidBridgeKey,
+    #[msg("Signer isn't the program's upgrade authority")]
+    Unauthorized,
+    #[msg("Fee pool account is required to receive its share of the fees")]
+    MissingFeePool,
+    #[msg("Treasury account doesn't match the deposit fees")]
+    InvalidTreasury,
+    #[msg("Deposit was already minted to the receiver")]
+    DepositAlreadyClaimed,
+    #[msg("Amount overflows when converted to lamports")]
+    AmountOverflow,
+    #[msg("Taproot tweak isn't a valid scalar or tweaks the key to infinity")]
+    InvalidTaprootTweak,
 }
@@ -23,3 +23,46 @@ pub struct ChainReorg {
     pub tip_block_hash: [u8; 32],
     pub tip_commit_hash: [u8; 32],
 }
+
+#[event]
+pub struct RelayerRewarded {
+    pub relayer: Pubkey,
+    pub headers: u32,
+    pub amount: u64,
+}
+
+#[event]
+pub struct DepositMinted {
+    pub reversed_txid: [u8; 32],
+    pub receiver: Pubkey,
+    pub amount: u64,   // Deposited satoshis
+    pub fee: u64,      // Satoshis of the deposit paid as fees
+    pub pool_fee: u64, // Satoshis of the fee paid into the fee pool, the rest goes to the treasury
+    pub minted: u64,   // Lamports minted to the receiver
+    pub treasury: Pubkey,
+    pub group_key: [u8; 32], // Bridge key the receiver's deposit script is derived from, zero before it's initialized
+    pub outputs: Vec<DepositOutput>, // Outputs paying the receiver's deposit script
+    pub legacy_outputs: Vec<DepositOutput>, // Outputs paying the receiver's legacy P2WSH deposit script
+}
+
+#[event]
+pub struct BridgeKeyRegistered {
+    pub group_key: [u8; 32],
+    pub authority: Pubkey,
+}
+
+#[event]
+pub struct WithdrawalRequested {
+    pub requester: Pubkey,
+    pub amount: u64, // Satoshis to pay out, including the Bitcoin transaction fee
+    pub script_pubkey: Vec<u8>,
+    pub fee: u64, // Satoshis paid on top of the amount as the withdrawal fee
+}
+
+#[event]
+pub struct TxVerified {
+    pub reversed_txid: [u8; 32],
+    pub block_height: u32,
+    pub confirmations: u32,
+    pub outputs_hash: [u8; 32],
+}
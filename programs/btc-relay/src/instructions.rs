@@ -1,5 +1,7 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::bpf_loader_upgradeable;
 
+use crate::errors::RelayErrorCode;
 use crate::state::*;
 use crate::structs::*;
 
@@ -22,7 +24,7 @@ pub struct Initialize<'info> {
 
     /// CHECK: This is only used for indexing purposes
     #[account(
-        seeds = [b"header".as_ref(), data.get_block_hash()?.as_ref()],
+        seeds = [b"header".as_ref(), data.get_block_hash().as_ref()],
         bump
     )]
     pub header_topic: AccountInfo<'info>,
@@ -41,6 +43,68 @@ pub struct SubmitBlockHeaders<'info> {
         bump
     )]
     pub main_state: AccountLoader<'info, MainState>,
+
+    /// Pays the signer for the headers, if initialized
+    #[account(
+        mut,
+        seeds = [b"fee_pool".as_ref()],
+        bump
+    )]
+    pub fee_pool: Option<Account<'info, FeePool>>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeFeePool<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        init,
+        seeds = [b"fee_pool".as_ref()],
+        bump,
+        payer = signer,
+        space = FeePool::space()
+    )]
+    pub fee_pool: Account<'info, FeePool>,
+
+    /// Only the program's upgrade authority can create the fee pool
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(signer.key()) @ RelayErrorCode::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetHeaderReward<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"fee_pool".as_ref()],
+        bump,
+        has_one = authority
+    )]
+    pub fee_pool: Account<'info, FeePool>,
+}
+
+#[derive(Accounts)]
+pub struct FundFeePool<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"fee_pool".as_ref()],
+        bump
+    )]
+    pub fee_pool: Account<'info, FeePool>,
+
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
@@ -115,6 +179,136 @@ pub struct VerifyTransaction<'info> {
     pub deposit_account: AccountLoader<'info, DepositState>,
     #[account(mut)]
     pub mint_receiver: SystemAccount<'info>,
+    /// CHECK: Group key of the signer nodes the deposit scripts are derived from, only legacy
+    /// deposits are minted while it isn't initialized
+    #[account(seeds = [b"bridge_key".as_ref()], bump)]
+    pub bridge_key: UncheckedAccount<'info>,
+    /// CHECK: The deposit fees, deposits are minted without fees while they aren't initialized
+    #[account(seeds = [b"deposit_fees".as_ref()], bump)]
+    pub deposit_fees: UncheckedAccount<'info>,
+    /// CHECK: Only receives the deposit fee, checked against the treasury of the deposit fees
+    /// when the treasury's share of the fee isn't zero
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+    /// Receives the fee pool's share of the fees, required when the share isn't zero
+    #[account(mut, seeds = [b"fee_pool".as_ref()], bump)]
+    pub fee_pool: Option<Account<'info, FeePool>>,
+    /// CHECK: Created when the deposit is minted, so it can't be minted to the receiver again. Its
+    /// seeds `["deposit_claim", txid, receiver]` are checked against the txid of the transaction
+    #[account(mut)]
+    pub deposit_claim: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeDepositFees<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        init,
+        seeds = [b"deposit_fees".as_ref()],
+        bump,
+        payer = signer,
+        space = DepositFees::space()
+    )]
+    pub deposit_fees: Account<'info, DepositFees>,
+
+    /// Only the program's upgrade authority can create the deposit fees
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(signer.key()) @ RelayErrorCode::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetDepositFees<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"deposit_fees".as_ref()],
+        bump,
+        has_one = authority
+    )]
+    pub deposit_fees: Account<'info, DepositFees>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeBridgeKey<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        init,
+        seeds = [b"bridge_key".as_ref()],
+        bump,
+        payer = signer,
+        space = BridgeKey::space()
+    )]
+    pub bridge_key: Account<'info, BridgeKey>,
+
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(signer.key()) @ RelayErrorCode::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetBridgeKey<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"bridge_key".as_ref()],
+        bump,
+        has_one = authority
+    )]
+    pub bridge_key: Account<'info, BridgeKey>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    reversed_txid: [u8; 32]
+)]
+pub struct VerifyTransactionReceipt<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [b"state".as_ref()],
+        bump
+    )]
+    pub main_state: AccountLoader<'info, MainState>,
+    /// The receipt account storing the verification result. This should be a derived PDA (Program Derived Address).
+    #[account(
+        init_if_needed,
+        seeds = [b"receipt".as_ref(), reversed_txid.as_ref()],
+        bump,
+        payer = signer,
+        space = TxVerificationReceipt::space()
+    )]
+    pub receipt: Account<'info, TxVerificationReceipt>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyWitness<'info> {
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [b"state".as_ref()],
+        bump
+    )]
+    pub main_state: AccountLoader<'info, MainState>,
 }
 
 #[derive(Accounts)]
@@ -139,6 +333,26 @@ pub struct Deposit<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct RequestWithdrawal<'info> {
+    /// The user burning the bridged amount.
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(mut, seeds = [b"solana_deposit".as_ref()], bump)]
+    pub deposit_account: AccountLoader<'info, DepositState>,
+    /// CHECK: The deposit fees, withdrawals don't pay a fee while they aren't initialized
+    #[account(seeds = [b"deposit_fees".as_ref()], bump)]
+    pub deposit_fees: UncheckedAccount<'info>,
+    /// CHECK: Only receives the withdrawal fee, checked against the treasury of the deposit fees
+    /// when the treasury's share of the fee isn't zero
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+    /// Receives the fee pool's share of the fees, required when the share isn't zero
+    #[account(mut, seeds = [b"fee_pool".as_ref()], bump)]
+    pub fee_pool: Option<Account<'info, FeePool>>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 #[instruction(
     tx_id: [u8; 32],
@@ -187,4 +401,28 @@ pub struct FinalizeTx<'info> {
     pub deposit_account: AccountLoader<'info, DepositState>,
     #[account(mut)]
     pub mint_receiver: SystemAccount<'info>,
+    /// CHECK: Group key of the signer nodes the deposit scripts are derived from, only legacy
+    /// deposits are minted while it isn't initialized
+    #[account(seeds = [b"bridge_key".as_ref()], bump)]
+    pub bridge_key: UncheckedAccount<'info>,
+    /// CHECK: The deposit fees, deposits are minted without fees while they aren't initialized
+    #[account(seeds = [b"deposit_fees".as_ref()], bump)]
+    pub deposit_fees: UncheckedAccount<'info>,
+    /// CHECK: Only receives the deposit fee, checked against the treasury of the deposit fees
+    /// when the treasury's share of the fee isn't zero
+    #[account(mut)]
+    pub treasury: Option<UncheckedAccount<'info>>,
+    /// Receives the fee pool's share of the fees, required when the share isn't zero
+    #[account(mut, seeds = [b"fee_pool".as_ref()], bump)]
+    pub fee_pool: Option<Account<'info, FeePool>>,
+    /// Created when the deposit is minted, so it can't be minted to the receiver again
+    #[account(
+        init_if_needed,
+        seeds = [b"deposit_claim".as_ref(), tx_id.as_ref(), mint_receiver.key().as_ref()],
+        bump,
+        payer = signer,
+        space = DepositClaim::space()
+    )]
+    pub deposit_claim: Account<'info, DepositClaim>,
+    pub system_program: Program<'info, System>,
 }
@@ -1,26 +1,302 @@
 use anchor_lang::prelude::*;
-use bitcoin::consensus::Decodable;
-use bitcoin::Transaction;
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_lang::solana_program::system_instruction;
 
 use errors::*;
 use events::*;
 use instructions::*;
+use state::{BridgeKey, DepositClaim, DepositFees, DepositState, FeePool};
 use structs::*;
+use txutils::ParsedTransaction;
+use u256::U256;
 
-mod arrayutils;
-mod errors;
+pub mod errors;
 pub mod events;
 mod instructions;
 pub mod state;
 pub mod structs;
+pub mod txutils;
+pub mod u256;
 pub mod utils;
 
 declare_id!("3YS97dtVcWjXUnR8JrZUm1oACYdjhoNQEuMM7y7VdvTa");
 
+// Loads the deposit fees, none while they aren't initialized
+fn load_deposit_fees(deposit_fees: &UncheckedAccount) -> Result<Option<DepositFees>> {
+    if deposit_fees.data_is_empty() {
+        return Ok(None);
+    }
+    require_keys_eq!(
+        *deposit_fees.owner,
+        crate::ID,
+        ErrorCode::AccountOwnedByWrongProgram
+    );
+    let data = deposit_fees.try_borrow_data()?;
+    Ok(Some(DepositFees::try_deserialize(&mut &data[..])?))
+}
+
+// The bridge key, only legacy deposits are minted while it isn't initialized
+fn load_bridge_key(bridge_key: &UncheckedAccount) -> Result<Option<BridgeKey>> {
+    if bridge_key.data_is_empty() {
+        return Ok(None);
+    }
+    require_keys_eq!(
+        *bridge_key.owner,
+        crate::ID,
+        ErrorCode::AccountOwnedByWrongProgram
+    );
+    let data = bridge_key.try_borrow_data()?;
+    Ok(Some(BridgeKey::try_deserialize(&mut &data[..])?))
+}
+
+// Outputs of a deposit to the receiver
+struct DepositOutputs {
+    group_key: [u8; 32], // Zero while the bridge key isn't initialized
+    outputs: Vec<DepositOutput>,
+    legacy_outputs: Vec<DepositOutput>,
+}
+
+impl DepositOutputs {
+    fn amount(&self) -> u64 {
+        self.outputs
+            .iter()
+            .chain(&self.legacy_outputs)
+            .map(|output| output.amount)
+            .sum()
+    }
+}
+
+// Outputs of the transaction paying the receiver's taproot deposit script of the bridge key, and
+// the legacy P2WSH deposit script until the bridge key's authority ends legacy deposits
+fn deposit_outputs(
+    bitcoin_tx: &ParsedTransaction,
+    receiver: &Pubkey,
+    bridge_key: &UncheckedAccount,
+) -> Result<DepositOutputs> {
+    let bridge_key = load_bridge_key(bridge_key)?;
+    let (group_key, outputs) = match &bridge_key {
+        Some(bridge_key) => (
+            bridge_key.group_key,
+            utils::bridge_deposit_outputs(bitcoin_tx, receiver.to_bytes(), bridge_key.group_key)?,
+        ),
+        None => ([0; 32], vec![]),
+    };
+    let legacy_outputs = match &bridge_key {
+        Some(bridge_key) if !bridge_key.legacy_deposits => vec![],
+        _ => utils::outputs_paying(
+            bitcoin_tx,
+            &utils::legacy_deposit_script_pubkey(receiver.to_bytes()),
+        ),
+    };
+    let deposit = DepositOutputs {
+        group_key,
+        outputs,
+        legacy_outputs,
+    };
+    require!(deposit.amount() > 0, RelayErrorCode::NoDepositOutputs);
+    Ok(deposit)
+}
+
+// The treasury account, it has to match the treasury of the deposit fees
+fn checked_treasury<'a, 'info>(
+    treasury: &'a Option<UncheckedAccount<'info>>,
+    deposit_fees: &DepositFees,
+) -> Result<&'a UncheckedAccount<'info>> {
+    treasury
+        .as_ref()
+        .filter(|treasury| treasury.key() == deposit_fees.treasury)
+        .ok_or_else(|| error!(RelayErrorCode::InvalidTreasury))
+}
+
+// Records the claim of the deposit's outputs to the receiver, failing when they were already minted
+fn claim_deposit(
+    deposit_claim: &mut DepositClaim,
+    reversed_txid: [u8; 32],
+    receiver: Pubkey,
+) -> Result<()> {
+    require!(
+        deposit_claim.reversed_txid == [0; 32],
+        RelayErrorCode::DepositAlreadyClaimed
+    );
+    deposit_claim.reversed_txid = reversed_txid;
+    deposit_claim.receiver = receiver;
+    Ok(())
+}
+
+// Loads the deposit claim PDA of `verify_small_tx`, creating it like `init_if_needed` does. Its
+// seeds use the txid computed from the transaction, so Anchor can't derive it from the arguments
+fn init_deposit_claim<'info>(
+    signer: &Signer<'info>,
+    deposit_claim: &UncheckedAccount<'info>,
+    reversed_txid: &[u8; 32],
+    receiver: &Pubkey,
+) -> Result<DepositClaim> {
+    let (address, bump) = Pubkey::find_program_address(
+        &[b"deposit_claim".as_ref(), reversed_txid, receiver.as_ref()],
+        &crate::ID,
+    );
+    require_keys_eq!(deposit_claim.key(), address, ErrorCode::ConstraintSeeds);
+    if *deposit_claim.owner == crate::ID {
+        let data = deposit_claim.try_borrow_data()?;
+        return DepositClaim::try_deserialize(&mut &data[..]);
+    }
+
+    let space = DepositClaim::space();
+    let rent = Rent::get()?.minimum_balance(space);
+    let seeds: &[&[u8]] = &[
+        b"deposit_claim".as_ref(),
+        reversed_txid,
+        receiver.as_ref(),
+        &[bump],
+    ];
+    let lamports = deposit_claim.lamports();
+    if lamports == 0 {
+        let ix = system_instruction::create_account(
+            signer.key,
+            &address,
+            rent,
+            space as u64,
+            &crate::ID,
+        );
+        invoke_signed(
+            &ix,
+            &[signer.to_account_info(), deposit_claim.to_account_info()],
+            &[seeds],
+        )?;
+    } else {
+        // Anyone can transfer lamports to the address before it's created
+        if rent > lamports {
+            transfer_from_signer(signer, deposit_claim.to_account_info(), rent - lamports)?;
+        }
+        let ix = system_instruction::allocate(&address, space as u64);
+        invoke_signed(&ix, &[deposit_claim.to_account_info()], &[seeds])?;
+        let ix = system_instruction::assign(&address, &crate::ID);
+        invoke_signed(&ix, &[deposit_claim.to_account_info()], &[seeds])?;
+    }
+    Ok(DepositClaim {
+        reversed_txid: [0; 32],
+        receiver: Pubkey::default(),
+    })
+}
+
+// Accounts paying out a minted deposit
+struct MintAccounts<'a, 'info> {
+    deposit_account: &'a AccountLoader<'info, DepositState>,
+    mint_receiver: &'a SystemAccount<'info>,
+    deposit_fees: &'a UncheckedAccount<'info>,
+    treasury: &'a Option<UncheckedAccount<'info>>,
+    fee_pool: &'a Option<Account<'info, FeePool>>,
+}
+
+impl<'info> VerifyTransaction<'info> {
+    fn mint_accounts(&self) -> MintAccounts<'_, 'info> {
+        MintAccounts {
+            deposit_account: &self.deposit_account,
+            mint_receiver: &self.mint_receiver,
+            deposit_fees: &self.deposit_fees,
+            treasury: &self.treasury,
+            fee_pool: &self.fee_pool,
+        }
+    }
+}
+
+impl<'info> FinalizeTx<'info> {
+    fn mint_accounts(&self) -> MintAccounts<'_, 'info> {
+        MintAccounts {
+            deposit_account: &self.deposit_account,
+            mint_receiver: &self.mint_receiver,
+            deposit_fees: &self.deposit_fees,
+            treasury: &self.treasury,
+            fee_pool: &self.fee_pool,
+        }
+    }
+}
+
+// Mints the deposited amount minus the deposit fee to the receiver, paying the fee to the treasury
+// and the fee pool's share of it to the fee pool. All are paid out of the deposit account at 10
+// lamports per satoshi. Deposits are minted without a fee while the deposit fees aren't initialized
+fn mint_deposit(
+    accounts: MintAccounts,
+    reversed_txid: [u8; 32],
+    deposit: DepositOutputs,
+) -> Result<()> {
+    let MintAccounts {
+        deposit_account,
+        mint_receiver,
+        deposit_fees,
+        treasury,
+        fee_pool,
+    } = accounts;
+    let deposit_fees = load_deposit_fees(deposit_fees)?;
+    let amount = deposit.amount();
+    let (fee, pool_fee) = match &deposit_fees {
+        Some(deposit_fees) => {
+            let fee = deposit_fees.fee(amount);
+            require!(
+                amount >= deposit_fees.min_deposit && amount > fee,
+                RelayErrorCode::DepositTooSmall
+            );
+            (fee, deposit_fees.pool_share(fee))
+        }
+        None => (0, 0),
+    };
+
+    let minted = to_lamports(amount - fee)?;
+    let fee_lamports = to_lamports(fee)?;
+    let pool_lamports = to_lamports(pool_fee)?;
+
+    **deposit_account.as_ref().try_borrow_mut_lamports()? -= to_lamports(amount)?;
+    **mint_receiver.try_borrow_mut_lamports()? += minted;
+    if pool_lamports > 0 {
+        let fee_pool = fee_pool.as_ref().ok_or(RelayErrorCode::MissingFeePool)?;
+        **fee_pool.to_account_info().try_borrow_mut_lamports()? += pool_lamports;
+    }
+    let mut treasury_key = Pubkey::default();
+    if let Some(deposit_fees) = &deposit_fees {
+        treasury_key = deposit_fees.treasury;
+        if fee_lamports > pool_lamports {
+            let treasury = checked_treasury(treasury, deposit_fees)?;
+            **treasury.try_borrow_mut_lamports()? += fee_lamports - pool_lamports;
+        }
+    }
+
+    emit!(DepositMinted {
+        reversed_txid,
+        receiver: mint_receiver.key(),
+        amount,
+        fee,
+        pool_fee,
+        minted,
+        treasury: treasury_key,
+        group_key: deposit.group_key,
+        outputs: deposit.outputs,
+        legacy_outputs: deposit.legacy_outputs
+    });
+    Ok(())
+}
+
+// Converts satoshis to lamports at 10 lamports per satoshi
+fn to_lamports(satoshis: u64) -> Result<u64> {
+    Ok(satoshis
+        .checked_mul(10)
+        .ok_or(RelayErrorCode::AmountOverflow)?)
+}
+
+// Transfers lamports from the signer with the system program
+fn transfer_from_signer<'info>(
+    signer: &Signer<'info>,
+    to: AccountInfo<'info>,
+    lamports: u64,
+) -> Result<()> {
+    let ix =
+        anchor_lang::solana_program::system_instruction::transfer(signer.key, to.key, lamports);
+    anchor_lang::solana_program::program::invoke(&ix, &[signer.to_account_info(), to])?;
+    Ok(())
+}
+
 #[program]
 pub mod btc_relay {
     use super::*;
-    use crate::utils::bridge_mint_amount;
 
     // Initializes the program with the initial block header,
     // this can be any past block header with high enough confirmations to be sure it doesn't get re-orged.
@@ -51,8 +327,8 @@ pub mod btc_relay {
             prev_block_timestamps,
         };
 
-        let hash_result = commited_header.get_commit_hash()?;
-        let block_hash = data.get_block_hash()?;
+        let hash_result = commited_header.get_commit_hash();
+        let block_hash = data.get_block_hash();
 
         main_state.block_commitments[0] = hash_result;
 
@@ -76,17 +352,26 @@ pub mod btc_relay {
         ctx: Context<SubmitBlockHeaders>,
         data: Vec<BlockHeader>,
         commited_header: CommittedBlockHeader,
+        header_topic_bumps: Vec<u8>,
     ) -> Result<()> {
         require!(!data.is_empty(), RelayErrorCode::NoHeaders);
 
         require!(
-            data.len() == ctx.remaining_accounts.len(),
+            data.len() == ctx.remaining_accounts.len() && data.len() == header_topic_bumps.len(),
             RelayErrorCode::InvalidRemainingAccounts
         );
 
         //Verify commited header was indeed committed
-        let commit_hash = commited_header.get_commit_hash()?;
+        let commit_hash = commited_header.get_commit_hash();
         let main_state = &mut ctx.accounts.main_state.load_mut()?;
+
+        // A competing relayer submitted headers on top of the committed header first
+        require!(
+            commited_header.blockheight >= main_state.block_height
+                || commit_hash != main_state.get_commitment(commited_header.blockheight),
+            RelayErrorCode::TipAlreadyExtended
+        );
+
         let main_state_tip = main_state.get_commitment(main_state.block_height);
         require!(
             commit_hash == main_state_tip,
@@ -94,7 +379,7 @@ pub mod btc_relay {
         );
 
         let mut last_commited_header = commited_header;
-        let mut last_block_hash: [u8; 32] = commited_header.header.get_block_hash()?;
+        let mut last_block_hash: [u8; 32] = commited_header.header.get_block_hash();
         let mut block_height = main_state.block_height;
         let mut block_commit_hash: [u8; 32] = [0; 32];
 
@@ -111,12 +396,13 @@ pub mod btc_relay {
                 header,
                 &mut last_commited_header,
                 &ctx.remaining_accounts[block_cnt],
+                header_topic_bumps[block_cnt],
                 &ctx.accounts.signer,
                 ctx.program_id,
             )?;
 
             // Compute commit hash
-            block_commit_hash = last_commited_header.get_commit_hash()?;
+            block_commit_hash = last_commited_header.get_commit_hash();
 
             // Store and emit
             main_state.store_block_commitment(block_height, block_commit_hash);
@@ -134,25 +420,73 @@ pub mod btc_relay {
         main_state.tip_commit_hash = block_commit_hash;
         main_state.tip_block_hash = last_block_hash;
 
+        // Reward the relayer from the fee pool, as far as it's funded above its rent exemption
+        if let Some(fee_pool) = &mut ctx.accounts.fee_pool {
+            let headers = data.len() as u32;
+            let available = fee_pool
+                .get_lamports()
+                .saturating_sub(Rent::get()?.minimum_balance(FeePool::space()));
+            let amount = fee_pool
+                .header_reward
+                .saturating_mul(headers as u64)
+                .min(available);
+            if amount > 0 {
+                fee_pool.sub_lamports(amount)?;
+                ctx.accounts.signer.add_lamports(amount)?;
+                fee_pool.total_rewards += amount;
+                emit!(RelayerRewarded {
+                    relayer: ctx.accounts.signer.key(),
+                    headers,
+                    amount
+                });
+            }
+        }
+
         Ok(())
     }
 
+    // Creates the fee pool paying relayers header_reward lamports per main chain header they submit,
+    // funded by its share of the deposit and withdrawal fees and by fund_fee_pool. Only the program's
+    // upgrade authority can create it, becoming the authority able to change the reward
+    pub fn initialize_fee_pool(ctx: Context<InitializeFeePool>, header_reward: u64) -> Result<()> {
+        let fee_pool = &mut ctx.accounts.fee_pool;
+        fee_pool.authority = ctx.accounts.signer.key();
+        fee_pool.header_reward = header_reward;
+        fee_pool.total_rewards = 0;
+        Ok(())
+    }
+
+    pub fn set_header_reward(ctx: Context<SetHeaderReward>, header_reward: u64) -> Result<()> {
+        ctx.accounts.fee_pool.header_reward = header_reward;
+        Ok(())
+    }
+
+    // Anyone can fund the relayer rewards
+    pub fn fund_fee_pool(ctx: Context<FundFeePool>, amount: u64) -> Result<()> {
+        transfer_from_signer(
+            &ctx.accounts.signer,
+            ctx.accounts.fee_pool.to_account_info(),
+            amount,
+        )
+    }
+
     // Submit new headers forking the chain at some point in the past,
     // only allows submission of up to 7 blockheaders, due to Solana tx size limitation
     pub fn submit_short_fork_headers(
         ctx: Context<SubmitShortForkHeaders>,
         data: Vec<BlockHeader>,
         commited_header: CommittedBlockHeader,
+        header_topic_bumps: Vec<u8>,
     ) -> Result<()> {
         require!(!data.is_empty(), RelayErrorCode::NoHeaders);
 
         require!(
-            data.len() == ctx.remaining_accounts.len(),
+            data.len() == ctx.remaining_accounts.len() && data.len() == header_topic_bumps.len(),
             RelayErrorCode::InvalidRemainingAccounts
         );
 
         //Verify commited header was indeed committed
-        let commit_hash = commited_header.get_commit_hash()?;
+        let commit_hash = commited_header.get_commit_hash();
 
         let main_state = &mut ctx.accounts.main_state.load_mut()?;
 
@@ -163,7 +497,7 @@ pub mod btc_relay {
 
         let fork_id = main_state.fork_counter;
         let mut last_commited_header = commited_header;
-        let mut last_block_hash: [u8; 32] = commited_header.header.get_block_hash()?;
+        let mut last_block_hash: [u8; 32] = commited_header.header.get_block_hash();
         let mut block_height = commited_header.blockheight;
 
         let mut block_commit_hash: [u8; 32] = [0; 32];
@@ -181,12 +515,13 @@ pub mod btc_relay {
                 header,
                 &mut last_commited_header,
                 &ctx.remaining_accounts[block_cnt],
+                header_topic_bumps[block_cnt],
                 &ctx.accounts.signer,
                 ctx.program_id,
             )?;
 
             //Compute commit hash
-            block_commit_hash = last_commited_header.get_commit_hash()?;
+            block_commit_hash = last_commited_header.get_commit_hash();
 
             //Store and emit
             main_state.store_block_commitment(block_height, block_commit_hash);
@@ -200,7 +535,8 @@ pub mod btc_relay {
 
         // Verify if fork chain's work exceeded main chain's work
         require!(
-            arrayutils::gt_arr(last_commited_header.chain_work, main_state.chain_work),
+            U256::from_be_bytes(last_commited_header.chain_work)
+                > U256::from_be_bytes(main_state.chain_work),
             RelayErrorCode::ForkTooShort
         );
 
@@ -225,11 +561,12 @@ pub mod btc_relay {
         commited_header: CommittedBlockHeader,
         fork_id: u64,
         init: bool,
+        header_topic_bumps: Vec<u8>,
     ) -> Result<()> {
         require!(!data.is_empty(), RelayErrorCode::NoHeaders);
 
         require!(
-            data.len() == ctx.remaining_accounts.len(),
+            data.len() == ctx.remaining_accounts.len() && data.len() == header_topic_bumps.len(),
             RelayErrorCode::InvalidRemainingAccounts
         );
 
@@ -252,7 +589,7 @@ pub mod btc_relay {
 
             let main_state = &mut ctx.accounts.main_state.load_mut()?;
 
-            let commit_hash = commited_header.get_commit_hash()?;
+            let commit_hash = commited_header.get_commit_hash();
 
             let mut block_height = commited_header.blockheight;
 
@@ -283,7 +620,7 @@ pub mod btc_relay {
             }
 
             let mut last_commited_header = commited_header;
-            let mut last_block_hash: [u8; 32] = commited_header.header.get_block_hash()?;
+            let mut last_block_hash: [u8; 32] = commited_header.header.get_block_hash();
 
             let mut block_commit_hash: [u8; 32] = [0; 32];
 
@@ -300,12 +637,13 @@ pub mod btc_relay {
                     header,
                     &mut last_commited_header,
                     &ctx.remaining_accounts[block_cnt],
+                    header_topic_bumps[block_cnt],
                     &ctx.accounts.signer,
                     ctx.program_id,
                 )?;
 
                 //Compute commit hash
-                block_commit_hash = last_commited_header.get_commit_hash()?;
+                block_commit_hash = last_commited_header.get_commit_hash();
 
                 //Store and emit
                 fork_state.store_block_commitment(block_commit_hash);
@@ -317,7 +655,9 @@ pub mod btc_relay {
                 });
             }
 
-            if arrayutils::gt_arr(last_commited_header.chain_work, main_state.chain_work) {
+            if U256::from_be_bytes(last_commited_header.chain_work)
+                > U256::from_be_bytes(main_state.chain_work)
+            {
                 //Successful fork, fork's work exceeded main chain's work
 
                 msg!("Successful fork...");
@@ -393,40 +733,187 @@ pub mod btc_relay {
             RelayErrorCode::BlockConfirmations
         );
 
-        let commit_hash = commited_header.get_commit_hash()?;
+        let commit_hash = commited_header.get_commit_hash();
         require!(
             commit_hash == main_state.get_commitment(block_height),
             RelayErrorCode::PrevBlockCommitment
         );
 
-        let bitcoin_tx = Transaction::consensus_decode(&mut tx_bytes.as_slice()).unwrap();
-        let amount_to_transfer =
-            bridge_mint_amount(&bitcoin_tx, ctx.accounts.mint_receiver.key().to_bytes());
+        let bitcoin_tx =
+            ParsedTransaction::parse(&tx_bytes).map_err(|_| RelayErrorCode::InvalidTransaction)?;
+        let deposit = deposit_outputs(
+            &bitcoin_tx,
+            &ctx.accounts.mint_receiver.key(),
+            &ctx.accounts.bridge_key,
+        )?;
 
-        require!(amount_to_transfer > 0, RelayErrorCode::NoDepositOutputs);
+        let reversed_txid = bitcoin_tx.compute_txid();
+        let computed_merkle =
+            utils::compute_merkle(&reversed_txid, tx_index, reversed_merkle_proof);
 
-        let computed_merkle = utils::compute_merkle(
-            bitcoin_tx.compute_txid().as_ref(),
-            tx_index,
-            reversed_merkle_proof,
+        require!(
+            computed_merkle == commited_header.header.merkle_root,
+            RelayErrorCode::MerkleRoot
         );
 
+        let receiver = ctx.accounts.mint_receiver.key();
+        let mut deposit_claim = init_deposit_claim(
+            &ctx.accounts.signer,
+            &ctx.accounts.deposit_claim,
+            &reversed_txid,
+            &receiver,
+        )?;
+        claim_deposit(&mut deposit_claim, reversed_txid, receiver)?;
+        deposit_claim
+            .try_serialize(&mut &mut ctx.accounts.deposit_claim.try_borrow_mut_data()?[..])?;
+        mint_deposit(ctx.accounts.mint_accounts(), reversed_txid, deposit)
+    }
+
+    // Verifies transaction block inclusion proof, requiring certain amount of confirmations,
+    // without any bridge specific processing. The result is stored in the receipt PDA
+    // derived from the txid, which other programs can check (see btc-relay-cpi crate).
+    // The receipt stores the block's commitment, so its confirmations are read from the main
+    // chain when it's used and a reorg invalidates it. Verifying the transaction again
+    // after a reorg stores the block it was included in on the new main chain
+    pub fn verify_transaction(
+        ctx: Context<VerifyTransactionReceipt>,
+        reversed_txid: [u8; 32],
+        tx_bytes: Vec<u8>,
+        confirmations: u32,
+        tx_index: u32,
+        reversed_merkle_proof: Vec<[u8; 32]>,
+        commited_header: CommittedBlockHeader,
+    ) -> Result<()> {
+        let block_height = commited_header.blockheight;
+
+        let main_state = ctx.accounts.main_state.load()?;
+
+        let commit_hash = commited_header.get_commit_hash();
+        require!(
+            commit_hash == main_state.get_commitment(block_height),
+            RelayErrorCode::PrevBlockCommitment
+        );
+
+        let block_confirmations = main_state.block_height - block_height + 1;
+        require!(
+            block_confirmations >= confirmations,
+            RelayErrorCode::BlockConfirmations
+        );
+
+        let bitcoin_tx =
+            ParsedTransaction::parse(&tx_bytes).map_err(|_| RelayErrorCode::InvalidTransaction)?;
+        require!(
+            bitcoin_tx.compute_txid() == reversed_txid,
+            RelayErrorCode::InvalidTxId
+        );
+
+        let computed_merkle =
+            utils::compute_merkle(&reversed_txid, tx_index, reversed_merkle_proof);
         require!(
             computed_merkle == commited_header.header.merkle_root,
             RelayErrorCode::MerkleRoot
         );
 
-        let sol_amount = amount_to_transfer * 10;
+        let receipt = &mut ctx.accounts.receipt;
+        receipt.reversed_txid = reversed_txid;
+        receipt.block_height = block_height;
+        receipt.commit_hash = commit_hash;
+        receipt.outputs_hash = bitcoin_tx.outputs_hash();
+
+        emit!(TxVerified {
+            reversed_txid,
+            block_height,
+            confirmations: block_confirmations,
+            outputs_hash: receipt.outputs_hash
+        });
 
-        **ctx
-            .accounts
-            .deposit_account
-            .as_ref()
-            .try_borrow_mut_lamports()? -= sol_amount;
-        **ctx.accounts.mint_receiver.try_borrow_mut_lamports()? += sol_amount;
         Ok(())
     }
 
+    // Verifies segwit transaction inclusion by its wtxid, requiring certain amount of confirmations,
+    // the witness merkle root is checked against the witness commitment in the coinbase transaction,
+    // which itself is proven to be the first transaction of the block.
+    // Returns the verified witness data, so it can be used by other programs via CPI
+    pub fn verify_witness(
+        ctx: Context<VerifyWitness>,
+        reversed_wtxid: [u8; 32],
+        confirmations: u32,
+        tx_index: u32,
+        reversed_witness_proof: Vec<[u8; 32]>,
+        coinbase: CoinbaseProof,
+        commited_header: CommittedBlockHeader,
+    ) -> Result<VerifiedWitness> {
+        let block_height = commited_header.blockheight;
+
+        let main_state = ctx.accounts.main_state.load()?;
+
+        let commit_hash = commited_header.get_commit_hash();
+        require!(
+            commit_hash == main_state.get_commitment(block_height),
+            RelayErrorCode::PrevBlockCommitment
+        );
+
+        let block_confirmations = main_state.block_height - block_height + 1;
+        require!(
+            block_confirmations >= confirmations,
+            RelayErrorCode::BlockConfirmations
+        );
+
+        // Witness merkle tree has the same shape as the transaction merkle tree,
+        // with the coinbase's wtxid replaced by zeroes. The index can't have bits above the
+        // proof's depth, those would be ignored when computing the root
+        let depth = reversed_witness_proof.len() as u32;
+        require!(
+            tx_index != 0
+                && reversed_witness_proof.len() == coinbase.reversed_merkle_proof.len()
+                && tx_index.checked_shr(depth).unwrap_or(0) == 0,
+            RelayErrorCode::WitnessCommitment
+        );
+
+        // Coinbase transaction has to be the first transaction of the block
+        let coinbase_tx = ParsedTransaction::parse(&coinbase.tx_bytes)
+            .map_err(|_| RelayErrorCode::InvalidCoinbase)?;
+        require!(coinbase_tx.is_coinbase(), RelayErrorCode::InvalidCoinbase);
+
+        let computed_merkle = utils::compute_merkle(
+            &coinbase_tx.compute_txid(),
+            0,
+            coinbase.reversed_merkle_proof,
+        );
+        require!(
+            computed_merkle == commited_header.header.merkle_root,
+            RelayErrorCode::MerkleRoot
+        );
+
+        let witness_commitment = utils::find_witness_commitment(&coinbase_tx)
+            .ok_or(RelayErrorCode::NoWitnessCommitment)?;
+
+        // Witness reserved value is the single 32-byte item of the coinbase input's witness
+        let mut coinbase_witness = coinbase_tx.witness(0);
+        let witness_reserved_value: [u8; 32] = coinbase_witness
+            .next()
+            .and_then(|item| item.try_into().ok())
+            .ok_or(RelayErrorCode::InvalidCoinbase)?;
+        require!(
+            coinbase_witness.next().is_none(),
+            RelayErrorCode::InvalidCoinbase
+        );
+
+        let witness_root = utils::compute_merkle(&reversed_wtxid, tx_index, reversed_witness_proof);
+        require!(
+            utils::compute_witness_commitment(&witness_root, &witness_reserved_value)
+                == witness_commitment,
+            RelayErrorCode::WitnessCommitment
+        );
+
+        Ok(VerifiedWitness {
+            reversed_wtxid,
+            block_hash: commited_header.header.get_block_hash(),
+            blockheight: block_height,
+            confirmations: block_confirmations,
+        })
+    }
+
     // Verifies block height of the main chain
     // Supports many operators
     // 0 - block height has to be < value
@@ -457,22 +944,162 @@ pub mod btc_relay {
 
     pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
         // Transfer SOL from the user to the program's account
-        let ix = anchor_lang::solana_program::system_instruction::transfer(
-            &ctx.accounts.signer.key,
-            &ctx.accounts.deposit_account.as_ref().key,
+        transfer_from_signer(
+            &ctx.accounts.signer,
+            ctx.accounts.deposit_account.to_account_info(),
             amount,
+        )
+    }
+
+    // Creates the deposit fees deducted when minting deposits and the withdrawal fee, only the
+    // program's upgrade authority can create them and becomes the authority able to change them.
+    // Fees and the minimum deposit are in satoshis, pool_share_bps of every fee is paid into the
+    // fee pool
+    pub fn initialize_deposit_fees(
+        ctx: Context<InitializeDepositFees>,
+        treasury: Pubkey,
+        fixed_fee: u64,
+        fee_bps: u16,
+        min_deposit: u64,
+        withdrawal_fee: u64,
+        pool_share_bps: u16,
+    ) -> Result<()> {
+        require!(
+            fee_bps <= DepositFees::MAX_FEE_BPS && pool_share_bps <= 10_000,
+            RelayErrorCode::InvalidDepositFees
         );
-        anchor_lang::solana_program::program::invoke(
-            &ix,
-            &[
-                ctx.accounts.signer.to_account_info(),
-                ctx.accounts.deposit_account.to_account_info(),
-            ],
+
+        let deposit_fees = &mut ctx.accounts.deposit_fees;
+        deposit_fees.authority = ctx.accounts.signer.key();
+        deposit_fees.treasury = treasury;
+        deposit_fees.fixed_fee = fixed_fee;
+        deposit_fees.fee_bps = fee_bps;
+        deposit_fees.min_deposit = min_deposit;
+        deposit_fees.withdrawal_fee = withdrawal_fee;
+        deposit_fees.pool_share_bps = pool_share_bps;
+        Ok(())
+    }
+
+    pub fn set_deposit_fees(
+        ctx: Context<SetDepositFees>,
+        treasury: Pubkey,
+        fixed_fee: u64,
+        fee_bps: u16,
+        min_deposit: u64,
+        withdrawal_fee: u64,
+        pool_share_bps: u16,
+    ) -> Result<()> {
+        require!(
+            fee_bps <= DepositFees::MAX_FEE_BPS && pool_share_bps <= 10_000,
+            RelayErrorCode::InvalidDepositFees
+        );
+
+        let deposit_fees = &mut ctx.accounts.deposit_fees;
+        deposit_fees.treasury = treasury;
+        deposit_fees.fixed_fee = fixed_fee;
+        deposit_fees.fee_bps = fee_bps;
+        deposit_fees.min_deposit = min_deposit;
+        deposit_fees.withdrawal_fee = withdrawal_fee;
+        deposit_fees.pool_share_bps = pool_share_bps;
+        Ok(())
+    }
+
+    // Registers the taproot output key of the signer nodes' group, generated by their key generation
+    // ceremony. Only the program's upgrade authority can, it becomes the authority able to register
+    // the key of a new group. Legacy deposits are still minted until the authority ends them
+    pub fn initialize_bridge_key(
+        ctx: Context<InitializeBridgeKey>,
+        group_key: [u8; 32],
+    ) -> Result<()> {
+        utils::taproot_output_key(group_key, None)?;
+
+        let bridge_key = &mut ctx.accounts.bridge_key;
+        bridge_key.authority = ctx.accounts.signer.key();
+        bridge_key.group_key = group_key;
+        bridge_key.legacy_deposits = true;
+        emit!(BridgeKeyRegistered {
+            group_key,
+            authority: bridge_key.authority
+        });
+        Ok(())
+    }
+
+    pub fn set_bridge_key(ctx: Context<SetBridgeKey>, group_key: [u8; 32]) -> Result<()> {
+        utils::taproot_output_key(group_key, None)?;
+
+        let bridge_key = &mut ctx.accounts.bridge_key;
+        bridge_key.group_key = group_key;
+        emit!(BridgeKeyRegistered {
+            group_key,
+            authority: bridge_key.authority
+        });
+        Ok(())
+    }
+
+    // Stops minting deposits to the legacy P2WSH deposit scripts, once their outputs were swept to the
+    // bridge key's deposit scripts. Legacy deposits can't be resumed
+    pub fn end_legacy_deposits(ctx: Context<SetBridgeKey>) -> Result<()> {
+        ctx.accounts.bridge_key.legacy_deposits = false;
+        Ok(())
+    }
+
+    // Burns the bridged amount back to the deposit account, the signer nodes pay it out on Bitcoin to
+    // the script pubkey, deducting the transaction fee. The withdrawal fee is paid on top, split
+    // between the treasury and the fee pool, once the deposit fees are initialized. Amounts are in
+    // satoshis
+    pub fn request_withdrawal(
+        ctx: Context<RequestWithdrawal>,
+        amount: u64,
+        script_pubkey: Vec<u8>,
+    ) -> Result<()> {
+        require!(
+            utils::is_withdrawal_output(&script_pubkey, amount),
+            RelayErrorCode::InvalidWithdrawal
+        );
+
+        let signer = &ctx.accounts.signer;
+        transfer_from_signer(
+            signer,
+            ctx.accounts.deposit_account.to_account_info(),
+            to_lamports(amount)?,
         )?;
 
+        let deposit_fees = load_deposit_fees(&ctx.accounts.deposit_fees)?;
+        let (fee, pool_fee) = match &deposit_fees {
+            Some(deposit_fees) => (
+                deposit_fees.withdrawal_fee,
+                deposit_fees.pool_share(deposit_fees.withdrawal_fee),
+            ),
+            None => (0, 0),
+        };
+        if pool_fee > 0 {
+            let fee_pool = ctx
+                .accounts
+                .fee_pool
+                .as_ref()
+                .ok_or(RelayErrorCode::MissingFeePool)?;
+            transfer_from_signer(signer, fee_pool.to_account_info(), to_lamports(pool_fee)?)?;
+        }
+        if let Some(deposit_fees) = deposit_fees.as_ref().filter(|_| fee > pool_fee) {
+            let treasury = checked_treasury(&ctx.accounts.treasury, deposit_fees)?;
+            transfer_from_signer(
+                signer,
+                treasury.to_account_info(),
+                to_lamports(fee - pool_fee)?,
+            )?;
+        }
+
+        emit!(WithdrawalRequested {
+            requester: signer.key(),
+            amount,
+            script_pubkey,
+            fee
+        });
         Ok(())
     }
 
+    // tx_size only sizes the transaction's account
+    #[allow(unused_variables)]
     pub fn init_big_tx_verify(
         ctx: Context<InitBigTxVerify>,
         tx_id: [u8; 32],
@@ -491,7 +1118,7 @@ pub mod btc_relay {
             RelayErrorCode::BlockConfirmations
         );
 
-        let commit_hash = commited_header.get_commit_hash()?;
+        let commit_hash = commited_header.get_commit_hash();
         require!(
             commit_hash == main_state.get_commitment(block_height),
             RelayErrorCode::PrevBlockCommitment
@@ -507,6 +1134,8 @@ pub mod btc_relay {
         Ok(())
     }
 
+    // tx_id only derives the transaction's account
+    #[allow(unused_variables)]
     pub fn store_tx_bytes(
         ctx: Context<StoreTxBytes>,
         tx_id: [u8; 32],
@@ -517,25 +1146,24 @@ pub mod btc_relay {
     }
 
     pub fn finalize_tx_processing(ctx: Context<FinalizeTx>, tx_id: [u8; 32]) -> Result<()> {
-        let bitcoin_tx =
-            Transaction::consensus_decode(&mut ctx.accounts.tx_account.tx_bytes.as_slice())
-                .unwrap();
-
-        assert_eq!(tx_id, bitcoin_tx.compute_txid().as_ref());
-        let amount_to_transfer =
-            bridge_mint_amount(&bitcoin_tx, ctx.accounts.mint_receiver.key().to_bytes());
-
-        require!(amount_to_transfer > 0, RelayErrorCode::NoDepositOutputs);
-
-        let sol_amount = amount_to_transfer * 10;
+        let bitcoin_tx = ParsedTransaction::parse(&ctx.accounts.tx_account.tx_bytes)
+            .map_err(|_| RelayErrorCode::InvalidTransaction)?;
 
-        **ctx
-            .accounts
-            .deposit_account
-            .as_ref()
-            .try_borrow_mut_lamports()? -= sol_amount;
+        require!(
+            tx_id == bitcoin_tx.compute_txid(),
+            RelayErrorCode::InvalidTxId
+        );
+        let deposit = deposit_outputs(
+            &bitcoin_tx,
+            &ctx.accounts.mint_receiver.key(),
+            &ctx.accounts.bridge_key,
+        )?;
 
-        **ctx.accounts.mint_receiver.try_borrow_mut_lamports()? += sol_amount;
-        Ok(())
+        claim_deposit(
+            &mut ctx.accounts.deposit_claim,
+            tx_id,
+            ctx.accounts.mint_receiver.key(),
+        )?;
+        mint_deposit(ctx.accounts.mint_accounts(), tx_id, deposit)
     }
 }
@@ -16,6 +16,90 @@ pub struct BigTxState {
     pub tx_bytes: Vec<u8>,
 }
 
+#[account]
+#[derive(Debug)]
+pub struct TxVerificationReceipt {
+    pub reversed_txid: [u8; 32], // Id of the verified transaction
+    pub block_height: u32,       // Height of the block containing the transaction
+    pub commit_hash: [u8; 32], // Commitment of the block's header, checked against the main chain when the receipt is used
+    pub outputs_hash: [u8; 32], // Double sha256 of the serialized transaction outputs (BIP143 hashOutputs)
+}
+
+impl TxVerificationReceipt {
+    pub fn space() -> usize {
+        8 + 32 + 4 + 32 + 32
+    }
+}
+
+#[account]
+pub struct FeePool {
+    pub authority: Pubkey,  // Can change the header reward
+    pub header_reward: u64, // Lamports paid to the relayer per submitted main chain header
+    pub total_rewards: u64, // Lamports paid to relayers so far
+}
+
+impl FeePool {
+    pub fn space() -> usize {
+        8 + 32 + 8 + 8
+    }
+}
+
+#[account]
+pub struct DepositFees {
+    pub authority: Pubkey,   // Can change the fees and the treasury
+    pub treasury: Pubkey,    // Receives the deposit and withdrawal fees
+    pub fixed_fee: u64,      // Satoshis deducted from every deposit
+    pub fee_bps: u16, // Basis points of the deposited amount deducted on top of the fixed fee
+    pub min_deposit: u64, // Satoshis a deposit has to pay at least to be minted
+    pub withdrawal_fee: u64, // Satoshis paid on top of every withdrawal
+    pub pool_share_bps: u16, // Basis points of the deposit and withdrawal fees paid into the fee pool instead of the treasury
+}
+
+impl DepositFees {
+    // Highest fee_bps the deposit fees can be set to
+    pub const MAX_FEE_BPS: u16 = 1_000;
+
+    pub fn space() -> usize {
+        8 + 32 + 32 + 8 + 2 + 8 + 8 + 2
+    }
+
+    // Fee in satoshis deducted from the deposited amount
+    pub fn fee(&self, amount: u64) -> u64 {
+        let bps_fee = amount as u128 * self.fee_bps as u128 / 10_000;
+        self.fixed_fee.saturating_add(bps_fee as u64)
+    }
+
+    // Part of the fee in satoshis paid into the fee pool, the rest goes to the treasury
+    pub fn pool_share(&self, fee: u64) -> u64 {
+        (fee as u128 * self.pool_share_bps as u128 / 10_000) as u64
+    }
+}
+
+#[account]
+pub struct DepositClaim {
+    pub reversed_txid: [u8; 32], // Id of the minted deposit transaction, zero until it's claimed
+    pub receiver: Pubkey,        // Receiver the deposit was minted to
+}
+
+impl DepositClaim {
+    pub fn space() -> usize {
+        8 + 32 + 32
+    }
+}
+
+#[account]
+pub struct BridgeKey {
+    pub authority: Pubkey,     // Can register another group key
+    pub group_key: [u8; 32],   // X-only taproot output key of the signer nodes' group
+    pub legacy_deposits: bool, // Whether the P2WSH deposits of BITCOIN_DEPOSIT_PUBKEY are still minted
+}
+
+impl BridgeKey {
+    pub fn space() -> usize {
+        8 + 32 + 32 + 1
+    }
+}
+
 #[account(zero_copy)]
 #[repr(C)]
 pub struct MainState {
@@ -63,8 +147,13 @@ impl MainState {
         if block_height > self.block_height {
             return [0; 32];
         }
-        //Check block_height out of bounds for the ring buffer
-        if block_height <= self.block_height - PRUNING_FACTOR_U32 {
+        //Check block_height out of bounds for the ring buffer,
+        // all blocks are within bounds while the tip is lower than PRUNING_FACTOR
+        if self
+            .block_height
+            .checked_sub(PRUNING_FACTOR_U32)
+            .is_some_and(|oldest| block_height <= oldest)
+        {
             return [0; 32];
         }
         let pos = self.get_position(block_height);
@@ -74,6 +163,15 @@ impl MainState {
         self.block_commitments[pos]
     }
 
+    //Gets the confirmations of the block with the commitment, none when the block isn't
+    // in the main chain (anymore) or was pruned from the ring buffer
+    pub fn get_confirmations(&self, block_height: u32, commit_hash: &[u8; 32]) -> Option<u32> {
+        if *commit_hash == [0; 32] || self.get_commitment(block_height) != *commit_hash {
+            return None;
+        }
+        Some(self.block_height - block_height + 1)
+    }
+
     //Stores the block commitment for the specified block_height in a ring buffer
     // returns false in case that block commitment would fall out of bounds for
     // ring buffer (more than PRUNING_FACTOR blocks in the past)
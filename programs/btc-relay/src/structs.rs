@@ -13,11 +13,23 @@ pub struct BlockHeader {
 }
 
 impl BlockHeader {
-    //Double sha256 of the blockheader
-    pub fn get_block_hash(&self) -> Result<[u8; 32]> {
-        let arr = self.try_to_vec()?;
+    pub const SIZE: usize = 80;
 
-        Ok(hash::hash(&hash::hash(&arr).to_bytes()).to_bytes())
+    //Serializes the blockheader into a stack buffer, same as its borsh serialization (all fields little endian)
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut arr = [0u8; Self::SIZE];
+        arr[0..4].copy_from_slice(&self.version.to_le_bytes());
+        arr[4..36].copy_from_slice(&self.reversed_prev_blockhash);
+        arr[36..68].copy_from_slice(&self.merkle_root);
+        arr[68..72].copy_from_slice(&self.timestamp.to_le_bytes());
+        arr[72..76].copy_from_slice(&self.nbits.to_le_bytes());
+        arr[76..80].copy_from_slice(&self.nonce.to_le_bytes());
+        arr
+    }
+
+    //Double sha256 of the blockheader
+    pub fn get_block_hash(&self) -> [u8; 32] {
+        hash::hash(&hash::hash(&self.to_bytes()).to_bytes()).to_bytes()
     }
 }
 
@@ -35,10 +47,81 @@ pub struct CommittedBlockHeader {
 }
 
 impl CommittedBlockHeader {
+    pub const SIZE: usize = 32 + BlockHeader::SIZE + 4 + 4 + 10 * 4;
+
+    //Serializes the committed header into a stack buffer, same as its borsh serialization
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut arr = [0u8; Self::SIZE];
+        arr[0..32].copy_from_slice(&self.chain_work);
+        arr[32..112].copy_from_slice(&self.header.to_bytes());
+        arr[112..116].copy_from_slice(&self.last_diff_adjustment.to_le_bytes());
+        arr[116..120].copy_from_slice(&self.blockheight.to_le_bytes());
+        for (i, timestamp) in self.prev_block_timestamps.iter().enumerate() {
+            arr[120 + i * 4..124 + i * 4].copy_from_slice(&timestamp.to_le_bytes());
+        }
+        arr
+    }
+
     //Returns the commit hash (fingerprint) of the block header data to be saved to the ring buffer
-    pub fn get_commit_hash(&self) -> Result<[u8; 32]> {
-        let arr = self.try_to_vec()?;
+    pub fn get_commit_hash(&self) -> [u8; 32] {
+        hash::hash(&self.to_bytes()).to_bytes()
+    }
+}
+
+//Coinbase transaction with its block inclusion proof, used to get the witness commitment of the block
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize, Eq, PartialEq)]
+pub struct CoinbaseProof {
+    pub tx_bytes: Vec<u8>, //Serialized coinbase transaction, including the witness
+    pub reversed_merkle_proof: Vec<[u8; 32]>, //Merkle proof of the coinbase transaction (always at index 0)
+}
+
+//Output of a deposit transaction paying the receiver's deposit script
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize, Copy, Eq, PartialEq)]
+pub struct DepositOutput {
+    pub vout: u32,   //Index of the output in the deposit transaction
+    pub amount: u64, //Satoshis of the output
+}
+
+//Result of the witness verification, returned to the caller (e.g. via CPI)
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize, Copy, Eq, PartialEq)]
+pub struct VerifiedWitness {
+    pub reversed_wtxid: [u8; 32], //Witness transaction id, whose inclusion in the block was verified
+    pub block_hash: [u8; 32],     //Hash of the block containing the transaction
+    pub blockheight: u32,         //Height of the block containing the transaction
+    pub confirmations: u32,       //Block confirmations at the time of verification
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn committed_header() -> CommittedBlockHeader {
+        CommittedBlockHeader {
+            chain_work: core::array::from_fn(|i| i as u8),
+            header: BlockHeader {
+                version: 0x2000_0000,
+                reversed_prev_blockhash: [0xAB; 32],
+                merkle_root: core::array::from_fn(|i| 0xFF - i as u8),
+                timestamp: 1_721_024_744,
+                nbits: 0x1703_4219,
+                nonce: 0xDEAD_BEEF,
+            },
+            last_diff_adjustment: 1_720_000_000,
+            blockheight: 853_000,
+            prev_block_timestamps: core::array::from_fn(|i| 1_721_020_000 + i as u32),
+        }
+    }
 
-        Ok(hash::hash(&arr).to_bytes())
+    #[test]
+    fn stack_serialization_matches_borsh() {
+        let committed = committed_header();
+        assert_eq!(
+            committed.header.to_bytes().to_vec(),
+            committed.header.try_to_vec().unwrap()
+        );
+        assert_eq!(
+            committed.to_bytes().to_vec(),
+            committed.try_to_vec().unwrap()
+        );
     }
 }
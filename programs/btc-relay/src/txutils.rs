@@ -0,0 +1,454 @@
+// Utilities for parsing bitcoin transactions on-chain,
+// all the parsed data is borrowed from the serialized transaction, so nothing is allocated on the heap
+// Format description: https://en.bitcoin.it/wiki/Transaction
+use anchor_lang::solana_program::hash;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TxParseError {
+    UnexpectedEnd,             // Data ended before the transaction was fully parsed
+    NonMinimalVarInt,          // VarInt isn't encoded using the shortest possible form
+    UnsupportedSegwitFlag(u8), // Segwit flag other than 1
+    NoWitnesses,               // Segwit flag set, but all the input witnesses are empty
+    TrailingData,              // Data continues after the transaction's locktime
+}
+
+pub struct TxInput<'a> {
+    pub reversed_prev_txid: &'a [u8; 32],
+    pub prev_vout: u32,
+    pub script_sig: &'a [u8],
+    pub sequence: u32,
+}
+
+pub struct TxOutput<'a> {
+    pub value: u64,
+    pub script_pubkey: &'a [u8],
+}
+
+pub struct ParsedTransaction<'a> {
+    data: &'a [u8],
+    pub version: i32,
+    pub segwit: bool,
+    pub input_count: u64,
+    pub output_count: u64,
+    pub locktime: u32,
+
+    inputs: &'a [u8],    // Serialized inputs, excluding the input count
+    outputs: &'a [u8],   // Serialized outputs, excluding the output count
+    witnesses: &'a [u8], // Serialized input witnesses, empty without the segwit flag
+    stripped: &'a [u8],  // Input count, inputs, output count and outputs, hashed for the txid
+}
+
+// Bounds-checked cursor over the serialized transaction
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn read_slice(&mut self, len: usize) -> Result<&'a [u8], TxParseError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .ok_or(TxParseError::UnexpectedEnd)?;
+        let slice = self
+            .data
+            .get(self.pos..end)
+            .ok_or(TxParseError::UnexpectedEnd)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<&'a [u8; N], TxParseError> {
+        Ok(self.read_slice(N)?.try_into().unwrap())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, TxParseError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32, TxParseError> {
+        Ok(u32::from_le_bytes(*self.read_array()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, TxParseError> {
+        Ok(u64::from_le_bytes(*self.read_array()?))
+    }
+
+    //Reads a varint, rejecting non-canonical encodings same as bitcoin core does
+    //varint description: https://en.bitcoin.it/wiki/Protocol_documentation#Variable_length_integer
+    fn read_var_int(&mut self) -> Result<u64, TxParseError> {
+        let (value, min) = match self.read_u8()? {
+            0xFF => (self.read_u64()?, 0x1_0000_0000),
+            0xFE => (self.read_u32()? as u64, 0x1_0000),
+            0xFD => (u16::from_le_bytes(*self.read_array()?) as u64, 0xFD),
+            n => return Ok(n as u64),
+        };
+        if value < min {
+            return Err(TxParseError::NonMinimalVarInt);
+        }
+        Ok(value)
+    }
+
+    fn read_var_slice(&mut self) -> Result<&'a [u8], TxParseError> {
+        let len = self.read_var_int()?;
+        self.read_slice(usize::try_from(len).map_err(|_| TxParseError::UnexpectedEnd)?)
+    }
+
+    fn skip_input(&mut self) -> Result<(), TxParseError> {
+        self.read_slice(32 + 4)?; // Previous txid + vout
+        self.read_var_slice()?; // Script sig
+        self.read_slice(4)?; // Sequence
+        Ok(())
+    }
+
+    fn skip_output(&mut self) -> Result<(), TxParseError> {
+        self.read_slice(8)?; // Value
+        self.read_var_slice()?; // Script pubkey
+        Ok(())
+    }
+}
+
+impl<'a> ParsedTransaction<'a> {
+    //Parses a bitcoin transaction from raw data, supports both, witness transactions and non-witness transactions
+    //Accepts exactly the same transactions as the `bitcoin` crate's deserialize
+    pub fn parse(data: &'a [u8]) -> Result<Self, TxParseError> {
+        let mut reader = Reader::new(data);
+
+        let version = reader.read_u32()? as i32;
+
+        let mut stripped_start = reader.pos;
+        let mut input_count = reader.read_var_int()?;
+
+        //BIP144 marker (zero inputs) followed by the flag
+        let segwit = input_count == 0;
+        if segwit {
+            let flag = reader.read_u8()?;
+            if flag != 1 {
+                return Err(TxParseError::UnsupportedSegwitFlag(flag));
+            }
+            stripped_start = reader.pos;
+            input_count = reader.read_var_int()?;
+        }
+
+        let inputs_start = reader.pos;
+        for _ in 0..input_count {
+            reader.skip_input()?;
+        }
+        let inputs_end = reader.pos;
+
+        let output_count = reader.read_var_int()?;
+        let outputs_start = reader.pos;
+        for _ in 0..output_count {
+            reader.skip_output()?;
+        }
+        let outputs_end = reader.pos;
+
+        let witnesses_start = reader.pos;
+        if segwit {
+            let mut has_witness = false;
+            for _ in 0..input_count {
+                let items = reader.read_var_int()?;
+                has_witness |= items > 0;
+                for _ in 0..items {
+                    reader.read_var_slice()?;
+                }
+            }
+            if input_count > 0 && !has_witness {
+                return Err(TxParseError::NoWitnesses);
+            }
+        }
+
+        let witnesses_end = reader.pos;
+
+        let locktime = reader.read_u32()?;
+
+        if reader.pos != data.len() {
+            return Err(TxParseError::TrailingData);
+        }
+
+        Ok(ParsedTransaction {
+            data,
+            version,
+            segwit,
+            input_count,
+            output_count,
+            locktime,
+            inputs: &data[inputs_start..inputs_end],
+            outputs: &data[outputs_start..outputs_end],
+            witnesses: &data[witnesses_start..witnesses_end],
+            stripped: &data[stripped_start..outputs_end],
+        })
+    }
+
+    pub fn inputs(&self) -> impl Iterator<Item = TxInput<'a>> {
+        let mut reader = Reader::new(self.inputs);
+        (0..self.input_count).map_while(move |_| {
+            Some(TxInput {
+                reversed_prev_txid: reader.read_array().ok()?,
+                prev_vout: reader.read_u32().ok()?,
+                script_sig: reader.read_var_slice().ok()?,
+                sequence: reader.read_u32().ok()?,
+            })
+        })
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = TxOutput<'a>> {
+        let mut reader = Reader::new(self.outputs);
+        (0..self.output_count).map_while(move |_| {
+            Some(TxOutput {
+                value: reader.read_u64().ok()?,
+                script_pubkey: reader.read_var_slice().ok()?,
+            })
+        })
+    }
+
+    //Witness items of the input at the index, none for transactions without witness data
+    pub fn witness(&self, index: u64) -> impl Iterator<Item = &'a [u8]> {
+        let mut reader = Reader::new(self.witnesses);
+        let items = (0..index)
+            .try_for_each(|_| {
+                for _ in 0..reader.read_var_int()? {
+                    reader.read_var_slice()?;
+                }
+                Ok(())
+            })
+            .and_then(|_| reader.read_var_int())
+            .unwrap_or(0);
+        (0..items).map_while(move |_| reader.read_var_slice().ok())
+    }
+
+    //Coinbase transactions have a single input spending the null outpoint
+    pub fn is_coinbase(&self) -> bool {
+        let mut inputs = self.inputs();
+        match (inputs.next(), inputs.next()) {
+            (Some(input), None) => {
+                *input.reversed_prev_txid == [0; 32] && input.prev_vout == u32::MAX
+            }
+            _ => false,
+        }
+    }
+
+    //Double sha256 of the transaction serialized without witness data (marker, flag and witnesses)
+    pub fn compute_txid(&self) -> [u8; 32] {
+        let locktime = &self.data[self.data.len() - 4..];
+        let first = hash::hashv(&[&self.data[..4], self.stripped, locktime]);
+        hash::hash(&first.to_bytes()).to_bytes()
+    }
+
+    //Double sha256 of the whole serialized transaction, including witness data
+    pub fn compute_wtxid(&self) -> [u8; 32] {
+        hash::hash(&hash::hash(self.data).to_bytes()).to_bytes()
+    }
+
+    //Double sha256 of the serialized outputs, same as hashOutputs of the BIP143 signature message
+    pub fn outputs_hash(&self) -> [u8; 32] {
+        hash::hash(&hash::hash(self.outputs).to_bytes()).to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::consensus::{deserialize, serialize};
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness};
+
+    fn input(seed: u8, script_len: usize, witness: &[&[u8]]) -> TxIn {
+        TxIn {
+            previous_output: OutPoint {
+                txid: Txid::from_byte_array([seed; 32]),
+                vout: seed as u32,
+            },
+            script_sig: ScriptBuf::from_bytes(vec![seed; script_len]),
+            sequence: Sequence(0xFFFF_FFF0 | seed as u32),
+            witness: Witness::from_slice(witness),
+        }
+    }
+
+    fn output(value: u64, script_len: usize) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: ScriptBuf::from_bytes(vec![0x51; script_len]),
+        }
+    }
+
+    fn sample_transactions() -> Vec<Transaction> {
+        vec![
+            Transaction {
+                version: Version::ONE,
+                lock_time: LockTime::ZERO,
+                input: vec![input(1, 107, &[])],
+                output: vec![output(5_000_000_000, 25)],
+            },
+            Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::from_consensus(840_000),
+                input: vec![input(2, 0, &[&[3; 72], &[4; 33]]), input(5, 0, &[])],
+                output: vec![output(1, 34), output(2, 300), output(0, 0)],
+            },
+            Transaction {
+                version: Version(-5),
+                lock_time: LockTime::from_consensus(0xFFFF_FFFF),
+                input: vec![input(6, 253, &[&[7; 300], &[], &[8]])],
+                output: vec![],
+            },
+            Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![],
+                output: vec![output(1000, 22)],
+            },
+            Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::null(),
+                    script_sig: ScriptBuf::from_bytes(vec![3, 0x40, 0x0d, 0x03]),
+                    sequence: Sequence::MAX,
+                    witness: Witness::from_slice(&[[0; 32]]),
+                }],
+                output: vec![output(312_500_000, 22), output(0, 38)],
+            },
+        ]
+    }
+
+    // Parses the data with both, the bitcoin crate and ParsedTransaction, checking the results match
+    fn assert_same_as_bitcoin(data: &[u8]) {
+        let expected: Result<Transaction, _> = deserialize(data);
+        let parsed = ParsedTransaction::parse(data);
+
+        match (expected, parsed) {
+            (Ok(tx), Ok(parsed)) => {
+                assert_eq!(parsed.version, tx.version.0);
+                assert_eq!(parsed.locktime, tx.lock_time.to_consensus_u32());
+                assert_eq!(parsed.compute_txid(), tx.compute_txid().to_byte_array());
+                assert_eq!(parsed.compute_wtxid(), tx.compute_wtxid().to_byte_array());
+
+                let inputs: Vec<_> = parsed.inputs().collect();
+                assert_eq!(inputs.len(), tx.input.len());
+                for (parsed, expected) in inputs.iter().zip(tx.input.iter()) {
+                    assert_eq!(
+                        parsed.reversed_prev_txid,
+                        expected.previous_output.txid.as_byte_array()
+                    );
+                    assert_eq!(parsed.prev_vout, expected.previous_output.vout);
+                    assert_eq!(parsed.script_sig, expected.script_sig.as_bytes());
+                    assert_eq!(parsed.sequence, expected.sequence.0);
+                }
+                for (index, expected) in tx.input.iter().enumerate() {
+                    assert!(parsed.witness(index as u64).eq(expected.witness.iter()));
+                }
+                assert_eq!(parsed.is_coinbase(), tx.is_coinbase());
+
+                let outputs: Vec<_> = parsed.outputs().collect();
+                assert_eq!(outputs.len(), tx.output.len());
+                for (parsed, expected) in outputs.iter().zip(tx.output.iter()) {
+                    assert_eq!(parsed.value, expected.value.to_sat());
+                    assert_eq!(parsed.script_pubkey, expected.script_pubkey.as_bytes());
+                }
+            }
+            (Err(_), Err(_)) => {}
+            (expected, parsed) => panic!(
+                "bitcoin: {:?}, parsed: {:?}, data: {:x?}",
+                expected.map(|tx| tx.compute_txid()),
+                parsed.map(|tx| tx.compute_txid()),
+                data
+            ),
+        }
+    }
+
+    #[test]
+    fn parses_sample_transactions() {
+        for tx in sample_transactions() {
+            let data = serialize(&tx);
+            let parsed = ParsedTransaction::parse(&data).unwrap();
+            assert_eq!(
+                parsed.segwit,
+                tx.input.is_empty() || tx.input.iter().any(|input| !input.witness.is_empty())
+            );
+            assert_same_as_bitcoin(&data);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_transactions() {
+        let data = serialize(&sample_transactions()[1]);
+
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert_eq!(
+            ParsedTransaction::parse(&trailing).err(),
+            Some(TxParseError::TrailingData)
+        );
+
+        assert_eq!(
+            ParsedTransaction::parse(&data[..data.len() - 1]).err(),
+            Some(TxParseError::UnexpectedEnd)
+        );
+
+        let mut bad_flag = data.clone();
+        bad_flag[5] = 2;
+        assert_eq!(
+            ParsedTransaction::parse(&bad_flag).err(),
+            Some(TxParseError::UnsupportedSegwitFlag(2))
+        );
+
+        // Version, 0xFD-prefixed input count 1
+        let non_minimal = [1, 0, 0, 0, 0xFD, 1, 0];
+        assert_eq!(
+            ParsedTransaction::parse(&non_minimal).err(),
+            Some(TxParseError::NonMinimalVarInt)
+        );
+
+        // Segwit serialization of a transaction without any witness
+        let mut no_witness = serialize(&sample_transactions()[0]);
+        no_witness.splice(4..4, [0, 1]);
+        let locktime_pos = no_witness.len() - 4;
+        no_witness.insert(locktime_pos, 0);
+        assert_eq!(
+            ParsedTransaction::parse(&no_witness).err(),
+            Some(TxParseError::NoWitnesses)
+        );
+        assert_same_as_bitcoin(&no_witness);
+    }
+
+    // Differential fuzzing against the bitcoin crate's decoder using mutated sample transactions,
+    // see fuzz/ for the coverage guided version
+    #[test]
+    fn fuzz_against_bitcoin_decoder() {
+        let mut rng_state: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut next = move || {
+            rng_state ^= rng_state << 13;
+            rng_state ^= rng_state >> 7;
+            rng_state ^= rng_state << 17;
+            rng_state
+        };
+
+        for tx in sample_transactions() {
+            let original = serialize(&tx);
+            for _ in 0..5_000 {
+                let mut data = original.clone();
+                for _ in 0..=(next() % 3) {
+                    let pos = (next() as usize) % (data.len() + 1);
+                    let byte =
+                        [0x00, 0x01, 0xFC, 0xFD, 0xFE, 0xFF, next() as u8][next() as usize % 7];
+                    match next() % 4 {
+                        0 if pos < data.len() => data[pos] = byte,
+                        1 => data.insert(pos, byte),
+                        2 if pos < data.len() => {
+                            data.remove(pos);
+                        }
+                        _ => data.truncate(pos),
+                    }
+                }
+                assert_same_as_bitcoin(&data);
+            }
+        }
+    }
+}
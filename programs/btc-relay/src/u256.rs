@@ -0,0 +1,355 @@
+// 256-bit unsigned integer used for difficulty targets and chain work,
+// stored as 4 u64 limbs, most significant limb first - the same order as the big endian byte arrays
+// stored on-chain, which also makes the derived ordering correct
+use core::ops::{Not, Shl, Shr};
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    pub const ZERO: U256 = U256([0; 4]);
+    pub const ONE: U256 = U256([0, 0, 0, 1]);
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+
+    //Limbs are ordered from the most significant one
+    pub const fn from_limbs(limbs: [u64; 4]) -> Self {
+        U256(limbs)
+    }
+
+    pub const fn from_u64(value: u64) -> Self {
+        U256([0, 0, 0, value])
+    }
+
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        U256(core::array::from_fn(|i| {
+            u64::from_be_bytes(bytes[i * 8..(i + 1) * 8].try_into().unwrap())
+        }))
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            bytes[i * 8..(i + 1) * 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    //Least significant 64 bits
+    pub fn low_u64(&self) -> u64 {
+        self.0[3]
+    }
+
+    //Number of bits needed to represent the number, 0 for zero
+    pub fn bits(&self) -> u32 {
+        for (i, limb) in self.0.iter().enumerate() {
+            if *limb != 0 {
+                return (4 - i as u32) * 64 - limb.leading_zeros();
+            }
+        }
+        0
+    }
+
+    pub fn overflowing_add(self, rhs: U256) -> (U256, bool) {
+        let mut result = [0u64; 4];
+        let mut carry = false;
+        for i in (0..4).rev() {
+            let (sum, overflow1) = self.0[i].overflowing_add(rhs.0[i]);
+            let (sum, overflow2) = sum.overflowing_add(carry as u64);
+            result[i] = sum;
+            carry = overflow1 || overflow2;
+        }
+        (U256(result), carry)
+    }
+
+    pub fn overflowing_sub(self, rhs: U256) -> (U256, bool) {
+        let mut result = [0u64; 4];
+        let mut borrow = false;
+        for i in (0..4).rev() {
+            let (diff, overflow1) = self.0[i].overflowing_sub(rhs.0[i]);
+            let (diff, overflow2) = diff.overflowing_sub(borrow as u64);
+            result[i] = diff;
+            borrow = overflow1 || overflow2;
+        }
+        (U256(result), borrow)
+    }
+
+    pub fn checked_add(self, rhs: U256) -> Option<U256> {
+        match self.overflowing_add(rhs) {
+            (result, false) => Some(result),
+            _ => None,
+        }
+    }
+
+    pub fn checked_sub(self, rhs: U256) -> Option<U256> {
+        match self.overflowing_sub(rhs) {
+            (result, false) => Some(result),
+            _ => None,
+        }
+    }
+
+    pub fn wrapping_add(self, rhs: U256) -> U256 {
+        self.overflowing_add(rhs).0
+    }
+
+    pub fn checked_mul_u32(self, rhs: u32) -> Option<U256> {
+        let mut result = [0u64; 4];
+        let mut carry: u128 = 0;
+        for i in (0..4).rev() {
+            let val = (self.0[i] as u128) * (rhs as u128) + carry;
+            result[i] = val as u64;
+            carry = val >> 64;
+        }
+        if carry != 0 {
+            return None;
+        }
+        Some(U256(result))
+    }
+
+    //Floor division by a small divisor, returns None for zero divisor
+    pub fn checked_div_u32(self, rhs: u32) -> Option<U256> {
+        if rhs == 0 {
+            return None;
+        }
+        let mut result = self.0;
+        let mut remainder: u128 = 0;
+        for limb in result.iter_mut() {
+            let val = (remainder << 64) | (*limb as u128);
+            *limb = (val / rhs as u128) as u64;
+            remainder = val % rhs as u128;
+        }
+        Some(U256(result))
+    }
+
+    //Floor division and remainder, returns None for zero divisor
+    //Shift-subtract long division, only iterating over the bits where the quotient can be non-zero,
+    // so dividing by a number of similar magnitude (e.g. 2^256 / target) is cheap
+    pub fn checked_div_rem(self, rhs: U256) -> Option<(U256, U256)> {
+        if rhs.is_zero() {
+            return None;
+        }
+        if self < rhs {
+            return Some((U256::ZERO, self));
+        }
+
+        let shift = self.bits() - rhs.bits();
+        let mut divisor = rhs << shift;
+        let mut remainder = self;
+        let mut quotient = U256::ZERO;
+
+        for i in (0..=shift).rev() {
+            if remainder >= divisor {
+                remainder = remainder.overflowing_sub(divisor).0;
+                quotient.0[3 - (i / 64) as usize] |= 1 << (i % 64);
+            }
+            divisor = divisor >> 1;
+        }
+
+        Some((quotient, remainder))
+    }
+
+    pub fn checked_div(self, rhs: U256) -> Option<U256> {
+        self.checked_div_rem(rhs).map(|(quotient, _)| quotient)
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256(self.0.map(|limb| !limb))
+    }
+}
+
+//Logical shift left, bits shifted past the most significant bit are dropped
+impl Shl<u32> for U256 {
+    type Output = U256;
+
+    fn shl(self, shift: u32) -> U256 {
+        let mut result = [0u64; 4];
+        let limb_shift = (shift / 64) as usize;
+        let bit_shift = shift % 64;
+        for (i, limb) in result
+            .iter_mut()
+            .enumerate()
+            .take(4usize.saturating_sub(limb_shift))
+        {
+            let src = i + limb_shift;
+            *limb = self.0[src] << bit_shift;
+            if bit_shift > 0 && src + 1 < 4 {
+                *limb |= self.0[src + 1] >> (64 - bit_shift);
+            }
+        }
+        U256(result)
+    }
+}
+
+//Logical shift right
+impl Shr<u32> for U256 {
+    type Output = U256;
+
+    fn shr(self, shift: u32) -> U256 {
+        let mut result = [0u64; 4];
+        let limb_shift = (shift / 64) as usize;
+        let bit_shift = shift % 64;
+        for i in limb_shift..4 {
+            let dst = i;
+            let src = i - limb_shift;
+            result[dst] = self.0[src] >> bit_shift;
+            if bit_shift > 0 && src > 0 {
+                result[dst] |= self.0[src - 1] << (64 - bit_shift);
+            }
+        }
+        U256(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(hex: &str) -> U256 {
+        let padded = format!("{:0>64}", hex);
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&padded[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        U256::from_be_bytes(bytes)
+    }
+
+    //Values spread over all magnitudes, used to cross-check the operations against u128 and each other
+    fn sample_values() -> Vec<U256> {
+        let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut values = vec![U256::ZERO, U256::ONE, U256::MAX];
+        for bits in [1, 7, 63, 64, 65, 127, 128, 129, 200, 255, 256] {
+            let value = U256([next(), next(), next(), next()]);
+            values.push(value >> (256 - bits));
+        }
+        values
+    }
+
+    #[test]
+    fn byte_conversion_round_trip() {
+        let bytes: [u8; 32] = core::array::from_fn(|i| i as u8 + 1);
+        let value = U256::from_be_bytes(bytes);
+        assert_eq!(value.to_be_bytes(), bytes);
+        assert_eq!(value.low_u64(), 0x191A_1B1C_1D1E_1F20);
+        assert_eq!(value.bits(), 249);
+        assert_eq!(U256::ZERO.bits(), 0);
+        assert_eq!(U256::MAX.bits(), 256);
+    }
+
+    #[test]
+    fn ordering_matches_big_endian_bytes() {
+        let values = sample_values();
+        for a in values.iter() {
+            for b in values.iter() {
+                assert_eq!(a.cmp(b), a.to_be_bytes().cmp(&b.to_be_bytes()));
+            }
+        }
+    }
+
+    #[test]
+    fn matches_u128_arithmetic() {
+        let samples: [u128; 6] = [
+            0,
+            1,
+            0xFFFF_FFFF,
+            u64::MAX as u128,
+            1 << 100,
+            u128::MAX >> 1,
+        ];
+        for a in samples {
+            for b in samples {
+                let (ua, ub) = (U256::from_be_bytes(pad(a)), U256::from_be_bytes(pad(b)));
+                assert_eq!(ua.checked_add(ub).unwrap().to_be_bytes(), pad_sum(a, b));
+                if a >= b {
+                    assert_eq!(ua.checked_sub(ub).unwrap(), U256::from_be_bytes(pad(a - b)));
+                } else {
+                    assert_eq!(ua.checked_sub(ub), None);
+                }
+                if let (Some(quotient), Some(remainder)) = (a.checked_div(b), a.checked_rem(b)) {
+                    assert_eq!(
+                        ua.checked_div_rem(ub).unwrap(),
+                        (
+                            U256::from_be_bytes(pad(quotient)),
+                            U256::from_be_bytes(pad(remainder))
+                        )
+                    );
+                }
+            }
+            for n in [1u32, 3, 600, 1_209_600, u32::MAX] {
+                let ua = U256::from_be_bytes(pad(a));
+                assert_eq!(
+                    ua.checked_div_u32(n).unwrap(),
+                    U256::from_be_bytes(pad(a / n as u128))
+                );
+            }
+        }
+
+        fn pad(value: u128) -> [u8; 32] {
+            let mut bytes = [0u8; 32];
+            bytes[16..].copy_from_slice(&value.to_be_bytes());
+            bytes
+        }
+
+        //a + b can overflow u128, carry it into the upper half
+        fn pad_sum(a: u128, b: u128) -> [u8; 32] {
+            let (sum, carry) = a.overflowing_add(b);
+            let mut bytes = pad(sum);
+            bytes[15] = carry as u8;
+            bytes
+        }
+    }
+
+    #[test]
+    fn overflow_is_reported() {
+        assert_eq!(U256::MAX.checked_add(U256::ONE), None);
+        assert_eq!(U256::MAX.wrapping_add(U256::ONE), U256::ZERO);
+        assert_eq!(U256::ZERO.checked_sub(U256::ONE), None);
+        assert_eq!(U256::MAX.checked_mul_u32(2), None);
+        assert_eq!((U256::MAX >> 1).checked_mul_u32(2), Some(U256::MAX << 1));
+        assert_eq!(U256::ONE.checked_div_u32(0), None);
+        assert_eq!(U256::ONE.checked_div_rem(U256::ZERO), None);
+    }
+
+    #[test]
+    fn division_identity() {
+        let values = sample_values();
+        for a in values.iter() {
+            for b in values.iter().filter(|b| !b.is_zero()) {
+                let (quotient, remainder) = a.checked_div_rem(*b).unwrap();
+                assert!(remainder < *b);
+
+                //quotient * b + remainder == a, checked with shift-and-add multiplication
+                let mut product = U256::ZERO;
+                for bit in 0..quotient.bits() {
+                    if (quotient >> bit).low_u64() & 1 == 1 {
+                        product = product.checked_add(*b << bit).unwrap();
+                    }
+                }
+                assert_eq!(product.checked_add(remainder).unwrap(), *a);
+            }
+        }
+    }
+
+    #[test]
+    fn shifts() {
+        let value = from_hex("8000000000000000000000000000000000000000000000000000000000000001");
+        assert_eq!(value << 1, U256::from_u64(2));
+        assert_eq!(value >> 255, U256::ONE);
+        assert_eq!(value >> 256, U256::ZERO);
+        assert_eq!(U256::ONE << 255 >> 255, U256::ONE);
+        assert_eq!(U256::ONE << 64, from_hex("10000000000000000"));
+        assert_eq!(!U256::ZERO, U256::MAX);
+    }
+}
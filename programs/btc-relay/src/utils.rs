@@ -1,39 +1,47 @@
+use anchor_lang::solana_program::secp256k1_recover::secp256k1_recover;
 use anchor_lang::{prelude::*, solana_program::clock};
 use bitcoin::blockdata::opcodes::all::*;
+use bitcoin::consensus::Encodable;
 use bitcoin::hashes::hash160::Hash as Hash160;
 use bitcoin::hashes::sha256d;
 use bitcoin::hashes::Hash;
 use bitcoin::hex::FromHex;
 use bitcoin::script::Builder;
-use bitcoin::{Address, Network, Transaction};
+use bitcoin::secp256k1::{Scalar, SecretKey, XOnlyPublicKey};
+use bitcoin::taproot::{LeafVersion, TapLeafHash, TapNodeHash, TapTweakHash};
+use bitcoin::{Script, ScriptBuf, Transaction};
 // Utilities for block header verification
-use crate::arrayutils;
 use crate::errors::*;
 use crate::structs::*;
+use crate::txutils::ParsedTransaction;
+use crate::u256::U256;
 
+// Public key of the P2WSH deposit scripts used before deposits were derived from the bridge key,
+// their outputs are minted until the bridge key's authority ends legacy deposits
 pub const BITCOIN_DEPOSIT_PUBKEY: &str =
     "0288e64b7fd0bcdaf5c0081d068f6a6f7b6ea0036ebabf3daabc74c2c7e1191e2d";
 
+//secp256k1's curve order n
+const CURVE_ORDER: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+];
+
+//Script prefix of the coinbase output carrying the witness commitment: OP_RETURN OP_PUSHBYTES_36 aa21a9ed
+//https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki#commitment-structure
+const WITNESS_COMMITMENT_PREFIX: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
 // Returns current timestamp read from Solana's on-chain clock
 pub fn now_ts() -> Result<u32> {
     Ok(clock::Clock::get()?.unix_timestamp.try_into().unwrap())
 }
 
-// https://en.bitcoin.it/wiki/Difficulty#How_is_difficulty_calculated.3F_What_is_the_difference_between_bdiff_and_pdiff.3F
-const MAX_DIFFICULTY: [u8; 32] = [
-    0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0xFF_u8, 0xFF_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8,
-    0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8,
-    0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8,
-    0x00_u8, 0x00_u8,
-];
-
+//Maximum allowed difficulty target (lowest possible mining difficulty), powLimit in Bitcoin Core's chainparams
 //https://en.bitcoin.it/wiki/Target#What_is_the_maximum_target.3F
-const UNROUNDED_MAX_TARGET: [u8; 32] = [
-    0x00_u8, 0x00_u8, 0x00_u8, 0x00_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8,
-    0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8,
-    0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8, 0xFF_u8,
-    0xFF_u8, 0xFF_u8,
-];
+#[cfg(not(feature = "bitcoin_regtest"))]
+pub const POW_LIMIT: U256 = U256::from_limbs([0x0000_0000_FFFF_FFFF, u64::MAX, u64::MAX, u64::MAX]);
+#[cfg(feature = "bitcoin_regtest")]
+pub const POW_LIMIT: U256 = U256::from_limbs([0x7FFF_FFFF_FFFF_FFFF, u64::MAX, u64::MAX, u64::MAX]);
 
 //Bitcoin constants
 const DIFF_ADJUSTMENT_INTERVAL: u32 = 2016;
@@ -49,133 +57,113 @@ const TARGET_TIMESPAN_MUL_4: u32 = TARGET_TIMESPAN * 4;
 // with the possibility of the Solana's on-chain clock being skewed, we chose double the value - 4 hours
 const MAX_FUTURE_BLOCKTIME: u32 = 4 * 60 * 60;
 
-//Compresses difficulty target to nBits
+//Compresses difficulty target to nBits, same as Bitcoin Core's arith_uint256::GetCompact
 //Description: https://btcinformation.org/en/developer-reference#target-nbits
-pub fn target_to_nbits(target: [u8; 32]) -> u32 {
-    let mut n_size: u32 = 0;
-    #[allow(clippy::needless_range_loop)]
-    for i in 0..32 {
-        if target[i] > 0 {
-            n_size = (32 - i) as u32;
-            break;
-        }
-    }
+pub fn target_to_nbits(target: U256) -> u32 {
+    let mut n_size = target.bits().div_ceil(8);
 
-    let mut n_compact: u32 = 0;
-
-    for i in 0..3 {
-        let pos = (32 - n_size + i) as usize;
-        if pos < 32 {
-            n_compact |= (target[pos] as u32) << ((2 - i) * 8);
-        }
-    }
+    let mut n_compact = if n_size <= 3 {
+        (target.low_u64() << (8 * (3 - n_size))) as u32
+    } else {
+        (target >> (8 * (n_size - 3))).low_u64() as u32
+    };
 
+    //Mantissa's highest bit is the sign bit, so it's moved to the exponent instead
     if (n_compact & 0x00800000) > 0 {
         n_compact >>= 8;
         n_size += 1;
     }
 
-    n_compact = n_compact & 0x00FFFFFF | (n_size << 24) & 0xFF000000;
+    n_compact | (n_size << 24)
+}
 
-    n_compact
+//Difficulty target decoded from nBits
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DecodedTarget {
+    pub target: U256,
+    pub negative: bool, //Sign bit is set and the mantissa is non-zero
+    pub overflow: bool, //Target doesn't fit into 256 bits
 }
 
-//Calculates difficulty target from nBits
+//Calculates difficulty target from nBits, same as Bitcoin Core's arith_uint256::SetCompact
 //Description: https://btcinformation.org/en/developer-reference#target-nbits
-pub fn nbits_to_target(nbits: u32) -> [u8; 32] {
-    let mut target: [u8; 32] = [0; 32];
-
-    let n_size = (nbits >> 24) & 0xFF;
-
-    let n_word: [u8; 3] = [
-        ((nbits >> 16) & 0x7F) as u8,
-        ((nbits >> 8) & 0xFF) as u8,
-        ((nbits) & 0xFF) as u8,
-    ];
-
-    let start = (32 - n_size) as usize;
-    for i in 0..3 {
-        if start + i < 32 {
-            target[start + i] = n_word[i];
-        }
+pub fn nbits_to_target(nbits: u32) -> DecodedTarget {
+    let n_size = nbits >> 24;
+    let mut n_word = nbits & 0x007FFFFF;
+
+    //Small exponents cut off the lowest bytes of the mantissa, sign and overflow are checked after that
+    let target = if n_size <= 3 {
+        n_word >>= 8 * (3 - n_size);
+        U256::from_u64(n_word as u64)
+    } else {
+        U256::from_u64(n_word as u64) << (8 * (n_size - 3))
+    };
+
+    DecodedTarget {
+        target,
+        negative: n_word != 0 && (nbits & 0x00800000) != 0,
+        overflow: n_word != 0
+            && (n_size > 34 || (n_word > 0xFF && n_size > 33) || (n_word > 0xFFFF && n_size > 32)),
     }
-
-    target
 }
 
-//Calculates difficulty from difficulty target
-//https://en.bitcoin.it/wiki/Difficulty#How_is_difficulty_calculated.3F_What_is_the_difference_between_bdiff_and_pdiff.3F
-// difficulty = MAX_DIFFICULTY/target
-pub fn get_difficulty(target: [u8; 32]) -> [u8; 32] {
-    //Find leading byte (first non-zero byte)
-    let mut start = 0;
-
-    #[allow(clippy::needless_range_loop)]
-    for i in 0..32 {
-        if target[i] > 0 {
-            start = i;
-            break;
-        }
+//Returns the difficulty target if nBits is valid for a block header: not negative, not overflowing,
+// non-zero and not above the pow limit, same checks as Bitcoin Core's CheckProofOfWork
+pub fn pow_target(nbits: u32) -> Option<U256> {
+    let decoded = nbits_to_target(nbits);
+    if decoded.negative
+        || decoded.overflow
+        || decoded.target.is_zero()
+        || decoded.target > POW_LIMIT
+    {
+        return None;
     }
+    Some(decoded.target)
+}
 
-    let shift = 32 - start - 3;
-
-    //Target calculated from nBits will only ever have 3 bytes set,
-    // preceeded and followed by zeroes 00 00 00 ... A1 D0 21 ... 00 00
-    //We extract these first 3 bytes here to a u32,
-    // essentially doing a floor division on the target:
-    // num = target//(2^shift)
-    let mut num: u32 = 0;
-    for i in 0..3 {
-        num |= (target[start + i] as u32) << ((2 - i) * 8);
+//Calculates the work represented by a block with the given target, same as Bitcoin Core's GetBlockProof
+//https://github.com/bitcoin/bitcoin/blob/master/src/chain.cpp
+// work = 2^256 / (target + 1)
+//2^256 doesn't fit into U256, but since it's equal to ~target + target + 1:
+// work = (~target + target + 1) / (target + 1) = ~target / (target + 1) + 1
+pub fn get_block_proof(target: U256) -> U256 {
+    if target.is_zero() {
+        return U256::ZERO;
     }
-
-    //Do the division
-    // arr = MAX_DIFFICULTY//num
-    let mut arr: [u8; 32] = MAX_DIFFICULTY;
-    arrayutils::div_in_place(&mut arr, num);
-
-    let mut result: [u8; 32] = [0; 32];
-
-    //Shift the result back:
-    // result = arr//(2^shift)
-    #[allow(clippy::manual_memcpy)]
-    for i in 0..(32 - shift) {
-        result[i + shift] = arr[i];
+    match target.checked_add(U256::ONE) {
+        Some(divisor) => (!target)
+            .checked_div(divisor)
+            .unwrap_or(U256::ZERO)
+            .wrapping_add(U256::ONE),
+        //target = 2^256 - 1, work = 2^256 / 2^256
+        None => U256::ONE,
     }
-
-    //Result
-    // result = (MAX_DIFFICULTY // (target // (2^shift))) // (2^shift)
-    // result = ((MAX_DIFFICULTY * (2^shift)) // target) // (2^shift)
-    // result = (MAX_DIFFICULTY * (2^shift)) // (target * (2^shift))
-    // result = MAX_DIFFICULTY // target
-    result
 }
 
 //Difficulty retargetting algorithm
 //https://minerdaily.com/2021/how-are-bitcoins-difficulty-and-hash-rate-calculated/#Difficulty_Adjustments
 // new_difficulty_target = prev_difficulty_target * (timespan / target_timespan)
-pub fn compute_new_nbits(prev_time: u32, start_time: u32, prev_target: &mut [u8; 32]) -> u32 {
-    let mut time_span = prev_time - start_time;
+pub fn compute_new_nbits(prev_time: u32, start_time: u32, prev_target: U256) -> u32 {
+    //The period's last block can be older than its first one (only the median time past has to
+    // increase), Core's signed timespan is then clamped to the minimum
+    let time_span = prev_time.saturating_sub(start_time);
 
     //Difficulty increase/decrease multiples are clamped between 0.25 (-75%) and 4 (+300%)
-    if time_span < TARGET_TIMESPAN_DIV_4 {
-        time_span = TARGET_TIMESPAN_DIV_4;
-    }
-    if time_span > TARGET_TIMESPAN_MUL_4 {
-        time_span = TARGET_TIMESPAN_MUL_4;
-    }
+    let time_span = time_span.clamp(TARGET_TIMESPAN_DIV_4, TARGET_TIMESPAN_MUL_4);
 
-    arrayutils::mul_in_place(prev_target, time_span);
-    arrayutils::div_in_place(prev_target, TARGET_TIMESPAN);
+    //Overflow means the new target would be past the maximum allowed target anyway
+    let new_target = prev_target
+        .checked_mul_u32(time_span)
+        .and_then(|target| target.checked_div_u32(TARGET_TIMESPAN))
+        .unwrap_or(U256::MAX);
 
     //Check if the target isn't past maximum allowed target (lowest possible mining difficulty)
     //https://en.bitcoin.it/wiki/Target#What_is_the_maximum_target.3F
-    if arrayutils::gt_arr(*prev_target, UNROUNDED_MAX_TARGET) {
-        return target_to_nbits(UNROUNDED_MAX_TARGET);
+    if new_target > POW_LIMIT {
+        return target_to_nbits(POW_LIMIT);
     }
 
-    target_to_nbits(*prev_target)
+    target_to_nbits(new_target)
 }
 
 pub fn should_diff_adjust(block_height: u32) -> bool {
@@ -190,15 +178,14 @@ pub fn has_correct_difficulty_target(
 ) -> bool {
     let prev_nbits = prev_committed_header.header.nbits;
 
-    if should_diff_adjust(prev_committed_header.blockheight + 1) {
-        let mut prev_target = nbits_to_target(prev_nbits);
+    //Regtest never adjusts the difficulty (fPowNoRetargeting)
+    if should_diff_adjust(prev_committed_header.blockheight + 1)
+        && !cfg!(feature = "bitcoin_regtest")
+    {
+        let prev_target = nbits_to_target(prev_nbits).target;
         let prev_time = prev_committed_header.header.timestamp;
         let start_time = prev_committed_header.last_diff_adjustment;
-        msg!("Prev target: {:x?}", prev_target);
-        let new_nbits = compute_new_nbits(prev_time, start_time, &mut prev_target);
-        msg!("New computed nbits: {:x?}", new_nbits);
-        msg!("New target: {:x?}", prev_target);
-        current_nbits == new_nbits
+        current_nbits == compute_new_nbits(prev_time, start_time, prev_target)
     } else {
         current_nbits == prev_nbits
     }
@@ -225,9 +212,39 @@ pub fn verify_header(
     header: &BlockHeader,
     last_commited_header: &mut CommittedBlockHeader,
     remaining_account: &AccountInfo,
+    header_topic_bump: u8,
     _signer: &Signer,
     program_id: &Pubkey,
 ) -> Result<[u8; 32]> {
+    //Check if valid topic was specified in remaining accounts
+    //Each block is assigned a unique generated PDA,
+    // this is used purely for indexing purposes
+    //The bump is provided by the caller, as searching for it with find_program_address is expensive
+    let last_block_hash = header.get_block_hash();
+    let block_header_topic = Pubkey::create_program_address(
+        &[b"header", &last_block_hash, &[header_topic_bump]],
+        program_id,
+    )
+    .map_err(|_| RelayErrorCode::InvalidHeaderTopic)?;
+    require!(
+        block_header_topic == *remaining_account.key,
+        RelayErrorCode::InvalidHeaderTopic
+    );
+
+    apply_header(header, last_block_hash, last_commited_header, now_ts()?)?;
+
+    Ok(last_block_hash)
+}
+
+//Verifies the header against the previous committed header and updates it in place to the new one,
+// doesn't depend on any on-chain state, so it can also be used off-chain to simulate the relay
+//block_hash is the header's double sha256 hash (as returned by get_block_hash), current_timestamp is unix time
+pub fn apply_header(
+    header: &BlockHeader,
+    block_hash: [u8; 32],
+    last_commited_header: &mut CommittedBlockHeader,
+    current_timestamp: u32,
+) -> Result<()> {
     //Correct difficulty target
     //
     //Should be disabled for testnet, since if no valid block is
@@ -248,23 +265,12 @@ pub fn verify_header(
         last_commited_header.last_diff_adjustment = timestamp;
     }
 
-    //Check if valid topic was specified in remaining accounts
-    //Each block is assigned a unique generated PDA,
-    // this is used purely for indexing purposes
-    let last_block_hash = header.get_block_hash()?;
-    let (block_header_topic, _block_header_bump) =
-        Pubkey::find_program_address(&[b"header", &last_block_hash], program_id);
-    require!(
-        block_header_topic == *remaining_account.key,
-        RelayErrorCode::InvalidHeaderTopic
-    );
-
     //Check block's PoW, it's hash has to be less than the target
-    let mut block_hash = last_block_hash;
+    let mut block_hash = block_hash;
     block_hash.reverse();
-    let target = nbits_to_target(header.nbits);
+    let target = pow_target(header.nbits).ok_or(RelayErrorCode::InvalidNbits)?;
     require!(
-        arrayutils::lte_arr(block_hash, target),
+        U256::from_be_bytes(block_hash) <= target,
         RelayErrorCode::ErrPowToolow
     );
 
@@ -280,8 +286,6 @@ pub fn verify_header(
         RelayErrorCode::ErrTimestampToolow
     );
 
-    let current_timestamp = now_ts()?;
-
     //Verify timestamp is no more than MAX_FUTURE_BLOCKTIME in the future
     require!(
         timestamp < current_timestamp + MAX_FUTURE_BLOCKTIME,
@@ -296,9 +300,12 @@ pub fn verify_header(
             last_commited_header.prev_block_timestamps[i];
     }
     last_commited_header.prev_block_timestamps[9] = prev_block_timestamp;
-    arrayutils::add_in_place(&mut last_commited_header.chain_work, get_difficulty(target));
+    last_commited_header.chain_work = U256::from_be_bytes(last_commited_header.chain_work)
+        .checked_add(get_block_proof(target))
+        .ok_or(RelayErrorCode::ChainWorkOverflow)?
+        .to_be_bytes();
 
-    Ok(last_block_hash)
+    Ok(())
 }
 
 // Calculates merkle root based on the transaction id and merkle proof,
@@ -329,26 +336,613 @@ pub fn compute_merkle(
     current_hash
 }
 
-pub fn bridge_deposit_script(solana_pub: [u8; 32], bitcoin_pubkey_hash: [u8; 20]) -> Builder {
+// Finds the witness commitment in the coinbase transaction outputs,
+// if there are multiple matching outputs, the one with the highest output index is used (BIP141)
+pub fn find_witness_commitment(coinbase_tx: &ParsedTransaction) -> Option<[u8; 32]> {
+    coinbase_tx
+        .outputs()
+        .map(|out| out.script_pubkey)
+        .filter(|script| script.len() >= 38 && script[..6] == WITNESS_COMMITMENT_PREFIX)
+        .last()
+        .map(|script| script[6..38].try_into().unwrap())
+}
+
+// Computes the witness commitment from the witness merkle root and the witness reserved value,
+// Double-SHA256(witness root hash|witness reserved value)
+pub fn compute_witness_commitment(
+    reversed_witness_root: &[u8; 32],
+    witness_reserved_value: &[u8; 32],
+) -> [u8; 32] {
+    let bytes = [*reversed_witness_root, *witness_reserved_value].concat();
+    sha256d::Hash::hash(&bytes).to_byte_array()
+}
+
+// Computes the hash of all transaction outputs, same as hashOutputs of the BIP143 signature message
+// https://github.com/bitcoin/bips/blob/master/bip-0143.mediawiki#specification
+pub fn outputs_hash(bitcoin_tx: &Transaction) -> [u8; 32] {
+    let mut engine = sha256d::Hash::engine();
+    for out in bitcoin_tx.output.iter() {
+        out.consensus_encode(&mut engine).unwrap();
+    }
+    sha256d::Hash::from_engine(engine).to_byte_array()
+}
+
+// Tapscript of the receiver's deposit, the only leaf of its taproot output. Only the group key of the
+// signer nodes can sign for it, the receiver is dropped
+pub fn bridge_deposit_script(solana_pub: [u8; 32], group_key: [u8; 32]) -> ScriptBuf {
     Builder::new()
         .push_slice(solana_pub)
         .push_opcode(OP_DROP)
-        .push_opcode(OP_HASH160)
-        .push_slice(bitcoin_pubkey_hash)
-        .push_opcode(OP_EQUALVERIFY)
+        .push_slice(group_key)
         .push_opcode(OP_CHECKSIG)
+        .into_script()
 }
 
-pub fn bridge_mint_amount(bitcoin_tx: &Transaction, solana_pub: [u8; 32]) -> u64 {
+// Adds tweak * G to the point of the x-only key with the even y coordinate, returning the x-only
+// result. The point addition is done by the secp256k1_recover syscall, which returns
+// r^-1 * (s * R - z * G) for the point R of x coordinate r (r + n with the recovery id's second
+// bit) and the recovery id's y parity. With s = r and z = -r * tweak it returns R + tweak * G
+pub fn tweak_x_only_key(
+    key: [u8; 32],
+    tweak: [u8; 32],
+) -> std::result::Result<[u8; 32], RelayErrorCode> {
+    XOnlyPublicKey::from_slice(&key).map_err(|_| RelayErrorCode::InvalidBridgeKey)?;
+    let tweak = Scalar::from_be_bytes(tweak).map_err(|_| RelayErrorCode::InvalidTaprootTweak)?;
+
+    //The x coordinate is below the field size p, which is below 2n
+    let (r, recovery_id) =
+        match U256::from_be_bytes(key).checked_sub(U256::from_be_bytes(CURVE_ORDER)) {
+            Some(r) => (r.to_be_bytes(), 2),
+            None => (key, 0),
+        };
+    //r is zero for the key of x coordinate n, which can't be recovered
+    let r = SecretKey::from_slice(&r).map_err(|_| RelayErrorCode::InvalidBridgeKey)?;
+    let z = r
+        .mul_tweak(&tweak)
+        .map_err(|_| RelayErrorCode::InvalidTaprootTweak)?
+        .negate();
+    let mut signature = [0; 64];
+    signature[..32].copy_from_slice(&r.secret_bytes());
+    signature[32..].copy_from_slice(&r.secret_bytes());
+    //Fails for the point at infinity, when the tweak is the key's negated discrete logarithm
+    let tweaked = secp256k1_recover(&z.secret_bytes(), recovery_id, &signature)
+        .map_err(|_| RelayErrorCode::InvalidTaprootTweak)?;
+    let mut output_key = [0; 32];
+    output_key.copy_from_slice(&tweaked.to_bytes()[..32]);
+    Ok(output_key)
+}
+
+// BIP341 output key of the x-only internal key committing to the script tree's merkle root
+// https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki#constructing-and-spending-taproot-outputs
+pub fn taproot_output_key(
+    internal_key: [u8; 32],
+    merkle_root: Option<TapNodeHash>,
+) -> std::result::Result<[u8; 32], RelayErrorCode> {
+    let key =
+        XOnlyPublicKey::from_slice(&internal_key).map_err(|_| RelayErrorCode::InvalidBridgeKey)?;
+    let tweak = TapTweakHash::from_key_and_tweak(key, merkle_root);
+    tweak_x_only_key(internal_key, tweak.to_byte_array())
+}
+
+// Taproot output key of the receiver's deposit: the x-only group key tweaked with the deposit script
+// as the internal key's only leaf
+pub fn bridge_deposit_output_key(
+    solana_pub: [u8; 32],
+    group_key: [u8; 32],
+) -> std::result::Result<[u8; 32], RelayErrorCode> {
+    let script = bridge_deposit_script(solana_pub, group_key);
+    let leaf = TapNodeHash::from(TapLeafHash::from_script(&script, LeafVersion::TapScript));
+    taproot_output_key(group_key, Some(leaf))
+}
+
+// Script pubkey of the receiver's P2TR deposit output
+pub fn bridge_deposit_script_pubkey(
+    solana_pub: [u8; 32],
+    group_key: [u8; 32],
+) -> std::result::Result<ScriptBuf, RelayErrorCode> {
+    let output_key = bridge_deposit_output_key(solana_pub, group_key)?;
+    Ok(Builder::new()
+        .push_opcode(OP_PUSHNUM_1)
+        .push_slice(output_key)
+        .into_script())
+}
+
+// Script pubkey of the receiver's legacy P2WSH deposit output, spent with the key of
+// BITCOIN_DEPOSIT_PUBKEY
+pub fn legacy_deposit_script_pubkey(solana_pub: [u8; 32]) -> ScriptBuf {
     let bridge_pubkey: [u8; 33] = FromHex::from_hex(BITCOIN_DEPOSIT_PUBKEY).unwrap();
     let pubkey_hash = Hash160::hash(&bridge_pubkey);
-    let expected_script = bridge_deposit_script(solana_pub, pubkey_hash.to_byte_array());
-    let expected_script_pubkey =
-        Address::p2wsh(expected_script.as_script(), Network::Regtest).script_pubkey();
+    let script = Builder::new()
+        .push_slice(solana_pub)
+        .push_opcode(OP_DROP)
+        .push_opcode(OP_HASH160)
+        .push_slice(pubkey_hash.to_byte_array())
+        .push_opcode(OP_EQUALVERIFY)
+        .push_opcode(OP_CHECKSIG)
+        .into_script();
+    ScriptBuf::new_p2wsh(&script.wscript_hash())
+}
 
+// Withdrawals can only pay standard outputs the signer nodes can relay, above the dust limit
+pub fn is_withdrawal_output(script_pubkey: &[u8], amount: u64) -> bool {
+    let script = Script::from_bytes(script_pubkey);
+    (script.is_p2pkh()
+        || script.is_p2sh()
+        || script.is_p2wpkh()
+        || script.is_p2wsh()
+        || script.is_p2tr())
+        && amount >= script.minimal_non_dust().to_sat()
+}
+
+// Outputs of the transaction paying the script pubkey
+pub fn outputs_paying(
+    bitcoin_tx: &ParsedTransaction,
+    script_pubkey: &Script,
+) -> Vec<DepositOutput> {
     bitcoin_tx
-        .output
-        .iter()
-        .filter(|out| out.script_pubkey == expected_script_pubkey)
-        .fold(0, |total, out| total + out.value.to_sat())
+        .outputs()
+        .zip(0..)
+        .filter(|(out, _)| out.script_pubkey == script_pubkey.as_bytes())
+        .map(|(out, vout)| DepositOutput {
+            vout,
+            amount: out.value,
+        })
+        .collect()
+}
+
+// Outputs of the transaction paying the receiver's deposit script, the deposited amount is their sum
+pub fn bridge_deposit_outputs(
+    bitcoin_tx: &ParsedTransaction,
+    solana_pub: [u8; 32],
+    group_key: [u8; 32],
+) -> std::result::Result<Vec<DepositOutput>, RelayErrorCode> {
+    let script_pubkey = bridge_deposit_script_pubkey(solana_pub, group_key)?;
+    Ok(outputs_paying(bitcoin_tx, &script_pubkey))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hex::{DisplayHex, FromHex};
+
+    fn from_hex(hex: &str) -> U256 {
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        U256::from_be_bytes(bytes)
+    }
+
+    #[test]
+    fn block_proof_matches_core() {
+        //Bitcoin Core's GetBlockProof values: 2^256 / (target + 1)
+        let cases = [
+            (
+                0x1d00ffff,
+                "0000000000000000000000000000000000000000000000000000000100010001",
+            ),
+            (
+                0x1d00d86a,
+                "000000000000000000000000000000000000000000000000000000012ed3afd2",
+            ),
+            (
+                0x1b0404cb,
+                "00000000000000000000000000000000000000000000000000003fb3ab764c00",
+            ),
+            (
+                0x17034219,
+                "000000000000000000000000000000000000000000004e9235f043634662e0cb",
+            ),
+            //Regtest, each block adds 2 to the chain work
+            (
+                0x207fffff,
+                "0000000000000000000000000000000000000000000000000000000000000002",
+            ),
+        ];
+        for (nbits, expected) in cases {
+            assert_eq!(
+                get_block_proof(nbits_to_target(nbits).target),
+                from_hex(expected),
+                "nbits {nbits:x}"
+            );
+        }
+
+        assert_eq!(get_block_proof(U256::ZERO), U256::ZERO);
+        assert_eq!(get_block_proof(U256::MAX), U256::ONE);
+    }
+
+    #[test]
+    fn chain_work_matches_mainnet() {
+        //Chain work reported by Bitcoin Core for mainnet blocks, the difficulty stayed at 1 (nBits 0x1d00ffff)
+        // until the first adjustment to 0x1d00d86a at height 32256
+        let expected = [
+            (
+                0,
+                "0000000000000000000000000000000000000000000000000000000100010001",
+            ),
+            (
+                1,
+                "0000000000000000000000000000000000000000000000000000000200020002",
+            ),
+            (
+                2015,
+                "000000000000000000000000000000000000000000000000000007e007e007e0",
+            ),
+            (
+                32255,
+                "00000000000000000000000000000000000000000000000000007e007e007e00",
+            ),
+            (
+                32256,
+                "00000000000000000000000000000000000000000000000000007e01acd42dd2",
+            ),
+        ];
+
+        let mut chain_work = U256::ZERO;
+        let mut checked = 0;
+        for height in 0..=32256 {
+            let nbits = if height < 32256 {
+                0x1d00ffff
+            } else {
+                0x1d00d86a
+            };
+            chain_work = chain_work
+                .checked_add(get_block_proof(nbits_to_target(nbits).target))
+                .unwrap();
+            if let Some((_, hex)) = expected.iter().find(|(h, _)| *h == height) {
+                assert_eq!(chain_work, from_hex(hex), "height {height}");
+                checked += 1;
+            }
+        }
+        assert_eq!(checked, expected.len());
+    }
+
+    #[test]
+    #[cfg(not(feature = "bitcoin_regtest"))]
+    fn new_nbits_clamped_to_max_target() {
+        //Blocks mined 4x slower than expected, difficulty can't go below the minimum
+        assert_eq!(
+            compute_new_nbits(TARGET_TIMESPAN * 4, 0, nbits_to_target(0x1d00ffff).target),
+            0x1d00ffff
+        );
+        //Regtest target is above the maximum mainnet target
+        assert_eq!(
+            compute_new_nbits(TARGET_TIMESPAN, 0, nbits_to_target(0x207fffff).target),
+            0x1d00ffff
+        );
+        //Blocks mined exactly on time, difficulty doesn't change
+        assert_eq!(
+            compute_new_nbits(TARGET_TIMESPAN, 0, nbits_to_target(0x17034219).target),
+            0x17034219
+        );
+        //Blocks mined 4x faster, target divided by 4
+        assert_eq!(
+            compute_new_nbits(TARGET_TIMESPAN / 4, 0, nbits_to_target(0x1d00ffff).target),
+            0x1c3fffc0
+        );
+        //Last block of the period older than the first one, clamped like the fastest period
+        assert_eq!(
+            compute_new_nbits(1_000, 2_000, nbits_to_target(0x1d00ffff).target),
+            0x1c3fffc0
+        );
+    }
+
+    //Mainnet periods of Bitcoin Core's pow_tests: the period's first block time, its last block
+    // height, time and nBits, and the nBits required from the next block
+    #[test]
+    #[cfg(not(any(feature = "bitcoin_testnet", feature = "bitcoin_regtest")))]
+    fn retargets_mainnet_periods_like_core() {
+        let cases = [
+            //Difficulty kept at the pow limit after the first period
+            (1231006505, 2015, 1233061996, 0x1d00ffff, 0x1d00ffff),
+            //First difficulty increase, at block 32256
+            (1261130161, 32255, 1262152739, 0x1d00ffff, 0x1d00d86a),
+            //Period faster than a quarter of the target timespan, clamped
+            (1279008237, 68543, 1279297671, 0x1c05a3f4, 0x1c0168fd),
+            //Period slower than four times the target timespan (the first time isn't a real
+            // block's), clamped
+            (1263163443, 46367, 1269211443, 0x1c387f6f, 0x1d00e1fd),
+        ];
+        for (first_time, last_height, last_time, last_nbits, next_nbits) in cases {
+            let last = CommittedBlockHeader {
+                chain_work: [0; 32],
+                header: BlockHeader {
+                    version: 1,
+                    reversed_prev_blockhash: [0; 32],
+                    merkle_root: [0; 32],
+                    timestamp: last_time,
+                    nbits: last_nbits,
+                    nonce: 0,
+                },
+                last_diff_adjustment: first_time,
+                blockheight: last_height,
+                prev_block_timestamps: [0; 10],
+            };
+            assert!(
+                has_correct_difficulty_target(last, next_nbits),
+                "height {last_height}"
+            );
+            assert!(!has_correct_difficulty_target(last, next_nbits - 1));
+            assert!(!has_correct_difficulty_target(last, next_nbits + 1));
+            assert!(
+                last_nbits == next_nbits || !has_correct_difficulty_target(last, last_nbits),
+                "height {last_height}"
+            );
+        }
+    }
+
+    //Test vectors from Bitcoin Core's arith_uint256_tests (bignum_SetCompact)
+    #[test]
+    fn nbits_decoding_matches_core() {
+        let cases: [(u32, u64, bool, bool, u32); 18] = [
+            //nBits, target, negative, overflow, re-encoded nBits
+            (0x00000000, 0, false, false, 0),
+            (0x00123456, 0, false, false, 0),
+            (0x01003456, 0, false, false, 0),
+            (0x02000056, 0, false, false, 0),
+            (0x03000000, 0, false, false, 0),
+            (0x04000000, 0, false, false, 0),
+            (0x00923456, 0, false, false, 0),
+            (0x01803456, 0, false, false, 0),
+            (0x02800056, 0, false, false, 0),
+            (0x03800000, 0, false, false, 0),
+            (0x04800000, 0, false, false, 0),
+            (0x01123456, 0x12, false, false, 0x01120000),
+            (0x01fedcba, 0x7e, true, false, 0x017e0000),
+            (0x02123456, 0x1234, false, false, 0x02123400),
+            (0x03123456, 0x123456, false, false, 0x03123456),
+            (0x04123456, 0x12345600, false, false, 0x04123456),
+            (0x04923456, 0x12345600, true, false, 0x04123456),
+            (0x05009234, 0x92340000, false, false, 0x05009234),
+        ];
+        for (nbits, target, negative, overflow, encoded) in cases {
+            let decoded = nbits_to_target(nbits);
+            assert_eq!(
+                decoded,
+                DecodedTarget {
+                    target: U256::from_u64(target),
+                    negative,
+                    overflow
+                },
+                "nbits {nbits:x}"
+            );
+            assert_eq!(target_to_nbits(decoded.target), encoded, "nbits {nbits:x}");
+        }
+
+        let decoded = nbits_to_target(0x20123456);
+        assert_eq!(
+            decoded.target,
+            from_hex("1234560000000000000000000000000000000000000000000000000000000000")
+        );
+        assert!(!decoded.negative && !decoded.overflow);
+        assert_eq!(target_to_nbits(decoded.target), 0x20123456);
+
+        assert!(nbits_to_target(0xff123456).overflow);
+        assert!(nbits_to_target(0x22010000).overflow);
+        assert!(nbits_to_target(0x22000100).overflow);
+        assert!(!nbits_to_target(0x22000001).overflow);
+        assert!(nbits_to_target(0x21010000).overflow);
+        assert!(!nbits_to_target(0x21000100).overflow);
+    }
+
+    #[test]
+    fn pow_target_rejects_invalid_nbits() {
+        assert_eq!(pow_target(0x1d00ffff), Some(U256::from_u64(0xffff) << 208));
+        assert_eq!(
+            pow_target(0x17034219),
+            Some(nbits_to_target(0x17034219).target)
+        );
+        //Negative
+        assert_eq!(pow_target(0x17834219), None);
+        //Overflow
+        assert_eq!(pow_target(0xff123456), None);
+        //Zero
+        assert_eq!(pow_target(0x00000000), None);
+        assert_eq!(pow_target(0x01003456), None);
+        //Above the pow limit
+        if cfg!(feature = "bitcoin_regtest") {
+            assert_eq!(
+                pow_target(0x207fffff),
+                Some(nbits_to_target(0x207fffff).target)
+            );
+            assert_eq!(pow_target(0x21008000), None);
+        } else {
+            assert_eq!(pow_target(0x1e00ffff), None);
+            assert_eq!(pow_target(0x1d010000), None);
+            assert_eq!(pow_target(0x207fffff), None);
+        }
+    }
+
+    //Decoding and encoding round trip for every exponent and a spread of mantissas
+    #[test]
+    fn nbits_round_trip() {
+        let edge_mantissas = [
+            0x000000, 0x000001, 0x00007f, 0x000080, 0x0000ff, 0x000100, 0x007fff, 0x008000,
+            0x00ffff, 0x010000, 0x7fffff,
+        ];
+        let mantissas = edge_mantissas
+            .into_iter()
+            .chain((0..0x800000).step_by(0x1f3));
+
+        for mantissa in mantissas {
+            for n_size in 0..=0x22u32 {
+                let nbits = (n_size << 24) | mantissa;
+                let decoded = nbits_to_target(nbits);
+                if decoded.overflow {
+                    continue;
+                }
+                assert!(!decoded.negative);
+
+                //Encoding is canonical, decoding it again gives the same target
+                let encoded = target_to_nbits(decoded.target);
+                assert_eq!(
+                    nbits_to_target(encoded).target,
+                    decoded.target,
+                    "nbits {nbits:x}"
+                );
+                assert_eq!(target_to_nbits(nbits_to_target(encoded).target), encoded);
+
+                //Canonical encodings are left untouched: non-zero target, mantissa's highest byte is
+                // non-zero unless that would set the sign bit, and no bits are cut off by small exponents
+                let canonical = if decoded.target.is_zero() {
+                    nbits == 0
+                } else {
+                    mantissa >= 0x8000
+                        && (n_size >= 3 || mantissa & ((1 << (8 * (3 - n_size))) - 1) == 0)
+                };
+                assert_eq!(encoded == nbits, canonical, "nbits {nbits:x}");
+
+                //Setting the sign bit on a non-zero target makes it negative
+                let negative = nbits_to_target(nbits | 0x00800000);
+                assert_eq!(negative.target, decoded.target);
+                assert_eq!(negative.negative, !decoded.target.is_zero());
+            }
+        }
+    }
+
+    #[test]
+    fn derives_taproot_deposit_outputs() {
+        //Group key of the generator's x coordinate, the output key computed independently
+        let group_key: [u8; 32] =
+            FromHex::from_hex("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                .unwrap();
+        let script_pubkey = bridge_deposit_script_pubkey([7; 32], group_key).unwrap();
+        assert_eq!(
+            script_pubkey.as_bytes().to_lower_hex_string(),
+            "51203279423932c3e29f4d28d7911ab722e677a5001e07f98e8a880df4b89137efda"
+        );
+        assert!(script_pubkey.is_p2tr());
+
+        //Each receiver has its own output
+        assert_ne!(
+            bridge_deposit_script_pubkey([8; 32], group_key).unwrap(),
+            script_pubkey
+        );
+        //Not on the curve
+        assert!(matches!(
+            bridge_deposit_script_pubkey([7; 32], [0; 32]),
+            Err(RelayErrorCode::InvalidBridgeKey)
+        ));
+    }
+
+    #[test]
+    fn derives_legacy_deposit_outputs() {
+        //Output of the regtest transaction 155ad532984baae90e7d4e71fa0c74748c95b2f53742e9ca80946f835c64d7b1
+        // of `anchor test`, paying 5Xy6zEA64yENXm9Zz5xDmTdB8t9cQpNaD3ZwNLBeiSc5
+        let receiver: [u8; 32] =
+            FromHex::from_hex("435d9babb858d9a9304f2dafaf742dd050cb0393b73ec4f19df6dacd96c71dae")
+                .unwrap();
+        let script_pubkey = legacy_deposit_script_pubkey(receiver);
+        assert_eq!(
+            script_pubkey.as_bytes().to_lower_hex_string(),
+            "00204a3a5f1583c04e6c45ada0e4724f3e394122ce97d36762d24cc9f6563faee485"
+        );
+        assert_ne!(legacy_deposit_script_pubkey([8; 32]), script_pubkey);
+    }
+
+    #[test]
+    fn taproot_output_keys_match_bip341() {
+        //keyPathSpending-free scriptPubKey vectors of Bitcoin Core's bip341_wallet_vectors.json:
+        // internal key, merkle root and tweaked output key
+        let vectors = [
+            (
+                "d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d",
+                None,
+                "53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343",
+            ),
+            (
+                "187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27",
+                Some("5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21"),
+                "147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3",
+            ),
+            (
+                "93478e9488f956df2396be2ce6c5cced75f900dfa18e7dabd2428aae78451820",
+                Some("c525714a7f49c28aedbbba78c005931a81c234b2f6c99a73e4d06082adc8bf2b"),
+                "e4d810fd50586274face62b8a807eb9719cef49c04177cc6b76a9a4251d5450e",
+            ),
+            (
+                "ee4fe085983462a184015d1f782d6a5f8b9c2b60130aff050ce221ecf3786592",
+                Some("6c2dc106ab816b73f9d07e3cd1ef2c8c1256f519748e0813e4edd2405d277bef"),
+                "712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5",
+            ),
+            (
+                "f9f400803e683727b14f463836e1e78e1c64417638aa066919291a225f0e8dd8",
+                Some("ab179431c28d3b68fb798957faf5497d69c883c6fb1e1cd9f81483d87bac90cc"),
+                "77e30a5522dd9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220",
+            ),
+            (
+                "e0dfe2300b0dd746a3f8674dfd4525623639042569d829c7f0eed9602d263e6f",
+                Some("ccbd66c6f7e8fdab47b3a486f59d28262be857f30d4773f2d5ea47f7761ce0e2"),
+                "91b64d5324723a985170e4dc5a0f84c041804f2cd12660fa5dec09fc21783605",
+            ),
+            (
+                "55adf4e8967fbd2e29f20ac896e60c3b0f1d5b0efa9d34941b5958c7b0a0312d",
+                Some("2f6b2c5397b6d68ca18e09a3f05161668ffe93a988582d55c6f07bd5b3329def"),
+                "75169f4001aa68f15bbed28b218df1d0a62cbbcf1188c6665110c293c907b831",
+            ),
+        ];
+        for (internal_key, merkle_root, output_key) in vectors {
+            let merkle_root = merkle_root
+                .map(|root| TapNodeHash::from_byte_array(FromHex::from_hex(root).unwrap()));
+            let key = taproot_output_key(FromHex::from_hex(internal_key).unwrap(), merkle_root);
+            assert_eq!(
+                key.unwrap().to_lower_hex_string(),
+                output_key,
+                "internal key {internal_key}"
+            );
+        }
+    }
+
+    #[test]
+    fn taproot_output_keys_match_bitcoin() {
+        use bitcoin::key::TapTweak;
+        use bitcoin::secp256k1::ffi::types::AlignedType;
+        use bitcoin::secp256k1::Secp256k1;
+
+        let mut buf = vec![AlignedType::zeroed(); Secp256k1::preallocate_verification_size()];
+        let secp = Secp256k1::preallocated_verification_only(&mut buf).unwrap();
+        let merkle_root = TapNodeHash::from_byte_array([5; 32]);
+        let keys = [
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d",
+            //x coordinates above the curve order are recovered from x - n
+            "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364143",
+        ];
+        for key in keys {
+            let internal_key: [u8; 32] = FromHex::from_hex(key).unwrap();
+            let x_only = XOnlyPublicKey::from_slice(&internal_key).unwrap();
+            for merkle_root in [None, Some(merkle_root)] {
+                let (expected, _) = x_only.tap_tweak(&secp, merkle_root);
+                assert_eq!(
+                    taproot_output_key(internal_key, merkle_root).unwrap(),
+                    expected.to_inner().serialize(),
+                    "internal key {key}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_taproot_tweaks() {
+        let key: [u8; 32] =
+            FromHex::from_hex("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                .unwrap();
+        //Tweaks aren't reduced modulo the curve order
+        assert!(matches!(
+            tweak_x_only_key(key, CURVE_ORDER),
+            Err(RelayErrorCode::InvalidTaprootTweak)
+        ));
+        //G - G is the point at infinity
+        let mut minus_one = CURVE_ORDER;
+        minus_one[31] -= 1;
+        assert!(matches!(
+            tweak_x_only_key(key, minus_one),
+            Err(RelayErrorCode::InvalidTaprootTweak)
+        ));
+        //The key of x coordinate n is on the curve but can't be recovered
+        assert!(matches!(
+            taproot_output_key(CURVE_ORDER, None),
+            Err(RelayErrorCode::InvalidBridgeKey)
+        ));
+    }
 }
@@ -0,0 +1,1449 @@
+// Replays real mainnet headers through the relay program running in solana-program-test,
+// checking the stored commitments and chain work, plus retargetting at the pow limit and the
+// errors returned for invalid submissions
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::bpf_loader_upgradeable::{self, UpgradeableLoaderState};
+use anchor_lang::solana_program::entrypoint::ProgramResult;
+use anchor_lang::solana_program::instruction::{Instruction, InstructionError};
+use anchor_lang::system_program;
+use anchor_lang::{InstructionData, ToAccountMetas};
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::hex::FromHex;
+use btc_relay::errors::RelayErrorCode;
+use btc_relay::state::{BridgeKey, DepositFees, FeePool, MainState, TxVerificationReceipt};
+use btc_relay::structs::{BlockHeader, CoinbaseProof, CommittedBlockHeader};
+use btc_relay::u256::U256;
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::account::AccountSharedData;
+use solana_sdk::signature::Signer;
+use solana_sdk::transaction::{Transaction, TransactionError};
+
+const MAINNET_HEADERS: &str = include_str!("fixtures/mainnet_headers.txt");
+
+// Genesis block coinbase transaction, the only transaction of the block
+const GENESIS_COINBASE: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+
+// x-only keys of the generator point and its double, registered as the signer nodes' group keys
+const GROUP_KEY: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+const NEW_GROUP_KEY: &str = "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+const DIFF_ADJUSTMENT_INTERVAL: u32 = 2016;
+const TARGET_TIMESPAN: u32 = 14 * 24 * 60 * 60;
+const MAX_FUTURE_BLOCKTIME: u32 = 4 * 60 * 60;
+
+// Headers per submit_block_headers transaction, while replaying
+const BATCH_SIZE: usize = 3;
+
+struct FixtureHeader {
+    height: u32,
+    chain_work: [u8; 32],
+    header: BlockHeader,
+}
+
+fn mainnet_headers() -> Vec<FixtureHeader> {
+    let headers: Vec<FixtureHeader> = MAINNET_HEADERS
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            FixtureHeader {
+                height: fields[0].parse().unwrap(),
+                chain_work: <[u8; 32]>::from_hex(fields[1]).unwrap(),
+                header: BlockHeader::try_from_slice(&Vec::from_hex(fields[2]).unwrap()).unwrap(),
+            }
+        })
+        .collect();
+
+    //The relay is initialized at the first header, which has to be a difficulty adjustment block,
+    // so its timestamp is the last_diff_adjustment
+    assert_eq!(headers[0].height % DIFF_ADJUSTMENT_INTERVAL, 0);
+    for pair in headers.windows(2) {
+        assert_eq!(pair[0].height + 1, pair[1].height);
+    }
+    headers
+}
+
+fn genesis_committed_header() -> CommittedBlockHeader {
+    let genesis = &mainnet_headers()[0];
+    CommittedBlockHeader {
+        chain_work: genesis.chain_work,
+        header: genesis.header,
+        last_diff_adjustment: genesis.header.timestamp,
+        blockheight: genesis.height,
+        prev_block_timestamps: [0; 10],
+    }
+}
+
+// Committed header expected after appending the header
+fn next_committed_header(
+    prev: &CommittedBlockHeader,
+    header: BlockHeader,
+    chain_work: [u8; 32],
+) -> CommittedBlockHeader {
+    let blockheight = prev.blockheight + 1;
+    let mut prev_block_timestamps = [0; 10];
+    prev_block_timestamps[..9].copy_from_slice(&prev.prev_block_timestamps[1..]);
+    prev_block_timestamps[9] = prev.header.timestamp;
+    CommittedBlockHeader {
+        chain_work,
+        header,
+        last_diff_adjustment: if blockheight % DIFF_ADJUSTMENT_INTERVAL == 0 {
+            header.timestamp
+        } else {
+            prev.last_diff_adjustment
+        },
+        blockheight,
+        prev_block_timestamps,
+    }
+}
+
+fn header_topic(block_hash: &[u8; 32]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"header", block_hash], &btc_relay::ID)
+}
+
+fn main_state_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"state"], &btc_relay::ID).0
+}
+
+fn fee_pool_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"fee_pool"], &btc_relay::ID).0
+}
+
+// Anchor's entrypoint needs the accounts to live as long as the account infos they contain
+fn deposit_fees_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"deposit_fees"], &btc_relay::ID).0
+}
+
+fn deposit_claim_address(reversed_txid: &[u8; 32], receiver: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"deposit_claim", reversed_txid, receiver.as_ref()],
+        &btc_relay::ID,
+    )
+    .0
+}
+
+fn bridge_key_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"bridge_key"], &btc_relay::ID).0
+}
+
+fn program_data_address() -> Pubkey {
+    Pubkey::find_program_address(&[btc_relay::ID.as_ref()], &bpf_loader_upgradeable::ID).0
+}
+
+fn deposit_account_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"solana_deposit"], &btc_relay::ID).0
+}
+
+fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    btc_relay::entry(program_id, accounts, data)
+}
+
+struct Relay {
+    context: ProgramTestContext,
+    //Passed to header submissions once initialized
+    fee_pool: Option<Pubkey>,
+}
+
+impl Relay {
+    async fn start(committed: &CommittedBlockHeader) -> Relay {
+        let program_test =
+            ProgramTest::new("btc_relay", btc_relay::ID, processor!(process_instruction));
+        let mut relay = Relay {
+            context: program_test.start_with_context().await,
+            fee_pool: None,
+        };
+
+        let block_hash = committed.header.get_block_hash();
+        let accounts = btc_relay::accounts::Initialize {
+            signer: relay.payer(),
+            main_state: main_state_address(),
+            header_topic: header_topic(&block_hash).0,
+            system_program: system_program::ID,
+        };
+        let args = btc_relay::instruction::Initialize {
+            data: committed.header,
+            block_height: committed.blockheight,
+            chain_work: committed.chain_work,
+            last_diff_adjustment: committed.last_diff_adjustment,
+            prev_block_timestamps: committed.prev_block_timestamps,
+        };
+        relay.process(accounts, args, vec![]).await.unwrap();
+        let payer = relay.payer();
+        relay.set_upgrade_authority(Some(payer)).await;
+        relay
+    }
+
+    //The program isn't deployed with the upgradeable loader, so its program data account is
+    // created with the upgrade authority gating the program's configuration. Also moves to a new
+    // blockhash, so the same transaction can be sent again
+    async fn set_upgrade_authority(&mut self, upgrade_authority_address: Option<Pubkey>) {
+        self.context.get_new_latest_blockhash().await.unwrap();
+        let program_data = UpgradeableLoaderState::ProgramData {
+            slot: 0,
+            upgrade_authority_address,
+        };
+        let account =
+            AccountSharedData::new_data(1_000_000_000, &program_data, &bpf_loader_upgradeable::ID)
+                .unwrap();
+        self.context.set_account(&program_data_address(), &account);
+    }
+
+    fn payer(&self) -> Pubkey {
+        self.context.payer.pubkey()
+    }
+
+    async fn process(
+        &mut self,
+        accounts: impl ToAccountMetas,
+        args: impl InstructionData,
+        remaining_accounts: Vec<AccountMeta>,
+    ) -> std::result::Result<(), BanksClientError> {
+        let mut account_metas = accounts.to_account_metas(None);
+        account_metas.extend(remaining_accounts);
+        let instruction = Instruction {
+            program_id: btc_relay::ID,
+            accounts: account_metas,
+            data: args.data(),
+        };
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&self.payer()),
+            &[&self.context.payer],
+            self.context.last_blockhash,
+        );
+        self.context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+    }
+
+    async fn submit(
+        &mut self,
+        headers: &[BlockHeader],
+        commited_header: CommittedBlockHeader,
+    ) -> std::result::Result<(), BanksClientError> {
+        let (remaining_accounts, header_topic_bumps) =
+            header_topic_accounts(headers.iter().map(BlockHeader::get_block_hash));
+        self.submit_with_topics(
+            headers,
+            commited_header,
+            remaining_accounts,
+            header_topic_bumps,
+        )
+        .await
+    }
+
+    async fn submit_with_topics(
+        &mut self,
+        headers: &[BlockHeader],
+        commited_header: CommittedBlockHeader,
+        remaining_accounts: Vec<AccountMeta>,
+        header_topic_bumps: Vec<u8>,
+    ) -> std::result::Result<(), BanksClientError> {
+        let accounts = btc_relay::accounts::SubmitBlockHeaders {
+            signer: self.payer(),
+            main_state: main_state_address(),
+            fee_pool: self.fee_pool,
+        };
+        let args = btc_relay::instruction::SubmitBlockHeaders {
+            data: headers.to_vec(),
+            commited_header,
+            header_topic_bumps,
+        };
+        self.process(accounts, args, remaining_accounts).await
+    }
+
+    async fn submit_fork(
+        &mut self,
+        headers: &[BlockHeader],
+        commited_header: CommittedBlockHeader,
+        fork_id: u64,
+        init: bool,
+    ) -> std::result::Result<(), BanksClientError> {
+        let (remaining_accounts, header_topic_bumps) =
+            header_topic_accounts(headers.iter().map(BlockHeader::get_block_hash));
+        let fork_state = Pubkey::find_program_address(
+            &[b"fork", &fork_id.to_le_bytes(), &self.payer().to_bytes()],
+            &btc_relay::ID,
+        )
+        .0;
+        let accounts = btc_relay::accounts::SubmitForkHeaders {
+            signer: self.payer(),
+            main_state: main_state_address(),
+            fork_state,
+            system_program: system_program::ID,
+        };
+        let args = btc_relay::instruction::SubmitForkHeaders {
+            data: headers.to_vec(),
+            commited_header,
+            fork_id,
+            init,
+            header_topic_bumps,
+        };
+        self.process(accounts, args, remaining_accounts).await
+    }
+
+    async fn verify_transaction(
+        &mut self,
+        tx_bytes: Vec<u8>,
+        reversed_txid: [u8; 32],
+        confirmations: u32,
+        reversed_merkle_proof: Vec<[u8; 32]>,
+        commited_header: CommittedBlockHeader,
+    ) -> std::result::Result<(), BanksClientError> {
+        let accounts = btc_relay::accounts::VerifyTransactionReceipt {
+            signer: self.payer(),
+            main_state: main_state_address(),
+            receipt: Pubkey::find_program_address(&[b"receipt", &reversed_txid], &btc_relay::ID).0,
+            system_program: system_program::ID,
+        };
+        let args = btc_relay::instruction::VerifyTransaction {
+            reversed_txid,
+            tx_bytes,
+            confirmations,
+            tx_index: 0,
+            reversed_merkle_proof,
+            commited_header,
+        };
+        self.process(accounts, args, vec![]).await
+    }
+
+    async fn verify_witness(
+        &mut self,
+        reversed_wtxid: [u8; 32],
+        tx_index: u32,
+        reversed_witness_proof: Vec<[u8; 32]>,
+        coinbase: CoinbaseProof,
+        commited_header: CommittedBlockHeader,
+    ) -> std::result::Result<(), BanksClientError> {
+        let accounts = btc_relay::accounts::VerifyWitness {
+            signer: self.payer(),
+            main_state: main_state_address(),
+        };
+        let args = btc_relay::instruction::VerifyWitness {
+            reversed_wtxid,
+            confirmations: 1,
+            tx_index,
+            reversed_witness_proof,
+            coinbase,
+            commited_header,
+        };
+        self.process(accounts, args, vec![]).await
+    }
+
+    async fn initialize_fee_pool(
+        &mut self,
+        header_reward: u64,
+        funding: u64,
+    ) -> std::result::Result<(), BanksClientError> {
+        let accounts = btc_relay::accounts::InitializeFeePool {
+            signer: self.payer(),
+            fee_pool: fee_pool_address(),
+            program_data: program_data_address(),
+            system_program: system_program::ID,
+        };
+        let args = btc_relay::instruction::InitializeFeePool { header_reward };
+        self.process(accounts, args, vec![]).await?;
+
+        let accounts = btc_relay::accounts::FundFeePool {
+            signer: self.payer(),
+            fee_pool: fee_pool_address(),
+            system_program: system_program::ID,
+        };
+        let args = btc_relay::instruction::FundFeePool { amount: funding };
+        self.process(accounts, args, vec![]).await?;
+        self.fee_pool = Some(fee_pool_address());
+        Ok(())
+    }
+
+    async fn initialize_deposit_fees(
+        &mut self,
+        treasury: Pubkey,
+        fixed_fee: u64,
+        fee_bps: u16,
+        min_deposit: u64,
+        withdrawal_fee: u64,
+        pool_share_bps: u16,
+    ) -> std::result::Result<(), BanksClientError> {
+        let accounts = btc_relay::accounts::InitializeDepositFees {
+            signer: self.payer(),
+            deposit_fees: deposit_fees_address(),
+            program_data: program_data_address(),
+            system_program: system_program::ID,
+        };
+        let args = btc_relay::instruction::InitializeDepositFees {
+            treasury,
+            fixed_fee,
+            fee_bps,
+            min_deposit,
+            withdrawal_fee,
+            pool_share_bps,
+        };
+        self.process(accounts, args, vec![]).await
+    }
+
+    async fn initialize_bridge_key(
+        &mut self,
+        group_key: [u8; 32],
+    ) -> std::result::Result<(), BanksClientError> {
+        let accounts = btc_relay::accounts::InitializeBridgeKey {
+            signer: self.payer(),
+            bridge_key: bridge_key_address(),
+            program_data: program_data_address(),
+            system_program: system_program::ID,
+        };
+        let args = btc_relay::instruction::InitializeBridgeKey { group_key };
+        self.process(accounts, args, vec![]).await
+    }
+
+    async fn deposit_fees(&mut self) -> DepositFees {
+        let account = self
+            .context
+            .banks_client
+            .get_account(deposit_fees_address())
+            .await
+            .unwrap()
+            .unwrap();
+        DepositFees::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    //Fee pool and its lamports above the rent exemption
+    async fn fee_pool(&mut self) -> (FeePool, u64) {
+        let account = self
+            .context
+            .banks_client
+            .get_account(fee_pool_address())
+            .await
+            .unwrap()
+            .unwrap();
+        let rent = self.context.banks_client.get_rent().await.unwrap();
+        (
+            FeePool::try_deserialize(&mut account.data.as_slice()).unwrap(),
+            account.lamports - rent.minimum_balance(FeePool::space()),
+        )
+    }
+
+    async fn bridge_key(&mut self) -> BridgeKey {
+        let account = self
+            .context
+            .banks_client
+            .get_account(bridge_key_address())
+            .await
+            .unwrap()
+            .unwrap();
+        BridgeKey::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    async fn receipt(&mut self, reversed_txid: &[u8; 32]) -> TxVerificationReceipt {
+        let address = Pubkey::find_program_address(&[b"receipt", reversed_txid], &btc_relay::ID).0;
+        let account = self
+            .context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .unwrap();
+        TxVerificationReceipt::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    async fn balance(&mut self, address: Pubkey) -> u64 {
+        self.context
+            .banks_client
+            .get_balance(address)
+            .await
+            .unwrap()
+    }
+
+    async fn main_state(&mut self) -> MainState {
+        let account = self
+            .context
+            .banks_client
+            .get_account(main_state_address())
+            .await
+            .unwrap()
+            .unwrap();
+        bytemuck::pod_read_unaligned(&account.data[8..8 + std::mem::size_of::<MainState>()])
+    }
+
+    //Also moves to a new blockhash, so the same transaction can be sent again
+    async fn set_unix_timestamp(&mut self, unix_timestamp: u32) {
+        self.context.get_new_latest_blockhash().await.unwrap();
+        let mut clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp = unix_timestamp as i64;
+        self.context.set_sysvar(&clock);
+    }
+}
+
+fn header_topic_accounts(
+    block_hashes: impl Iterator<Item = [u8; 32]>,
+) -> (Vec<AccountMeta>, Vec<u8>) {
+    block_hashes
+        .map(|block_hash| {
+            let (topic, bump) = header_topic(&block_hash);
+            (AccountMeta::new_readonly(topic, false), bump)
+        })
+        .unzip()
+}
+
+fn assert_error(result: std::result::Result<(), BanksClientError>, expected: u32) {
+    match result.expect_err("transaction should fail").unwrap() {
+        TransactionError::InstructionError(0, InstructionError::Custom(code)) => {
+            assert_eq!(code, expected)
+        }
+        err => panic!("expected error {expected}, got {err:?}"),
+    }
+}
+
+fn assert_relay_error(result: std::result::Result<(), BanksClientError>, expected: RelayErrorCode) {
+    assert_error(result, expected.into());
+}
+
+// Relay with the first three mainnet blocks submitted, returning the committed headers
+async fn relay_at_height_2() -> (Relay, Vec<CommittedBlockHeader>) {
+    let fixture = mainnet_headers();
+    let genesis = genesis_committed_header();
+    let mut relay = Relay::start(&genesis).await;
+
+    let block_1 = next_committed_header(&genesis, fixture[1].header, fixture[1].chain_work);
+    let block_2 = next_committed_header(&block_1, fixture[2].header, fixture[2].chain_work);
+    relay
+        .submit(&[block_1.header, block_2.header], genesis)
+        .await
+        .unwrap();
+    (relay, vec![genesis, block_1, block_2])
+}
+
+#[tokio::test]
+async fn replays_mainnet_headers() {
+    let fixture = mainnet_headers();
+    let genesis = genesis_committed_header();
+    let mut relay = Relay::start(&genesis).await;
+
+    let mut committed = genesis;
+    for batch in fixture[1..].chunks(BATCH_SIZE) {
+        let headers: Vec<BlockHeader> = batch.iter().map(|fixture| fixture.header).collect();
+        relay.submit(&headers, committed).await.unwrap();
+
+        let main_state = relay.main_state().await;
+        for fixture in batch {
+            committed = next_committed_header(&committed, fixture.header, fixture.chain_work);
+            assert_eq!(
+                main_state.get_commitment(fixture.height),
+                committed.get_commit_hash(),
+                "commitment of block {}",
+                fixture.height
+            );
+        }
+
+        assert_eq!(main_state.block_height, committed.blockheight);
+        assert_eq!(main_state.chain_work, committed.chain_work);
+        assert_eq!(
+            main_state.last_diff_adjustment,
+            committed.last_diff_adjustment
+        );
+        assert_eq!(main_state.tip_commit_hash, committed.get_commit_hash());
+        assert_eq!(main_state.tip_block_hash, committed.header.get_block_hash());
+    }
+    assert_eq!(committed.blockheight, fixture.last().unwrap().height);
+}
+
+#[tokio::test]
+#[cfg(not(any(feature = "bitcoin_testnet", feature = "bitcoin_regtest")))]
+async fn clamps_retarget_to_pow_limit() {
+    let fixture = mainnet_headers();
+
+    //Block 5 relabelled as the last block of a period, so block 6 is submitted at a difficulty
+    // adjustment: it keeps the pow limit target only if the period took at least the target
+    // timespan. Retargets of real mainnet periods are checked by utils' unit tests.
+    let before_adjustment = |timespan: u32| CommittedBlockHeader {
+        chain_work: fixture[5].chain_work,
+        header: fixture[5].header,
+        last_diff_adjustment: fixture[5].header.timestamp - timespan,
+        blockheight: DIFF_ADJUSTMENT_INTERVAL - 1,
+        prev_block_timestamps: core::array::from_fn(|i| match i {
+            0..=4 => 0,
+            _ => fixture[i - 5].header.timestamp,
+        }),
+    };
+
+    let committed = before_adjustment(TARGET_TIMESPAN);
+    let mut relay = Relay::start(&committed).await;
+    relay.submit(&[fixture[6].header], committed).await.unwrap();
+
+    let main_state = relay.main_state().await;
+    assert_eq!(main_state.block_height, DIFF_ADJUSTMENT_INTERVAL);
+    assert_eq!(main_state.last_diff_adjustment, fixture[6].header.timestamp);
+    assert_eq!(
+        main_state.tip_commit_hash,
+        next_committed_header(&committed, fixture[6].header, fixture[6].chain_work)
+            .get_commit_hash()
+    );
+
+    //Period twice as fast as the target requires twice the difficulty
+    let committed = before_adjustment(TARGET_TIMESPAN / 2);
+    let mut relay = Relay::start(&committed).await;
+    assert_relay_error(
+        relay.submit(&[fixture[6].header], committed).await,
+        RelayErrorCode::ErrDiffTarget,
+    );
+}
+
+#[tokio::test]
+async fn rejects_invalid_block_headers() {
+    let fixture = mainnet_headers();
+    let (mut relay, committed) = relay_at_height_2().await;
+    let tip = committed[2];
+    let block_3 = fixture[3].header;
+
+    assert_relay_error(relay.submit(&[], tip).await, RelayErrorCode::NoHeaders);
+
+    let mut not_committed = tip;
+    not_committed.chain_work = U256::MAX.to_be_bytes();
+    assert_relay_error(
+        relay.submit(&[block_3], not_committed).await,
+        RelayErrorCode::PrevBlockCommitment,
+    );
+
+    assert_relay_error(
+        relay.submit(&[fixture[4].header], tip).await,
+        RelayErrorCode::PrevBlock,
+    );
+
+    #[cfg(not(feature = "bitcoin_testnet"))]
+    {
+        let mut wrong_target = block_3;
+        wrong_target.nbits = 0x1c00_ffff;
+        assert_relay_error(
+            relay.submit(&[wrong_target], tip).await,
+            RelayErrorCode::ErrDiffTarget,
+        );
+    }
+
+    let mut low_pow = block_3;
+    low_pow.nonce ^= 1;
+    assert_relay_error(
+        relay.submit(&[low_pow], tip).await,
+        RelayErrorCode::ErrPowToolow,
+    );
+
+    let (topics, mut bumps) = header_topic_accounts([block_3.get_block_hash()].into_iter());
+    assert_relay_error(
+        relay
+            .submit_with_topics(&[block_3], tip, topics.clone(), vec![])
+            .await,
+        RelayErrorCode::InvalidRemainingAccounts,
+    );
+    let other_topic = header_topic(&fixture[4].header.get_block_hash()).0;
+    assert_relay_error(
+        relay
+            .submit_with_topics(
+                &[block_3],
+                tip,
+                vec![AccountMeta::new_readonly(other_topic, false)],
+                bumps.clone(),
+            )
+            .await,
+        RelayErrorCode::InvalidHeaderTopic,
+    );
+    bumps[0] = bumps[0].wrapping_sub(1);
+    assert_relay_error(
+        relay
+            .submit_with_topics(&[block_3], tip, topics, bumps)
+            .await,
+        RelayErrorCode::InvalidHeaderTopic,
+    );
+
+    //Headers racing the ones already submitted on top of the same committed header
+    assert_relay_error(
+        relay.submit(&[committed[1].header], committed[0]).await,
+        RelayErrorCode::TipAlreadyExtended,
+    );
+    assert_relay_error(
+        relay.submit(&[committed[2].header], committed[1]).await,
+        RelayErrorCode::TipAlreadyExtended,
+    );
+
+    //Rejected submissions don't change the relay
+    let main_state = relay.main_state().await;
+    assert_eq!(main_state.block_height, 2);
+    assert_eq!(main_state.tip_commit_hash, tip.get_commit_hash());
+}
+
+#[tokio::test]
+async fn rewards_relayers_from_the_fee_pool() {
+    let fixture = mainnet_headers();
+    let genesis = genesis_committed_header();
+    let mut relay = Relay::start(&genesis).await;
+    //Only the program's upgrade authority can create the pool
+    relay
+        .set_upgrade_authority(Some(Pubkey::new_unique()))
+        .await;
+    assert_relay_error(
+        relay.initialize_fee_pool(1_000, 2_500).await,
+        RelayErrorCode::Unauthorized,
+    );
+    let payer = relay.payer();
+    relay.set_upgrade_authority(Some(payer)).await;
+    relay.initialize_fee_pool(1_000, 2_500).await.unwrap();
+
+    let block_1 = next_committed_header(&genesis, fixture[1].header, fixture[1].chain_work);
+    let block_2 = next_committed_header(&block_1, fixture[2].header, fixture[2].chain_work);
+    relay
+        .submit(&[block_1.header, block_2.header], genesis)
+        .await
+        .unwrap();
+    let (fee_pool, available) = relay.fee_pool().await;
+    assert_eq!(fee_pool.authority, relay.payer());
+    assert_eq!(fee_pool.total_rewards, 2_000);
+    assert_eq!(available, 500);
+
+    //The reward is limited to what the pool has left, keeping it rent exempt
+    let block_3 = next_committed_header(&block_2, fixture[3].header, fixture[3].chain_work);
+    relay.submit(&[block_3.header], block_2).await.unwrap();
+    let (fee_pool, available) = relay.fee_pool().await;
+    assert_eq!(fee_pool.total_rewards, 2_500);
+    assert_eq!(available, 0);
+
+    //Headers are still accepted from an empty pool
+    let block_4 = next_committed_header(&block_3, fixture[4].header, fixture[4].chain_work);
+    relay.submit(&[block_4.header], block_3).await.unwrap();
+    assert_eq!(relay.fee_pool().await.0.total_rewards, 2_500);
+
+    relay
+        .process(
+            btc_relay::accounts::SetHeaderReward {
+                authority: relay.payer(),
+                fee_pool: fee_pool_address(),
+            },
+            btc_relay::instruction::SetHeaderReward { header_reward: 0 },
+            vec![],
+        )
+        .await
+        .unwrap();
+    assert_eq!(relay.fee_pool().await.0.header_reward, 0);
+}
+
+#[tokio::test]
+async fn configures_deposit_fees() {
+    let mut relay = Relay::start(&genesis_committed_header()).await;
+    let treasury = Pubkey::new_unique();
+
+    //Only the upgrade authority can create the deposit fees
+    relay
+        .set_upgrade_authority(Some(Pubkey::new_unique()))
+        .await;
+    assert_relay_error(
+        relay
+            .initialize_deposit_fees(treasury, 500, 30, 0, 0, 0)
+            .await,
+        RelayErrorCode::Unauthorized,
+    );
+    relay.set_upgrade_authority(Some(relay.payer())).await;
+
+    assert_relay_error(
+        relay
+            .initialize_deposit_fees(treasury, 500, 1_001, 0, 0, 0)
+            .await,
+        RelayErrorCode::InvalidDepositFees,
+    );
+    assert_relay_error(
+        relay
+            .initialize_deposit_fees(treasury, 500, 30, 0, 0, 10_001)
+            .await,
+        RelayErrorCode::InvalidDepositFees,
+    );
+    relay
+        .initialize_deposit_fees(treasury, 500, 30, 10_000, 1_000, 2_500)
+        .await
+        .unwrap();
+    let deposit_fees = relay.deposit_fees().await;
+    assert_eq!(deposit_fees.authority, relay.payer());
+    assert_eq!(deposit_fees.treasury, treasury);
+    //The fixed fee plus 0.3% of the deposit, rounded down
+    assert_eq!(deposit_fees.fee(10_000), 530);
+    assert_eq!(deposit_fees.fee(123_456), 870);
+    assert_eq!(deposit_fees.withdrawal_fee, 1_000);
+    //A quarter of the fees goes to the fee pool
+    assert_eq!(deposit_fees.pool_share(530), 132);
+
+    let authority = relay.payer();
+    let set_deposit_fees = |fee_bps| {
+        (
+            btc_relay::accounts::SetDepositFees {
+                authority,
+                deposit_fees: deposit_fees_address(),
+            },
+            btc_relay::instruction::SetDepositFees {
+                treasury,
+                fixed_fee: 0,
+                fee_bps,
+                min_deposit: 0,
+                withdrawal_fee: 0,
+                pool_share_bps: 10_000,
+            },
+        )
+    };
+    let (accounts, args) = set_deposit_fees(1_001);
+    assert_relay_error(
+        relay.process(accounts, args, vec![]).await,
+        RelayErrorCode::InvalidDepositFees,
+    );
+    let (accounts, args) = set_deposit_fees(1_000);
+    relay.process(accounts, args, vec![]).await.unwrap();
+    let deposit_fees = relay.deposit_fees().await;
+    assert_eq!(deposit_fees.fee(10_000), 1_000);
+    assert_eq!(deposit_fees.min_deposit, 0);
+    assert_eq!(deposit_fees.pool_share(10_000), 10_000);
+}
+
+#[tokio::test]
+async fn registers_the_bridge_key() {
+    let mut relay = Relay::start(&genesis_committed_header()).await;
+    let signer = relay.payer();
+    let group_key = <[u8; 32]>::from_hex(GROUP_KEY).unwrap();
+    let new_group_key = <[u8; 32]>::from_hex(NEW_GROUP_KEY).unwrap();
+
+    //Only the upgrade authority can register the first key
+    relay
+        .set_upgrade_authority(Some(Pubkey::new_unique()))
+        .await;
+    assert_relay_error(
+        relay.initialize_bridge_key(group_key).await,
+        RelayErrorCode::Unauthorized,
+    );
+    relay.set_upgrade_authority(Some(signer)).await;
+
+    //The key has to be the x coordinate of a curve point
+    assert_relay_error(
+        relay.initialize_bridge_key([0; 32]).await,
+        RelayErrorCode::InvalidBridgeKey,
+    );
+    assert_relay_error(
+        relay.initialize_bridge_key([0xff; 32]).await,
+        RelayErrorCode::InvalidBridgeKey,
+    );
+    relay.initialize_bridge_key(group_key).await.unwrap();
+    let bridge_key = relay.bridge_key().await;
+    assert_eq!(bridge_key.authority, signer);
+    assert_eq!(bridge_key.group_key, group_key);
+
+    //A new group's key replaces it
+    let set_bridge_key = |group_key| {
+        (
+            btc_relay::accounts::SetBridgeKey {
+                authority: signer,
+                bridge_key: bridge_key_address(),
+            },
+            btc_relay::instruction::SetBridgeKey { group_key },
+        )
+    };
+    let (accounts, args) = set_bridge_key([0xff; 32]);
+    assert_relay_error(
+        relay.process(accounts, args, vec![]).await,
+        RelayErrorCode::InvalidBridgeKey,
+    );
+    let (accounts, args) = set_bridge_key(new_group_key);
+    relay.process(accounts, args, vec![]).await.unwrap();
+    assert_eq!(relay.bridge_key().await.group_key, new_group_key);
+}
+
+#[tokio::test]
+async fn requests_withdrawals_to_standard_scripts() {
+    let mut relay = Relay::start(&genesis_committed_header()).await;
+    let deposit_account = deposit_account_address();
+    relay
+        .process(
+            btc_relay::accounts::Deposit {
+                signer: relay.payer(),
+                deposit_account,
+                system_program: system_program::ID,
+            },
+            btc_relay::instruction::Deposit { amount: 1_000_000 },
+            vec![],
+        )
+        .await
+        .unwrap();
+    let balance = relay.balance(deposit_account).await;
+
+    let signer = relay.payer();
+    let request_withdrawal = |amount, script_pubkey, treasury, fee_pool| {
+        (
+            btc_relay::accounts::RequestWithdrawal {
+                signer,
+                deposit_account,
+                deposit_fees: deposit_fees_address(),
+                treasury,
+                fee_pool,
+                system_program: system_program::ID,
+            },
+            btc_relay::instruction::RequestWithdrawal {
+                amount,
+                script_pubkey,
+            },
+        )
+    };
+    let mut p2wpkh = vec![0x00, 0x14];
+    p2wpkh.extend_from_slice(&[0x07; 20]);
+
+    //No fee is paid while the deposit fees aren't initialized
+    let (accounts, args) = request_withdrawal(1_000, p2wpkh.clone(), None, None);
+    relay.process(accounts, args, vec![]).await.unwrap();
+    assert_eq!(relay.balance(deposit_account).await, balance + 10_000);
+
+    let treasury = Pubkey::new_unique();
+    relay
+        .initialize_deposit_fees(treasury, 0, 0, 0, 1_000_000, 2_500)
+        .await
+        .unwrap();
+
+    //The fee pool's share of the withdrawal fee can't be skipped
+    let (accounts, args) = request_withdrawal(1_000, p2wpkh.clone(), Some(treasury), None);
+    assert_relay_error(
+        relay.process(accounts, args, vec![]).await,
+        RelayErrorCode::MissingFeePool,
+    );
+
+    //Neither can the treasury's
+    relay.initialize_fee_pool(0, 0).await.unwrap();
+    let (accounts, args) = request_withdrawal(
+        1_000,
+        p2wpkh.clone(),
+        Some(Pubkey::new_unique()),
+        Some(fee_pool_address()),
+    );
+    assert_relay_error(
+        relay.process(accounts, args, vec![]).await,
+        RelayErrorCode::InvalidTreasury,
+    );
+
+    //The burnt amount goes back to the deposit account and the withdrawal fee is split between
+    // the treasury and the fee pool, 10 lamports per satoshi
+    let (accounts, args) = request_withdrawal(
+        1_000,
+        p2wpkh.clone(),
+        Some(treasury),
+        Some(fee_pool_address()),
+    );
+    relay.process(accounts, args, vec![]).await.unwrap();
+    assert_eq!(relay.balance(deposit_account).await, balance + 20_000);
+    assert_eq!(relay.balance(treasury).await, 7_500_000);
+    assert_eq!(relay.fee_pool().await.1, 2_500_000);
+
+    //Overflows when converted to lamports
+    let (accounts, args) = request_withdrawal(
+        u64::MAX / 10 + 1,
+        p2wpkh.clone(),
+        Some(treasury),
+        Some(fee_pool_address()),
+    );
+    assert_relay_error(
+        relay.process(accounts, args, vec![]).await,
+        RelayErrorCode::AmountOverflow,
+    );
+
+    //Below the P2WPKH dust limit of 294 sats
+    let (accounts, args) =
+        request_withdrawal(293, p2wpkh, Some(treasury), Some(fee_pool_address()));
+    assert_relay_error(
+        relay.process(accounts, args, vec![]).await,
+        RelayErrorCode::InvalidWithdrawal,
+    );
+    let mut op_return = vec![0x6a, 0x14];
+    op_return.extend_from_slice(&[0x07; 20]);
+    let (accounts, args) =
+        request_withdrawal(1_000, op_return, Some(treasury), Some(fee_pool_address()));
+    assert_relay_error(
+        relay.process(accounts, args, vec![]).await,
+        RelayErrorCode::InvalidWithdrawal,
+    );
+}
+
+#[tokio::test]
+async fn rejects_invalid_timestamps() {
+    let fixture = mainnet_headers();
+    let block_1 = fixture[1].header;
+
+    //Block 1 is not later than the median of the previous timestamps
+    let mut genesis = genesis_committed_header();
+    genesis.prev_block_timestamps = [block_1.timestamp; 10];
+    let mut relay = Relay::start(&genesis).await;
+    assert_relay_error(
+        relay.submit(&[block_1], genesis).await,
+        RelayErrorCode::ErrTimestampToolow,
+    );
+
+    let genesis = genesis_committed_header();
+    let mut relay = Relay::start(&genesis).await;
+    relay
+        .set_unix_timestamp(block_1.timestamp - MAX_FUTURE_BLOCKTIME)
+        .await;
+    assert_relay_error(
+        relay.submit(&[block_1], genesis).await,
+        RelayErrorCode::ErrTimestampTooHigh,
+    );
+    relay
+        .set_unix_timestamp(block_1.timestamp - MAX_FUTURE_BLOCKTIME + 1)
+        .await;
+    relay.submit(&[block_1], genesis).await.unwrap();
+}
+
+#[tokio::test]
+async fn rejects_invalid_chain_work_and_nbits() {
+    let fixture = mainnet_headers();
+
+    let mut genesis = genesis_committed_header();
+    genesis.chain_work = U256::MAX.to_be_bytes();
+    let mut relay = Relay::start(&genesis).await;
+    assert_relay_error(
+        relay.submit(&[fixture[1].header], genesis).await,
+        RelayErrorCode::ChainWorkOverflow,
+    );
+
+    //Negative target, the header is rejected before its PoW is checked
+    let mut genesis = genesis_committed_header();
+    genesis.header.nbits = 0x0180_3456;
+    let mut relay = Relay::start(&genesis).await;
+    let mut block_1 = fixture[1].header;
+    block_1.nbits = genesis.header.nbits;
+    block_1.reversed_prev_blockhash = genesis.header.get_block_hash();
+    assert_relay_error(
+        relay.submit(&[block_1], genesis).await,
+        RelayErrorCode::InvalidNbits,
+    );
+}
+
+#[tokio::test]
+async fn rejects_invalid_forks() {
+    let (mut relay, committed) = relay_at_height_2().await;
+
+    //Block 1 again, forking from the genesis has less work than the main chain
+    assert_relay_error(
+        relay
+            .process(
+                btc_relay::accounts::SubmitShortForkHeaders {
+                    signer: relay.payer(),
+                    main_state: main_state_address(),
+                },
+                btc_relay::instruction::SubmitShortForkHeaders {
+                    data: vec![committed[1].header],
+                    commited_header: committed[0],
+                    header_topic_bumps: vec![header_topic(&committed[1].header.get_block_hash()).1],
+                },
+                header_topic_accounts([committed[1].header.get_block_hash()].into_iter()).0,
+            )
+            .await,
+        RelayErrorCode::ForkTooShort,
+    );
+
+    assert_relay_error(
+        relay
+            .submit_fork(&[committed[1].header], committed[0], 1, true)
+            .await,
+        RelayErrorCode::InvalidForkId,
+    );
+
+    //Fork stored in the fork PDA, as it doesn't have more work than the main chain yet
+    relay
+        .submit_fork(&[committed[1].header], committed[0], 0, true)
+        .await
+        .unwrap();
+    assert_eq!(relay.main_state().await.fork_counter, 1);
+
+    //ErrInit can't be reached, Anchor already rejects initializing an existing fork PDA
+    // and loading a fork PDA that wasn't initialized
+    assert_error(
+        relay
+            .submit_fork(&[committed[2].header], committed[1], 0, true)
+            .await,
+        ErrorCode::AccountDiscriminatorAlreadySet.into(),
+    );
+    assert_error(
+        relay
+            .submit_fork(&[committed[1].header], committed[0], 1, false)
+            .await,
+        ErrorCode::AccountDiscriminatorMismatch.into(),
+    );
+}
+
+fn sha256d_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    sha256d::Hash::hash(&[*left, *right].concat()).to_byte_array()
+}
+
+// Segwit coinbase committing to the witness root, with the witness reserved value as the single
+// item of its input's witness
+fn segwit_coinbase(
+    witness_root: &[u8; 32],
+    witness_reserved_value: &[u8],
+    extra_outputs: Vec<bitcoin::TxOut>,
+) -> bitcoin::Transaction {
+    let commitment = sha256d::Hash::hash(&[&witness_root[..], witness_reserved_value].concat());
+    let mut commitment_script = Vec::from_hex("6a24aa21a9ed").unwrap();
+    commitment_script.extend_from_slice(commitment.as_byte_array());
+    let mut output = vec![bitcoin::TxOut {
+        value: bitcoin::Amount::from_sat(312_500_000),
+        script_pubkey: bitcoin::ScriptBuf::from_bytes(commitment_script),
+    }];
+    output.extend(extra_outputs);
+    bitcoin::Transaction {
+        version: bitcoin::transaction::Version::TWO,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::null(),
+            script_sig: bitcoin::ScriptBuf::from_bytes(vec![3, 0x40, 0x0d, 0x03]),
+            sequence: bitcoin::Sequence::MAX,
+            witness: bitcoin::Witness::from_slice(&[witness_reserved_value]),
+        }],
+        output,
+    }
+}
+
+#[tokio::test]
+async fn verifies_witness_commitments() {
+    //Block with the coinbase and a single segwit transaction, the coinbase's wtxid is all zeroes
+    let reversed_wtxid = [0x11; 32];
+    let reversed_txid = [0x22; 32];
+    let witness_root = sha256d_pair(&[0; 32], &reversed_wtxid);
+    let coinbase_tx = segwit_coinbase(&witness_root, &[0; 32], vec![]);
+    let coinbase_txid = coinbase_tx.compute_txid().to_byte_array();
+
+    let mut committed = genesis_committed_header();
+    committed.header.merkle_root = sha256d_pair(&coinbase_txid, &reversed_txid);
+    let mut relay = Relay::start(&committed).await;
+
+    let coinbase = |tx: &bitcoin::Transaction| CoinbaseProof {
+        tx_bytes: bitcoin::consensus::serialize(tx),
+        reversed_merkle_proof: vec![reversed_txid],
+    };
+    relay
+        .verify_witness(
+            reversed_wtxid,
+            1,
+            vec![[0; 32]],
+            coinbase(&coinbase_tx),
+            committed,
+        )
+        .await
+        .unwrap();
+
+    assert_relay_error(
+        relay
+            .verify_witness(
+                [0x33; 32],
+                1,
+                vec![[0; 32]],
+                coinbase(&coinbase_tx),
+                committed,
+            )
+            .await,
+        RelayErrorCode::WitnessCommitment,
+    );
+    //Bits of the index above the proof's depth would be ignored by the merkle root computation
+    assert_relay_error(
+        relay
+            .verify_witness(
+                reversed_wtxid,
+                3,
+                vec![[0; 32]],
+                coinbase(&coinbase_tx),
+                committed,
+            )
+            .await,
+        RelayErrorCode::WitnessCommitment,
+    );
+    assert_relay_error(
+        relay
+            .verify_witness(reversed_wtxid, 1, vec![], coinbase(&coinbase_tx), committed)
+            .await,
+        RelayErrorCode::WitnessCommitment,
+    );
+
+    //Only the last output matching the commitment's prefix counts
+    let mut committed_twice = coinbase_tx.clone();
+    committed_twice
+        .output
+        .push(segwit_coinbase(&[0x44; 32], &[0; 32], vec![]).output[0].clone());
+    let mut header = committed;
+    header.header.merkle_root = sha256d_pair(
+        &committed_twice.compute_txid().to_byte_array(),
+        &reversed_txid,
+    );
+    let mut relay = Relay::start(&header).await;
+    assert_relay_error(
+        relay
+            .verify_witness(
+                reversed_wtxid,
+                1,
+                vec![[0; 32]],
+                coinbase(&committed_twice),
+                header,
+            )
+            .await,
+        RelayErrorCode::WitnessCommitment,
+    );
+
+    //The witness reserved value has to be the single 32 byte item of the coinbase's witness
+    for witness in [vec![vec![0; 32], vec![0; 32]], vec![vec![0; 31]], vec![]] {
+        let mut invalid_witness = coinbase_tx.clone();
+        invalid_witness.input[0].witness = bitcoin::Witness::from_slice(&witness);
+        let mut header = committed;
+        header.header.merkle_root = sha256d_pair(
+            &invalid_witness.compute_txid().to_byte_array(),
+            &reversed_txid,
+        );
+        let mut relay = Relay::start(&header).await;
+        assert_relay_error(
+            relay
+                .verify_witness(
+                    reversed_wtxid,
+                    1,
+                    vec![[0; 32]],
+                    coinbase(&invalid_witness),
+                    header,
+                )
+                .await,
+            RelayErrorCode::InvalidCoinbase,
+        );
+    }
+
+    //Coinbases of blocks without segwit transactions don't commit to a witness root
+    let mut no_commitment = coinbase_tx.clone();
+    no_commitment.output[0].script_pubkey = bitcoin::ScriptBuf::new_op_return([0; 36]);
+    let mut header = committed;
+    header.header.merkle_root = sha256d_pair(
+        &no_commitment.compute_txid().to_byte_array(),
+        &reversed_txid,
+    );
+    let mut relay = Relay::start(&header).await;
+    assert_relay_error(
+        relay
+            .verify_witness(
+                reversed_wtxid,
+                1,
+                vec![[0; 32]],
+                coinbase(&no_commitment),
+                header,
+            )
+            .await,
+        RelayErrorCode::NoWitnessCommitment,
+    );
+
+    //The coinbase has to spend the null outpoint
+    let mut not_coinbase = coinbase_tx.clone();
+    not_coinbase.input[0].previous_output.vout = 0;
+    let mut header = committed;
+    header.header.merkle_root =
+        sha256d_pair(&not_coinbase.compute_txid().to_byte_array(), &reversed_txid);
+    let mut relay = Relay::start(&header).await;
+    assert_relay_error(
+        relay
+            .verify_witness(
+                reversed_wtxid,
+                1,
+                vec![[0; 32]],
+                coinbase(&not_coinbase),
+                header,
+            )
+            .await,
+        RelayErrorCode::InvalidCoinbase,
+    );
+}
+
+#[tokio::test]
+async fn stores_transaction_verification_receipts() {
+    let (mut relay, committed) = relay_at_height_2().await;
+    let genesis = committed[0];
+    let coinbase = Vec::from_hex(GENESIS_COINBASE).unwrap();
+    let txid = genesis.header.merkle_root;
+
+    relay
+        .verify_transaction(coinbase.clone(), txid, 3, vec![], genesis)
+        .await
+        .unwrap();
+    let receipt = relay.receipt(&txid).await;
+    assert_eq!(receipt.reversed_txid, txid);
+    assert_eq!(receipt.block_height, 0);
+    assert_eq!(receipt.commit_hash, genesis.get_commit_hash());
+    assert_eq!(
+        receipt.outputs_hash,
+        btc_relay::txutils::ParsedTransaction::parse(&coinbase)
+            .unwrap()
+            .outputs_hash()
+    );
+    let main_state = relay.main_state().await;
+    assert_eq!(
+        main_state.get_confirmations(receipt.block_height, &receipt.commit_hash),
+        Some(3)
+    );
+
+    //The receipt's confirmations grow with the main chain, without verifying it again
+    let fixture = mainnet_headers();
+    let block_3 = next_committed_header(&committed[2], fixture[3].header, fixture[3].chain_work);
+    relay.submit(&[block_3.header], committed[2]).await.unwrap();
+    let main_state = relay.main_state().await;
+    assert_eq!(
+        main_state.get_confirmations(receipt.block_height, &receipt.commit_hash),
+        Some(4)
+    );
+    assert_eq!(main_state.get_confirmations(0, &[0x11; 32]), None);
+
+    //Verifying the transaction again keeps the receipt
+    relay
+        .verify_transaction(coinbase, txid, 4, vec![], genesis)
+        .await
+        .unwrap();
+    let reverified = relay.receipt(&txid).await;
+    assert_eq!(reverified.commit_hash, receipt.commit_hash);
+}
+
+#[tokio::test]
+async fn rejects_invalid_transaction_proofs() {
+    let (mut relay, committed) = relay_at_height_2().await;
+    let genesis = committed[0];
+    let coinbase = Vec::from_hex(GENESIS_COINBASE).unwrap();
+    let txid = genesis.header.merkle_root;
+
+    relay
+        .verify_transaction(coinbase.clone(), txid, 3, vec![], genesis)
+        .await
+        .unwrap();
+
+    assert_relay_error(
+        relay
+            .verify_transaction(coinbase.clone(), txid, 4, vec![], genesis)
+            .await,
+        RelayErrorCode::BlockConfirmations,
+    );
+    assert_relay_error(
+        relay
+            .verify_transaction(coinbase[..40].to_vec(), txid, 1, vec![], genesis)
+            .await,
+        RelayErrorCode::InvalidTransaction,
+    );
+    assert_relay_error(
+        relay
+            .verify_transaction(coinbase.clone(), [0x11; 32], 1, vec![], genesis)
+            .await,
+        RelayErrorCode::InvalidTxId,
+    );
+    assert_relay_error(
+        relay
+            .verify_transaction(coinbase.clone(), txid, 1, vec![[0x22; 32]], genesis)
+            .await,
+        RelayErrorCode::MerkleRoot,
+    );
+
+    //Only the first output of the coinbase exists and it doesn't pay to a deposit address
+    relay
+        .initialize_bridge_key(<[u8; 32]>::from_hex(GROUP_KEY).unwrap())
+        .await
+        .unwrap();
+    relay
+        .process(
+            btc_relay::accounts::Deposit {
+                signer: relay.payer(),
+                deposit_account: Pubkey::find_program_address(&[b"solana_deposit"], &btc_relay::ID)
+                    .0,
+                system_program: system_program::ID,
+            },
+            btc_relay::instruction::Deposit { amount: 1_000_000 },
+            vec![],
+        )
+        .await
+        .unwrap();
+    let mint_receiver = Pubkey::new_unique();
+    assert_relay_error(
+        relay
+            .process(
+                btc_relay::accounts::VerifyTransaction {
+                    signer: relay.payer(),
+                    main_state: main_state_address(),
+                    deposit_account: Pubkey::find_program_address(
+                        &[b"solana_deposit"],
+                        &btc_relay::ID,
+                    )
+                    .0,
+                    mint_receiver,
+                    bridge_key: bridge_key_address(),
+                    deposit_fees: deposit_fees_address(),
+                    treasury: None,
+                    fee_pool: None,
+                    deposit_claim: deposit_claim_address(&txid, &mint_receiver),
+                    system_program: system_program::ID,
+                },
+                btc_relay::instruction::VerifySmallTx {
+                    tx_bytes: coinbase.clone(),
+                    confirmations: 1,
+                    tx_index: 0,
+                    reversed_merkle_proof: vec![],
+                    commited_header: genesis,
+                },
+                vec![],
+            )
+            .await,
+        RelayErrorCode::NoDepositOutputs,
+    );
+
+    let genesis_coinbase = |tx_bytes: Vec<u8>| CoinbaseProof {
+        tx_bytes,
+        reversed_merkle_proof: vec![[0x22; 32]],
+    };
+    assert_relay_error(
+        relay
+            .verify_witness(
+                [0x11; 32],
+                0,
+                vec![[0; 32]],
+                genesis_coinbase(coinbase.clone()),
+                genesis,
+            )
+            .await,
+        RelayErrorCode::WitnessCommitment,
+    );
+    assert_relay_error(
+        relay
+            .verify_witness(
+                [0x11; 32],
+                1,
+                vec![[0; 32]],
+                genesis_coinbase(coinbase[..40].to_vec()),
+                genesis,
+            )
+            .await,
+        RelayErrorCode::InvalidCoinbase,
+    );
+    //The genesis block has a single transaction
+    assert_relay_error(
+        relay
+            .verify_witness(
+                [0x11; 32],
+                1,
+                vec![[0; 32]],
+                genesis_coinbase(coinbase),
+                genesis,
+            )
+            .await,
+        RelayErrorCode::MerkleRoot,
+    );
+
+    assert_relay_error(
+        relay
+            .process(
+                btc_relay::accounts::BlockHeight {
+                    signer: relay.payer(),
+                    main_state: main_state_address(),
+                },
+                btc_relay::instruction::BlockHeight {
+                    value: 3,
+                    operation: 3,
+                },
+                vec![],
+            )
+            .await,
+        RelayErrorCode::InvalidBlockheight,
+    );
+}
@@ -0,0 +1,59 @@
+//! Measures the compute units of btc-relay's transaction parser against the `bitcoin` crate's
+//! decoder it replaced. Both instructions do the transaction work of `verify_small_tx` on the same
+//! bytes: parse the transaction, compute its txid and sum the outputs paying `script_pubkey`.
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program::set_return_data;
+use bitcoin::consensus::Decodable;
+use bitcoin::hashes::Hash;
+use bitcoin::Transaction;
+use btc_relay::errors::RelayErrorCode;
+use btc_relay::txutils::ParsedTransaction;
+
+declare_id!("CxmyxNsJ9oNQw4prTFKHwQB2CbeQMcgpkbqhWCM3RxwW");
+
+// Returns the txid and the amount so the work isn't optimized out
+fn return_result(txid: &[u8; 32], amount: u64) {
+    let mut data = [0; 40];
+    data[..32].copy_from_slice(txid);
+    data[32..].copy_from_slice(&amount.to_le_bytes());
+    set_return_data(&data);
+}
+
+#[program]
+pub mod tx_parser_bench {
+    use super::*;
+
+    /// The previous decoder, allocating every input, output and witness on the heap
+    pub fn decode_with_bitcoin(
+        _ctx: Context<Bench>,
+        tx_bytes: Vec<u8>,
+        script_pubkey: Vec<u8>,
+    ) -> Result<()> {
+        let bitcoin_tx = Transaction::consensus_decode(&mut tx_bytes.as_slice())
+            .map_err(|_| RelayErrorCode::InvalidTransaction)?;
+        let amount = bitcoin_tx
+            .output
+            .iter()
+            .filter(|out| out.script_pubkey.as_bytes() == script_pubkey.as_slice())
+            .fold(0, |total, out| total + out.value.to_sat());
+        return_result(&bitcoin_tx.compute_txid().to_byte_array(), amount);
+        Ok(())
+    }
+
+    /// btc-relay's `ParsedTransaction`, borrowing everything from the serialized transaction
+    pub fn parse(_ctx: Context<Bench>, tx_bytes: Vec<u8>, script_pubkey: Vec<u8>) -> Result<()> {
+        let bitcoin_tx =
+            ParsedTransaction::parse(&tx_bytes).map_err(|_| RelayErrorCode::InvalidTransaction)?;
+        let amount = bitcoin_tx
+            .outputs()
+            .filter(|out| out.script_pubkey == script_pubkey.as_slice())
+            .fold(0, |total, out| total + out.value);
+        return_result(&bitcoin_tx.compute_txid(), amount);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct Bench<'info> {
+    pub signer: Signer<'info>,
+}
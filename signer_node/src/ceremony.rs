@@ -0,0 +1,508 @@
+//! Key generation ceremony of the signer nodes, running the [`dkg`](crate::dkg) rounds over a
+//! shared directory or TCP.
+//!
+//! Round two shares are encrypted to their recipient with AES-256-GCM-SIV, under a key agreed
+//! with ephemeral secp256k1 Diffie-Hellman keys sent along the round one packages. Participants
+//! aren't authenticated, so they have to compare the resulting group key out of band before the
+//! group holds funds.
+use crate::dkg::{self, Round1Package, Round2Package};
+use crate::frost::{FrostError, Identifier, KeyPackage, PublicKeyPackage};
+use aes_gcm_siv::aead::{Aead, NewAead};
+use aes_gcm_siv::Aes256GcmSiv;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::hex::{DisplayHex, FromHex};
+use k256::elliptic_curve::ff::Field;
+use k256::elliptic_curve::point::AffineCoordinates;
+use k256::elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint};
+use k256::{AffinePoint, EncodedPoint, ProjectivePoint, Scalar};
+use log::{debug, info, warn};
+use rand::{CryptoRng, RngCore};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Curve point of the share encryption keys, serialized compressed in hex
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Point(pub ProjectivePoint);
+
+impl Point {
+    pub fn to_bytes(&self) -> [u8; 33] {
+        let encoded = self.0.to_affine().to_encoded_point(true);
+        let mut bytes = [0; 33];
+        // The identity encodes to a single zero byte, which stays all zeroes
+        bytes[..encoded.len()].copy_from_slice(encoded.as_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Point, FrostError> {
+        let encoded = EncodedPoint::from_bytes(bytes).map_err(|_| FrostError::InvalidEncoding)?;
+        Option::<AffinePoint>::from(AffinePoint::from_encoded_point(&encoded))
+            .map(|point| Point(point.into()))
+            .ok_or(FrostError::InvalidEncoding)
+    }
+
+    /// BIP340 x-only encoding
+    pub fn x_only(&self) -> [u8; 32] {
+        self.0.to_affine().x().into()
+    }
+}
+
+impl fmt::Debug for Point {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Point({})", self.to_bytes().to_lower_hex_string())
+    }
+}
+
+impl Serialize for Point {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_bytes().to_lower_hex_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Point {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        let bytes = Vec::<u8>::from_hex(&hex).map_err(D::Error::custom)?;
+        Point::from_bytes(&bytes).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum CeremonyMessage {
+    /// Round one package, the same for every participant, with the sender's encryption key
+    Round1 {
+        package: Box<Round1Package>,
+        encryption_key: Point,
+    },
+    /// Round two share encrypted to its recipient, hex encoded
+    Round2 { nonce: String, ciphertext: String },
+}
+
+impl CeremonyMessage {
+    pub fn round(&self) -> u8 {
+        match self {
+            CeremonyMessage::Round1 { .. } => 1,
+            CeremonyMessage::Round2 { .. } => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CeremonyError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The participant's message of the round didn't arrive in time
+    Timeout {
+        from: Identifier,
+        round: u8,
+    },
+    Unreachable(Identifier),
+    /// The participant's round two share can't be decrypted
+    Decryption(Identifier),
+    Frost(FrostError),
+}
+
+impl fmt::Display for CeremonyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CeremonyError::Io(e) => write!(f, "{e}"),
+            CeremonyError::Json(e) => write!(f, "{e}"),
+            CeremonyError::Timeout { from, round } => write!(
+                f,
+                "Timed out waiting for the round {round} message of participant {from}"
+            ),
+            CeremonyError::Unreachable(peer) => write!(f, "Participant {peer} is unreachable"),
+            CeremonyError::Decryption(from) => {
+                write!(f, "Could not decrypt the share of participant {from}")
+            }
+            CeremonyError::Frost(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for CeremonyError {}
+
+impl From<io::Error> for CeremonyError {
+    fn from(error: io::Error) -> Self {
+        CeremonyError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for CeremonyError {
+    fn from(error: serde_json::Error) -> Self {
+        CeremonyError::Json(error)
+    }
+}
+
+impl From<FrostError> for CeremonyError {
+    fn from(error: FrostError) -> Self {
+        CeremonyError::Frost(error)
+    }
+}
+
+/// How the participants of a ceremony exchange their messages
+pub trait CeremonyTransport {
+    fn send(&self, to: Identifier, message: &CeremonyMessage) -> Result<(), CeremonyError>;
+    /// Waits for the participant's message of the round
+    fn receive(&self, from: Identifier, round: u8) -> Result<CeremonyMessage, CeremonyError>;
+}
+
+/// Messages written to a directory shared by the participants, as `round{r}-{from}-{to}.json`
+pub struct FileTransport {
+    dir: PathBuf,
+    identifier: Identifier,
+    timeout: Duration,
+}
+
+impl FileTransport {
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        identifier: Identifier,
+        timeout: Duration,
+    ) -> FileTransport {
+        FileTransport {
+            dir: dir.into(),
+            identifier,
+            timeout,
+        }
+    }
+
+    fn path(&self, round: u8, from: Identifier, to: Identifier) -> PathBuf {
+        self.dir.join(format!("round{round}-{from}-{to}.json"))
+    }
+}
+
+impl CeremonyTransport for FileTransport {
+    fn send(&self, to: Identifier, message: &CeremonyMessage) -> Result<(), CeremonyError> {
+        let path = self.path(message.round(), self.identifier, to);
+        // Renamed into place, so the recipient never reads a partly written message
+        let partial = path.with_extension("partial");
+        fs::write(&partial, serde_json::to_string(message)?)?;
+        fs::rename(partial, path)?;
+        Ok(())
+    }
+
+    fn receive(&self, from: Identifier, round: u8) -> Result<CeremonyMessage, CeremonyError> {
+        let path = self.path(round, from, self.identifier);
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match fs::read_to_string(&path) {
+                Ok(contents) => return Ok(serde_json::from_str(&contents)?),
+                Err(e) if e.kind() == io::ErrorKind::NotFound && Instant::now() < deadline => {
+                    thread::sleep(POLL_INTERVAL)
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Err(CeremonyError::Timeout { from, round })
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    from: Identifier,
+    to: Identifier,
+    message: CeremonyMessage,
+}
+
+type Inbox = Arc<(Mutex<BTreeMap<(Identifier, u8), CeremonyMessage>>, Condvar)>;
+
+/// Newline delimited JSON over TCP, a connection per message. Messages are received by a listener
+/// thread from the start, so participants may run ahead of each other.
+pub struct TcpTransport {
+    identifier: Identifier,
+    peers: BTreeMap<Identifier, String>,
+    timeout: Duration,
+    inbox: Inbox,
+}
+
+impl TcpTransport {
+    pub fn new(
+        identifier: Identifier,
+        listener: TcpListener,
+        peers: BTreeMap<Identifier, String>,
+        timeout: Duration,
+    ) -> TcpTransport {
+        let inbox = Inbox::default();
+        let listener_inbox = inbox.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream
+                    .map_err(CeremonyError::from)
+                    .and_then(|stream| receive_envelopes(stream, identifier, &listener_inbox));
+                if let Err(e) = result {
+                    warn!("Failed to receive a ceremony message: {e}");
+                }
+            }
+        });
+        TcpTransport {
+            identifier,
+            peers,
+            timeout,
+            inbox,
+        }
+    }
+}
+
+fn receive_envelopes(
+    stream: TcpStream,
+    identifier: Identifier,
+    inbox: &Inbox,
+) -> Result<(), CeremonyError> {
+    for line in BufReader::new(stream).lines() {
+        let envelope: Envelope = serde_json::from_str(&line?)?;
+        if envelope.to != identifier {
+            warn!("Ignoring a message for participant {}", envelope.to);
+            continue;
+        }
+        let (messages, arrived) = &**inbox;
+        messages
+            .lock()
+            .expect("inbox mutex not to be poisoned")
+            .insert((envelope.from, envelope.message.round()), envelope.message);
+        arrived.notify_all();
+    }
+    Ok(())
+}
+
+impl CeremonyTransport for TcpTransport {
+    fn send(&self, to: Identifier, message: &CeremonyMessage) -> Result<(), CeremonyError> {
+        let address = self.peers.get(&to).ok_or(CeremonyError::Unreachable(to))?;
+        // The peer may not listen yet
+        let deadline = Instant::now() + self.timeout;
+        let mut stream = loop {
+            match TcpStream::connect(address) {
+                Ok(stream) => break stream,
+                Err(e) if Instant::now() < deadline => {
+                    debug!("Couldn't connect to participant {to} at {address}: {e}");
+                    thread::sleep(POLL_INTERVAL)
+                }
+                Err(_) => return Err(CeremonyError::Unreachable(to)),
+            }
+        };
+        let envelope = Envelope {
+            from: self.identifier,
+            to,
+            message: message.clone(),
+        };
+        let mut line = serde_json::to_string(&envelope)?;
+        line.push('\n');
+        stream.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    fn receive(&self, from: Identifier, round: u8) -> Result<CeremonyMessage, CeremonyError> {
+        let (messages, arrived) = &*self.inbox;
+        let deadline = Instant::now() + self.timeout;
+        let mut messages = messages.lock().expect("inbox mutex not to be poisoned");
+        loop {
+            if let Some(message) = messages.remove(&(from, round)) {
+                return Ok(message);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(CeremonyError::Timeout { from, round });
+            }
+            messages = arrived
+                .wait_timeout(messages, remaining)
+                .expect("inbox mutex not to be poisoned")
+                .0;
+        }
+    }
+}
+
+fn share_cipher(
+    secret: &Scalar,
+    peer_key: &Point,
+    from: Identifier,
+    to: Identifier,
+) -> Aes256GcmSiv {
+    let shared = Point(peer_key.0 * secret);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&shared.x_only());
+    engine.input(&from.to_be_bytes());
+    engine.input(&to.to_be_bytes());
+    let key = sha256::Hash::from_engine(engine).to_byte_array();
+    Aes256GcmSiv::new_from_slice(&key).expect("a 32 byte key")
+}
+
+/// Runs the key generation ceremony with the other `max_signers - 1` participants, returning the
+/// participant's key package
+pub fn run_ceremony(
+    identifier: Identifier,
+    max_signers: u16,
+    threshold: u16,
+    transport: &dyn CeremonyTransport,
+    rng: &mut (impl RngCore + CryptoRng),
+) -> Result<KeyPackage, CeremonyError> {
+    let others: Vec<Identifier> = (1..=max_signers).filter(|id| *id != identifier).collect();
+    let encryption_secret = Scalar::random(&mut *rng);
+    let (round1_secret, package) = dkg::part1(identifier, max_signers, threshold, &mut *rng)?;
+    let round1 = CeremonyMessage::Round1 {
+        package: Box::new(package),
+        encryption_key: Point(ProjectivePoint::GENERATOR * encryption_secret),
+    };
+    for peer in &others {
+        transport.send(*peer, &round1)?;
+    }
+
+    let mut packages = BTreeMap::new();
+    let mut encryption_keys = BTreeMap::new();
+    for peer in &others {
+        match transport.receive(*peer, 1)? {
+            CeremonyMessage::Round1 {
+                package,
+                encryption_key,
+            } => {
+                packages.insert(*peer, *package);
+                encryption_keys.insert(*peer, encryption_key);
+            }
+            CeremonyMessage::Round2 { .. } => return Err(FrostError::MissingPackage(*peer).into()),
+        }
+    }
+    info!("Received the round one packages of all the participants");
+
+    let (round2_secret, shares) = dkg::part2(round1_secret, &packages)?;
+    for (peer, share) in shares {
+        let mut nonce = [0; 12];
+        rng.fill_bytes(&mut nonce);
+        let ciphertext = share_cipher(
+            &encryption_secret,
+            &encryption_keys[&peer],
+            identifier,
+            peer,
+        )
+        .encrypt(
+            (&nonce).into(),
+            share.serialize().map_err(FrostError::from)?.as_slice(),
+        )
+        .expect("a serialized share to be encrypted");
+        let message = CeremonyMessage::Round2 {
+            nonce: nonce.to_lower_hex_string(),
+            ciphertext: ciphertext.to_lower_hex_string(),
+        };
+        transport.send(peer, &message)?;
+    }
+
+    let mut received = BTreeMap::new();
+    for peer in &others {
+        let CeremonyMessage::Round2 { nonce, ciphertext } = transport.receive(*peer, 2)? else {
+            return Err(CeremonyError::Decryption(*peer));
+        };
+        let nonce = <[u8; 12]>::from_hex(&nonce).map_err(|_| CeremonyError::Decryption(*peer))?;
+        let ciphertext =
+            Vec::<u8>::from_hex(&ciphertext).map_err(|_| CeremonyError::Decryption(*peer))?;
+        let share = share_cipher(
+            &encryption_secret,
+            &encryption_keys[peer],
+            *peer,
+            identifier,
+        )
+        .decrypt((&nonce).into(), ciphertext.as_slice())
+        .map_err(|_| CeremonyError::Decryption(*peer))?;
+        let share =
+            Round2Package::deserialize(&share).map_err(|_| CeremonyError::Decryption(*peer))?;
+        received.insert(*peer, share);
+    }
+    Ok(dkg::part3(round2_secret, &received)?)
+}
+
+/// Public outcome of the ceremony, the same for every participant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupInfo {
+    pub public: PublicKeyPackage,
+    /// X-only taproot internal key, hex encoded
+    pub internal_key: String,
+    /// X-only taproot output key registered in the relay program, hex encoded
+    pub output_key: String,
+    pub address: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_all(
+        transports: Vec<impl CeremonyTransport + Send + 'static>,
+        threshold: u16,
+    ) -> Vec<KeyPackage> {
+        let max_signers = transports.len() as u16;
+        let participants: Vec<_> = transports
+            .into_iter()
+            .enumerate()
+            .map(|(index, transport)| {
+                thread::spawn(move || {
+                    run_ceremony(
+                        index as u16 + 1,
+                        max_signers,
+                        threshold,
+                        &transport,
+                        &mut rand::thread_rng(),
+                    )
+                })
+            })
+            .collect();
+        participants
+            .into_iter()
+            .map(|participant| participant.join().unwrap().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn runs_ceremonies_over_files_and_tcp() {
+        let dir = std::env::temp_dir().join(format!("ceremony-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let transports = (1..=3)
+            .map(|id| FileTransport::new(&dir, id, Duration::from_secs(10)))
+            .collect();
+        let keys = run_all(transports, 2);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(keys.iter().all(|key| key.public == keys[0].public));
+        assert_eq!(keys[2].identifier, 3);
+
+        let listeners: Vec<_> = (0..3)
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let addresses: BTreeMap<Identifier, String> = listeners
+            .iter()
+            .zip(1..)
+            .map(|(listener, id)| (id, listener.local_addr().unwrap().to_string()))
+            .collect();
+        let transports = listeners
+            .into_iter()
+            .zip(1..)
+            .map(|(listener, id)| {
+                TcpTransport::new(id, listener, addresses.clone(), Duration::from_secs(10))
+            })
+            .collect();
+        let keys = run_all(transports, 3);
+        assert!(keys.iter().all(|key| key.public == keys[0].public));
+        assert_eq!(keys[0].public.threshold, 3);
+    }
+
+    #[test]
+    fn times_out_waiting_for_missing_participants() {
+        let dir = std::env::temp_dir().join(format!("ceremony-timeout-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let transport = FileTransport::new(&dir, 1, Duration::from_millis(300));
+        let result = run_ceremony(1, 2, 2, &transport, &mut rand::thread_rng());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(
+            result,
+            Err(CeremonyError::Timeout { from: 2, round: 1 })
+        ));
+    }
+}
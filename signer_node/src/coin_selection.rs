@@ -0,0 +1,339 @@
+//! Picks the group's outputs funding a payout, the way Bitcoin Core's wallet does. Branch and bound
+//! looks for outputs paying the withdrawn amount without change, the largest outputs are spent
+//! first otherwise. While the fee rate is below its long term rate, payouts with change also spend
+//! some of the group's smallest outputs, consolidating them into the change while it's cheap.
+//! Deposits are spent through their script path, each input is weighed by the script it spends.
+use crate::wallet::{ScriptVariant, TrackedUtxo, WalletError};
+
+/// Virtual size of a taproot key path input, rounded up from 57.5 vbytes
+pub const KEY_SPEND_INPUT_VSIZE: u64 = 58;
+/// Virtual size of a deposit input spent through its leaf, with the 68 byte deposit script and a
+/// 33 byte control block, rounded up from 83.25 vbytes
+pub const DEPOSIT_INPUT_VSIZE: u64 = 84;
+/// Virtual size of a taproot output
+pub const TAPROOT_OUTPUT_VSIZE: u64 = 43;
+
+const BNB_TRIES: usize = 100_000;
+const MAX_CONSOLIDATED_INPUTS: usize = 50;
+
+/// Fee rates and budget the coordinator selects the group's outputs with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoinSelection {
+    /// Fee rate of the payouts in sats per virtual byte
+    pub fee_rate: u64,
+    /// Fee rate the group expects to pay on average, outputs are consolidated below it
+    pub long_term_fee_rate: u64,
+    /// Transaction fee in sats the group pays to spend extra outputs in a payout
+    pub max_consolidation_fee: u64,
+}
+
+/// Virtual size of an input spending the output
+pub fn input_vsize(variant: &ScriptVariant) -> u64 {
+    match variant {
+        ScriptVariant::Group => KEY_SPEND_INPUT_VSIZE,
+        ScriptVariant::Deposit { .. } => DEPOSIT_INPUT_VSIZE,
+    }
+}
+
+impl CoinSelection {
+    fn input_fee(&self, tracked: &TrackedUtxo) -> u64 {
+        input_vsize(&tracked.variant) * self.fee_rate
+    }
+
+    /// Waste of spending the output now rather than at the long term fee rate, negative while
+    /// fees are low
+    fn input_waste(&self, tracked: &TrackedUtxo) -> i64 {
+        input_vsize(&tracked.variant) as i64
+            * (self.fee_rate as i64 - self.long_term_fee_rate as i64)
+    }
+
+    /// Creating change costs its output now and its input later
+    fn cost_of_change(&self) -> u64 {
+        TAPROOT_OUTPUT_VSIZE * self.fee_rate + KEY_SPEND_INPUT_VSIZE * self.long_term_fee_rate
+    }
+}
+
+/// Outputs funding the withdrawn amount and the ones spent on top to consolidate them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selection {
+    pub funding: Vec<TrackedUtxo>,
+    pub consolidated: Vec<TrackedUtxo>,
+}
+
+struct Search<'a> {
+    values: &'a [u64],
+    input_wastes: &'a [i64],
+    /// Sum of the values from each index on
+    remaining: Vec<u64>,
+    target: u64,
+    upper_bound: u64,
+    /// Fees are above their long term rate, so every input wastes
+    high_fees: bool,
+    tries: usize,
+    best: Option<(i64, Vec<usize>)>,
+}
+
+impl Search<'_> {
+    fn explore(&mut self, index: usize, selected: &mut Vec<usize>, total: u64, inputs_waste: i64) {
+        if self.tries == 0 || total > self.upper_bound {
+            return;
+        }
+        self.tries -= 1;
+        if total >= self.target {
+            let waste = inputs_waste + (total - self.target) as i64;
+            if self.best.as_ref().is_none_or(|(best, _)| waste < *best) {
+                self.best = Some((waste, selected.clone()));
+            }
+            return;
+        }
+        if index == self.values.len() || total + self.remaining[index] < self.target {
+            return;
+        }
+        // While fees are high, more inputs only waste more
+        if let Some((best, _)) = &self.best {
+            if self.high_fees && inputs_waste > *best {
+                return;
+            }
+        }
+
+        selected.push(index);
+        self.explore(
+            index + 1,
+            selected,
+            total + self.values[index],
+            inputs_waste + self.input_wastes[index],
+        );
+        selected.pop();
+        // Leaving out an output like the one left out gives the same selections
+        let mut next = index + 1;
+        while next < self.values.len()
+            && self.values[next] == self.values[index]
+            && self.input_wastes[next] == self.input_wastes[index]
+        {
+            next += 1;
+        }
+        self.explore(next, selected, total, inputs_waste);
+    }
+}
+
+/// Outputs adding up to between `target` and `target + max_excess` sats with the least waste, the
+/// candidates sorted by decreasing value
+fn branch_and_bound(
+    candidates: &[TrackedUtxo],
+    target: u64,
+    max_excess: u64,
+    selection: &CoinSelection,
+) -> Option<Vec<TrackedUtxo>> {
+    let values: Vec<u64> = candidates
+        .iter()
+        .map(|tracked| tracked.utxo.output.value.to_sat())
+        .collect();
+    let input_wastes: Vec<i64> = candidates
+        .iter()
+        .map(|tracked| selection.input_waste(tracked))
+        .collect();
+    let mut remaining = vec![0; values.len() + 1];
+    for index in (0..values.len()).rev() {
+        remaining[index] = remaining[index + 1] + values[index];
+    }
+    let mut search = Search {
+        values: &values,
+        input_wastes: &input_wastes,
+        remaining,
+        target,
+        upper_bound: target + max_excess,
+        high_fees: selection.fee_rate > selection.long_term_fee_rate,
+        tries: BNB_TRIES,
+        best: None,
+    };
+    search.explore(0, &mut vec![], 0, 0);
+    search.best.map(|(_, selected)| {
+        selected
+            .into_iter()
+            .map(|index| candidates[index].clone())
+            .collect()
+    })
+}
+
+/// Selects the outputs paying `target` sats. A selection without change may spend up to
+/// `max_excess` sats more, which go to the miners.
+pub fn select_coins(
+    utxos: &[TrackedUtxo],
+    target: u64,
+    max_excess: u64,
+    selection: &CoinSelection,
+) -> Result<Selection, WalletError> {
+    let mut candidates = utxos.to_vec();
+    candidates.sort_by_key(|tracked| std::cmp::Reverse(tracked.utxo.output.value));
+    let available: u64 = candidates
+        .iter()
+        .map(|tracked| tracked.utxo.output.value.to_sat())
+        .sum();
+    if available < target {
+        return Err(WalletError::InsufficientFunds {
+            needed: target,
+            available,
+        });
+    }
+
+    let max_excess = max_excess.min(selection.cost_of_change());
+    if let Some(funding) = branch_and_bound(&candidates, target, max_excess, selection) {
+        return Ok(Selection {
+            funding,
+            consolidated: vec![],
+        });
+    }
+
+    let mut funding = vec![];
+    let mut total = 0;
+    while total < target {
+        let tracked = candidates.remove(0);
+        total += tracked.utxo.output.value.to_sat();
+        funding.push(tracked);
+    }
+
+    let mut consolidated = vec![];
+    if selection.fee_rate < selection.long_term_fee_rate {
+        // Smallest first, as long as they're worth more than spending them
+        let mut consolidation_fee = 0;
+        for tracked in candidates.into_iter().rev() {
+            let input_fee = selection.input_fee(&tracked);
+            if consolidated.len() == MAX_CONSOLIDATED_INPUTS
+                || consolidation_fee + input_fee > selection.max_consolidation_fee
+            {
+                break;
+            }
+            if tracked.utxo.output.value.to_sat() > 2 * input_fee {
+                consolidation_fee += input_fee;
+                consolidated.push(tracked);
+            }
+        }
+    }
+    Ok(Selection {
+        funding,
+        consolidated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::Utxo;
+    use anchor_client::solana_sdk::pubkey::Pubkey;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, TxOut, Txid};
+
+    fn utxos_of(values: &[u64], variant: ScriptVariant) -> Vec<TrackedUtxo> {
+        values
+            .iter()
+            .zip(0..)
+            .map(|(value, vout)| TrackedUtxo {
+                utxo: Utxo {
+                    outpoint: OutPoint::new(Txid::all_zeros(), vout),
+                    output: TxOut {
+                        value: Amount::from_sat(*value),
+                        script_pubkey: ScriptBuf::new(),
+                    },
+                },
+                variant,
+                spent_by: None,
+            })
+            .collect()
+    }
+
+    fn utxos(values: &[u64]) -> Vec<TrackedUtxo> {
+        utxos_of(values, ScriptVariant::Group)
+    }
+
+    fn deposits(values: &[u64]) -> Vec<TrackedUtxo> {
+        utxos_of(
+            values,
+            ScriptVariant::Deposit {
+                receiver: Pubkey::new_unique(),
+            },
+        )
+    }
+
+    fn values(utxos: &[TrackedUtxo]) -> Vec<u64> {
+        utxos
+            .iter()
+            .map(|tracked| tracked.utxo.output.value.to_sat())
+            .collect()
+    }
+
+    const HIGH_FEES: CoinSelection = CoinSelection {
+        fee_rate: 20,
+        long_term_fee_rate: 10,
+        max_consolidation_fee: 2_000,
+    };
+
+    const LOW_FEES: CoinSelection = CoinSelection {
+        fee_rate: 1,
+        long_term_fee_rate: 10,
+        max_consolidation_fee: 2_000,
+    };
+
+    #[test]
+    fn finds_selections_without_change() {
+        let group = utxos(&[50_000, 30_000, 20_000, 12_000, 8_000]);
+        let selection = select_coins(&group, 40_000, 330, &HIGH_FEES).unwrap();
+        assert_eq!(values(&selection.funding), [20_000, 12_000, 8_000]);
+        assert!(selection.consolidated.is_empty());
+
+        // Fewer inputs waste less while fees are high
+        let selection = select_coins(&group, 62_000, 330, &HIGH_FEES).unwrap();
+        assert_eq!(values(&selection.funding), [50_000, 12_000]);
+
+        // Excess up to the cost of change goes to the miners, otherwise change is needed
+        let selection = select_coins(&group, 49_800, 330, &HIGH_FEES).unwrap();
+        assert_eq!(values(&selection.funding), [50_000]);
+        let selection = select_coins(&group, 52_000, 330, &HIGH_FEES).unwrap();
+        assert_eq!(values(&selection.funding), [50_000, 30_000]);
+    }
+
+    #[test]
+    fn consolidates_small_outputs_while_fees_are_low() {
+        let group = utxos(&[50_000, 30_000, 900, 700, 100]);
+        let selection = select_coins(&group, 60_000, 330, &HIGH_FEES).unwrap();
+        assert_eq!(values(&selection.funding), [50_000, 30_000]);
+        assert!(selection.consolidated.is_empty());
+
+        // The output worth less than spending it stays
+        let selection = select_coins(&group, 60_000, 330, &LOW_FEES).unwrap();
+        assert_eq!(values(&selection.funding), [50_000, 30_000]);
+        assert_eq!(values(&selection.consolidated), [700, 900]);
+
+        let budget = CoinSelection {
+            max_consolidation_fee: 60,
+            ..LOW_FEES
+        };
+        let selection = select_coins(&group, 60_000, 330, &budget).unwrap();
+        assert_eq!(values(&selection.consolidated), [700]);
+
+        assert!(matches!(
+            select_coins(&group, 90_000, 330, &LOW_FEES),
+            Err(WalletError::InsufficientFunds {
+                needed: 90_000,
+                available: 81_700
+            })
+        ));
+    }
+
+    #[test]
+    fn weighs_deposits_by_their_script_path_inputs() {
+        // Both pay the amount without change, the group's output wastes less
+        let mut candidates = deposits(&[20_000]);
+        candidates.extend(utxos(&[20_000]));
+        let selection = select_coins(&candidates, 20_000, 330, &HIGH_FEES).unwrap();
+        assert_eq!(selection.funding.len(), 1);
+        assert_eq!(selection.funding[0].variant, ScriptVariant::Group);
+
+        // A deposit of 150 sats is worth less than its 84 vbyte input at twice the fee rate
+        let mut candidates = utxos(&[50_000, 150]);
+        candidates.extend(deposits(&[150, 40_000]));
+        let selection = select_coins(&candidates, 60_000, 330, &LOW_FEES).unwrap();
+        assert_eq!(values(&selection.funding), [50_000, 40_000]);
+        assert_eq!(values(&selection.consolidated), [150]);
+        assert_eq!(selection.consolidated[0].variant, ScriptVariant::Group);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::frost::Identifier;
+use bitcoin::Network;
+use bitcoincore_rpc::Auth;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use toml;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "lowercase")]
+pub enum BitcoinAuth {
+    Cookie { path: String },
+    UserPass { user: String, password: String },
+}
+
+impl From<BitcoinAuth> for Auth {
+    fn from(value: BitcoinAuth) -> Self {
+        match value {
+            BitcoinAuth::Cookie { path } => Auth::CookieFile(path.into()),
+            BitcoinAuth::UserPass { user, password } => Auth::UserPass(user, password),
+        }
+    }
+}
+
+/// Another signer node of the group
+#[derive(Deserialize, Debug, Clone)]
+pub struct Peer {
+    pub id: Identifier,
+    /// Address the peer serves the signing protocol at
+    pub address: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SignerConfig {
+    /// The node's FROST identifier, the one of its key package
+    pub id: Identifier,
+    /// JSON file with the node's key package
+    pub key_package: String,
+    pub peers: Vec<Peer>,
+    /// Where the node serves the signing protocol to the coordinator
+    #[serde(default = "default_listen_address")]
+    pub listen_address: String,
+    /// Identifier of the node building the payouts and running the signing rounds
+    pub coordinator: Identifier,
+    pub yona_http: String,
+    pub bitcoind_url: String,
+    pub bitcoin_auth: BitcoinAuth,
+    #[serde(default = "default_network")]
+    pub network: Network,
+    /// Fee rate of the payouts in sats per virtual byte
+    #[serde(default = "default_fee_rate")]
+    pub fee_rate: u64,
+    /// Fee rate the group expects to pay on average, payouts below it consolidate small outputs
+    #[serde(default = "default_long_term_fee_rate")]
+    pub long_term_fee_rate: u64,
+    /// Transaction fee in sats the group pays to consolidate outputs in a payout, payouts proposed
+    /// spending more of the group's funds aren't signed
+    #[serde(default = "default_max_consolidation_fee")]
+    pub max_consolidation_fee: u64,
+    /// Transaction fee in sats a payout may deduct from the withdrawn amount, payouts proposed
+    /// with a higher fee aren't signed
+    #[serde(default = "default_max_withdrawal_fee")]
+    pub max_withdrawal_fee: u64,
+    #[serde(default = "default_store_path")]
+    pub store_path: String,
+    /// Seconds between the coordinator's checks for new withdrawals
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+}
+
+fn default_listen_address() -> String {
+    "0.0.0.0:8299".into()
+}
+
+fn default_network() -> Network {
+    Network::Regtest
+}
+
+fn default_fee_rate() -> u64 {
+    2
+}
+
+fn default_long_term_fee_rate() -> u64 {
+    10
+}
+
+fn default_max_consolidation_fee() -> u64 {
+    2_000
+}
+
+fn default_max_withdrawal_fee() -> u64 {
+    10_000
+}
+
+fn default_store_path() -> String {
+    "signer.sqlite".into()
+}
+
+fn default_poll_interval() -> u64 {
+    30
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(error: io::Error) -> Self {
+        ConfigError::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(error: toml::de::Error) -> Self {
+        ConfigError::Toml(error)
+    }
+}
+
+pub fn read_config() -> Result<SignerConfig, ConfigError> {
+    let config_contents = fs::read_to_string("./config.toml")?;
+    Ok(toml::from_str(&config_contents)?)
+}
@@ -0,0 +1,248 @@
+//! Distributed generation of the group key (the Pedersen DKG of the FROST paper), so no
+//! participant ever knows the whole key, with the DKG of the `frost-secp256k1-tr` crate.
+//!
+//! In round one each participant broadcasts commitments to the coefficients of a random polynomial
+//! with a proof of knowledge of its constant term. In round two it sends each other participant
+//! its polynomial evaluated at their identifier. The shares are checked against the commitments,
+//! so a participant sending an invalid share is named.
+use crate::frost::{self, FrostError, Identifier, KeyPackage, PublicKeyPackage};
+use frost_secp256k1_tr::keys::dkg;
+use rand::{CryptoRng, RngCore};
+use std::collections::BTreeMap;
+
+pub use frost_secp256k1_tr::keys::dkg::round1::Package as Round1Package;
+pub use frost_secp256k1_tr::keys::dkg::round1::SecretPackage as Round1Secret;
+pub use frost_secp256k1_tr::keys::dkg::round2::Package as Round2Package;
+
+/// Secret state of a participant between rounds two and three, with the round one packages the
+/// shares are checked against
+#[derive(Debug)]
+pub struct Round2Secret {
+    secret: dkg::round2::SecretPackage,
+    packages: BTreeMap<frost_secp256k1_tr::Identifier, Round1Package>,
+}
+
+// Names the participant of the crate's error out of the `max_signers`
+fn dkg_error(error: frost_secp256k1_tr::Error, max_signers: u16) -> FrostError {
+    match error {
+        frost_secp256k1_tr::Error::InvalidProofOfKnowledge { culprit } => {
+            FrostError::InvalidProof(frost::participant(&culprit, max_signers))
+        }
+        frost_secp256k1_tr::Error::InvalidSecretShare {
+            culprit: Some(culprit),
+        } => FrostError::InvalidShare(frost::participant(&culprit, max_signers)),
+        e => FrostError::Frost(e),
+    }
+}
+
+// The packages of the participants other than `identifier` by the crate's identifiers, naming
+// the first participant without one
+fn others_packages<P: Clone>(
+    identifier: Identifier,
+    max_signers: u16,
+    packages: &BTreeMap<Identifier, P>,
+    valid: impl Fn(&P) -> bool,
+) -> Result<BTreeMap<frost_secp256k1_tr::Identifier, P>, FrostError> {
+    (1..=max_signers)
+        .filter(|participant| *participant != identifier)
+        .map(|participant| {
+            let package = packages
+                .get(&participant)
+                .filter(|package| valid(package))
+                .ok_or(FrostError::MissingPackage(participant))?;
+            Ok((frost::frost_identifier(participant)?, package.clone()))
+        })
+        .collect()
+}
+
+/// Round one: samples the participant's polynomial, the package is broadcast to the others.
+/// Fails unless `2 <= threshold <= max_signers` and the identifier is one of the `max_signers`.
+pub fn part1(
+    identifier: Identifier,
+    max_signers: u16,
+    threshold: u16,
+    rng: &mut (impl RngCore + CryptoRng),
+) -> Result<(Round1Secret, Round1Package), FrostError> {
+    if identifier > max_signers {
+        return Err(FrostError::InvalidSigners);
+    }
+    Ok(dkg::part1(
+        frost::frost_identifier(identifier)?,
+        max_signers,
+        threshold,
+        rng,
+    )?)
+}
+
+/// Round two: checks the others' round one packages, returning the share to send each of them
+pub fn part2(
+    secret: Round1Secret,
+    packages: &BTreeMap<Identifier, Round1Package>,
+) -> Result<(Round2Secret, BTreeMap<Identifier, Round2Package>), FrostError> {
+    let max_signers = *secret.max_signers();
+    let threshold = usize::from(*secret.min_signers());
+    let identifier = frost::participant(secret.identifier(), max_signers);
+    let packages = others_packages(identifier, max_signers, packages, |package| {
+        package
+            .commitment()
+            .serialize()
+            .is_ok_and(|coefficients| coefficients.len() == threshold)
+    })?;
+    let (round2_secret, shares) =
+        dkg::part2(secret, &packages).map_err(|e| dkg_error(e, max_signers))?;
+    let shares = shares
+        .into_iter()
+        .map(|(participant, share)| (frost::participant(&participant, max_signers), share))
+        .collect();
+    let secret = Round2Secret {
+        secret: round2_secret,
+        packages,
+    };
+    Ok((secret, shares))
+}
+
+/// Round three: checks the shares received from the others against their commitments and
+/// combines them into the participant's key package
+pub fn part3(
+    secret: Round2Secret,
+    shares: &BTreeMap<Identifier, Round2Package>,
+) -> Result<KeyPackage, FrostError> {
+    let max_signers = *secret.secret.max_signers();
+    let identifier = frost::participant(secret.secret.identifier(), max_signers);
+    let shares = others_packages(identifier, max_signers, shares, |_| true)?;
+    let (key, package) = dkg::part3(&secret.secret, &secret.packages, &shares)
+        .map_err(|e| dkg_error(e, max_signers))?;
+    Ok(KeyPackage {
+        identifier,
+        signing_share: *key.signing_share(),
+        public: PublicKeyPackage {
+            package,
+            threshold: *key.min_signers(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frost::{aggregate, commit, output_key, sign, signing_package, verify};
+
+    type Round2Shares = BTreeMap<Identifier, BTreeMap<Identifier, Round2Package>>;
+
+    fn run_part1(
+        max_signers: u16,
+        threshold: u16,
+    ) -> (
+        BTreeMap<Identifier, Round1Secret>,
+        BTreeMap<Identifier, Round1Package>,
+    ) {
+        let mut rng = rand::thread_rng();
+        (1..=max_signers)
+            .map(|id| {
+                let (secret, package) = part1(id, max_signers, threshold, &mut rng).unwrap();
+                ((id, secret), (id, package))
+            })
+            .unzip()
+    }
+
+    fn run_rounds_1_and_2(
+        max_signers: u16,
+        threshold: u16,
+    ) -> (BTreeMap<Identifier, Round2Secret>, Round2Shares) {
+        let (secrets, packages) = run_part1(max_signers, threshold);
+        let mut round2_secrets = BTreeMap::new();
+        let mut received: Round2Shares = BTreeMap::new();
+        for (id, secret) in secrets {
+            let mut others = packages.clone();
+            others.remove(&id);
+            let (round2, shares) = part2(secret, &others).unwrap();
+            round2_secrets.insert(id, round2);
+            for (to, share) in shares {
+                received.entry(to).or_default().insert(id, share);
+            }
+        }
+        (round2_secrets, received)
+    }
+
+    #[test]
+    fn generated_keys_sign_together() {
+        let (secrets, mut received) = run_rounds_1_and_2(3, 2);
+        let keys: Vec<KeyPackage> = secrets
+            .into_iter()
+            .map(|(id, secret)| part3(secret, &received.remove(&id).unwrap()).unwrap())
+            .collect();
+        assert!(keys.iter().all(|key| key.public == keys[0].public));
+        assert_eq!(keys[2].identifier, 3);
+        assert_eq!(keys[0].public.threshold, 2);
+
+        let mut rng = rand::thread_rng();
+        let (nonces_1, nonces_3) = (commit(&keys[0], &mut rng), commit(&keys[2], &mut rng));
+        let package = signing_package(
+            BTreeMap::from([(1, *nonces_1.commitments()), (3, *nonces_3.commitments())]),
+            &[5; 32],
+        )
+        .unwrap();
+        let shares = BTreeMap::from([
+            (1, sign(&keys[0], nonces_1, &package).unwrap()),
+            (3, sign(&keys[2], nonces_3, &package).unwrap()),
+        ]);
+        let signature = aggregate(&keys[0].public, &package, &shares).unwrap();
+        verify(&output_key(&keys[0].public), &[5; 32], &signature).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        let mut rng = rand::thread_rng();
+        assert!(matches!(
+            part1(1, 3, 1, &mut rng).unwrap_err(),
+            FrostError::Frost(frost_secp256k1_tr::Error::InvalidMinSigners)
+        ));
+        assert!(matches!(
+            part1(1, 2, 3, &mut rng).unwrap_err(),
+            FrostError::Frost(frost_secp256k1_tr::Error::InvalidMinSigners)
+        ));
+        assert_eq!(
+            part1(0, 3, 2, &mut rng).unwrap_err(),
+            FrostError::InvalidSigners
+        );
+        assert_eq!(
+            part1(4, 3, 2, &mut rng).unwrap_err(),
+            FrostError::InvalidSigners
+        );
+    }
+
+    #[test]
+    fn names_participants_with_invalid_proofs_and_shares() {
+        let (mut secrets, packages) = run_part1(3, 2);
+        assert_eq!(
+            part2(
+                secrets.remove(&1).unwrap(),
+                &BTreeMap::from([(2, packages[&2].clone())])
+            )
+            .unwrap_err(),
+            FrostError::MissingPackage(3)
+        );
+        // Participant 3 claims participant 1's proof of knowledge for its own commitments
+        let forged = Round1Package::new(
+            packages[&3].commitment().clone(),
+            *packages[&1].proof_of_knowledge(),
+        );
+        assert_eq!(
+            part2(
+                secrets.remove(&2).unwrap(),
+                &BTreeMap::from([(1, packages[&1].clone()), (3, forged)])
+            )
+            .unwrap_err(),
+            FrostError::InvalidProof(3)
+        );
+
+        let (mut secrets, mut received) = run_rounds_1_and_2(3, 2);
+        let mut shares = received.remove(&1).unwrap();
+        // Participant 2 sends participant 1 the share meant for participant 3
+        shares.insert(2, received[&3][&2].clone());
+        assert_eq!(
+            part3(secrets.remove(&1).unwrap(), &shares).unwrap_err(),
+            FrostError::InvalidShare(2)
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use anchor_client::anchor_lang::{AnchorDeserialize, Discriminator};
+use anchor_client::solana_client::client_error::ClientError;
+use anchor_client::solana_client::rpc_client::{
+    GetConfirmedSignaturesForAddress2Config, RpcClient,
+};
+use anchor_client::solana_client::rpc_config::RpcTransactionConfig;
+use anchor_client::solana_sdk::commitment_config::CommitmentConfig;
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use anchor_client::solana_sdk::signature::{ParseSignatureError, Signature};
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use bitcoin::hashes::Hash;
+use bitcoin::{ScriptBuf, Txid};
+use btc_relay::events::{DepositMinted, WithdrawalRequested};
+use btc_relay::structs::DepositOutput;
+use log::debug;
+use solana_transaction_status::UiTransactionEncoding;
+use std::fmt;
+use std::str::FromStr;
+
+/// Withdrawal requested from the relay program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Withdrawal {
+    /// `{signature}:{index}` of the Yona transaction and the request's index among its withdrawals
+    pub id: String,
+    pub requester: Pubkey,
+    /// Satoshis to pay out, including the Bitcoin transaction fee
+    pub amount: u64,
+    pub script_pubkey: ScriptBuf,
+}
+
+/// Deposit the relay program verified and minted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deposit {
+    pub txid: Txid,
+    pub receiver: Pubkey,
+    /// Bridge key the receiver's deposit script was derived from
+    pub group_key: [u8; 32],
+    /// Outputs paying the receiver's deposit script
+    pub outputs: Vec<DepositOutput>,
+}
+
+/// Bridge events the relay program logged, oldest first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RelayEvents {
+    pub withdrawals: Vec<Withdrawal>,
+    pub deposits: Vec<Deposit>,
+}
+
+#[derive(Debug)]
+pub enum IndexError {
+    Rpc(Box<ClientError>),
+    InvalidCursor(ParseSignatureError),
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexError::Rpc(e) => write!(f, "{e}"),
+            IndexError::InvalidCursor(e) => write!(f, "Invalid index cursor: {e}"),
+        }
+    }
+}
+
+impl From<ClientError> for IndexError {
+    fn from(error: ClientError) -> Self {
+        IndexError::Rpc(Box::new(error))
+    }
+}
+
+/// Where the signer node learns about the requested withdrawals and the minted deposits
+pub trait EventSource {
+    /// Events logged after the cursor, with the cursor following them
+    fn events_after(
+        &self,
+        cursor: Option<&str>,
+    ) -> Result<(RelayEvents, Option<String>), IndexError>;
+}
+
+impl<T: EventSource> EventSource for &T {
+    fn events_after(
+        &self,
+        cursor: Option<&str>,
+    ) -> Result<(RelayEvents, Option<String>), IndexError> {
+        (*self).events_after(cursor)
+    }
+}
+
+/// Bridge events the relay program logged in the transaction. Events logged while another program
+/// is executing are ignored, as any program invoked by the transaction could log them.
+pub fn parse_events(signature: &str, logs: &[String], program_id: &Pubkey) -> RelayEvents {
+    let program_id = program_id.to_string();
+    let mut invoked: Vec<&str> = vec![];
+    let mut events = RelayEvents::default();
+    for log in logs {
+        if let Some(invocation) = log.strip_prefix("Program ") {
+            if let Some((program, rest)) = invocation.split_once(' ') {
+                if rest.starts_with("invoke [") {
+                    invoked.push(program);
+                    continue;
+                }
+                if rest == "success" || rest.starts_with("failed") {
+                    invoked.pop();
+                    continue;
+                }
+            }
+        }
+        let Some(data) = log.strip_prefix("Program data: ") else {
+            continue;
+        };
+        if invoked.last() != Some(&program_id.as_str()) {
+            continue;
+        }
+        let Ok(data) = BASE64_STANDARD.decode(data) else {
+            continue;
+        };
+        if let Some(event) = data.strip_prefix(WithdrawalRequested::DISCRIMINATOR.as_slice()) {
+            match WithdrawalRequested::try_from_slice(event) {
+                Ok(event) => events.withdrawals.push(Withdrawal {
+                    id: format!("{signature}:{}", events.withdrawals.len()),
+                    requester: event.requester,
+                    amount: event.amount,
+                    script_pubkey: ScriptBuf::from_bytes(event.script_pubkey),
+                }),
+                Err(e) => debug!("Undecodable withdrawal event in {signature}: {e}"),
+            }
+        } else if let Some(event) = data.strip_prefix(DepositMinted::DISCRIMINATOR.as_slice()) {
+            match DepositMinted::try_from_slice(event) {
+                Ok(event) => events.deposits.push(Deposit {
+                    txid: Txid::from_byte_array(event.reversed_txid),
+                    receiver: event.receiver,
+                    group_key: event.group_key,
+                    outputs: event.outputs,
+                }),
+                Err(e) => debug!("Undecodable deposit event in {signature}: {e}"),
+            }
+        }
+    }
+    events
+}
+
+/// Indexes the bridge events of the relay program's finalized transactions
+pub struct YonaEvents {
+    rpc: RpcClient,
+    program_id: Pubkey,
+}
+
+impl YonaEvents {
+    pub fn new(yona_http: &str, program_id: Pubkey) -> YonaEvents {
+        YonaEvents {
+            rpc: RpcClient::new_with_commitment(
+                yona_http.to_string(),
+                CommitmentConfig::finalized(),
+            ),
+            program_id,
+        }
+    }
+}
+
+impl EventSource for YonaEvents {
+    fn events_after(
+        &self,
+        cursor: Option<&str>,
+    ) -> Result<(RelayEvents, Option<String>), IndexError> {
+        let until = cursor
+            .map(Signature::from_str)
+            .transpose()
+            .map_err(IndexError::InvalidCursor)?;
+
+        // Signatures are returned newest first, a page at a time
+        let mut statuses = vec![];
+        let mut before = None;
+        loop {
+            let page = self.rpc.get_signatures_for_address_with_config(
+                &self.program_id,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    until,
+                    limit: None,
+                    commitment: Some(CommitmentConfig::finalized()),
+                },
+            )?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            before = Some(Signature::from_str(&last.signature).map_err(IndexError::InvalidCursor)?);
+            statuses.extend(page);
+        }
+        statuses.reverse();
+
+        let mut events = RelayEvents::default();
+        for status in &statuses {
+            if status.err.is_some() {
+                continue;
+            }
+            let signature =
+                Signature::from_str(&status.signature).map_err(IndexError::InvalidCursor)?;
+            let transaction = self.rpc.get_transaction_with_config(
+                &signature,
+                RpcTransactionConfig {
+                    encoding: Some(UiTransactionEncoding::Json),
+                    commitment: Some(CommitmentConfig::finalized()),
+                    max_supported_transaction_version: Some(0),
+                },
+            )?;
+            let logs: Option<Vec<String>> = transaction
+                .transaction
+                .meta
+                .and_then(|meta| meta.log_messages.into());
+            let transaction_events = parse_events(
+                &status.signature,
+                &logs.unwrap_or_default(),
+                &self.program_id,
+            );
+            events.withdrawals.extend(transaction_events.withdrawals);
+            events.deposits.extend(transaction_events.deposits);
+        }
+
+        let cursor = statuses
+            .last()
+            .map(|status| status.signature.clone())
+            .or(cursor.map(String::from));
+        Ok((events, cursor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_client::anchor_lang::AnchorSerialize;
+
+    fn event_log(amount: u64) -> String {
+        let mut data = WithdrawalRequested::DISCRIMINATOR.to_vec();
+        WithdrawalRequested {
+            requester: Pubkey::new_unique(),
+            amount,
+            script_pubkey: vec![0x51, 0x20],
+            fee: 0,
+        }
+        .serialize(&mut data)
+        .unwrap();
+        format!("Program data: {}", BASE64_STANDARD.encode(data))
+    }
+
+    fn deposit_log(receiver: Pubkey, outputs: Vec<DepositOutput>) -> String {
+        let amount = outputs.iter().map(|output| output.amount).sum();
+        let mut data = DepositMinted::DISCRIMINATOR.to_vec();
+        DepositMinted {
+            reversed_txid: [3; 32],
+            receiver,
+            amount,
+            fee: 0,
+            pool_fee: 0,
+            minted: amount * 10,
+            treasury: Pubkey::new_unique(),
+            group_key: [2; 32],
+            outputs,
+            legacy_outputs: vec![],
+        }
+        .serialize(&mut data)
+        .unwrap();
+        format!("Program data: {}", BASE64_STANDARD.encode(data))
+    }
+
+    #[test]
+    fn parses_events_logged_by_the_relay_program_only() {
+        let relay = btc_relay::ID;
+        let other = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        let outputs = vec![
+            DepositOutput {
+                vout: 0,
+                amount: 20_000,
+            },
+            DepositOutput {
+                vout: 2,
+                amount: 5_000,
+            },
+        ];
+        let logs = vec![
+            format!("Program {relay} invoke [1]"),
+            "Program log: Instruction: RequestWithdrawal".to_string(),
+            event_log(1_000),
+            deposit_log(receiver, outputs.clone()),
+            format!("Program {other} invoke [2]"),
+            deposit_log(Pubkey::new_unique(), outputs.clone()),
+            event_log(2_000),
+            format!("Program {other} success"),
+            event_log(3_000),
+            format!("Program {relay} success"),
+            format!("Program {other} invoke [1]"),
+            event_log(4_000),
+            format!("Program {other} success"),
+        ];
+
+        let events = parse_events("sig", &logs, &relay);
+        let amounts: Vec<_> = events
+            .withdrawals
+            .iter()
+            .map(|w| (w.id.as_str(), w.amount))
+            .collect();
+        assert_eq!(amounts, [("sig:0", 1_000), ("sig:1", 3_000)]);
+        assert_eq!(
+            events.deposits,
+            [Deposit {
+                txid: Txid::from_byte_array([3; 32]),
+                receiver,
+                group_key: [2; 32],
+                outputs,
+            }]
+        );
+    }
+}
@@ -0,0 +1,369 @@
+//! FROST threshold Schnorr signatures over secp256k1, producing BIP340 signatures for taproot key
+//! path spends with the FROST(secp256k1, SHA-256-TR) ciphersuite of the `frost-secp256k1-tr` crate.
+//!
+//! Any `threshold` of the participants sign in two rounds: each signer commits to a pair of nonces,
+//! then signs the message with the commitments of all the chosen signers. The coordinator checks
+//! each signature share before aggregating them, so a misbehaving signer can be named.
+//!
+//! Participants are numbered from 1 like the crate's default identifiers, and the group key is the
+//! taproot internal key, so signatures are by the output key tweaked without a script tree.
+use frost_secp256k1_tr::keys::{self, Tweak};
+use frost_secp256k1_tr::{round1, round2, Signature, VerifyingKey};
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub use frost_secp256k1_tr::keys::SigningShare;
+pub use frost_secp256k1_tr::round1::{SigningCommitments, SigningNonces};
+pub use frost_secp256k1_tr::round2::SignatureShare;
+pub use frost_secp256k1_tr::SigningPackage;
+
+/// Index of a participant, from 1
+pub type Identifier = u16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrostError {
+    /// Fewer signers than the threshold, or signers without a verifying share
+    InvalidSigners,
+    /// The signing package doesn't contain the signer's commitments
+    MissingCommitments(Identifier),
+    /// The participant's signature share, or key generation share, doesn't verify
+    InvalidShare(Identifier),
+    InvalidSignature,
+    InvalidEncoding,
+    /// The participant's key generation package is missing or malformed
+    MissingPackage(Identifier),
+    /// The participant's proof of knowledge of its secret doesn't verify
+    InvalidProof(Identifier),
+    /// Any other error of the FROST implementation
+    Frost(frost_secp256k1_tr::Error),
+}
+
+impl fmt::Display for FrostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrostError::InvalidSigners => write!(f, "Not enough or unknown signers"),
+            FrostError::MissingCommitments(id) => write!(f, "No commitments of signer {id}"),
+            FrostError::InvalidShare(id) => write!(f, "Invalid share of participant {id}"),
+            FrostError::InvalidSignature => write!(f, "Aggregated signature is invalid"),
+            FrostError::InvalidEncoding => write!(f, "Invalid scalar or point encoding"),
+            FrostError::MissingPackage(id) => write!(f, "No valid package of participant {id}"),
+            FrostError::InvalidProof(id) => {
+                write!(f, "Invalid proof of knowledge of participant {id}")
+            }
+            FrostError::Frost(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for FrostError {}
+
+impl From<frost_secp256k1_tr::Error> for FrostError {
+    fn from(e: frost_secp256k1_tr::Error) -> Self {
+        FrostError::Frost(e)
+    }
+}
+
+// The crate's identifier of the participant
+pub(crate) fn frost_identifier(
+    identifier: Identifier,
+) -> Result<frost_secp256k1_tr::Identifier, FrostError> {
+    frost_secp256k1_tr::Identifier::try_from(identifier).map_err(|_| FrostError::InvalidSigners)
+}
+
+// The participant the crate's identifier belongs to, out of the participants numbered from 1
+pub(crate) fn participant(
+    identifier: &frost_secp256k1_tr::Identifier,
+    participants: u16,
+) -> Identifier {
+    (1..=participants)
+        .find(|participant| frost_identifier(*participant).ok().as_ref() == Some(identifier))
+        .unwrap_or_default()
+}
+
+/// Public keys of the signing group, known to every participant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicKeyPackage {
+    /// Group key before the BIP341 tweak and the participants' verifying shares
+    pub package: keys::PublicKeyPackage,
+    pub threshold: u16,
+}
+
+impl PublicKeyPackage {
+    /// Compressed group key
+    pub fn group_key(&self) -> [u8; 33] {
+        serialize_key(self.package.verifying_key())
+    }
+
+    /// Taproot internal key, the x-only group key
+    pub fn internal_key(&self) -> [u8; 32] {
+        x_only(&self.group_key())
+    }
+
+    fn participant(&self, identifier: &frost_secp256k1_tr::Identifier) -> Identifier {
+        participant(identifier, self.package.verifying_shares().len() as u16)
+    }
+}
+
+/// A participant's secret share with the group's public keys
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyPackage {
+    pub identifier: Identifier,
+    pub signing_share: SigningShare,
+    pub public: PublicKeyPackage,
+}
+
+impl KeyPackage {
+    fn frost_key(&self) -> Result<keys::KeyPackage, FrostError> {
+        let identifier = frost_identifier(self.identifier)?;
+        let verifying_share = *self
+            .public
+            .package
+            .verifying_shares()
+            .get(&identifier)
+            .ok_or(FrostError::InvalidSigners)?;
+        Ok(keys::KeyPackage::new(
+            identifier,
+            self.signing_share,
+            verifying_share,
+            *self.public.package.verifying_key(),
+            self.public.threshold,
+        ))
+    }
+}
+
+fn serialize_key(key: &VerifyingKey) -> [u8; 33] {
+    key.serialize()
+        .expect("the group key not to be the identity")
+        .try_into()
+        .expect("keys to serialize compressed")
+}
+
+fn x_only(key: &[u8; 33]) -> [u8; 32] {
+    key[1..].try_into().unwrap()
+}
+
+/// Splits a random secret into `max_signers` shares, any `threshold` of them can sign. The dealer
+/// knows the secret, so it's only meant for tests and development groups.
+pub fn generate_with_dealer(
+    max_signers: u16,
+    threshold: u16,
+    rng: &mut (impl RngCore + CryptoRng),
+) -> Result<Vec<KeyPackage>, FrostError> {
+    let (mut shares, package) =
+        keys::generate_with_dealer(max_signers, threshold, keys::IdentifierList::Default, rng)?;
+    let public = PublicKeyPackage { package, threshold };
+    (1..=max_signers)
+        .map(|identifier| {
+            let share = shares
+                .remove(&frost_identifier(identifier)?)
+                .ok_or(FrostError::InvalidSigners)?;
+            let key = keys::KeyPackage::try_from(share)?;
+            Ok(KeyPackage {
+                identifier,
+                signing_share: *key.signing_share(),
+                public: public.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Round one: commits to fresh nonces for one signature
+pub fn commit(key: &KeyPackage, rng: &mut (impl RngCore + CryptoRng)) -> SigningNonces {
+    round1::commit(&key.signing_share, rng).0
+}
+
+/// The message to sign with the commitments of the chosen signers
+pub fn signing_package(
+    commitments: BTreeMap<Identifier, SigningCommitments>,
+    message: &[u8; 32],
+) -> Result<SigningPackage, FrostError> {
+    let commitments = commitments
+        .into_iter()
+        .map(|(identifier, commitments)| Ok((frost_identifier(identifier)?, commitments)))
+        .collect::<Result<_, FrostError>>()?;
+    Ok(SigningPackage::new(commitments, message))
+}
+
+/// Round two: signs the package with the nonces committed to in round one, consuming them
+pub fn sign(
+    key: &KeyPackage,
+    nonces: SigningNonces,
+    package: &SigningPackage,
+) -> Result<SignatureShare, FrostError> {
+    round2::sign_with_tweak(package, &nonces, &key.frost_key()?, None).map_err(|e| match e {
+        frost_secp256k1_tr::Error::MissingCommitment
+        | frost_secp256k1_tr::Error::IncorrectCommitment => {
+            FrostError::MissingCommitments(key.identifier)
+        }
+        frost_secp256k1_tr::Error::IncorrectNumberOfCommitments
+        | frost_secp256k1_tr::Error::UnknownIdentifier => FrostError::InvalidSigners,
+        e => FrostError::Frost(e),
+    })
+}
+
+/// Checks the signature shares and aggregates them into a BIP340 signature by the tweaked group key
+pub fn aggregate(
+    public: &PublicKeyPackage,
+    package: &SigningPackage,
+    shares: &BTreeMap<Identifier, SignatureShare>,
+) -> Result<[u8; 64], FrostError> {
+    let signers = package.signing_commitments();
+    if signers.len() < usize::from(public.threshold)
+        || !signers
+            .keys()
+            .all(|identifier| public.package.verifying_shares().contains_key(identifier))
+    {
+        return Err(FrostError::InvalidSigners);
+    }
+    let frost_shares = shares
+        .iter()
+        .map(|(identifier, share)| Ok((frost_identifier(*identifier)?, *share)))
+        .collect::<Result<BTreeMap<_, _>, FrostError>>()?;
+    if let Some(missing) = signers
+        .keys()
+        .find(|identifier| !frost_shares.contains_key(identifier))
+    {
+        return Err(FrostError::InvalidShare(public.participant(missing)));
+    }
+
+    let signature =
+        frost_secp256k1_tr::aggregate_with_tweak(package, &frost_shares, &public.package, None)
+            .map_err(|e| match e {
+                frost_secp256k1_tr::Error::InvalidSignatureShare { culprit } => {
+                    FrostError::InvalidShare(public.participant(&culprit))
+                }
+                frost_secp256k1_tr::Error::InvalidSignature => FrostError::InvalidSignature,
+                e => FrostError::Frost(e),
+            })?;
+    signature
+        .serialize()?
+        .try_into()
+        .map_err(|_| FrostError::InvalidSignature)
+}
+
+/// Taproot output key of the group, the internal key tweaked without a script tree
+pub fn output_key(public: &PublicKeyPackage) -> [u8; 32] {
+    let tweaked = public.package.clone().tweak(None::<&[u8]>);
+    x_only(&serialize_key(tweaked.verifying_key()))
+}
+
+/// Verifies a BIP340 signature of the message by the x-only public key
+pub fn verify(
+    public_key: &[u8; 32],
+    message: &[u8; 32],
+    signature: &[u8; 64],
+) -> Result<(), FrostError> {
+    let mut encoded = [2; 33];
+    encoded[1..].copy_from_slice(public_key);
+    let public_key =
+        VerifyingKey::deserialize(&encoded).map_err(|_| FrostError::InvalidEncoding)?;
+    let signature = Signature::deserialize(signature).map_err(|_| FrostError::InvalidSignature)?;
+    public_key
+        .verify(message, &signature)
+        .map_err(|_| FrostError::InvalidSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::key::{TapTweak, UntweakedPublicKey};
+    use bitcoin::secp256k1::{schnorr, Message, Secp256k1};
+
+    fn sign_with(
+        keys: &[KeyPackage],
+        signers: &[Identifier],
+        message: [u8; 32],
+    ) -> Result<[u8; 64], FrostError> {
+        let mut rng = rand::thread_rng();
+        let nonces: BTreeMap<Identifier, SigningNonces> = signers
+            .iter()
+            .map(|id| (*id, commit(&keys[usize::from(*id) - 1], &mut rng)))
+            .collect();
+        let package = signing_package(
+            nonces
+                .iter()
+                .map(|(id, nonces)| (*id, *nonces.commitments()))
+                .collect(),
+            &message,
+        )?;
+        let shares = nonces
+            .into_iter()
+            .map(|(id, nonces)| Ok((id, sign(&keys[usize::from(id) - 1], nonces, &package)?)))
+            .collect::<Result<_, FrostError>>()?;
+        aggregate(&keys[0].public, &package, &shares)
+    }
+
+    #[test]
+    fn signs_taproot_key_spends_with_any_threshold_of_signers() {
+        let secp = Secp256k1::verification_only();
+        // Group and output keys have either y parity in some of the groups
+        for _ in 0..8 {
+            let keys = generate_with_dealer(3, 2, &mut rand::thread_rng()).unwrap();
+            let internal_key =
+                UntweakedPublicKey::from_slice(&keys[0].public.internal_key()).unwrap();
+            let (tweaked, _) = internal_key.tap_tweak(&secp, None);
+            assert_eq!(tweaked.to_inner().serialize(), output_key(&keys[0].public));
+
+            for signers in [[1, 2], [1, 3], [2, 3]] {
+                let message = [signers[0] as u8 * 7 + signers[1] as u8; 32];
+                let signature = sign_with(&keys, &signers, message).unwrap();
+                secp.verify_schnorr(
+                    &schnorr::Signature::from_slice(&signature).unwrap(),
+                    &Message::from_digest(message),
+                    &tweaked.to_inner(),
+                )
+                .unwrap();
+                verify(&output_key(&keys[0].public), &message, &signature).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_shares_and_too_few_signers() {
+        let keys = generate_with_dealer(3, 2, &mut rand::thread_rng()).unwrap();
+        assert_eq!(
+            sign_with(&keys, &[2], [1; 32]),
+            Err(FrostError::InvalidSigners)
+        );
+
+        let mut rng = rand::thread_rng();
+        let (nonces_1, nonces_3) = (commit(&keys[0], &mut rng), commit(&keys[2], &mut rng));
+        let package = signing_package(
+            BTreeMap::from([(1, *nonces_1.commitments()), (3, *nonces_3.commitments())]),
+            &[2; 32],
+        )
+        .unwrap();
+        let share_1 = sign(&keys[0], nonces_1, &package).unwrap();
+        let shares = BTreeMap::from([(1, share_1), (3, share_1)]);
+        assert_eq!(
+            aggregate(&keys[0].public, &package, &shares),
+            Err(FrostError::InvalidShare(3))
+        );
+        assert_eq!(
+            aggregate(&keys[0].public, &package, &BTreeMap::from([(1, share_1)])),
+            Err(FrostError::InvalidShare(3))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_thresholds() {
+        let mut rng = rand::thread_rng();
+        assert!(matches!(
+            generate_with_dealer(3, 4, &mut rng),
+            Err(FrostError::Frost(_))
+        ));
+        assert!(matches!(
+            generate_with_dealer(3, 1, &mut rng),
+            Err(FrostError::Frost(_))
+        ));
+    }
+
+    #[test]
+    fn serializes_key_packages() {
+        let keys = generate_with_dealer(2, 2, &mut rand::thread_rng()).unwrap();
+        let json = serde_json::to_string(&keys[1]).unwrap();
+        assert_eq!(serde_json::from_str::<KeyPackage>(&json).unwrap(), keys[1]);
+    }
+}
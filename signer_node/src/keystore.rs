@@ -0,0 +1,218 @@
+//! Key packages encrypted at rest with a passphrase. The signing share is encrypted with
+//! AES-256-GCM-SIV under a PBKDF2-HMAC-SHA256 key, the group's public keys stay readable.
+use crate::frost::{FrostError, Identifier, KeyPackage, PublicKeyPackage, SigningShare};
+use aes_gcm_siv::aead::{Aead, NewAead, Payload};
+use aes_gcm_siv::Aes256GcmSiv;
+use bitcoin::hex::{DisplayHex, FromHex, HexToArrayError, HexToBytesError};
+use hmac::Hmac;
+use rand::{CryptoRng, RngCore};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::Sha256;
+use std::fmt;
+
+/// Environment variable the signer node reads the key package's passphrase from
+pub const PASSPHRASE_VAR: &str = "SIGNER_KEY_PASSPHRASE";
+
+/// PBKDF2 rounds of the key packages the node encrypts
+pub const KEY_DERIVATION_ROUNDS: u32 = 600_000;
+
+#[derive(Debug)]
+pub enum KeystoreError {
+    MissingPassphrase,
+    /// Wrong passphrase, or a tampered key package
+    Decryption,
+    InvalidHex(String),
+    Frost(FrostError),
+}
+
+impl fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeystoreError::MissingPassphrase => {
+                write!(
+                    f,
+                    "The key package's passphrase has to be set in {PASSPHRASE_VAR}"
+                )
+            }
+            KeystoreError::Decryption => {
+                write!(f, "Could not decrypt the key package, wrong passphrase?")
+            }
+            KeystoreError::InvalidHex(e) => write!(f, "Invalid encrypted key package: {e}"),
+            KeystoreError::Frost(e) => write!(f, "Invalid signing share: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for KeystoreError {}
+
+impl From<HexToBytesError> for KeystoreError {
+    fn from(error: HexToBytesError) -> Self {
+        KeystoreError::InvalidHex(error.to_string())
+    }
+}
+
+impl From<HexToArrayError> for KeystoreError {
+    fn from(error: HexToArrayError) -> Self {
+        KeystoreError::InvalidHex(error.to_string())
+    }
+}
+
+impl From<FrostError> for KeystoreError {
+    fn from(error: FrostError) -> Self {
+        KeystoreError::Frost(error)
+    }
+}
+
+/// Key package with its signing share encrypted, the fields are hex encoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedKeyPackage {
+    pub identifier: Identifier,
+    pub public: PublicKeyPackage,
+    pub rounds: u32,
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// Key package file, as written by the key generation ceremony or by the dealer
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum StoredKeyPackage {
+    Encrypted(EncryptedKeyPackage),
+    Plain(KeyPackage),
+}
+
+// Told apart by hand, an untagged enum can't read the integer keys of the verifying shares
+impl<'de> Deserialize<'de> for StoredKeyPackage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        if value.get("ciphertext").is_some() {
+            serde_json::from_value(value).map(StoredKeyPackage::Encrypted)
+        } else {
+            serde_json::from_value(value).map(StoredKeyPackage::Plain)
+        }
+        .map_err(D::Error::custom)
+    }
+}
+
+fn cipher(passphrase: &str, salt: &[u8], rounds: u32) -> Aes256GcmSiv {
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, rounds, &mut key);
+    Aes256GcmSiv::new_from_slice(&key).expect("a 32 byte key")
+}
+
+// Binds the ciphertext to the participant and the group, so the share can't be moved to another
+// key package
+fn associated_data(identifier: Identifier, public: &PublicKeyPackage) -> Vec<u8> {
+    let mut data = identifier.to_be_bytes().to_vec();
+    data.extend(public.group_key());
+    data
+}
+
+pub fn encrypt(
+    key: &KeyPackage,
+    passphrase: &str,
+    rounds: u32,
+    rng: &mut (impl RngCore + CryptoRng),
+) -> EncryptedKeyPackage {
+    let mut salt = [0; 16];
+    let mut nonce = [0; 12];
+    rng.fill_bytes(&mut salt);
+    rng.fill_bytes(&mut nonce);
+    let ciphertext = cipher(passphrase, &salt, rounds)
+        .encrypt(
+            (&nonce).into(),
+            Payload {
+                msg: &key.signing_share.serialize(),
+                aad: &associated_data(key.identifier, &key.public),
+            },
+        )
+        .expect("a 32 byte share to be encrypted");
+    EncryptedKeyPackage {
+        identifier: key.identifier,
+        public: key.public.clone(),
+        rounds,
+        salt: salt.to_lower_hex_string(),
+        nonce: nonce.to_lower_hex_string(),
+        ciphertext: ciphertext.to_lower_hex_string(),
+    }
+}
+
+pub fn decrypt(
+    encrypted: &EncryptedKeyPackage,
+    passphrase: &str,
+) -> Result<KeyPackage, KeystoreError> {
+    let salt = Vec::<u8>::from_hex(&encrypted.salt)?;
+    let nonce = <[u8; 12]>::from_hex(&encrypted.nonce)?;
+    let ciphertext = Vec::<u8>::from_hex(&encrypted.ciphertext)?;
+    let share = cipher(passphrase, &salt, encrypted.rounds)
+        .decrypt(
+            (&nonce).into(),
+            Payload {
+                msg: &ciphertext,
+                aad: &associated_data(encrypted.identifier, &encrypted.public),
+            },
+        )
+        .map_err(|_| KeystoreError::Decryption)?;
+    Ok(KeyPackage {
+        identifier: encrypted.identifier,
+        signing_share: SigningShare::deserialize(&share).map_err(FrostError::from)?,
+        public: encrypted.public.clone(),
+    })
+}
+
+impl StoredKeyPackage {
+    /// The key package, decrypted with the passphrase of `SIGNER_KEY_PASSPHRASE` if it's encrypted
+    pub fn unlock(self) -> Result<KeyPackage, KeystoreError> {
+        match self {
+            StoredKeyPackage::Plain(key) => Ok(key),
+            StoredKeyPackage::Encrypted(encrypted) => {
+                let passphrase =
+                    std::env::var(PASSPHRASE_VAR).map_err(|_| KeystoreError::MissingPassphrase)?;
+                decrypt(&encrypted, &passphrase)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frost::generate_with_dealer;
+
+    #[test]
+    fn encrypts_the_signing_share() {
+        let mut rng = rand::thread_rng();
+        let keys = generate_with_dealer(2, 2, &mut rng).unwrap();
+        let encrypted = encrypt(&keys[0], "correct horse", 1_000, &mut rng);
+        assert!(!encrypted
+            .ciphertext
+            .contains(&keys[0].signing_share.serialize().to_lower_hex_string()));
+
+        let json = serde_json::to_string(&StoredKeyPackage::Encrypted(encrypted.clone())).unwrap();
+        match serde_json::from_str(&json).unwrap() {
+            StoredKeyPackage::Encrypted(read) => assert_eq!(read, encrypted),
+            StoredKeyPackage::Plain(_) => panic!("read as a plain key package"),
+        }
+        let json = serde_json::to_string(&keys[1]).unwrap();
+        assert!(matches!(
+            serde_json::from_str(&json).unwrap(),
+            StoredKeyPackage::Plain(key) if key == keys[1]
+        ));
+
+        assert_eq!(decrypt(&encrypted, "correct horse").unwrap(), keys[0]);
+        assert!(matches!(
+            decrypt(&encrypted, "battery staple"),
+            Err(KeystoreError::Decryption)
+        ));
+        let moved = EncryptedKeyPackage {
+            identifier: 2,
+            ..encrypted
+        };
+        assert!(matches!(
+            decrypt(&moved, "correct horse"),
+            Err(KeystoreError::Decryption)
+        ));
+    }
+}
@@ -0,0 +1,292 @@
+pub mod ceremony;
+pub mod coin_selection;
+pub mod config;
+pub mod dkg;
+pub mod events;
+pub mod frost;
+pub mod keystore;
+pub mod node;
+pub mod protocol;
+pub mod store;
+pub mod wallet;
+
+use crate::ceremony::{run_ceremony, CeremonyTransport, GroupInfo};
+use crate::coin_selection::CoinSelection;
+use crate::config::SignerConfig;
+use crate::events::YonaEvents;
+use crate::frost::{Identifier, KeyPackage};
+use crate::keystore::{StoredKeyPackage, KEY_DERIVATION_ROUNDS, PASSPHRASE_VAR};
+use crate::node::{Coordinator, Signer};
+use crate::protocol::{serve_tcp, TcpTransport};
+use crate::store::SignerStore;
+use crate::wallet::group_script;
+use anchor_client::anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use anchor_client::solana_client::rpc_client::RpcClient;
+use anchor_client::solana_sdk::bpf_loader_upgradeable;
+use anchor_client::solana_sdk::commitment_config::CommitmentConfig;
+use anchor_client::solana_sdk::instruction::Instruction;
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use anchor_client::solana_sdk::signature::{read_keypair_file, Signer as _};
+use anchor_client::solana_sdk::system_program;
+use anchor_client::solana_sdk::transaction::Transaction;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::Address;
+use bitcoincore_rpc::Client as BitcoinRpcClient;
+use btc_relay::state::BridgeKey;
+use log::{error, info, warn};
+use std::env;
+use std::error;
+use std::fs;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Reads a plain key package, or an encrypted one with the passphrase of `SIGNER_KEY_PASSPHRASE`
+pub fn read_key_package(path: impl AsRef<Path>) -> Result<KeyPackage, Box<dyn error::Error>> {
+    let stored: StoredKeyPackage = serde_json::from_str(&fs::read_to_string(path)?)?;
+    Ok(stored.unlock()?)
+}
+
+/// Splits a new group key into the key packages `key-package-{id}.json` of the directory
+pub fn run_deal_keys(
+    signers: u16,
+    threshold: u16,
+    out_dir: &Path,
+) -> Result<KeyPackage, Box<dyn error::Error>> {
+    let keys = frost::generate_with_dealer(signers, threshold, &mut rand::rngs::OsRng)?;
+    fs::create_dir_all(out_dir)?;
+    for key in &keys {
+        let path = out_dir.join(format!("key-package-{}.json", key.identifier));
+        fs::write(path, serde_json::to_string_pretty(key)?)?;
+    }
+    Ok(keys[0].clone())
+}
+
+/// Address of the group's taproot output
+pub fn group_address(key: &KeyPackage, network: bitcoin::Network) -> Address {
+    Address::from_script(&group_script(&key.public), network)
+        .expect("taproot script to have an address")
+}
+
+/// Runs the key generation ceremony, writing the participant's key package encrypted with the
+/// passphrase of `SIGNER_KEY_PASSPHRASE` to `key-package-{id}.json` and the group's public keys
+/// to `group.json`
+pub fn run_dkg(
+    identifier: Identifier,
+    signers: u16,
+    threshold: u16,
+    transport: &dyn CeremonyTransport,
+    out_dir: &Path,
+    network: bitcoin::Network,
+) -> Result<GroupInfo, Box<dyn error::Error>> {
+    if threshold < 2 || threshold > signers {
+        return Err("The threshold has to be between 2 and the number of signers".into());
+    }
+    if identifier == 0 || identifier > signers {
+        return Err("The identifier has to be between 1 and the number of signers".into());
+    }
+    // Checked up front, the ceremony can't be resumed
+    let passphrase = env::var(PASSPHRASE_VAR)
+        .map_err(|_| format!("The key package's passphrase has to be set in {PASSPHRASE_VAR}"))?;
+    fs::create_dir_all(out_dir)?;
+
+    let key = run_ceremony(
+        identifier,
+        signers,
+        threshold,
+        transport,
+        &mut rand::rngs::OsRng,
+    )?;
+    let encrypted = keystore::encrypt(
+        &key,
+        &passphrase,
+        KEY_DERIVATION_ROUNDS,
+        &mut rand::rngs::OsRng,
+    );
+    let path = out_dir.join(format!("key-package-{identifier}.json"));
+    fs::write(
+        path,
+        serde_json::to_string_pretty(&StoredKeyPackage::Encrypted(encrypted))?,
+    )?;
+
+    let group = GroupInfo {
+        internal_key: key.public.internal_key().to_lower_hex_string(),
+        output_key: frost::output_key(&key.public).to_lower_hex_string(),
+        address: group_address(&key, network).to_string(),
+        public: key.public,
+    };
+    fs::write(
+        out_dir.join("group.json"),
+        serde_json::to_string_pretty(&group)?,
+    )?;
+    Ok(group)
+}
+
+fn bridge_key_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"bridge_key"], &btc_relay::ID).0
+}
+
+/// Group key registered in the relay program, if any
+fn registered_bridge_key(yona_rpc: &RpcClient) -> Result<Option<BridgeKey>, Box<dyn error::Error>> {
+    let account = yona_rpc
+        .get_account_with_commitment(&bridge_key_address(), CommitmentConfig::finalized())?
+        .value;
+    match account {
+        Some(account) => Ok(Some(BridgeKey::try_deserialize(
+            &mut account.data.as_slice(),
+        )?)),
+        None => Ok(None),
+    }
+}
+
+/// Instruction registering the group's output key. The first registration has to be signed by the
+/// relay program's upgrade authority, which becomes the bridge key's authority
+pub fn register_group_key_instruction(
+    authority: Pubkey,
+    group_key: [u8; 32],
+    initialized: bool,
+) -> Instruction {
+    let (accounts, data) = if initialized {
+        (
+            btc_relay::accounts::SetBridgeKey {
+                authority,
+                bridge_key: bridge_key_address(),
+            }
+            .to_account_metas(None),
+            btc_relay::instruction::SetBridgeKey { group_key }.data(),
+        )
+    } else {
+        (
+            btc_relay::accounts::InitializeBridgeKey {
+                signer: authority,
+                bridge_key: bridge_key_address(),
+                program_data: Pubkey::find_program_address(
+                    &[btc_relay::ID.as_ref()],
+                    &bpf_loader_upgradeable::ID,
+                )
+                .0,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            btc_relay::instruction::InitializeBridgeKey { group_key }.data(),
+        )
+    };
+    Instruction {
+        program_id: btc_relay::ID,
+        accounts,
+        data,
+    }
+}
+
+/// Registers the group's output key of `group.json` in the relay program, signed by the
+/// authority's keypair. With `sign_only` the signed transaction is returned base64 encoded
+/// instead of being sent.
+pub fn run_register_group_key(
+    group_path: &Path,
+    authority_path: &Path,
+    yona_http: &str,
+    sign_only: bool,
+) -> Result<String, Box<dyn error::Error>> {
+    let group: GroupInfo = serde_json::from_str(&fs::read_to_string(group_path)?)?;
+    let output_key = <[u8; 32]>::from_hex(&group.output_key)?;
+    if output_key != frost::output_key(&group.public) {
+        return Err("The output key doesn't match the group's public keys".into());
+    }
+    let authority = read_keypair_file(authority_path)?;
+
+    let yona_rpc = RpcClient::new_with_commitment(yona_http, CommitmentConfig::confirmed());
+    let initialized = registered_bridge_key(&yona_rpc)?.is_some();
+    let instruction = register_group_key_instruction(authority.pubkey(), output_key, initialized);
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&authority.pubkey()),
+        &[&authority],
+        yona_rpc.get_latest_blockhash()?,
+    );
+    if sign_only {
+        return Ok(BASE64_STANDARD.encode(bincode::serialize(&transaction)?));
+    }
+    Ok(yona_rpc
+        .send_and_confirm_transaction(&transaction)?
+        .to_string())
+}
+
+/// Serves the signing protocol, paying out the withdrawals when the node is the coordinator
+pub fn run_signer(config: SignerConfig) -> Result<(), Box<dyn error::Error>> {
+    let key = read_key_package(&config.key_package)?;
+    if key.identifier != config.id {
+        return Err(format!(
+            "The key package belongs to signer {}, not {}",
+            key.identifier, config.id
+        )
+        .into());
+    }
+    info!(
+        "Signer {} of the group at {}",
+        config.id,
+        group_address(&key, config.network)
+    );
+    let yona_rpc = RpcClient::new(config.yona_http.clone());
+    match registered_bridge_key(&yona_rpc) {
+        Ok(Some(bridge_key)) if bridge_key.group_key != frost::output_key(&key.public) => warn!(
+            "The relay program's bridge key {} isn't the group's output key",
+            bridge_key.group_key.to_lower_hex_string()
+        ),
+        Ok(_) => {}
+        Err(e) => warn!("Could not read the relay program's bridge key: {e}"),
+    }
+
+    let store = SignerStore::open(&config.store_path)?;
+    let source = YonaEvents::new(&config.yona_http, btc_relay::ID);
+    let signer = Arc::new(Signer::new(
+        key,
+        store,
+        source,
+        config.max_withdrawal_fee,
+        config.max_consolidation_fee,
+    ));
+
+    let listener = TcpListener::bind(&config.listen_address)?;
+    info!("Serving the signing protocol at {}", config.listen_address);
+    let server_signer = signer.clone();
+    let server =
+        thread::spawn(move || serve_tcp(listener, |request| server_signer.handle(request)));
+
+    if config.id != config.coordinator {
+        server.join().map_err(|_| "The signing server panicked")?;
+        return Ok(());
+    }
+
+    let bitcoin_rpc = BitcoinRpcClient::new(&config.bitcoind_url, config.bitcoin_auth.into())?;
+    let transport = TcpTransport::new(
+        config
+            .peers
+            .iter()
+            .map(|peer| (peer.id, peer.address.clone()))
+            .collect(),
+    );
+    let peers = config.peers.iter().map(|peer| peer.id).collect();
+    let selection = CoinSelection {
+        fee_rate: config.fee_rate,
+        long_term_fee_rate: config.long_term_fee_rate,
+        max_consolidation_fee: config.max_consolidation_fee,
+    };
+    let coordinator = Coordinator::new(signer, bitcoin_rpc, transport, peers, selection);
+    loop {
+        loop {
+            match coordinator.pay_next_withdrawal() {
+                Ok(Some(_)) => continue,
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Failed to pay out a withdrawal: {e}");
+                    break;
+                }
+            }
+        }
+        thread::sleep(Duration::from_secs(config.poll_interval));
+    }
+}
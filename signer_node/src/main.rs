@@ -0,0 +1,166 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use signer_node_lib::ceremony::{CeremonyTransport, FileTransport, TcpTransport};
+use signer_node_lib::config::read_config;
+use signer_node_lib::frost::Identifier;
+use signer_node_lib::{group_address, run_deal_keys, run_dkg, run_register_group_key, run_signer};
+use std::fs;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CeremonyTransportKind {
+    /// Messages written to a directory shared by the participants
+    File,
+    /// Messages sent to the participants' listen addresses
+    Tcp,
+}
+
+/// `{id}={address}` of another participant of the ceremony
+fn parse_peer(peer: &str) -> Result<(Identifier, String), String> {
+    let (id, address) = peer
+        .split_once('=')
+        .ok_or("Peers are given as {id}={address}")?;
+    let id = id.parse().map_err(|e| format!("Invalid peer id: {e}"))?;
+    Ok((id, address.to_string()))
+}
+
+#[derive(Subcommand)]
+enum SignerCommand {
+    /// Split a new group key among the signers with a trusted dealer, for development groups
+    DealKeys {
+        #[arg(long)]
+        signers: u16,
+        #[arg(long)]
+        threshold: u16,
+        /// Directory the key packages are written to
+        #[arg(long, default_value = ".")]
+        out_dir: PathBuf,
+        #[arg(long, default_value = "regtest")]
+        network: bitcoin::Network,
+    },
+    /// Generate the group key with the other signers, no participant learns the whole key
+    Dkg {
+        /// The participant's identifier, from 1 to the number of signers
+        #[arg(long)]
+        id: Identifier,
+        #[arg(long)]
+        signers: u16,
+        #[arg(long)]
+        threshold: u16,
+        #[arg(long, value_enum, default_value = "file")]
+        transport: CeremonyTransportKind,
+        /// Directory shared by the participants, with the file transport
+        #[arg(long, default_value = "ceremony")]
+        dir: PathBuf,
+        /// Address the participant listens at, with the TCP transport
+        #[arg(long, default_value = "0.0.0.0:8298")]
+        listen: String,
+        /// The other participants as `{id}={address}`, with the TCP transport
+        #[arg(long = "peer", value_parser = parse_peer)]
+        peers: Vec<(Identifier, String)>,
+        /// Seconds to wait for each message of the other participants
+        #[arg(long, default_value_t = 600)]
+        timeout: u64,
+        /// Directory the encrypted key package and group.json are written to
+        #[arg(long, default_value = ".")]
+        out_dir: PathBuf,
+        #[arg(long, default_value = "regtest")]
+        network: bitcoin::Network,
+    },
+    /// Register the group's key of group.json in the relay program
+    RegisterGroupKey {
+        #[arg(long, default_value = "group.json")]
+        group: PathBuf,
+        /// Keypair file of the bridge key's authority, paying for the transaction
+        #[arg(long)]
+        authority: PathBuf,
+        #[arg(long)]
+        yona_http: String,
+        /// Print the signed transaction in base64 instead of sending it
+        #[arg(long)]
+        sign_only: bool,
+    },
+    /// Serve the signing protocol, paying out withdrawals on the coordinator
+    Run,
+}
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+#[command(propagate_version = true)]
+struct SignerCli {
+    #[command(subcommand)]
+    command: SignerCommand,
+}
+
+fn main() {
+    env_logger::init();
+    let cli = SignerCli::parse();
+
+    match cli.command {
+        SignerCommand::DealKeys {
+            signers,
+            threshold,
+            out_dir,
+            network,
+        } => {
+            let key = run_deal_keys(signers, threshold, &out_dir).expect("Key dealing failed");
+            println!("Group address {}", group_address(&key, network));
+        }
+        SignerCommand::Dkg {
+            id,
+            signers,
+            threshold,
+            transport,
+            dir,
+            listen,
+            peers,
+            timeout,
+            out_dir,
+            network,
+        } => {
+            let timeout = Duration::from_secs(timeout);
+            let transport: Box<dyn CeremonyTransport> = match transport {
+                CeremonyTransportKind::File => {
+                    fs::create_dir_all(&dir).expect("Could not create the ceremony directory");
+                    Box::new(FileTransport::new(dir, id, timeout))
+                }
+                CeremonyTransportKind::Tcp => {
+                    let listener = TcpListener::bind(&listen).expect("Could not listen");
+                    let peers = peers.into_iter().collect();
+                    Box::new(TcpTransport::new(id, listener, peers, timeout))
+                }
+            };
+            match run_dkg(id, signers, threshold, &*transport, &out_dir, network) {
+                Ok(group) => {
+                    println!("Group output key {}", group.output_key);
+                    println!("Group address {}", group.address);
+                }
+                Err(e) => {
+                    eprintln!("Key generation failed: {e}");
+                    process::exit(1);
+                }
+            }
+        }
+        SignerCommand::RegisterGroupKey {
+            group,
+            authority,
+            yona_http,
+            sign_only,
+        } => match run_register_group_key(&group, &authority, &yona_http, sign_only) {
+            Ok(output) => println!("{output}"),
+            Err(e) => {
+                eprintln!("Group key registration failed: {e}");
+                process::exit(1);
+            }
+        },
+        SignerCommand::Run => {
+            let config = read_config().expect("Could not read config file");
+            if let Err(e) = run_signer(config) {
+                eprintln!("Signer node failed: {e}");
+                process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,526 @@
+use crate::coin_selection::CoinSelection;
+use crate::events::{EventSource, IndexError, Withdrawal};
+use crate::frost::{
+    self, FrostError, Identifier, KeyPackage, SignatureShare, SigningCommitments, SigningNonces,
+    SigningPackage,
+};
+use crate::protocol::{decode_proposal, Request, Response, Transport, TransportError};
+use crate::store::{SignerStore, StoreError};
+use crate::wallet::{
+    build_withdrawal, check_withdrawal, group_script, unspent, ScriptVariant, TrackedUtxo,
+    WalletError, WithdrawalTransaction,
+};
+use bitcoin::{Script, ScriptBuf, Txid};
+use bitcoincore_rpc::{Error as BtcRpcError, RpcApi};
+use log::{info, warn};
+use rand::rngs::OsRng;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::iter;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+pub enum NodeError {
+    Store(StoreError),
+    Index(IndexError),
+    Wallet(WalletError),
+    Frost(FrostError),
+    BitcoinRpc(BtcRpcError),
+    /// The withdrawal isn't among the relay program's finalized withdrawal requests
+    UnknownWithdrawal(String),
+    /// The signer already signed another payout of the withdrawal
+    AlreadySigned {
+        withdrawal_id: String,
+        txid: Txid,
+    },
+    /// No nonces were committed to for the transaction, or they were used already
+    NoSession(Txid),
+    InvalidRequest(String),
+    /// Fewer signers than the threshold committed to the payout
+    NotEnoughSigners {
+        committed: usize,
+        threshold: u16,
+    },
+    /// A signer failed the signing round
+    SignerFailed {
+        signer: Identifier,
+        reason: String,
+    },
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeError::Store(e) => write!(f, "{e}"),
+            NodeError::Index(e) => write!(f, "{e}"),
+            NodeError::Wallet(e) => write!(f, "{e}"),
+            NodeError::Frost(e) => write!(f, "{e}"),
+            NodeError::BitcoinRpc(e) => write!(f, "{e}"),
+            NodeError::UnknownWithdrawal(id) => write!(f, "Unknown withdrawal {id}"),
+            NodeError::AlreadySigned {
+                withdrawal_id,
+                txid,
+            } => write!(
+                f,
+                "Withdrawal {withdrawal_id} was signed with transaction {txid}"
+            ),
+            NodeError::NoSession(txid) => write!(f, "No nonces committed to for {txid}"),
+            NodeError::InvalidRequest(reason) => write!(f, "Invalid request: {reason}"),
+            NodeError::NotEnoughSigners {
+                committed,
+                threshold,
+            } => write!(
+                f,
+                "Only {committed} signers committed, {threshold} are needed"
+            ),
+            NodeError::SignerFailed { signer, reason } => {
+                write!(f, "Signer {signer} failed: {reason}")
+            }
+        }
+    }
+}
+
+impl From<StoreError> for NodeError {
+    fn from(error: StoreError) -> Self {
+        NodeError::Store(error)
+    }
+}
+
+impl From<IndexError> for NodeError {
+    fn from(error: IndexError) -> Self {
+        NodeError::Index(error)
+    }
+}
+
+impl From<WalletError> for NodeError {
+    fn from(error: WalletError) -> Self {
+        NodeError::Wallet(error)
+    }
+}
+
+impl From<FrostError> for NodeError {
+    fn from(error: FrostError) -> Self {
+        NodeError::Frost(error)
+    }
+}
+
+impl From<BtcRpcError> for NodeError {
+    fn from(error: BtcRpcError) -> Self {
+        NodeError::BitcoinRpc(error)
+    }
+}
+
+/// Nonces committed to for the inputs of a proposed payout
+struct Session {
+    withdrawal_id: String,
+    sighashes: Vec<[u8; 32]>,
+    nonces: Vec<SigningNonces>,
+}
+
+/// A member of the signing group. It only signs payouts of withdrawals it indexed from the relay
+/// program itself, and a single payout per withdrawal.
+pub struct Signer<S> {
+    key: KeyPackage,
+    group_script: ScriptBuf,
+    store: SignerStore,
+    source: S,
+    max_fee: u64,
+    max_consolidation_fee: u64,
+    sessions: Mutex<HashMap<Txid, Session>>,
+}
+
+impl<S: EventSource> Signer<S> {
+    pub fn new(
+        key: KeyPackage,
+        store: SignerStore,
+        source: S,
+        max_fee: u64,
+        max_consolidation_fee: u64,
+    ) -> Signer<S> {
+        Signer {
+            group_script: group_script(&key.public),
+            key,
+            store,
+            source,
+            max_fee,
+            max_consolidation_fee,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn identifier(&self) -> Identifier {
+        self.key.identifier
+    }
+
+    pub fn key(&self) -> &KeyPackage {
+        &self.key
+    }
+
+    pub fn store(&self) -> &SignerStore {
+        &self.store
+    }
+
+    /// Indexes the withdrawals requested and the deposits minted since the last sync
+    pub fn sync_events(&self) -> Result<(), NodeError> {
+        let cursor = self.store.cursor()?;
+        let (events, cursor) = self.source.events_after(cursor.as_deref())?;
+        for withdrawal in &events.withdrawals {
+            info!(
+                "Indexed withdrawal {} of {} sats",
+                withdrawal.id, withdrawal.amount
+            );
+        }
+        for deposit in &events.deposits {
+            info!(
+                "Indexed deposit {} of {} outputs",
+                deposit.txid,
+                deposit.outputs.len()
+            );
+        }
+        self.store.insert_events(&events, cursor.as_deref())?;
+        Ok(())
+    }
+
+    /// Answers the coordinator's request
+    pub fn handle(&self, request: Request) -> Response {
+        let result = match request {
+            Request::Commit {
+                withdrawal_id,
+                transaction,
+                prevouts,
+                variants,
+            } => decode_proposal(&transaction, &prevouts)
+                .map_err(NodeError::InvalidRequest)
+                .and_then(|(transaction, prevouts)| {
+                    self.commit(&WithdrawalTransaction {
+                        withdrawal_id,
+                        transaction,
+                        prevouts,
+                        variants,
+                    })
+                })
+                .map(Response::Commitments),
+            Request::Sign { txid, packages } => self.sign(&txid, &packages).map(Response::Shares),
+        };
+        result.unwrap_or_else(|e| {
+            warn!("Signer {} rejected a request: {e}", self.key.identifier);
+            Response::Rejected(e.to_string())
+        })
+    }
+
+    fn check_not_signed(&self, withdrawal_id: &str, txid: &Txid) -> Result<(), NodeError> {
+        match self.store.signed_txid(withdrawal_id)? {
+            Some(signed) if signed != *txid => Err(NodeError::AlreadySigned {
+                withdrawal_id: withdrawal_id.to_string(),
+                txid: signed,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Round one: checks the payout and commits to nonces for its inputs
+    pub fn commit(
+        &self,
+        proposal: &WithdrawalTransaction,
+    ) -> Result<Vec<SigningCommitments>, NodeError> {
+        let withdrawal = match self.store.withdrawal(&proposal.withdrawal_id)? {
+            Some(withdrawal) => withdrawal,
+            None => {
+                self.sync_events()?;
+                self.store
+                    .withdrawal(&proposal.withdrawal_id)?
+                    .ok_or_else(|| NodeError::UnknownWithdrawal(proposal.withdrawal_id.clone()))?
+            }
+        };
+        check_withdrawal(
+            proposal,
+            &withdrawal.0,
+            &self.key.public,
+            self.max_fee,
+            self.max_consolidation_fee,
+        )?;
+        let txid = proposal.transaction.compute_txid();
+        self.check_not_signed(&proposal.withdrawal_id, &txid)?;
+
+        let nonces: Vec<_> = proposal
+            .transaction
+            .input
+            .iter()
+            .map(|_| frost::commit(&self.key, &mut OsRng))
+            .collect();
+        let commitments = nonces.iter().map(|nonces| *nonces.commitments()).collect();
+        self.sessions
+            .lock()
+            .expect("sessions mutex not to be poisoned")
+            .insert(
+                txid,
+                Session {
+                    withdrawal_id: proposal.withdrawal_id.clone(),
+                    sighashes: proposal.sighashes(&self.key.public),
+                    nonces,
+                },
+            );
+        Ok(commitments)
+    }
+
+    /// Round two: signs the sighashes of the payout committed to, each nonce is used once
+    pub fn sign(
+        &self,
+        txid: &Txid,
+        packages: &[SigningPackage],
+    ) -> Result<Vec<SignatureShare>, NodeError> {
+        let session = self
+            .sessions
+            .lock()
+            .expect("sessions mutex not to be poisoned")
+            .remove(txid)
+            .ok_or(NodeError::NoSession(*txid))?;
+        if packages.len() != session.nonces.len()
+            || packages
+                .iter()
+                .zip(&session.sighashes)
+                .any(|(package, sighash)| package.message()[..] != sighash[..])
+        {
+            return Err(NodeError::InvalidRequest(
+                "packages don't sign the committed transaction".into(),
+            ));
+        }
+        self.check_not_signed(&session.withdrawal_id, txid)?;
+        self.store.set_signed_txid(&session.withdrawal_id, txid)?;
+
+        let shares = session
+            .nonces
+            .into_iter()
+            .zip(packages)
+            .map(|(nonces, package)| frost::sign(&self.key, nonces, package))
+            .collect::<Result<_, _>>()?;
+        Ok(shares)
+    }
+}
+
+/// The signer building the payouts and running the signing rounds with the other signers
+pub struct Coordinator<S, B, T> {
+    signer: Arc<Signer<S>>,
+    bitcoin_rpc: B,
+    transport: T,
+    peers: Vec<Identifier>,
+    selection: CoinSelection,
+}
+
+impl<S: EventSource, B: RpcApi, T: Transport> Coordinator<S, B, T> {
+    pub fn new(
+        signer: Arc<Signer<S>>,
+        bitcoin_rpc: B,
+        transport: T,
+        peers: Vec<Identifier>,
+        selection: CoinSelection,
+    ) -> Coordinator<S, B, T> {
+        Coordinator {
+            signer,
+            bitcoin_rpc,
+            transport,
+            peers,
+            selection,
+        }
+    }
+
+    fn request(&self, signer: Identifier, request: &Request) -> Result<Response, TransportError> {
+        if signer == self.signer.identifier() {
+            Ok(self.signer.handle(request.clone()))
+        } else {
+            self.transport.request(signer, request)
+        }
+    }
+
+    /// Tracks the group's outputs in the node's UTXO set, marking the inputs of the broadcast
+    /// payouts spent once they confirmed
+    pub fn sync_utxos(&self) -> Result<(), NodeError> {
+        let store = self.signer.store();
+        let group_script = &self.signer.group_script;
+        // The deposits are tracked from the relay program's events, their scripts are scanned for
+        // the payouts spending them
+        let deposit_scripts: HashSet<ScriptBuf> = store
+            .unspent_utxos()?
+            .into_iter()
+            .filter(|tracked| tracked.variant != ScriptVariant::Group)
+            .map(|tracked| tracked.utxo.output.script_pubkey)
+            .collect();
+        let scripts: Vec<&Script> = iter::once(group_script.as_script())
+            .chain(deposit_scripts.iter().map(ScriptBuf::as_script))
+            .collect();
+        let confirmed = unspent(&self.bitcoin_rpc, &scripts)?;
+        let confirmed_outpoints: HashSet<_> = confirmed.iter().map(|utxo| utxo.outpoint).collect();
+        for payout in store.broadcast_payouts()? {
+            // Only the group spends its outputs, so they left the UTXO set with the payout
+            if payout
+                .transaction
+                .input
+                .iter()
+                .all(|input| !confirmed_outpoints.contains(&input.previous_output))
+            {
+                store.set_confirmed(&payout)?;
+                info!(
+                    "Payout {} of withdrawal {} confirmed",
+                    payout.transaction.compute_txid(),
+                    payout.withdrawal_id
+                );
+            }
+        }
+        let tracked: Vec<_> = confirmed
+            .into_iter()
+            .filter(|utxo| utxo.output.script_pubkey == *group_script)
+            .map(|utxo| TrackedUtxo {
+                utxo,
+                variant: ScriptVariant::Group,
+                spent_by: None,
+            })
+            .collect();
+        store.insert_utxos(&tracked)?;
+        Ok(())
+    }
+
+    /// The withdrawal's payout proposed before or a new one spending unreserved outputs
+    fn proposal(&self, withdrawal: &Withdrawal) -> Result<WithdrawalTransaction, NodeError> {
+        let store = self.signer.store();
+        if let Some(proposal) = store.proposal(&withdrawal.id)? {
+            return Ok(proposal);
+        }
+        let reserved = store.reserved_outpoints()?;
+        let public = &self.signer.key().public;
+        // Deposits to a previous bridge key aren't spendable with the group's key
+        let utxos: Vec<_> = store
+            .unspent_utxos()?
+            .into_iter()
+            .filter(|tracked| {
+                tracked.variant.script_pubkey(public).as_ref()
+                    == Some(&tracked.utxo.output.script_pubkey)
+            })
+            .filter(|tracked| !reserved.contains(&tracked.utxo.outpoint))
+            .collect();
+        let proposal = build_withdrawal(withdrawal, &utxos, public, &self.selection)?;
+        store.set_proposal(&proposal)?;
+        Ok(proposal)
+    }
+
+    /// Pays out the oldest unpaid withdrawal, returning the payout's txid. Withdrawals which can't
+    /// ever be paid out are rejected and skipped, while the later ones wait behind a withdrawal
+    /// the group's outputs don't cover yet
+    pub fn pay_next_withdrawal(&self) -> Result<Option<Txid>, NodeError> {
+        self.signer.sync_events()?;
+        self.sync_utxos()?;
+        let store = self.signer.store();
+        let mut unpaid = store.unpaid_withdrawals()?.into_iter();
+        let (withdrawal, proposal) = loop {
+            let Some(withdrawal) = unpaid.next() else {
+                return Ok(None);
+            };
+            match self.proposal(&withdrawal) {
+                Ok(proposal) => break (withdrawal, proposal),
+                Err(NodeError::Wallet(e)) if e.is_permanent() => {
+                    warn!("Rejected withdrawal {}: {e}", withdrawal.id);
+                    store.set_rejected(&withdrawal.id, &e.to_string())?;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        let txid = proposal.transaction.compute_txid();
+        let public = &self.signer.key().public;
+        let sighashes = proposal.sighashes(public);
+        let threshold = public.threshold;
+
+        // Round one with the first signers to commit, the coordinator first
+        let commit = Request::commit(
+            &withdrawal.id,
+            &proposal.transaction,
+            &proposal.prevouts,
+            &proposal.variants,
+        );
+        let mut commitments = BTreeMap::new();
+        let signers = [self.signer.identifier()]
+            .into_iter()
+            .chain(self.peers.iter().copied());
+        for signer in signers {
+            if commitments.len() == usize::from(threshold) {
+                break;
+            }
+            match self.request(signer, &commit) {
+                Ok(Response::Commitments(signer_commitments))
+                    if signer_commitments.len() == sighashes.len() =>
+                {
+                    commitments.insert(signer, signer_commitments);
+                }
+                Ok(Response::Rejected(reason)) => {
+                    warn!(
+                        "Signer {signer} rejected the payout of {}: {reason}",
+                        withdrawal.id
+                    )
+                }
+                Ok(response) => warn!("Unexpected response of signer {signer}: {response:?}"),
+                Err(e) => warn!("Signer {signer} didn't commit: {e}"),
+            }
+        }
+        if commitments.len() < usize::from(threshold) {
+            return Err(NodeError::NotEnoughSigners {
+                committed: commitments.len(),
+                threshold,
+            });
+        }
+
+        // Round two with the same signers
+        let packages: Vec<_> = sighashes
+            .iter()
+            .enumerate()
+            .map(|(index, sighash)| {
+                let input_commitments = commitments
+                    .iter()
+                    .map(|(signer, signer_commitments)| (*signer, signer_commitments[index]))
+                    .collect();
+                frost::signing_package(input_commitments, sighash)
+            })
+            .collect::<Result<_, FrostError>>()?;
+        let sign = Request::Sign {
+            txid,
+            packages: packages.clone(),
+        };
+        let mut shares = vec![BTreeMap::new(); packages.len()];
+        for signer in commitments.keys().copied() {
+            let signer_shares = match self.request(signer, &sign) {
+                Ok(Response::Shares(signer_shares)) if signer_shares.len() == packages.len() => {
+                    signer_shares
+                }
+                Ok(Response::Rejected(reason)) => {
+                    return Err(NodeError::SignerFailed { signer, reason })
+                }
+                Ok(response) => {
+                    return Err(NodeError::SignerFailed {
+                        signer,
+                        reason: format!("unexpected response {response:?}"),
+                    })
+                }
+                Err(e) => {
+                    return Err(NodeError::SignerFailed {
+                        signer,
+                        reason: e.to_string(),
+                    })
+                }
+            };
+            for (input_shares, share) in shares.iter_mut().zip(signer_shares) {
+                input_shares.insert(signer, share);
+            }
+        }
+
+        let signatures = packages
+            .iter()
+            .zip(&shares)
+            .map(|(package, shares)| frost::aggregate(public, package, shares))
+            .collect::<Result<Vec<_>, _>>()?;
+        let transaction = proposal.finalize(public, &signatures);
+        self.bitcoin_rpc.send_raw_transaction(&transaction)?;
+        self.signer.store().set_broadcast(&withdrawal.id)?;
+        info!(
+            "Paid out withdrawal {} of {} sats with {txid}",
+            withdrawal.id, withdrawal.amount
+        );
+        Ok(Some(txid))
+    }
+}
@@ -0,0 +1,274 @@
+use crate::frost::{Identifier, SignatureShare, SigningCommitments, SigningPackage};
+use crate::wallet::ScriptVariant;
+use bitcoin::consensus::encode::{deserialize_hex, serialize_hex};
+use bitcoin::{Transaction, TxOut, Txid};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Coordinator's request to a signer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Request {
+    /// Round one: checks the proposed payout and commits to nonces for each of its inputs
+    Commit {
+        withdrawal_id: String,
+        /// Consensus encoded unsigned transaction
+        transaction: String,
+        /// Consensus encoded outputs spent by the inputs
+        prevouts: Vec<String>,
+        /// Scripts of the outputs spent by the inputs
+        variants: Vec<ScriptVariant>,
+    },
+    /// Round two: signs each input's sighash with the nonces committed to for the transaction
+    Sign {
+        txid: Txid,
+        packages: Vec<SigningPackage>,
+    },
+}
+
+impl Request {
+    pub fn commit(
+        withdrawal_id: &str,
+        transaction: &Transaction,
+        prevouts: &[TxOut],
+        variants: &[ScriptVariant],
+    ) -> Request {
+        Request::Commit {
+            withdrawal_id: withdrawal_id.to_string(),
+            transaction: serialize_hex(transaction),
+            prevouts: prevouts.iter().map(serialize_hex).collect(),
+            variants: variants.to_vec(),
+        }
+    }
+}
+
+/// Decodes the transaction and prevouts of a commit request
+pub fn decode_proposal(
+    transaction: &str,
+    prevouts: &[String],
+) -> Result<(Transaction, Vec<TxOut>), String> {
+    let transaction = deserialize_hex(transaction).map_err(|e| e.to_string())?;
+    let prevouts = prevouts
+        .iter()
+        .map(|prevout| deserialize_hex(prevout).map_err(|e| e.to_string()))
+        .collect::<Result<_, _>>()?;
+    Ok((transaction, prevouts))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Response {
+    /// Commitments to the nonces of each input
+    Commitments(Vec<SigningCommitments>),
+    /// Signature shares of each input
+    Shares(Vec<SignatureShare>),
+    /// Why the signer refused the request
+    Rejected(String),
+}
+
+#[derive(Debug)]
+pub enum TransportError {
+    /// The peer can't be reached
+    Unreachable(Identifier),
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Unreachable(peer) => write!(f, "Signer {peer} is unreachable"),
+            TransportError::Io(e) => write!(f, "{e}"),
+            TransportError::Json(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<io::Error> for TransportError {
+    fn from(error: io::Error) -> Self {
+        TransportError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for TransportError {
+    fn from(error: serde_json::Error) -> Self {
+        TransportError::Json(error)
+    }
+}
+
+/// How the coordinator reaches the other signers
+pub trait Transport {
+    fn request(&self, peer: Identifier, request: &Request) -> Result<Response, TransportError>;
+}
+
+type Handler = Arc<dyn Fn(Request) -> Response + Send + Sync>;
+
+/// Transport between signers running in one process, requests and responses still go through
+/// their JSON encoding
+#[derive(Clone, Default)]
+pub struct InMemoryTransport {
+    handlers: Arc<Mutex<BTreeMap<Identifier, Handler>>>,
+    disconnected: Arc<Mutex<HashSet<Identifier>>>,
+}
+
+impl InMemoryTransport {
+    pub fn new() -> InMemoryTransport {
+        InMemoryTransport::default()
+    }
+
+    pub fn register(
+        &self,
+        peer: Identifier,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) {
+        self.handlers
+            .lock()
+            .expect("transport mutex not to be poisoned")
+            .insert(peer, Arc::new(handler));
+    }
+
+    /// Makes the peer unreachable until it's connected again
+    pub fn disconnect(&self, peer: Identifier) {
+        self.disconnected
+            .lock()
+            .expect("transport mutex not to be poisoned")
+            .insert(peer);
+    }
+
+    pub fn connect(&self, peer: Identifier) {
+        self.disconnected
+            .lock()
+            .expect("transport mutex not to be poisoned")
+            .remove(&peer);
+    }
+}
+
+impl Transport for InMemoryTransport {
+    fn request(&self, peer: Identifier, request: &Request) -> Result<Response, TransportError> {
+        if self
+            .disconnected
+            .lock()
+            .expect("transport mutex not to be poisoned")
+            .contains(&peer)
+        {
+            return Err(TransportError::Unreachable(peer));
+        }
+        let handler = self
+            .handlers
+            .lock()
+            .expect("transport mutex not to be poisoned")
+            .get(&peer)
+            .cloned()
+            .ok_or(TransportError::Unreachable(peer))?;
+        let request = serde_json::from_str(&serde_json::to_string(request)?)?;
+        let response = handler(request);
+        Ok(serde_json::from_str(&serde_json::to_string(&response)?)?)
+    }
+}
+
+/// Newline delimited JSON over TCP, a connection per request. Peers aren't authenticated, the
+/// network between the signers has to be trusted.
+pub struct TcpTransport {
+    peers: BTreeMap<Identifier, String>,
+    timeout: Duration,
+}
+
+impl TcpTransport {
+    pub fn new(peers: BTreeMap<Identifier, String>) -> TcpTransport {
+        TcpTransport {
+            peers,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn request(&self, peer: Identifier, request: &Request) -> Result<Response, TransportError> {
+        let address = self
+            .peers
+            .get(&peer)
+            .ok_or(TransportError::Unreachable(peer))?;
+        let mut stream = TcpStream::connect(address).map_err(|e| {
+            debug!("Couldn't connect to signer {peer} at {address}: {e}");
+            TransportError::Unreachable(peer)
+        })?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        stream.write_all(line.as_bytes())?;
+
+        let mut response = String::new();
+        BufReader::new(stream).read_line(&mut response)?;
+        Ok(serde_json::from_str(&response)?)
+    }
+}
+
+fn serve_connection(
+    stream: TcpStream,
+    handler: &impl Fn(Request) -> Response,
+) -> Result<(), TransportError> {
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response = match serde_json::from_str(&line) {
+        Ok(request) => handler(request),
+        Err(e) => Response::Rejected(format!("Invalid request: {e}")),
+    };
+    let mut line = serde_json::to_string(&response)?;
+    line.push('\n');
+    (&stream).write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Serves the requests of [`TcpTransport`] one connection at a time
+pub fn serve_tcp(listener: TcpListener, handler: impl Fn(Request) -> Response) {
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(TransportError::from)
+            .and_then(|stream| serve_connection(stream, &handler));
+        if let Err(e) = result {
+            warn!("Failed to serve a signing request: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn serves_requests_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            serve_tcp(listener, |request| match request {
+                Request::Commit { withdrawal_id, .. } => Response::Rejected(withdrawal_id),
+                Request::Sign { .. } => Response::Shares(vec![]),
+            })
+        });
+
+        let transport = TcpTransport::new([(2, address)].into());
+        let request = Request::Commit {
+            withdrawal_id: "withdrawal".into(),
+            transaction: String::new(),
+            prevouts: vec![],
+            variants: vec![],
+        };
+        assert_eq!(
+            transport.request(2, &request).unwrap(),
+            Response::Rejected("withdrawal".into())
+        );
+        assert!(matches!(
+            transport.request(3, &request),
+            Err(TransportError::Unreachable(3))
+        ));
+    }
+}
@@ -0,0 +1,497 @@
+use crate::events::{RelayEvents, Withdrawal};
+use crate::wallet::{deposit_script, ScriptVariant, TrackedUtxo, Utxo, WithdrawalTransaction};
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoin::{Amount, OutPoint, ScriptBuf, Transaction, TxOut, Txid};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub type StoreError = rusqlite::Error;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS withdrawals (
+        id TEXT PRIMARY KEY,
+        requester TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        script_pubkey BLOB NOT NULL,
+        status TEXT NOT NULL,
+        transaction_raw BLOB,
+        prevouts BLOB,
+        signed_txid TEXT,
+        rejection TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS utxos (
+        txid TEXT NOT NULL,
+        vout INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        script_pubkey BLOB NOT NULL,
+        variant TEXT NOT NULL,
+        receiver TEXT,
+        spent_by TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (txid, vout)
+    );
+    CREATE TABLE IF NOT EXISTS index_state (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WithdrawalStatus {
+    /// Indexed, no payout proposed yet
+    Pending,
+    /// The coordinator proposed its payout to the signers
+    Proposed,
+    /// The signed payout was broadcast
+    Broadcast,
+    /// The payout confirmed, its inputs are spent
+    Confirmed,
+    /// The withdrawal can't be paid out, it's skipped
+    Rejected,
+}
+
+impl WithdrawalStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            WithdrawalStatus::Pending => "pending",
+            WithdrawalStatus::Proposed => "proposed",
+            WithdrawalStatus::Broadcast => "broadcast",
+            WithdrawalStatus::Confirmed => "confirmed",
+            WithdrawalStatus::Rejected => "rejected",
+        }
+    }
+
+    fn parse(status: &str) -> Option<WithdrawalStatus> {
+        match status {
+            "pending" => Some(WithdrawalStatus::Pending),
+            "proposed" => Some(WithdrawalStatus::Proposed),
+            "broadcast" => Some(WithdrawalStatus::Broadcast),
+            "confirmed" => Some(WithdrawalStatus::Confirmed),
+            "rejected" => Some(WithdrawalStatus::Rejected),
+            _ => None,
+        }
+    }
+}
+
+/// Embedded SQLite store of the indexed withdrawals, their payouts and the bridge's outputs
+pub struct SignerStore {
+    connection: Mutex<Connection>,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time to be after unix epoch")
+        .as_secs() as i64
+}
+
+fn to_sql_error(error: impl std::error::Error + Send + Sync + 'static) -> StoreError {
+    rusqlite::Error::ToSqlConversionFailure(Box::new(error))
+}
+
+const CURSOR_KEY: &str = "withdrawals_cursor";
+
+fn parse_variant(variant: String, receiver: Option<String>) -> Result<ScriptVariant, StoreError> {
+    match (variant.as_str(), receiver) {
+        ("group", _) => Ok(ScriptVariant::Group),
+        ("deposit", Some(receiver)) => Ok(ScriptVariant::Deposit {
+            receiver: Pubkey::from_str(&receiver).map_err(to_sql_error)?,
+        }),
+        _ => Err(rusqlite::Error::InvalidColumnType(
+            4,
+            variant,
+            rusqlite::types::Type::Text,
+        )),
+    }
+}
+
+fn insert_utxo(connection: &Connection, tracked: &TrackedUtxo, now: i64) -> Result<(), StoreError> {
+    let (variant, receiver) = match tracked.variant {
+        ScriptVariant::Group => ("group", None),
+        ScriptVariant::Deposit { receiver } => ("deposit", Some(receiver.to_string())),
+    };
+    connection.execute(
+        "INSERT OR IGNORE INTO utxos
+         (txid, vout, amount, script_pubkey, variant, receiver, spent_by, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)",
+        params![
+            tracked.utxo.outpoint.txid.to_string(),
+            tracked.utxo.outpoint.vout,
+            tracked.utxo.output.value.to_sat() as i64,
+            tracked.utxo.output.script_pubkey.as_bytes(),
+            variant,
+            receiver,
+            tracked.spent_by.map(|txid| txid.to_string()),
+            now
+        ],
+    )?;
+    Ok(())
+}
+
+impl SignerStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<SignerStore, StoreError> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Result<SignerStore, StoreError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<SignerStore, StoreError> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(SignerStore {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .expect("store mutex not to be poisoned")
+    }
+
+    /// Where indexing the relay program's events carries on from
+    pub fn cursor(&self) -> Result<Option<String>, StoreError> {
+        self.connection()
+            .query_row(
+                "SELECT value FROM index_state WHERE key = ?1",
+                params![CURSOR_KEY],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Records the indexed withdrawals and the outputs of the minted deposits along with the cursor
+    /// following them
+    pub fn insert_events(
+        &self,
+        events: &RelayEvents,
+        cursor: Option<&str>,
+    ) -> Result<(), StoreError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let now = now();
+        for withdrawal in &events.withdrawals {
+            transaction.execute(
+                "INSERT OR IGNORE INTO withdrawals
+                 (id, requester, amount, script_pubkey, status, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)",
+                params![
+                    withdrawal.id,
+                    withdrawal.requester.to_string(),
+                    withdrawal.amount as i64,
+                    withdrawal.script_pubkey.as_bytes(),
+                    WithdrawalStatus::Pending.as_str(),
+                    now
+                ],
+            )?;
+        }
+        for deposit in &events.deposits {
+            let Some(script_pubkey) = deposit_script(&deposit.receiver, deposit.group_key) else {
+                continue;
+            };
+            for output in &deposit.outputs {
+                let utxo = Utxo {
+                    outpoint: OutPoint::new(deposit.txid, output.vout),
+                    output: TxOut {
+                        value: Amount::from_sat(output.amount),
+                        script_pubkey: script_pubkey.clone(),
+                    },
+                };
+                let variant = ScriptVariant::Deposit {
+                    receiver: deposit.receiver,
+                };
+                insert_utxo(
+                    &transaction,
+                    &TrackedUtxo {
+                        utxo,
+                        variant,
+                        spent_by: None,
+                    },
+                    now,
+                )?;
+            }
+        }
+        if let Some(cursor) = cursor {
+            transaction.execute(
+                "INSERT OR REPLACE INTO index_state (key, value) VALUES (?1, ?2)",
+                params![CURSOR_KEY, cursor],
+            )?;
+        }
+        transaction.commit()
+    }
+
+    pub fn withdrawal(
+        &self,
+        id: &str,
+    ) -> Result<Option<(Withdrawal, WithdrawalStatus)>, StoreError> {
+        self.withdrawals("WHERE id = ?1", params![id])
+            .map(|withdrawals| withdrawals.into_iter().next())
+    }
+
+    /// Withdrawals without a broadcast payout, oldest first
+    pub fn unpaid_withdrawals(&self) -> Result<Vec<Withdrawal>, StoreError> {
+        let withdrawals = self.withdrawals(
+            "WHERE status IN (?1, ?2) ORDER BY rowid",
+            params![
+                WithdrawalStatus::Pending.as_str(),
+                WithdrawalStatus::Proposed.as_str()
+            ],
+        )?;
+        Ok(withdrawals
+            .into_iter()
+            .map(|(withdrawal, _)| withdrawal)
+            .collect())
+    }
+
+    fn withdrawals(
+        &self,
+        filter: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<(Withdrawal, WithdrawalStatus)>, StoreError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!(
+            "SELECT id, requester, amount, script_pubkey, status FROM withdrawals {filter}"
+        ))?;
+        let rows = statement.query_map(params, |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, Vec<u8>>(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (id, requester, amount, script_pubkey, status) = row?;
+            let withdrawal = Withdrawal {
+                id,
+                requester: Pubkey::from_str(&requester).map_err(to_sql_error)?,
+                amount: amount as u64,
+                script_pubkey: ScriptBuf::from_bytes(script_pubkey),
+            };
+            let status = WithdrawalStatus::parse(&status).ok_or_else(|| {
+                rusqlite::Error::InvalidColumnType(4, status, rusqlite::types::Type::Text)
+            })?;
+            Ok((withdrawal, status))
+        })
+        .collect()
+    }
+
+    /// Stores the payout proposed for the withdrawal, it's proposed again until it's broadcast
+    pub fn set_proposal(&self, proposal: &WithdrawalTransaction) -> Result<(), StoreError> {
+        self.connection().execute(
+            "UPDATE withdrawals SET status = ?2, transaction_raw = ?3, prevouts = ?4, updated_at = ?5
+             WHERE id = ?1",
+            params![
+                proposal.withdrawal_id,
+                WithdrawalStatus::Proposed.as_str(),
+                serialize(&proposal.transaction),
+                serialize(&proposal.prevouts),
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The proposed payout of the withdrawal, with the scripts of the tracked outputs it spends
+    pub fn proposal(
+        &self,
+        withdrawal_id: &str,
+    ) -> Result<Option<WithdrawalTransaction>, StoreError> {
+        let connection = self.connection();
+        let raw = connection
+            .query_row(
+                "SELECT transaction_raw, prevouts FROM withdrawals
+                 WHERE id = ?1 AND transaction_raw IS NOT NULL",
+                params![withdrawal_id],
+                |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?)),
+            )
+            .optional()?;
+        let Some((transaction, prevouts)) = raw else {
+            return Ok(None);
+        };
+        let transaction = deserialize::<Transaction>(&transaction).map_err(to_sql_error)?;
+        let variants = transaction
+            .input
+            .iter()
+            .map(|input| {
+                let (variant, receiver) = connection.query_row(
+                    "SELECT variant, receiver FROM utxos WHERE txid = ?1 AND vout = ?2",
+                    params![
+                        input.previous_output.txid.to_string(),
+                        input.previous_output.vout
+                    ],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?;
+                parse_variant(variant, receiver)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Some(WithdrawalTransaction {
+            withdrawal_id: withdrawal_id.to_string(),
+            transaction,
+            prevouts: deserialize::<Vec<TxOut>>(&prevouts).map_err(to_sql_error)?,
+            variants,
+        }))
+    }
+
+    pub fn set_broadcast(&self, withdrawal_id: &str) -> Result<(), StoreError> {
+        self.connection().execute(
+            "UPDATE withdrawals SET status = ?2, updated_at = ?3 WHERE id = ?1",
+            params![withdrawal_id, WithdrawalStatus::Broadcast.as_str(), now()],
+        )?;
+        Ok(())
+    }
+
+    /// Skips the withdrawal for good, recording why it can't be paid out
+    pub fn set_rejected(&self, withdrawal_id: &str, reason: &str) -> Result<(), StoreError> {
+        self.connection().execute(
+            "UPDATE withdrawals SET status = ?2, rejection = ?3, updated_at = ?4 WHERE id = ?1",
+            params![
+                withdrawal_id,
+                WithdrawalStatus::Rejected.as_str(),
+                reason,
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Broadcast payouts not confirmed yet
+    pub fn broadcast_payouts(&self) -> Result<Vec<WithdrawalTransaction>, StoreError> {
+        let ids: Vec<String> = {
+            let connection = self.connection();
+            let mut statement = connection
+                .prepare("SELECT id FROM withdrawals WHERE status = ?1 ORDER BY rowid")?;
+            let rows = statement
+                .query_map(params![WithdrawalStatus::Broadcast.as_str()], |row| {
+                    row.get(0)
+                })?;
+            rows.collect::<Result<_, _>>()?
+        };
+        let mut payouts = vec![];
+        for id in ids {
+            payouts.extend(self.proposal(&id)?);
+        }
+        Ok(payouts)
+    }
+
+    /// Marks the payout confirmed along with the tracked outputs it spends
+    pub fn set_confirmed(&self, payout: &WithdrawalTransaction) -> Result<(), StoreError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let now = now();
+        let txid = payout.transaction.compute_txid().to_string();
+        for input in &payout.transaction.input {
+            transaction.execute(
+                "UPDATE utxos SET spent_by = ?3, updated_at = ?4 WHERE txid = ?1 AND vout = ?2",
+                params![
+                    input.previous_output.txid.to_string(),
+                    input.previous_output.vout,
+                    txid,
+                    now
+                ],
+            )?;
+        }
+        transaction.execute(
+            "UPDATE withdrawals SET status = ?2, updated_at = ?3 WHERE id = ?1",
+            params![
+                payout.withdrawal_id,
+                WithdrawalStatus::Confirmed.as_str(),
+                now
+            ],
+        )?;
+        transaction.commit()
+    }
+
+    /// Tracks the outputs, the ones tracked already are kept as they are
+    pub fn insert_utxos(&self, utxos: &[TrackedUtxo]) -> Result<(), StoreError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let now = now();
+        for tracked in utxos {
+            insert_utxo(&transaction, tracked, now)?;
+        }
+        transaction.commit()
+    }
+
+    /// Tracked outputs no confirmed payout spends, oldest first
+    pub fn unspent_utxos(&self) -> Result<Vec<TrackedUtxo>, StoreError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT txid, vout, amount, script_pubkey, variant, receiver FROM utxos
+             WHERE spent_by IS NULL ORDER BY rowid",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, u32>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, Vec<u8>>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, Option<String>>(5)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (txid, vout, amount, script_pubkey, variant, receiver) = row?;
+            let variant = parse_variant(variant, receiver)?;
+            Ok(TrackedUtxo {
+                utxo: Utxo {
+                    outpoint: OutPoint::new(Txid::from_str(&txid).map_err(to_sql_error)?, vout),
+                    output: TxOut {
+                        value: Amount::from_sat(amount as u64),
+                        script_pubkey: ScriptBuf::from_bytes(script_pubkey),
+                    },
+                },
+                variant,
+                spent_by: None,
+            })
+        })
+        .collect()
+    }
+
+    /// Outputs spent by the stored payouts, which other payouts mustn't spend
+    pub fn reserved_outpoints(&self) -> Result<HashSet<OutPoint>, StoreError> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare("SELECT transaction_raw FROM withdrawals WHERE transaction_raw IS NOT NULL")?;
+        let rows = statement.query_map([], |row| row.get::<_, Vec<u8>>(0))?;
+        let mut reserved = HashSet::new();
+        for raw in rows {
+            let transaction: Transaction = deserialize(&raw?).map_err(to_sql_error)?;
+            reserved.extend(transaction.input.iter().map(|input| input.previous_output));
+        }
+        Ok(reserved)
+    }
+
+    /// Transaction the signer signed a payout of the withdrawal with
+    pub fn signed_txid(&self, withdrawal_id: &str) -> Result<Option<Txid>, StoreError> {
+        let txid: Option<Option<String>> = self
+            .connection()
+            .query_row(
+                "SELECT signed_txid FROM withdrawals WHERE id = ?1",
+                params![withdrawal_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        txid.flatten()
+            .map(|txid| Txid::from_str(&txid).map_err(to_sql_error))
+            .transpose()
+    }
+
+    pub fn set_signed_txid(&self, withdrawal_id: &str, txid: &Txid) -> Result<(), StoreError> {
+        self.connection().execute(
+            "UPDATE withdrawals SET signed_txid = ?2, updated_at = ?3 WHERE id = ?1",
+            params![withdrawal_id, txid.to_string(), now()],
+        )?;
+        Ok(())
+    }
+}
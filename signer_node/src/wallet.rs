@@ -0,0 +1,448 @@
+use crate::coin_selection::{select_coins, CoinSelection, Selection};
+use crate::events::Withdrawal;
+use crate::frost::{self, PublicKeyPackage};
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use bitcoin::absolute::LockTime;
+use bitcoin::hashes::Hash;
+use bitcoin::key::TweakedPublicKey;
+use bitcoin::secp256k1::{Secp256k1, XOnlyPublicKey};
+use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoin::taproot::{LeafVersion, TapLeafHash, TaprootBuilder};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Amount, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use bitcoincore_rpc::json::ScanTxOutRequest;
+use bitcoincore_rpc::{Error as BtcRpcError, RpcApi};
+use btc_relay::utils::{bridge_deposit_script, bridge_deposit_script_pubkey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Unspent output of the group
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Utxo {
+    pub outpoint: OutPoint,
+    pub output: TxOut,
+}
+
+/// Script an output of the bridge pays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScriptVariant {
+    /// The group's taproot output, spent with the group's key path signature
+    Group,
+    /// The receiver's taproot deposit output, derived from the bridge key registered in the relay
+    /// program. It's spent through its only leaf, with the group's signature by its output key.
+    Deposit { receiver: Pubkey },
+}
+
+impl ScriptVariant {
+    /// Script pubkey of the variant's outputs for the group, `None` for deposits whose script
+    /// can't be derived
+    pub fn script_pubkey(&self, public: &PublicKeyPackage) -> Option<ScriptBuf> {
+        match self {
+            ScriptVariant::Group => Some(group_script(public)),
+            ScriptVariant::Deposit { receiver } => {
+                deposit_script(receiver, frost::output_key(public))
+            }
+        }
+    }
+
+    // Tapscript leaf the input is spent through, none for key path spends
+    fn leaf_script(&self, public: &PublicKeyPackage) -> Option<ScriptBuf> {
+        match self {
+            ScriptVariant::Group => None,
+            ScriptVariant::Deposit { receiver } => Some(bridge_deposit_script(
+                receiver.to_bytes(),
+                frost::output_key(public),
+            )),
+        }
+    }
+
+    /// Witness of an input spending the variant's output with the group's signature
+    pub fn witness(&self, public: &PublicKeyPackage, signature: &[u8; 64]) -> Witness {
+        let Some(leaf_script) = self.leaf_script(public) else {
+            return Witness::from_slice(&[signature.as_slice()]);
+        };
+        // The deposit's internal key is the group's output key, the leaf its only script
+        let control_block = TaprootBuilder::new()
+            .add_leaf(0, leaf_script.clone())
+            .expect("a single leaf at depth 0")
+            .finalize(&Secp256k1::verification_only(), output_key(public))
+            .expect("a complete tree")
+            .control_block(&(leaf_script.clone(), LeafVersion::TapScript))
+            .expect("the tree's only leaf");
+        Witness::from_slice(&[
+            signature.as_slice(),
+            leaf_script.as_bytes(),
+            &control_block.serialize(),
+        ])
+    }
+}
+
+/// Output of the bridge the signer node keeps track of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackedUtxo {
+    pub utxo: Utxo,
+    pub variant: ScriptVariant,
+    /// Confirmed payout spending the output
+    pub spent_by: Option<Txid>,
+}
+
+#[derive(Debug)]
+pub enum WalletError {
+    BitcoinRpc(BtcRpcError),
+    /// The group's unreserved outputs don't cover the withdrawal
+    InsufficientFunds {
+        needed: u64,
+        available: u64,
+    },
+    /// The withdrawal doesn't cover its transaction fee and the payout's dust limit
+    PayoutBelowDust {
+        payout: i64,
+    },
+    /// The withdrawal script isn't a standard output script, nodes wouldn't relay its payout
+    NonStandardScript,
+    /// A proposed transaction doesn't pay out the withdrawal as requested
+    InvalidTransaction(&'static str),
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletError::BitcoinRpc(e) => write!(f, "{e}"),
+            WalletError::InsufficientFunds { needed, available } => write!(
+                f,
+                "Insufficient funds: {needed} sats needed, {available} sats available"
+            ),
+            WalletError::PayoutBelowDust { payout } => {
+                write!(f, "Payout of {payout} sats is below the dust limit")
+            }
+            WalletError::NonStandardScript => write!(f, "Withdrawal script isn't standard"),
+            WalletError::InvalidTransaction(reason) => {
+                write!(f, "Invalid withdrawal transaction: {reason}")
+            }
+        }
+    }
+}
+
+impl WalletError {
+    /// The withdrawal can't be paid out whatever outputs the group holds
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            WalletError::PayoutBelowDust { .. } | WalletError::NonStandardScript
+        )
+    }
+}
+
+impl From<BtcRpcError> for WalletError {
+    fn from(error: BtcRpcError) -> Self {
+        WalletError::BitcoinRpc(error)
+    }
+}
+
+fn output_key(public: &PublicKeyPackage) -> XOnlyPublicKey {
+    XOnlyPublicKey::from_slice(&frost::output_key(public))
+        .expect("output key to be a valid x-only key")
+}
+
+/// Script pubkey of the group's taproot output, spent with the group's key path signature
+pub fn group_script(public: &PublicKeyPackage) -> ScriptBuf {
+    let output_key = TweakedPublicKey::dangerous_assume_tweaked(output_key(public));
+    ScriptBuf::new_p2tr_tweaked(output_key)
+}
+
+/// Script pubkey of the receiver's deposit address derived from the bridge key, as the relay
+/// program verifies it. `None` if the bridge key isn't a valid x-only key
+pub fn deposit_script(receiver: &Pubkey, group_key: [u8; 32]) -> Option<ScriptBuf> {
+    bridge_deposit_script_pubkey(receiver.to_bytes(), group_key).ok()
+}
+
+/// Confirmed unspent outputs paying the scripts, scanned from the node's UTXO set
+pub fn unspent(
+    bitcoin_rpc: &impl RpcApi,
+    script_pubkeys: &[&Script],
+) -> Result<Vec<Utxo>, BtcRpcError> {
+    let descriptors: Vec<_> = script_pubkeys
+        .iter()
+        .map(|script_pubkey| {
+            ScanTxOutRequest::Single(format!("raw({})", script_pubkey.to_hex_string()))
+        })
+        .collect();
+    let scan = bitcoin_rpc.scan_tx_out_set_blocking(&descriptors)?;
+    Ok(scan
+        .unspents
+        .into_iter()
+        .map(|utxo| Utxo {
+            outpoint: OutPoint::new(utxo.txid, utxo.vout),
+            output: TxOut {
+                value: utxo.amount,
+                script_pubkey: utxo.script_pub_key,
+            },
+        })
+        .collect())
+}
+
+/// Unsigned withdrawal payout with the outputs it spends
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WithdrawalTransaction {
+    pub withdrawal_id: String,
+    pub transaction: Transaction,
+    /// Spent outputs, in the order of the inputs
+    pub prevouts: Vec<TxOut>,
+    /// Scripts of the spent outputs, in the order of the inputs
+    pub variants: Vec<ScriptVariant>,
+}
+
+impl WithdrawalTransaction {
+    /// BIP341 sighashes of the inputs, the messages the group signs: key path sighashes of the
+    /// group's outputs and script path sighashes of the deposits
+    pub fn sighashes(&self, public: &PublicKeyPackage) -> Vec<[u8; 32]> {
+        let mut cache = SighashCache::new(&self.transaction);
+        let prevouts = Prevouts::All(&self.prevouts);
+        self.variants
+            .iter()
+            .enumerate()
+            .map(|(index, variant)| {
+                let sighash = match variant.leaf_script(public) {
+                    None => cache.taproot_key_spend_signature_hash(
+                        index,
+                        &prevouts,
+                        TapSighashType::Default,
+                    ),
+                    Some(leaf_script) => cache.taproot_script_spend_signature_hash(
+                        index,
+                        &prevouts,
+                        TapLeafHash::from_script(&leaf_script, LeafVersion::TapScript),
+                        TapSighashType::Default,
+                    ),
+                };
+                sighash
+                    .expect("prevouts to match the inputs")
+                    .to_byte_array()
+            })
+            .collect()
+    }
+
+    /// The transaction with the group's signatures of the inputs
+    pub fn finalize(&self, public: &PublicKeyPackage, signatures: &[[u8; 64]]) -> Transaction {
+        let mut transaction = self.transaction.clone();
+        for ((input, variant), signature) in transaction
+            .input
+            .iter_mut()
+            .zip(&self.variants)
+            .zip(signatures)
+        {
+            input.witness = variant.witness(public, signature);
+        }
+        transaction
+    }
+}
+
+fn fee_for(
+    transaction: &Transaction,
+    variants: &[ScriptVariant],
+    public: &PublicKeyPackage,
+    fee_rate: u64,
+) -> u64 {
+    // Inputs are weighed with their witnesses, with 64 byte signatures
+    let mut signed = transaction.clone();
+    for (input, variant) in signed.input.iter_mut().zip(variants) {
+        input.witness = variant.witness(public, &[0; 64]);
+    }
+    signed.vsize() as u64 * fee_rate
+}
+
+fn unsigned_input(utxo: &Utxo) -> TxIn {
+    TxIn {
+        previous_output: utxo.outpoint,
+        script_sig: ScriptBuf::new(),
+        // Replaceable, so a stuck payout can be bumped
+        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+        witness: Witness::new(),
+    }
+}
+
+/// Builds the payout of the withdrawal from the group's outputs and deposits. The requester pays
+/// the transaction fee out of the withdrawn amount, the change goes back to the group unless it's
+/// dust. The group pays the fee of the outputs consolidated into the change out of the change.
+pub fn build_withdrawal(
+    withdrawal: &Withdrawal,
+    utxos: &[TrackedUtxo],
+    public: &PublicKeyPackage,
+    selection: &CoinSelection,
+) -> Result<WithdrawalTransaction, WalletError> {
+    let script = &withdrawal.script_pubkey;
+    if !(script.is_p2pkh()
+        || script.is_p2sh()
+        || script.is_p2wpkh()
+        || script.is_p2wsh()
+        || script.is_p2tr())
+    {
+        return Err(WalletError::NonStandardScript);
+    }
+    // Checked before selecting the outputs, so it fails the same with any funds
+    if withdrawal.amount < script.minimal_non_dust().to_sat() {
+        return Err(WalletError::PayoutBelowDust {
+            payout: withdrawal.amount as i64,
+        });
+    }
+
+    let group_script = group_script(public);
+    let dust = group_script.minimal_non_dust().to_sat();
+    // Excess below the dust limit would be dropped along with the change
+    let Selection {
+        funding,
+        consolidated,
+    } = select_coins(utxos, withdrawal.amount, dust - 1, selection)?;
+    let spent: Vec<TrackedUtxo> = funding.iter().chain(&consolidated).cloned().collect();
+    let variants: Vec<ScriptVariant> = spent.iter().map(|tracked| tracked.variant).collect();
+    let total: u64 = spent
+        .iter()
+        .map(|tracked| tracked.utxo.output.value.to_sat())
+        .sum();
+
+    let mut transaction = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: spent
+            .iter()
+            .map(|tracked| unsigned_input(&tracked.utxo))
+            .collect(),
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: withdrawal.script_pubkey.clone(),
+        }],
+    };
+    let change = TxOut {
+        value: Amount::from_sat(total - withdrawal.amount),
+        script_pubkey: group_script,
+    };
+    if change.value.to_sat() >= dust {
+        transaction.output.push(change);
+    }
+
+    let fee = fee_for(&transaction, &variants, public, selection.fee_rate);
+    let mut requester_fee = fee;
+    if !consolidated.is_empty() {
+        // Consolidated outputs are worth more than their fee, so the change covers it
+        let mut without_consolidation = transaction.clone();
+        without_consolidation.input.truncate(funding.len());
+        requester_fee = fee_for(
+            &without_consolidation,
+            &variants,
+            public,
+            selection.fee_rate,
+        );
+        transaction.output[1].value -= Amount::from_sat(fee - requester_fee);
+    }
+    let payout = withdrawal.amount as i64 - requester_fee as i64;
+    if payout < withdrawal.script_pubkey.minimal_non_dust().to_sat() as i64 {
+        return Err(WalletError::PayoutBelowDust { payout });
+    }
+    transaction.output[0].value = Amount::from_sat(payout as u64);
+
+    Ok(WithdrawalTransaction {
+        withdrawal_id: withdrawal.id.clone(),
+        transaction,
+        prevouts: spent
+            .into_iter()
+            .map(|tracked| tracked.utxo.output)
+            .collect(),
+        variants,
+    })
+}
+
+/// Checks a proposed payout before signing it: it spends the group's outputs and deposits only,
+/// pays the withdrawal script once and everything else back to the group. The group parts with at
+/// most the withdrawn amount, plus change too small to be kept and `max_consolidation_fee` sats
+/// spent consolidating its outputs, and the requester pays at most `max_fee` sats for the
+/// transaction.
+pub fn check_withdrawal(
+    proposed: &WithdrawalTransaction,
+    withdrawal: &Withdrawal,
+    public: &PublicKeyPackage,
+    max_fee: u64,
+    max_consolidation_fee: u64,
+) -> Result<(), WalletError> {
+    let transaction = &proposed.transaction;
+    let group_script = &group_script(public);
+    if proposed.withdrawal_id != withdrawal.id {
+        return Err(WalletError::InvalidTransaction(
+            "it pays out another withdrawal",
+        ));
+    }
+    if transaction.input.is_empty()
+        || proposed.prevouts.len() != transaction.input.len()
+        || proposed.variants.len() != transaction.input.len()
+    {
+        return Err(WalletError::InvalidTransaction(
+            "prevouts don't match the inputs",
+        ));
+    }
+    let outpoints: HashSet<_> = transaction
+        .input
+        .iter()
+        .map(|input| input.previous_output)
+        .collect();
+    if outpoints.len() != transaction.input.len() {
+        return Err(WalletError::InvalidTransaction("an output is spent twice"));
+    }
+    // The deposit scripts commit to the receiver and the group's key, which the leaf is signed with
+    if proposed
+        .prevouts
+        .iter()
+        .zip(&proposed.variants)
+        .any(|(prevout, variant)| {
+            variant.script_pubkey(public).as_ref() != Some(&prevout.script_pubkey)
+        })
+    {
+        return Err(WalletError::InvalidTransaction(
+            "it spends outputs of another script",
+        ));
+    }
+
+    let mut payouts = transaction
+        .output
+        .iter()
+        .filter(|output| output.script_pubkey == withdrawal.script_pubkey);
+    let payout = match (payouts.next(), payouts.next()) {
+        (Some(payout), None) => payout.value.to_sat(),
+        _ => {
+            return Err(WalletError::InvalidTransaction(
+                "it has to pay the withdrawal script exactly once",
+            ))
+        }
+    };
+    if transaction.output.iter().any(|output| {
+        output.script_pubkey != withdrawal.script_pubkey && output.script_pubkey != *group_script
+    }) {
+        return Err(WalletError::InvalidTransaction("it pays another script"));
+    }
+
+    let spent: u64 = proposed
+        .prevouts
+        .iter()
+        .map(|prevout| prevout.value.to_sat())
+        .sum();
+    let change: u64 = transaction
+        .output
+        .iter()
+        .filter(|output| output.script_pubkey == *group_script)
+        .map(|output| output.value.to_sat())
+        .sum();
+    let allowance = group_script.minimal_non_dust().to_sat() + max_consolidation_fee;
+    if spent.saturating_sub(change) > withdrawal.amount + allowance {
+        return Err(WalletError::InvalidTransaction(
+            "it spends more than the withdrawn amount",
+        ));
+    }
+    if payout > withdrawal.amount || withdrawal.amount - payout > max_fee {
+        return Err(WalletError::InvalidTransaction(
+            "its fee exceeds the maximum fee",
+        ));
+    }
+    Ok(())
+}
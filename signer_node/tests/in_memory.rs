@@ -0,0 +1,606 @@
+//! Signing groups running in one process: the signers talk over the in-memory transport, the
+//! relay program's events come from a list and a mock Bitcoin node holds the group's outputs and
+//! deposits.
+
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use bitcoin::consensus::encode::deserialize_hex;
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{Secp256k1, XOnlyPublicKey};
+use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoin::taproot::{ControlBlock, LeafVersion, TapLeafHash};
+use bitcoin::{Amount, OutPoint, ScriptBuf, Transaction, TxOut, Txid};
+use bitcoincore_rpc::json::{ScanTxOutResult, Utxo as RpcUtxo};
+use bitcoincore_rpc::{Error as BtcRpcError, RpcApi};
+use btc_relay::structs::DepositOutput;
+use rand::rngs::OsRng;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use signer_node_lib::coin_selection::CoinSelection;
+use signer_node_lib::events::{Deposit, EventSource, IndexError, RelayEvents, Withdrawal};
+use signer_node_lib::frost::{self, Identifier, PublicKeyPackage};
+use signer_node_lib::node::{Coordinator, NodeError, Signer};
+use signer_node_lib::protocol::{InMemoryTransport, Request, Response};
+use signer_node_lib::store::{SignerStore, WithdrawalStatus};
+use signer_node_lib::wallet::{
+    build_withdrawal, deposit_script, group_script, ScriptVariant, TrackedUtxo, Utxo, WalletError,
+};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+const MAX_FEE: u64 = 5_000;
+const FEE_RATE: u64 = 2;
+const MAX_CONSOLIDATION_FEE: u64 = 1_000;
+const SELECTION: CoinSelection = CoinSelection {
+    fee_rate: FEE_RATE,
+    long_term_fee_rate: FEE_RATE,
+    max_consolidation_fee: MAX_CONSOLIDATION_FEE,
+};
+
+/// Events of the relay program's transactions, indexed by their position
+#[derive(Clone, Default)]
+struct RelayProgram(Arc<Mutex<Vec<RelayEvents>>>);
+
+impl RelayProgram {
+    fn request(&self, amount: u64, script_pubkey: ScriptBuf) -> Withdrawal {
+        let mut transactions = self.0.lock().unwrap();
+        let withdrawal = Withdrawal {
+            id: format!("request:{}", transactions.len()),
+            requester: Pubkey::new_unique(),
+            amount,
+            script_pubkey,
+        };
+        transactions.push(RelayEvents {
+            withdrawals: vec![withdrawal.clone()],
+            deposits: vec![],
+        });
+        withdrawal
+    }
+
+    fn mint(&self, deposit: Deposit) {
+        self.0.lock().unwrap().push(RelayEvents {
+            withdrawals: vec![],
+            deposits: vec![deposit],
+        });
+    }
+}
+
+impl EventSource for RelayProgram {
+    fn events_after(
+        &self,
+        cursor: Option<&str>,
+    ) -> Result<(RelayEvents, Option<String>), IndexError> {
+        let transactions = self.0.lock().unwrap();
+        let start = cursor.map_or(0, |cursor| cursor.parse().unwrap());
+        let mut events = RelayEvents::default();
+        for transaction in &transactions[start..] {
+            events.withdrawals.extend(transaction.withdrawals.clone());
+            events.deposits.extend(transaction.deposits.clone());
+        }
+        Ok((events, Some(transactions.len().to_string())))
+    }
+}
+
+/// Bitcoin node holding the UTXO set, accepting transactions whose key path signatures or deposit
+/// leaf signatures verify
+#[derive(Default)]
+struct MockBitcoind {
+    utxos: RefCell<BTreeMap<OutPoint, TxOut>>,
+    broadcast: RefCell<Vec<Transaction>>,
+}
+
+impl MockBitcoind {
+    fn fund(&self, script_pubkey: &ScriptBuf, amount: u64) -> OutPoint {
+        let outpoint = OutPoint::new(
+            Txid::from_byte_array([self.utxos.borrow().len() as u8 + 1; 32]),
+            0,
+        );
+        self.fund_outpoint(outpoint, script_pubkey, amount);
+        outpoint
+    }
+
+    fn fund_outpoint(&self, outpoint: OutPoint, script_pubkey: &ScriptBuf, amount: u64) {
+        self.utxos.borrow_mut().insert(
+            outpoint,
+            TxOut {
+                value: Amount::from_sat(amount),
+                script_pubkey: script_pubkey.clone(),
+            },
+        );
+    }
+
+    fn accept(&self, transaction: Transaction) -> Result<Txid, String> {
+        let prevouts = transaction
+            .input
+            .iter()
+            .map(|input| self.utxos.borrow().get(&input.previous_output).cloned())
+            .collect::<Option<Vec<_>>>()
+            .ok_or("missing inputs")?;
+        let mut cache = SighashCache::new(&transaction);
+        for (index, (input, prevout)) in transaction.input.iter().zip(&prevouts).enumerate() {
+            let output_key: [u8; 32] = prevout.script_pubkey.as_bytes()[2..].try_into().unwrap();
+            let (public_key, sighash) = match input.witness.len() {
+                1 => (
+                    output_key,
+                    cache
+                        .taproot_key_spend_signature_hash(
+                            index,
+                            &Prevouts::All(&prevouts),
+                            TapSighashType::Default,
+                        )
+                        .unwrap(),
+                ),
+                // The deposit leaf checks a signature by the key it pushes second
+                3 => {
+                    let leaf = ScriptBuf::from_bytes(input.witness[1].to_vec());
+                    let control_block =
+                        ControlBlock::decode(&input.witness[2]).map_err(|e| e.to_string())?;
+                    if !control_block.verify_taproot_commitment(
+                        &Secp256k1::verification_only(),
+                        XOnlyPublicKey::from_slice(&output_key).unwrap(),
+                        &leaf,
+                    ) {
+                        return Err("leaf not committed to".into());
+                    }
+                    let sighash = cache
+                        .taproot_script_spend_signature_hash(
+                            index,
+                            &Prevouts::All(&prevouts),
+                            TapLeafHash::from_script(&leaf, LeafVersion::TapScript),
+                            TapSighashType::Default,
+                        )
+                        .unwrap();
+                    (leaf.as_bytes()[35..67].try_into().unwrap(), sighash)
+                }
+                _ => return Err("unexpected witness".into()),
+            };
+            let signature: [u8; 64] = input
+                .witness
+                .nth(0)
+                .and_then(|signature| signature.try_into().ok())
+                .ok_or("missing signature")?;
+            frost::verify(&public_key, &sighash.to_byte_array(), &signature)
+                .map_err(|e| e.to_string())?;
+        }
+
+        let txid = transaction.compute_txid();
+        let mut utxos = self.utxos.borrow_mut();
+        for input in &transaction.input {
+            utxos.remove(&input.previous_output);
+        }
+        for (vout, output) in transaction.output.iter().enumerate() {
+            utxos.insert(OutPoint::new(txid, vout as u32), output.clone());
+        }
+        self.broadcast.borrow_mut().push(transaction);
+        Ok(txid)
+    }
+
+    fn handle(&self, cmd: &str, args: &[Value]) -> Result<Value, BtcRpcError> {
+        match cmd {
+            "scantxoutset" => {
+                let mut unspents = vec![];
+                for descriptor in args[1].as_array().unwrap() {
+                    let descriptor = descriptor.as_str().unwrap();
+                    let script = descriptor
+                        .strip_prefix("raw(")
+                        .and_then(|script| script.strip_suffix(')'))
+                        .unwrap();
+                    let script = ScriptBuf::from_hex(script).unwrap();
+                    unspents.extend(
+                        self.utxos
+                            .borrow()
+                            .iter()
+                            .filter(|(_, output)| output.script_pubkey == script)
+                            .map(|(outpoint, output)| RpcUtxo {
+                                txid: outpoint.txid,
+                                vout: outpoint.vout,
+                                script_pub_key: output.script_pubkey.clone(),
+                                descriptor: descriptor.to_string(),
+                                amount: output.value,
+                                height: 1,
+                            }),
+                    );
+                }
+                Ok(serde_json::to_value(ScanTxOutResult {
+                    success: Some(true),
+                    tx_outs: Some(unspents.len() as u64),
+                    height: Some(1),
+                    best_block_hash: None,
+                    total_amount: unspents.iter().map(|utxo| utxo.amount).sum(),
+                    unspents,
+                })?)
+            }
+            "sendrawtransaction" => {
+                let transaction = deserialize_hex(args[0].as_str().unwrap()).unwrap();
+                let txid = self
+                    .accept(transaction)
+                    .map_err(BtcRpcError::ReturnedError)?;
+                Ok(json!(txid))
+            }
+            _ => Err(BtcRpcError::ReturnedError(format!(
+                "{cmd} is not supported by the mock"
+            ))),
+        }
+    }
+}
+
+impl RpcApi for &MockBitcoind {
+    fn call<T: DeserializeOwned>(&self, cmd: &str, args: &[Value]) -> Result<T, BtcRpcError> {
+        Ok(serde_json::from_value(self.handle(cmd, args)?)?)
+    }
+}
+
+struct Group {
+    signers: Vec<Arc<Signer<RelayProgram>>>,
+    transport: InMemoryTransport,
+    relay: RelayProgram,
+    bitcoind: MockBitcoind,
+    public: PublicKeyPackage,
+    script: ScriptBuf,
+    /// Output key registered as the relay program's bridge key
+    group_key: [u8; 32],
+}
+
+impl Group {
+    fn new(max_signers: u16, threshold: u16) -> Group {
+        let transport = InMemoryTransport::new();
+        let relay = RelayProgram::default();
+        let keys = frost::generate_with_dealer(max_signers, threshold, &mut OsRng).unwrap();
+        let public = keys[0].public.clone();
+        let script = group_script(&public);
+        let group_key = frost::output_key(&public);
+        let signers: Vec<_> = keys
+            .into_iter()
+            .map(|key| {
+                let signer = Arc::new(Signer::new(
+                    key,
+                    SignerStore::in_memory().unwrap(),
+                    relay.clone(),
+                    MAX_FEE,
+                    MAX_CONSOLIDATION_FEE,
+                ));
+                let handler = signer.clone();
+                transport.register(signer.identifier(), move |request| handler.handle(request));
+                signer
+            })
+            .collect();
+        Group {
+            signers,
+            transport,
+            relay,
+            bitcoind: MockBitcoind::default(),
+            public,
+            script,
+            group_key,
+        }
+    }
+
+    /// Coordinator run by the first signer
+    fn coordinator(&self) -> Coordinator<RelayProgram, &MockBitcoind, InMemoryTransport> {
+        self.coordinator_with(SELECTION)
+    }
+
+    fn coordinator_with(
+        &self,
+        selection: CoinSelection,
+    ) -> Coordinator<RelayProgram, &MockBitcoind, InMemoryTransport> {
+        let peers: Vec<Identifier> = self.signers[1..]
+            .iter()
+            .map(|signer| signer.identifier())
+            .collect();
+        Coordinator::new(
+            self.signers[0].clone(),
+            &self.bitcoind,
+            self.transport.clone(),
+            peers,
+            selection,
+        )
+    }
+}
+
+fn user_script() -> ScriptBuf {
+    let mut script = vec![0x00, 0x14];
+    script.extend_from_slice(&[7; 20]);
+    ScriptBuf::from_bytes(script)
+}
+
+#[test]
+fn pays_out_withdrawals_signed_by_a_threshold_of_signers() {
+    let group = Group::new(3, 2);
+    group.bitcoind.fund(&group.script, 50_000);
+    group.bitcoind.fund(&group.script, 30_000);
+    let withdrawal = group.relay.request(40_000, user_script());
+    let coordinator = group.coordinator();
+
+    let txid = coordinator.pay_next_withdrawal().unwrap().unwrap();
+
+    let broadcast = group.bitcoind.broadcast.borrow();
+    assert_eq!(broadcast.len(), 1);
+    let payout = &broadcast[0];
+    assert_eq!(payout.compute_txid(), txid);
+    assert_eq!(payout.input.len(), 1);
+    let paid = &payout.output[0];
+    assert_eq!(paid.script_pubkey, withdrawal.script_pubkey);
+    assert!(paid.value.to_sat() < 40_000 && paid.value.to_sat() > 40_000 - MAX_FEE);
+    assert_eq!(payout.output[1].script_pubkey, group.script);
+    assert_eq!(payout.output[1].value.to_sat(), 10_000);
+    drop(broadcast);
+
+    // Paid withdrawals aren't paid again, the change funds the next ones
+    assert_eq!(coordinator.pay_next_withdrawal().unwrap(), None);
+    group.relay.request(35_000, user_script());
+    coordinator.pay_next_withdrawal().unwrap().unwrap();
+    assert_eq!(group.bitcoind.broadcast.borrow()[1].input.len(), 2);
+}
+
+#[test]
+fn pays_out_withdrawals_while_signers_are_offline() {
+    let group = Group::new(3, 2);
+    group.bitcoind.fund(&group.script, 50_000);
+    group.relay.request(10_000, user_script());
+    group.relay.request(10_000, user_script());
+    let coordinator = group.coordinator();
+
+    group.transport.disconnect(2);
+    coordinator.pay_next_withdrawal().unwrap().unwrap();
+
+    group.transport.disconnect(3);
+    match coordinator.pay_next_withdrawal() {
+        Err(NodeError::NotEnoughSigners {
+            committed: 1,
+            threshold: 2,
+        }) => (),
+        result => panic!("Expected too few signers, got {result:?}"),
+    }
+
+    group.transport.connect(2);
+    coordinator.pay_next_withdrawal().unwrap().unwrap();
+    assert_eq!(group.bitcoind.broadcast.borrow().len(), 2);
+}
+
+#[test]
+fn skips_withdrawals_which_cant_be_paid_out() {
+    let group = Group::new(3, 2);
+    group.bitcoind.fund(&group.script, 50_000);
+    let below_dust = group.relay.request(100, user_script());
+    let non_standard = group
+        .relay
+        .request(10_000, ScriptBuf::from_bytes(vec![0x51]));
+    // Above the dust limit, but not once the transaction fee is deducted
+    let below_fee = group.relay.request(300, user_script());
+    let too_large = group.relay.request(60_000, user_script());
+    group.relay.request(10_000, user_script());
+    let coordinator = group.coordinator();
+
+    // Withdrawals which can never be paid out are rejected, but the next one waits for funds
+    match coordinator.pay_next_withdrawal() {
+        Err(NodeError::Wallet(WalletError::InsufficientFunds { .. })) => (),
+        result => panic!("Expected insufficient funds, got {result:?}"),
+    }
+    let store = group.signers[0].store();
+    for rejected in [&below_dust, &non_standard, &below_fee] {
+        let (_, status) = store.withdrawal(&rejected.id).unwrap().unwrap();
+        assert_eq!(status, WithdrawalStatus::Rejected);
+    }
+    let (_, status) = store.withdrawal(&too_large.id).unwrap().unwrap();
+    assert_eq!(status, WithdrawalStatus::Pending);
+    assert!(group.bitcoind.broadcast.borrow().is_empty());
+
+    group.bitcoind.fund(&group.script, 30_000);
+    coordinator.pay_next_withdrawal().unwrap().unwrap();
+    coordinator.pay_next_withdrawal().unwrap().unwrap();
+    assert_eq!(coordinator.pay_next_withdrawal().unwrap(), None);
+    assert_eq!(group.bitcoind.broadcast.borrow().len(), 2);
+}
+
+#[test]
+fn signers_reject_payouts_not_matching_the_withdrawal() {
+    let group = Group::new(3, 2);
+    let outpoint = group.bitcoind.fund(&group.script, 50_000);
+    let withdrawal = group.relay.request(40_000, user_script());
+    let utxos = [TrackedUtxo {
+        utxo: Utxo {
+            outpoint,
+            output: group.bitcoind.utxos.borrow()[&outpoint].clone(),
+        },
+        variant: ScriptVariant::Group,
+        spent_by: None,
+    }];
+    let signer = &group.signers[1];
+    let assert_rejected = |request: &Request, expected: &str| match signer.handle(request.clone()) {
+        Response::Rejected(reason) => assert!(reason.contains(expected), "{reason}"),
+        response => panic!("Expected a rejection, got {response:?}"),
+    };
+
+    // Paying another script
+    let mut stolen = withdrawal.clone();
+    stolen.script_pubkey = ScriptBuf::from_bytes([&[0x00, 0x14][..], &[9; 20]].concat());
+    let proposal = build_withdrawal(&stolen, &utxos, &group.public, &SELECTION).unwrap();
+    assert_rejected(
+        &Request::commit(
+            &withdrawal.id,
+            &proposal.transaction,
+            &proposal.prevouts,
+            &proposal.variants,
+        ),
+        "exactly once",
+    );
+
+    // Paying out more than requested
+    let mut inflated = withdrawal.clone();
+    inflated.amount = 45_000;
+    let proposal = build_withdrawal(&inflated, &utxos, &group.public, &SELECTION).unwrap();
+    assert_rejected(
+        &Request::commit(
+            &withdrawal.id,
+            &proposal.transaction,
+            &proposal.prevouts,
+            &proposal.variants,
+        ),
+        "more than the withdrawn amount",
+    );
+
+    // Signing the group's output as a receiver's deposit
+    let mut mislabeled = build_withdrawal(&withdrawal, &utxos, &group.public, &SELECTION).unwrap();
+    mislabeled.variants = vec![ScriptVariant::Deposit {
+        receiver: Pubkey::new_unique(),
+    }];
+    assert_rejected(
+        &Request::commit(
+            &withdrawal.id,
+            &mislabeled.transaction,
+            &mislabeled.prevouts,
+            &mislabeled.variants,
+        ),
+        "outputs of another script",
+    );
+
+    // A withdrawal the relay program never logged
+    let proposal = build_withdrawal(&withdrawal, &utxos, &group.public, &SELECTION).unwrap();
+    assert_rejected(
+        &Request::commit(
+            "unknown:0",
+            &proposal.transaction,
+            &proposal.prevouts,
+            &proposal.variants,
+        ),
+        "Unknown withdrawal",
+    );
+
+    // Signing needs committed nonces, which are used once
+    let commit = Request::commit(
+        &withdrawal.id,
+        &proposal.transaction,
+        &proposal.prevouts,
+        &proposal.variants,
+    );
+    let Response::Commitments(commitments) = signer.handle(commit.clone()) else {
+        panic!("Expected commitments");
+    };
+    let Response::Commitments(coordinator_commitments) = group.signers[0].handle(commit) else {
+        panic!("Expected commitments");
+    };
+    let sign = Request::Sign {
+        txid: proposal.transaction.compute_txid(),
+        packages: vec![frost::signing_package(
+            [(1, coordinator_commitments[0]), (2, commitments[0])].into(),
+            &proposal.sighashes(&group.public)[0],
+        )
+        .unwrap()],
+    };
+    assert!(matches!(signer.handle(sign.clone()), Response::Shares(_)));
+    assert_rejected(&sign, "No nonces committed");
+
+    // Once a payout is signed, another one of the same withdrawal isn't
+    let higher_fee = CoinSelection {
+        fee_rate: FEE_RATE + 1,
+        ..SELECTION
+    };
+    let second = build_withdrawal(&withdrawal, &utxos, &group.public, &higher_fee).unwrap();
+    assert_rejected(
+        &Request::commit(
+            &withdrawal.id,
+            &second.transaction,
+            &second.prevouts,
+            &second.variants,
+        ),
+        "was signed with transaction",
+    );
+}
+
+#[test]
+fn tracks_the_bridge_outputs_and_consolidates_while_fees_are_low() {
+    let group = Group::new(3, 2);
+    let funding = group.bitcoind.fund(&group.script, 50_000);
+    let small: Vec<_> = (0..3)
+        .map(|_| group.bitcoind.fund(&group.script, 1_000))
+        .collect();
+    let withdrawal = group.relay.request(40_000, user_script());
+    let coordinator = group.coordinator_with(CoinSelection {
+        long_term_fee_rate: 10,
+        ..SELECTION
+    });
+
+    coordinator.pay_next_withdrawal().unwrap().unwrap();
+
+    // The requester pays the fee of the payout without the consolidated outputs
+    let payout = group.bitcoind.broadcast.borrow()[0].clone();
+    assert_eq!(payout.input.len(), 4);
+    let funding_utxo = [TrackedUtxo {
+        utxo: Utxo {
+            outpoint: funding,
+            output: TxOut {
+                value: Amount::from_sat(50_000),
+                script_pubkey: group.script.clone(),
+            },
+        },
+        variant: ScriptVariant::Group,
+        spent_by: None,
+    }];
+    let without_consolidation =
+        build_withdrawal(&withdrawal, &funding_utxo, &group.public, &SELECTION).unwrap();
+    assert_eq!(
+        payout.output[0],
+        without_consolidation.transaction.output[0]
+    );
+    let change = payout.output[1].value.to_sat();
+    assert!(change < 13_000 && change > 13_000 - MAX_CONSOLIDATION_FEE);
+
+    // The spent outputs are untracked once the payout confirms, the change and deposit are tracked
+    let receiver = Pubkey::new_unique();
+    let deposit_outpoint = OutPoint::new(Txid::from_byte_array([42; 32]), 1);
+    let deposit_script = deposit_script(&receiver, group.group_key).unwrap();
+    group
+        .bitcoind
+        .fund_outpoint(deposit_outpoint, &deposit_script, 25_000);
+    group.relay.mint(Deposit {
+        txid: deposit_outpoint.txid,
+        receiver,
+        group_key: group.group_key,
+        outputs: vec![DepositOutput {
+            vout: 1,
+            amount: 25_000,
+        }],
+    });
+    group.signers[0].sync_events().unwrap();
+    coordinator.sync_utxos().unwrap();
+    let tracked = group.signers[0].store().unspent_utxos().unwrap();
+    let outpoints: Vec<_> = tracked
+        .iter()
+        .map(|tracked| tracked.utxo.outpoint)
+        .collect();
+    assert!(!outpoints.contains(&funding));
+    assert!(small.iter().all(|outpoint| !outpoints.contains(outpoint)));
+    assert_eq!(tracked.len(), 2);
+    let deposit = &tracked[0];
+    assert_eq!(deposit.utxo.outpoint, deposit_outpoint);
+    assert_eq!(deposit.utxo.output.script_pubkey, deposit_script);
+    assert_eq!(deposit.variant, ScriptVariant::Deposit { receiver });
+    let change_output = OutPoint::new(payout.compute_txid(), 1);
+    assert_eq!(tracked[1].utxo.outpoint, change_output);
+    assert_eq!(tracked[1].variant, ScriptVariant::Group);
+
+    // Deposits fund payouts through their leaf, the mock node checks the group's signature
+    group.relay.request(20_000, user_script());
+    coordinator.pay_next_withdrawal().unwrap().unwrap();
+    let payout = group.bitcoind.broadcast.borrow()[1].clone();
+    let deposit_input = payout
+        .input
+        .iter()
+        .find(|input| input.previous_output == deposit_outpoint)
+        .unwrap();
+    assert_eq!(deposit_input.witness.len(), 3);
+    assert!(payout
+        .input
+        .iter()
+        .any(|input| input.previous_output == change_output));
+
+    // Payouts spending deposits are seen confirmed too
+    coordinator.sync_utxos().unwrap();
+    let tracked = group.signers[0].store().unspent_utxos().unwrap();
+    assert_eq!(tracked.len(), 1);
+    assert_eq!(
+        tracked[0].utxo.outpoint,
+        OutPoint::new(payout.compute_txid(), 1)
+    );
+}
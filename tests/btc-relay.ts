@@ -81,6 +81,14 @@ async function getCommitedHeaderFromTx(signature: string): Promise<any> {
     return commitedHeader;
 }
 
+// Prints compute units consumed by the transaction, used to track CU usage of the instructions
+async function logComputeUnits(signature: string, label: string) {
+    const transaction = await provider.connection.getTransaction(signature, {
+        commitment: "confirmed"
+    });
+    console.log(`${label} compute units: `, transaction.meta.computeUnitsConsumed);
+}
+
 describe("btc-relay", () => {
     // Configure the client to use the local cluster.
     anchor.setProvider(provider);
@@ -90,6 +98,20 @@ describe("btc-relay", () => {
         seed,
         program.programId
     );
+    const [depositFeesKey] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("deposit_fees")],
+        program.programId
+    );
+    const [bridgeKey] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("bridge_key")],
+        program.programId
+    );
+    const [programData] = anchor.web3.PublicKey.findProgramAddressSync(
+        [program.programId.toBuffer()],
+        new anchor.web3.PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+    );
+    // x-only key of the generator point, registered as the signer nodes' group key
+    const groupKey = Buffer.from("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798", "hex");
 
     it("Is initialized!", async () => {
         // Add your test here.
@@ -165,6 +187,17 @@ describe("btc-relay", () => {
         );
         const programBalanceAfter = await provider.connection.getBalance(depositAccount);
         console.log(`Program balance after ${programBalanceAfter}`);
+
+        // The provider's wallet deployed the program, so it's the upgrade authority registering the bridge key
+        await program.methods
+            .initializeBridgeKey([...groupKey])
+            .accounts({
+                signer: provider.wallet.publicKey,
+                bridgeKey,
+                programData,
+                systemProgram: SystemProgram.programId
+            })
+            .rpc();
     });
 
     it("Submit more blocks and verify small tx!", async () => {
@@ -199,48 +232,62 @@ describe("btc-relay", () => {
             },
         ];
 
-        let currentCommited = initCommittedHeader;
-        for (let nextHeader of headers) {
-            const header = {
-                version: nextHeader.bytes.readUInt32LE(),
-                reversedPrevBlockhash: [...nextHeader.bytes.slice(4, 36)],
-                merkleRoot: [...nextHeader.bytes.slice(36, 68)],
-                timestamp: nextHeader.bytes.readUInt32LE(68),
-                nbits: nextHeader.bytes.readUInt32LE(72),
-                nonce: nextHeader.bytes.readUInt32LE(76)
-            };
-
-            const headerHash = dblSha256(nextHeader.bytes);
-            const headerTopic = anchor.web3.PublicKey.findProgramAddressSync(
-                [Buffer.from(anchor.utils.bytes.utf8.encode(headerSeed)), headerHash],
-                program.programId
-            )[0];
+        // Submits the headers in a single transaction, returns its signature
+        async function submitHeaders(nextHeaders: {height: number, bytes: Buffer}[], commitedHeader: any): Promise<string> {
+            const submitted = nextHeaders.map(nextHeader => {
+                const header = {
+                    version: nextHeader.bytes.readUInt32LE(),
+                    reversedPrevBlockhash: [...nextHeader.bytes.slice(4, 36)],
+                    merkleRoot: [...nextHeader.bytes.slice(36, 68)],
+                    timestamp: nextHeader.bytes.readUInt32LE(68),
+                    nbits: nextHeader.bytes.readUInt32LE(72),
+                    nonce: nextHeader.bytes.readUInt32LE(76)
+                };
+
+                const headerHash = dblSha256(nextHeader.bytes);
+                const [headerTopic, headerTopicBump] = anchor.web3.PublicKey.findProgramAddressSync(
+                    [Buffer.from(anchor.utils.bytes.utf8.encode(headerSeed)), headerHash],
+                    program.programId
+                );
+
+                return {header, headerTopic, headerTopicBump};
+            });
 
             const tx = await program.methods
                 .submitBlockHeaders(
-                    [header],
-                    currentCommited
+                    submitted.map(e => e.header),
+                    commitedHeader,
+                    Buffer.from(submitted.map(e => e.headerTopicBump))
                 )
                 .accounts({
                     signer: signer.publicKey,
                     mainState: mainStateKey,
+                    feePool: null,
                     systemProgram: SystemProgram.programId,
                 })
-                .remainingAccounts([{
-                    pubkey: headerTopic,
+                .remainingAccounts(submitted.map(e => ({
+                    pubkey: e.headerTopic,
                     isSigner: false,
                     isWritable: false
-                }])
+                })))
                 .signers([signer])
                 .transaction();
 
-            const result = await provider.sendAndConfirm(tx, [signer], {
+            return await provider.sendAndConfirm(tx, [signer], {
                 skipPreflight: true
             });
-
-            currentCommited = await getCommitedHeaderFromTx(result);
         }
 
+        // Single header first, then the rest in one transaction to measure how many fit
+        const singleResult = await submitHeaders(headers.slice(0, 1), initCommittedHeader);
+        await logComputeUnits(singleResult, "submit_block_headers (1 header)");
+        let currentCommited = await getCommitedHeaderFromTx(singleResult);
+
+        const batchResult = await submitHeaders(headers.slice(1), currentCommited);
+        await logComputeUnits(batchResult, `submit_block_headers (${headers.length - 1} headers)`);
+        currentCommited = await getCommitedHeaderFromTx(batchResult);
+        chai.expect(currentCommited.blockheight).eq(headers[headers.length - 1].height);
+
         // raw bytes of 7c04665a396c766c68306c04ea3700975777fc8c198f352c92c2ebe0acb48443 Yona bitcoin regtest tx
         // http://139.59.156.238:8094/regtest/tx/7c04665a396c766c68306c04ea3700975777fc8c198f352c92c2ebe0acb48443
         const txBytes = "02000000000103d592a7cbfd1d3a2a79fc7b47fbafbd98db92c577910eba87614317f34b7c48100100000000fdffffff336d22988206b10ee6d8e8ecc882712046cb65bc0ef10f6da2c505352a9be6e30000000000fdffffff185b7398b1043b1f6e0d7df98bc049ddb4b427b678037313602124d596a04bf30000000000fdffffff01241acc1d000000002200204a3a5f1583c04e6c45ada0e4724f3e394122ce97d36762d24cc9f6563faee4850247304402202e017d47b1a6a0d65629171be7275b31c1cb3365d9f697d721895aca10d6125d022023d1cf4b458304cedfbd52ce59d7a80398ee8531b7d0ed352016a62d0239a5a60121021f0eeaabee6b006aa78ac93b26a9268e0079acb5a0a0e70e1ce03dd10b012d6802473044022046ff88c6af23c8cc02cebffd50f49aaddbb90cbfec5390a6821b5eabff3017f502201932303780e41e71d6effe1e1c5f944fb4c01baff6aa400a9d78bf5e61158155012102cc27969207f94386d7ae02a3f0605bbb87cec34d436d3a30cf22d447c164c9da02473044022001f4bb68622415dcb654101a4e9c73007c1e672f2a0370375e1d0aa8d63b3f7c022054dd59718599c12243077efe8b5ac6802cfb05f930e7646ef7e7cb339e4331c8012102cc27969207f94386d7ae02a3f0605bbb87cec34d436d3a30cf22d447c164c9dacd320000";
@@ -252,6 +299,12 @@ describe("btc-relay", () => {
             program.programId
         );
 
+        const txIdBytes = Buffer.from("7c04665a396c766c68306c04ea3700975777fc8c198f352c92c2ebe0acb48443", "hex").reverse();
+        const [depositClaim] = anchor.web3.PublicKey.findProgramAddressSync(
+            [Buffer.from("deposit_claim"), txIdBytes, mintReceiver.toBuffer()],
+            program.programId
+        );
+
         const receiverBalanceBefore = await provider.connection.getBalance(mintReceiver);
 
         const ix = await program.methods
@@ -266,7 +319,14 @@ describe("btc-relay", () => {
                 signer: signer.publicKey,
                 mainState: mainStateKey,
                 depositAccount,
-                mintReceiver
+                mintReceiver,
+                bridgeKey,
+                // Deposits are minted in full while the deposit fees aren't initialized
+                depositFees: depositFeesKey,
+                treasury: null,
+                feePool: null,
+                depositClaim,
+                systemProgram: SystemProgram.programId
             })
             .signers([signer])
             .instruction();
@@ -277,8 +337,8 @@ describe("btc-relay", () => {
         //  }))
         const tx = new anchor.web3.Transaction().add(ix);
 
-        // to catch error and log more details about it
-        // .catch(e => console.error(e));
+        // The transaction pays the legacy P2WSH deposit script, minted until the bridge key's authority ends legacy
+        // deposits
         let signature = await provider.sendAndConfirm(tx, [signer], {
             skipPreflight: false
         }).catch(e => {
@@ -294,6 +354,8 @@ describe("btc-relay", () => {
             },
             commitment
         );
+        await logComputeUnits(signature, "verify_small_tx");
+
         const receiverBalanceAfter = await provider.connection.getBalance(mintReceiver);
         const expectedBalance = receiverBalanceBefore + 4999153000;
         chai.expect(receiverBalanceAfter).eq(expectedBalance);
@@ -387,6 +449,11 @@ describe("btc-relay", () => {
             program.programId
         );
 
+        const [depositClaim] = anchor.web3.PublicKey.findProgramAddressSync(
+            [Buffer.from("deposit_claim"), txIdBytes, mintReceiver.toBuffer()],
+            program.programId
+        );
+
         const receiverBalanceBefore = await provider.connection.getBalance(mintReceiver);
         const finalizeIx = await program.methods
             .finalizeTxProcessing(
@@ -396,7 +463,14 @@ describe("btc-relay", () => {
                 signer: signer.publicKey,
                 txAccount,
                 depositAccount,
-                mintReceiver
+                mintReceiver,
+                bridgeKey,
+                // Deposits are minted in full while the deposit fees aren't initialized
+                depositFees: depositFeesKey,
+                treasury: null,
+                feePool: null,
+                depositClaim,
+                systemProgram: SystemProgram.programId
             })
             .signers([signer])
             .instruction();
@@ -409,8 +483,7 @@ describe("btc-relay", () => {
             units: 500_000, // Set the desired number of compute units
         })).add(finalizeIx);
 
-        // to catch error and log more details about it
-        // .catch(e => console.error(e));
+        // Like the small transaction, it pays the legacy P2WSH deposit script
         const finalizeResult = await provider.sendAndConfirm(finalizeTx, [signer], {
             skipPreflight: false
         }).catch(e => {
@@ -426,6 +499,8 @@ describe("btc-relay", () => {
             },
             commitment
         );
+        await logComputeUnits(finalizeResult, "finalize_tx_processing");
+
         const receiverBalanceAfter = await provider.connection.getBalance(mintReceiver);
         const expectedBalance = receiverBalanceBefore + LAMPORTS_PER_SOL;
         chai.expect(receiverBalanceAfter).eq(expectedBalance);
@@ -0,0 +1,61 @@
+import * as anchor from "@coral-xyz/anchor";
+import {Program} from "@coral-xyz/anchor";
+import {TxParserBench} from "../target/types/tx_parser_bench";
+
+import * as chai from 'chai';
+
+const provider = anchor.AnchorProvider.env();
+const program = anchor.workspace.TxParserBench as Program<TxParserBench>;
+
+// Same transactions as verify_small_tx with the script pubkey of their output
+const transactions = [
+    {
+        // 7c04665a396c766c68306c04ea3700975777fc8c198f352c92c2ebe0acb48443, relayed by the btc-relay tests
+        label: "segwit, 3 inputs",
+        txBytes: "02000000000103d592a7cbfd1d3a2a79fc7b47fbafbd98db92c577910eba87614317f34b7c48100100000000fdffffff336d22988206b10ee6d8e8ecc882712046cb65bc0ef10f6da2c505352a9be6e30000000000fdffffff185b7398b1043b1f6e0d7df98bc049ddb4b427b678037313602124d596a04bf30000000000fdffffff01241acc1d000000002200204a3a5f1583c04e6c45ada0e4724f3e394122ce97d36762d24cc9f6563faee4850247304402202e017d47b1a6a0d65629171be7275b31c1cb3365d9f697d721895aca10d6125d022023d1cf4b458304cedfbd52ce59d7a80398ee8531b7d0ed352016a62d0239a5a60121021f0eeaabee6b006aa78ac93b26a9268e0079acb5a0a0e70e1ce03dd10b012d6802473044022046ff88c6af23c8cc02cebffd50f49aaddbb90cbfec5390a6821b5eabff3017f502201932303780e41e71d6effe1e1c5f944fb4c01baff6aa400a9d78bf5e61158155012102cc27969207f94386d7ae02a3f0605bbb87cec34d436d3a30cf22d447c164c9da02473044022001f4bb68622415dcb654101a4e9c73007c1e672f2a0370375e1d0aa8d63b3f7c022054dd59718599c12243077efe8b5ac6802cfb05f930e7646ef7e7cb339e4331c8012102cc27969207f94386d7ae02a3f0605bbb87cec34d436d3a30cf22d447c164c9dacd320000",
+        scriptPubkey: "00204a3a5f1583c04e6c45ada0e4724f3e394122ce97d36762d24cc9f6563faee485",
+        amount: 499915300
+    },
+    {
+        // Mainnet genesis coinbase
+        label: "legacy, coinbase",
+        txBytes: "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000",
+        scriptPubkey: "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac",
+        amount: 5000000000
+    }
+];
+
+// Compute units consumed by the transaction and the data returned by its instruction
+async function runBench(signature: string): Promise<{computeUnits: number, returnData: Buffer}> {
+    await provider.connection.confirmTransaction(signature, "confirmed");
+    const transaction = await provider.connection.getTransaction(signature, {
+        commitment: "confirmed"
+    });
+    return {
+        computeUnits: transaction.meta.computeUnitsConsumed,
+        returnData: Buffer.from(transaction.meta.returnData.data[0], "base64")
+    };
+}
+
+describe("tx-parser-bench", () => {
+    anchor.setProvider(provider);
+
+    for (const {label, txBytes, scriptPubkey, amount} of transactions) {
+        it(`Parses ${label} transaction with fewer compute units than the bitcoin decoder`, async () => {
+            const args: [Buffer, Buffer] = [Buffer.from(txBytes, "hex"), Buffer.from(scriptPubkey, "hex")];
+            const accounts = {signer: provider.wallet.publicKey};
+
+            const decoded = await runBench(
+                await program.methods.decodeWithBitcoin(...args).accounts(accounts).rpc()
+            );
+            const parsed = await runBench(
+                await program.methods.parse(...args).accounts(accounts).rpc()
+            );
+            console.log(`${label}: bitcoin decoder ${decoded.computeUnits} compute units, parser ${parsed.computeUnits}`);
+
+            chai.expect(parsed.returnData).deep.eq(decoded.returnData);
+            chai.expect(parsed.returnData.subarray(32)).deep.eq(new anchor.BN(amount).toArrayLike(Buffer, "le", 8));
+            chai.expect(parsed.computeUnits).lessThan(decoded.computeUnits);
+        });
+    }
+});
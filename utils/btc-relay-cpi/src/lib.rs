@@ -0,0 +1,260 @@
+//! Helpers for programs relying on Bitcoin transaction verification done by the BTC relay program.
+//!
+//! A transaction is verified by the relay's `verify_transaction` instruction, called either as a
+//! standalone instruction or via CPI ([`verify_transaction`]). The result is stored in a
+//! [`TxVerificationReceipt`] PDA derived from the txid, which can later be checked with [`check_receipt`].
+//! The receipt is checked against the relay's main chain every time it's used, so its confirmations are
+//! current and a receipt of a block reorged out of the main chain is rejected.
+
+use anchor_lang::prelude::*;
+
+pub use btc_relay::cpi::accounts::VerifyTransactionReceipt;
+pub use btc_relay::cpi::verify_transaction;
+pub use btc_relay::program::BtcRelay;
+pub use btc_relay::state::MainState;
+pub use btc_relay::state::TxVerificationReceipt;
+pub use btc_relay::structs::CommittedBlockHeader;
+pub use btc_relay::utils::outputs_hash;
+
+pub const RECEIPT_SEED: &[u8] = b"receipt";
+
+#[error_code(offset = 7000)]
+pub enum ReceiptError {
+    #[msg("Receipt account is not owned by the BTC relay program")]
+    InvalidOwner,
+    #[msg("Receipt doesn't belong to the expected transaction")]
+    InvalidTxId,
+    #[msg("Transaction doesn't have required number of confirmations")]
+    NotEnoughConfirmations,
+    #[msg("Transaction outputs don't match the receipt")]
+    OutputsMismatch,
+    #[msg("Receipt's block is not in the relay's main chain")]
+    BlockNotInMainChain,
+}
+
+/// Returns the receipt PDA (and its bump) for the transaction with the given reversed txid.
+pub fn receipt_address(reversed_txid: &[u8; 32]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[RECEIPT_SEED, reversed_txid.as_ref()], &btc_relay::ID)
+}
+
+/// Deserializes the receipt account, checking that it was written by the BTC relay program
+/// for the expected transaction.
+///
+/// The relay only ever writes a receipt at the address derived from its txid, so checking the
+/// owner, the account discriminator and the stored txid is enough, no PDA derivation is needed.
+pub fn load_receipt(
+    receipt: &AccountInfo,
+    reversed_txid: &[u8; 32],
+) -> Result<TxVerificationReceipt> {
+    require_keys_eq!(*receipt.owner, btc_relay::ID, ReceiptError::InvalidOwner);
+
+    let data = receipt.try_borrow_data()?;
+    let receipt = TxVerificationReceipt::try_deserialize(&mut &data[..])?;
+
+    require!(
+        receipt.reversed_txid == *reversed_txid,
+        ReceiptError::InvalidTxId
+    );
+
+    Ok(receipt)
+}
+
+/// Confirmations of the receipt's block in the relay's main chain. Fails once the block was
+/// reorged out of the main chain, or when it's too old for the relay to still store its commitment.
+///
+/// `main_state` is the relay's main state, loaded from an `AccountLoader<MainState>`, which checks
+/// that the account is owned by the relay program.
+pub fn receipt_confirmations(
+    receipt: &TxVerificationReceipt,
+    main_state: &MainState,
+) -> Result<u32> {
+    main_state
+        .get_confirmations(receipt.block_height, &receipt.commit_hash)
+        .ok_or_else(|| error!(ReceiptError::BlockNotInMainChain))
+}
+
+/// Loads the receipt and checks the transaction currently has at least `min_confirmations`
+/// in the relay's main chain.
+pub fn check_receipt(
+    receipt: &AccountInfo,
+    main_state: &MainState,
+    reversed_txid: &[u8; 32],
+    min_confirmations: u32,
+) -> Result<TxVerificationReceipt> {
+    let receipt = load_receipt(receipt, reversed_txid)?;
+
+    require!(
+        receipt_confirmations(&receipt, main_state)? >= min_confirmations,
+        ReceiptError::NotEnoughConfirmations
+    );
+
+    Ok(receipt)
+}
+
+/// Checks the verified transaction has the expected outputs, `expected_outputs_hash` is the
+/// double sha256 of the serialized outputs, as computed by [`outputs_hash`].
+pub fn check_receipt_outputs(
+    receipt: &TxVerificationReceipt,
+    expected_outputs_hash: &[u8; 32],
+) -> Result<()> {
+    require!(
+        receipt.outputs_hash == *expected_outputs_hash,
+        ReceiptError::OutputsMismatch
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::error::ErrorCode;
+    use btc_relay::state::FeePool;
+    use bytemuck::Zeroable;
+
+    const TXID: [u8; 32] = [7; 32];
+    const COMMIT_HASH: [u8; 32] = [9; 32];
+
+    fn receipt() -> TxVerificationReceipt {
+        TxVerificationReceipt {
+            reversed_txid: TXID,
+            block_height: 102,
+            commit_hash: COMMIT_HASH,
+            outputs_hash: [3; 32],
+        }
+    }
+
+    // Main chain from height 100 to 105, with the receipt's block at 102
+    fn main_state() -> MainState {
+        let mut main_state = MainState::zeroed();
+        main_state.start_height = 100;
+        main_state.block_height = 105;
+        main_state.block_commitments[2] = COMMIT_HASH;
+        main_state
+    }
+
+    fn serialized(account: &impl AccountSerialize) -> Vec<u8> {
+        let mut data = vec![];
+        account.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    fn with_account<T>(owner: Pubkey, data: &[u8], f: impl FnOnce(&AccountInfo) -> T) -> T {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = data.to_vec();
+        let account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        f(&account)
+    }
+
+    #[test]
+    fn loads_receipts_written_by_the_relay() {
+        let data = serialized(&receipt());
+        let loaded = with_account(btc_relay::ID, &data, |account| {
+            load_receipt(account, &TXID).unwrap()
+        });
+        assert_eq!(loaded.block_height, 102);
+        assert_eq!(loaded.commit_hash, COMMIT_HASH);
+
+        with_account(Pubkey::new_unique(), &data, |account| {
+            assert_eq!(
+                load_receipt(account, &TXID).unwrap_err(),
+                ReceiptError::InvalidOwner.into()
+            );
+        });
+        with_account(btc_relay::ID, &data, |account| {
+            assert_eq!(
+                load_receipt(account, &[8; 32]).unwrap_err(),
+                ReceiptError::InvalidTxId.into()
+            );
+        });
+
+        let fee_pool = FeePool {
+            authority: Pubkey::new_unique(),
+            header_reward: 0,
+            total_rewards: 0,
+        };
+        with_account(btc_relay::ID, &serialized(&fee_pool), |account| {
+            assert_eq!(
+                load_receipt(account, &TXID).unwrap_err(),
+                ErrorCode::AccountDiscriminatorMismatch.into()
+            );
+        });
+    }
+
+    #[test]
+    fn reads_confirmations_from_the_main_chain() {
+        let mut main_state = main_state();
+        assert_eq!(receipt_confirmations(&receipt(), &main_state).unwrap(), 4);
+
+        let data = serialized(&receipt());
+        with_account(btc_relay::ID, &data, |account| {
+            let checked = check_receipt(account, &main_state, &TXID, 4).unwrap();
+            assert_eq!(checked.outputs_hash, [3; 32]);
+            assert_eq!(
+                check_receipt(account, &main_state, &TXID, 5).unwrap_err(),
+                ReceiptError::NotEnoughConfirmations.into()
+            );
+        });
+
+        // Confirmations grow with the main chain
+        main_state.block_height = 110;
+        with_account(btc_relay::ID, &data, |account| {
+            assert!(check_receipt(account, &main_state, &TXID, 9).is_ok());
+        });
+    }
+
+    #[test]
+    fn rejects_receipts_of_blocks_out_of_the_main_chain() {
+        // The receipt's block was reorged out
+        let mut reorged = main_state();
+        reorged.block_commitments[2] = [10; 32];
+        assert_eq!(
+            receipt_confirmations(&receipt(), &reorged).unwrap_err(),
+            ReceiptError::BlockNotInMainChain.into()
+        );
+
+        // The main chain is shorter than the receipt's block after a reorg
+        let mut shorter = main_state();
+        shorter.block_height = 101;
+        assert_eq!(
+            receipt_confirmations(&receipt(), &shorter).unwrap_err(),
+            ReceiptError::BlockNotInMainChain.into()
+        );
+
+        // The block's commitment was pruned from the ring buffer
+        let mut pruned = main_state();
+        pruned.block_height = 102 + 250;
+        assert_eq!(
+            receipt_confirmations(&receipt(), &pruned).unwrap_err(),
+            ReceiptError::BlockNotInMainChain.into()
+        );
+
+        let uninitialized = TxVerificationReceipt {
+            commit_hash: [0; 32],
+            ..receipt()
+        };
+        assert_eq!(
+            receipt_confirmations(&uninitialized, &MainState::zeroed()).unwrap_err(),
+            ReceiptError::BlockNotInMainChain.into()
+        );
+    }
+
+    #[test]
+    fn checks_receipt_outputs() {
+        assert!(check_receipt_outputs(&receipt(), &[3; 32]).is_ok());
+        assert_eq!(
+            check_receipt_outputs(&receipt(), &[4; 32]).unwrap_err(),
+            ReceiptError::OutputsMismatch.into()
+        );
+    }
+}
@@ -0,0 +1,255 @@
+//! Off-chain simulator of the BTC relay header chain.
+//!
+//! Headers are verified with the relay program's own `btc_relay::utils::apply_header`, so the
+//! difficulty, proof of work, timestamp and chain work rules are exactly the ones applied on-chain,
+//! just without the `Clock` sysvar and header topic accounts. This allows predicting the resulting
+//! `CommittedBlockHeader`s, their commit hashes and the chain work before sending a transaction.
+
+use anchor_lang::prelude::*;
+use btc_relay::errors::RelayErrorCode;
+use btc_relay::structs::{BlockHeader, CommittedBlockHeader};
+use btc_relay::u256::U256;
+use btc_relay::utils::apply_header;
+use std::fmt;
+
+/// Header accepted by the simulated relay.
+#[derive(Debug, Clone, Copy)]
+pub struct SimulatedHeader {
+    /// Double sha256 of the header, in the byte order used by the relay program.
+    pub block_hash: [u8; 32],
+    /// Commit hash stored by the relay for the header.
+    pub commit_hash: [u8; 32],
+    pub committed: CommittedBlockHeader,
+}
+
+impl SimulatedHeader {
+    pub fn chain_work(&self) -> U256 {
+        U256::from_be_bytes(self.committed.chain_work)
+    }
+}
+
+/// Header rejected by the simulated relay.
+#[derive(Debug)]
+pub struct SimulationError {
+    /// Index of the rejected header in the submitted batch.
+    pub index: usize,
+    /// Error the relay program would return.
+    pub error: Error,
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "header {} rejected: {}", self.index, self.error)
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+/// Header chain starting at a header committed by the relay.
+#[derive(Debug, Clone)]
+pub struct HeaderChain {
+    tip: CommittedBlockHeader,
+    tip_hash: [u8; 32],
+}
+
+impl HeaderChain {
+    pub fn new(tip: CommittedBlockHeader) -> Self {
+        HeaderChain {
+            tip_hash: tip.header.get_block_hash(),
+            tip,
+        }
+    }
+
+    pub fn tip(&self) -> &CommittedBlockHeader {
+        &self.tip
+    }
+
+    pub fn tip_hash(&self) -> [u8; 32] {
+        self.tip_hash
+    }
+
+    pub fn chain_work(&self) -> U256 {
+        U256::from_be_bytes(self.tip.chain_work)
+    }
+
+    /// Verifies the headers the same way `submit_block_headers` does, without changing the chain.
+    ///
+    /// `now` is the unix timestamp the future block time limit is checked against.
+    pub fn simulate(
+        &self,
+        headers: &[BlockHeader],
+        now: u32,
+    ) -> std::result::Result<Vec<SimulatedHeader>, SimulationError> {
+        let mut last_commited_header = self.tip;
+        let mut last_block_hash = self.tip_hash;
+
+        headers
+            .iter()
+            .enumerate()
+            .map(|(index, header)| {
+                let with_index = |error: Error| SimulationError { index, error };
+                if header.reversed_prev_blockhash != last_block_hash {
+                    return Err(with_index(RelayErrorCode::PrevBlock.into()));
+                }
+
+                let block_hash = header.get_block_hash();
+                apply_header(header, block_hash, &mut last_commited_header, now)
+                    .map_err(with_index)?;
+                last_block_hash = block_hash;
+
+                Ok(SimulatedHeader {
+                    block_hash,
+                    commit_hash: last_commited_header.get_commit_hash(),
+                    committed: last_commited_header,
+                })
+            })
+            .collect()
+    }
+
+    /// Verifies the headers and extends the chain with them.
+    ///
+    /// Like the relay program, either all headers are accepted or the chain is left unchanged.
+    pub fn submit(
+        &mut self,
+        headers: &[BlockHeader],
+        now: u32,
+    ) -> std::result::Result<Vec<SimulatedHeader>, SimulationError> {
+        let simulated = self.simulate(headers, now)?;
+        if let Some(last) = simulated.last() {
+            self.tip = last.committed;
+            self.tip_hash = last.block_hash;
+        }
+        Ok(simulated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hex::FromHex;
+
+    // Mainnet blocks 0 to 5
+    const MAINNET_HEADERS: [&str; 6] = [
+        "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c",
+        "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299",
+        "010000004860eb18bf1b1620e37e9490fc8a427514416fd75159ab86688e9a8300000000d5fdcc541e25de1c7a5addedf24858b8bb665c9f36ef744ee42c316022c90f9bb0bc6649ffff001d08d2bd61",
+        "01000000bddd99ccfda39da1b108ce1a5d70038d0a967bacb68b6b63065f626a0000000044f672226090d85db9a9f2fbfe5f0f9609b387af7be5b7fbb7a1767c831c9e995dbe6649ffff001d05e0ed6d",
+        "010000004944469562ae1c2c74d9a535e00b6f3e40ffbad4f2fda3895501b582000000007a06ea98cd40ba2e3288262b28638cec5337c1456aaf5eedc8e9e5a20f062bdf8cc16649ffff001d2bfee0a9",
+        "0100000085144a84488ea88d221c8bd6c059da090e88f8a2c99690ee55dbba4e00000000e11c48fecdd9e72510ca84f023370c9a38bf91ac5cae88019bee94d24528526344c36649ffff001d1d03e477",
+    ];
+    // Bitcoin Core's chainwork of block 5
+    const MAINNET_CHAIN_WORK: u64 = 0x0006_0006_0006;
+    const NOW: u32 = 1_700_000_000;
+
+    fn header(hex: &str) -> BlockHeader {
+        BlockHeader::try_from_slice(&Vec::from_hex(hex).unwrap()).unwrap()
+    }
+
+    fn headers() -> Vec<BlockHeader> {
+        MAINNET_HEADERS[1..].iter().map(|hex| header(hex)).collect()
+    }
+
+    fn genesis_chain() -> HeaderChain {
+        let genesis = header(MAINNET_HEADERS[0]);
+        HeaderChain::new(CommittedBlockHeader {
+            chain_work: U256::from_u64(0x0001_0001_0001).to_be_bytes(),
+            header: genesis,
+            last_diff_adjustment: genesis.timestamp,
+            blockheight: 0,
+            prev_block_timestamps: [0; 10],
+        })
+    }
+
+    fn error_index(
+        result: std::result::Result<Vec<SimulatedHeader>, SimulationError>,
+    ) -> (usize, Error) {
+        let err = result.unwrap_err();
+        (err.index, err.error)
+    }
+
+    #[test]
+    fn replays_mainnet_headers() {
+        let mut chain = genesis_chain();
+        let simulated = chain.submit(&headers(), NOW).unwrap();
+
+        assert_eq!(simulated.len(), 5);
+        for (i, header) in simulated.iter().enumerate() {
+            assert_eq!(header.committed.blockheight, i as u32 + 1);
+            assert_eq!(header.commit_hash, header.committed.get_commit_hash());
+            assert_eq!(
+                header.chain_work(),
+                U256::from_u64(0x0001_0001_0001 * (i as u64 + 2))
+            );
+        }
+
+        let mut tip_hash = chain.tip_hash();
+        tip_hash.reverse();
+        assert_eq!(
+            tip_hash.to_vec(),
+            Vec::from_hex("000000009b7262315dbf071787ad3656097b892abffd1f95a1a022f896f533fc")
+                .unwrap()
+        );
+        assert_eq!(chain.chain_work(), U256::from_u64(MAINNET_CHAIN_WORK));
+        assert_eq!(chain.tip().blockheight, 5);
+        assert_eq!(
+            chain.tip().prev_block_timestamps[5..].to_vec(),
+            MAINNET_HEADERS[..5]
+                .iter()
+                .map(|hex| header(hex).timestamp)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn submits_in_batches() {
+        let headers = headers();
+        let mut batched = genesis_chain();
+        let mut single = genesis_chain();
+
+        batched.submit(&headers[..2], NOW).unwrap();
+        batched.submit(&headers[2..], NOW).unwrap();
+        let last = single.submit(&headers, NOW).unwrap().pop().unwrap();
+
+        assert_eq!(batched.tip_hash(), single.tip_hash());
+        assert_eq!(batched.tip().get_commit_hash(), last.commit_hash);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let headers = headers();
+        let chain = genesis_chain();
+
+        let (index, error) = error_index(chain.simulate(&headers[1..], NOW));
+        assert_eq!((index, error), (0, RelayErrorCode::PrevBlock.into()));
+
+        let mut low_pow = headers.clone();
+        low_pow[2].nonce ^= 1;
+        let (index, error) = error_index(chain.simulate(&low_pow, NOW));
+        assert_eq!((index, error), (2, RelayErrorCode::ErrPowToolow.into()));
+
+        let mut wrong_target = headers.clone();
+        wrong_target[0].nbits = 0x1c00_ffff;
+        let (index, error) = error_index(chain.simulate(&wrong_target, NOW));
+        assert_eq!((index, error), (0, RelayErrorCode::ErrDiffTarget.into()));
+
+        //Only the last header is 4 hours (MAX_FUTURE_BLOCKTIME) ahead of the current time
+        let now = headers[4].timestamp - 4 * 60 * 60;
+        let (index, error) = error_index(chain.simulate(&headers, now));
+        assert_eq!(
+            (index, error),
+            (4, RelayErrorCode::ErrTimestampTooHigh.into())
+        );
+    }
+
+    #[test]
+    fn failed_submit_keeps_the_chain() {
+        let mut headers = headers();
+        headers[4].nonce ^= 1;
+        let mut chain = genesis_chain();
+
+        let err = chain.submit(&headers, NOW).unwrap_err();
+        assert_eq!(err.index, 4);
+        assert_eq!(chain.tip().blockheight, 0);
+        assert_eq!(chain.tip_hash(), genesis_chain().tip_hash());
+    }
+}
@@ -0,0 +1,359 @@
+//! Utility for verifying bitcoin transaction inclusion using btcrelay program and checking a prior executed instruction data.
+//!
+//! As Yona transactions are atomic, a successfully executed transaction that contains a BTC relay
+//! verification instruction means the verification passed. The instruction is read from the
+//! instructions sysvar and decoded using the relay's own instruction argument structs
+//! (`btc_relay::instruction::*`), so the decoding always matches the program's IDL.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
+use anchor_lang::Discriminator;
+use btc_relay::instruction::{VerifySmallTx, VerifyTransaction};
+use btc_relay::txutils::ParsedTransaction;
+
+#[error_code(offset = 7100)]
+pub enum TxVerifyError {
+    #[msg("Instruction doesn't belong to the BTC relay program")]
+    InvalidProgram,
+    #[msg("Instruction doesn't verify a transaction")]
+    NotTxVerification,
+    #[msg("Instruction data can't be decoded")]
+    InvalidInstructionData,
+    #[msg("Verified transaction id doesn't match")]
+    TxIdMismatch,
+    #[msg("Verification doesn't require enough confirmations")]
+    NotEnoughConfirmations,
+    #[msg("No prior instruction verifies the transaction")]
+    VerificationNotFound,
+}
+
+/// BTC relay instruction that verified the transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationKind {
+    /// `verify_small_tx`, the txid is computed from the transaction bytes.
+    SmallTx,
+    /// `verify_transaction`, storing the verification receipt.
+    Transaction,
+}
+
+/// Transaction inclusion verified by a BTC relay instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifiedTx {
+    pub kind: VerificationKind,
+    pub reversed_txid: [u8; 32],
+    /// Height of the block containing the transaction.
+    pub block_height: u32,
+    /// Confirmations required by the instruction, the block had at least this many.
+    pub confirmations: u32,
+}
+
+/// Decodes the BTC relay instruction, returning the transaction inclusion it verifies.
+///
+/// `init_big_tx_verify` isn't a verification: it only stores the claimed txid, the transaction
+/// bytes are checked against it by a later `finalize_tx_processing` instruction.
+pub fn decode_tx_ix(ix: &Instruction) -> Result<VerifiedTx> {
+    require_keys_eq!(ix.program_id, btc_relay::ID, TxVerifyError::InvalidProgram);
+    require!(ix.data.len() >= 8, TxVerifyError::NotTxVerification);
+
+    let (discriminator, mut args) = ix.data.split_at(8);
+
+    if discriminator == VerifySmallTx::DISCRIMINATOR {
+        let args = VerifySmallTx::deserialize(&mut args)
+            .map_err(|_| TxVerifyError::InvalidInstructionData)?;
+        let bitcoin_tx = ParsedTransaction::parse(&args.tx_bytes)
+            .map_err(|_| TxVerifyError::InvalidInstructionData)?;
+
+        Ok(VerifiedTx {
+            kind: VerificationKind::SmallTx,
+            reversed_txid: bitcoin_tx.compute_txid(),
+            block_height: args.commited_header.blockheight,
+            confirmations: args.confirmations,
+        })
+    } else if discriminator == VerifyTransaction::DISCRIMINATOR {
+        let args = VerifyTransaction::deserialize(&mut args)
+            .map_err(|_| TxVerifyError::InvalidInstructionData)?;
+
+        Ok(VerifiedTx {
+            kind: VerificationKind::Transaction,
+            reversed_txid: args.reversed_txid,
+            block_height: args.commited_header.blockheight,
+            confirmations: args.confirmations,
+        })
+    } else {
+        err!(TxVerifyError::NotTxVerification)
+    }
+}
+
+/// Checks the BTC relay instruction verifies the transaction with at least `min_confirmations`.
+pub fn verify_tx_ix(
+    ix: &Instruction,
+    reversed_txid: &[u8; 32],
+    min_confirmations: u32,
+) -> Result<VerifiedTx> {
+    let verified = decode_tx_ix(ix)?;
+
+    require!(
+        verified.reversed_txid == *reversed_txid,
+        TxVerifyError::TxIdMismatch
+    );
+    require!(
+        verified.confirmations >= min_confirmations,
+        TxVerifyError::NotEnoughConfirmations
+    );
+
+    Ok(verified)
+}
+
+/// Checks the instruction at `index` of the current Yona transaction verifies the transaction.
+pub fn verify_tx_at(
+    instructions_sysvar: &AccountInfo,
+    index: usize,
+    reversed_txid: &[u8; 32],
+    min_confirmations: u32,
+) -> Result<VerifiedTx> {
+    let ix = load_instruction_at_checked(index, instructions_sysvar)?;
+    verify_tx_ix(&ix, reversed_txid, min_confirmations)
+}
+
+/// Searches the instructions executed before the current one for a verification of the transaction.
+pub fn find_tx_verification(
+    instructions_sysvar: &AccountInfo,
+    reversed_txid: &[u8; 32],
+    min_confirmations: u32,
+) -> Result<VerifiedTx> {
+    let current_index = load_current_index_checked(instructions_sysvar)? as usize;
+
+    for index in 0..current_index {
+        let ix = load_instruction_at_checked(index, instructions_sysvar)?;
+        if let Ok(verified) = verify_tx_ix(&ix, reversed_txid, min_confirmations) {
+            return Ok(verified);
+        }
+    }
+
+    err!(TxVerifyError::VerificationNotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::solana_program::sysvar::instructions::{
+        construct_instructions_data, store_current_index, BorrowedAccountMeta, BorrowedInstruction,
+        ID as INSTRUCTIONS_SYSVAR_ID,
+    };
+    use anchor_lang::InstructionData;
+    use bitcoin::hex::FromHex;
+    use btc_relay::structs::{BlockHeader, CommittedBlockHeader};
+
+    // Genesis block coinbase transaction
+    const TX_HEX: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+    const TXID_HEX: &str = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+    fn reversed_txid() -> [u8; 32] {
+        let mut txid: [u8; 32] = FromHex::from_hex(TXID_HEX).unwrap();
+        txid.reverse();
+        txid
+    }
+
+    fn commited_header(blockheight: u32) -> CommittedBlockHeader {
+        CommittedBlockHeader {
+            chain_work: [0; 32],
+            header: BlockHeader {
+                version: 1,
+                reversed_prev_blockhash: [0; 32],
+                merkle_root: reversed_txid(),
+                timestamp: 1231006505,
+                nbits: 0x1d00ffff,
+                nonce: 2083236893,
+            },
+            last_diff_adjustment: 1231006505,
+            blockheight,
+            prev_block_timestamps: [0; 10],
+        }
+    }
+
+    fn relay_ix(data: Vec<u8>) -> Instruction {
+        Instruction {
+            program_id: btc_relay::ID,
+            accounts: vec![],
+            data,
+        }
+    }
+
+    fn small_tx_ix(confirmations: u32) -> Instruction {
+        relay_ix(
+            VerifySmallTx {
+                tx_bytes: Vec::from_hex(TX_HEX).unwrap(),
+                confirmations,
+                tx_index: 0,
+                reversed_merkle_proof: vec![],
+                commited_header: commited_header(0),
+            }
+            .data(),
+        )
+    }
+
+    #[test]
+    fn decodes_verify_small_tx() {
+        let verified = decode_tx_ix(&small_tx_ix(6)).unwrap();
+        assert_eq!(
+            verified,
+            VerifiedTx {
+                kind: VerificationKind::SmallTx,
+                reversed_txid: reversed_txid(),
+                block_height: 0,
+                confirmations: 6,
+            }
+        );
+    }
+
+    #[test]
+    fn decodes_verify_transaction() {
+        let ix = relay_ix(
+            VerifyTransaction {
+                reversed_txid: reversed_txid(),
+                tx_bytes: Vec::from_hex(TX_HEX).unwrap(),
+                confirmations: 3,
+                tx_index: 0,
+                reversed_merkle_proof: vec![[1; 32]],
+                commited_header: commited_header(100),
+            }
+            .data(),
+        );
+
+        let verified = verify_tx_ix(&ix, &reversed_txid(), 3).unwrap();
+        assert_eq!(verified.kind, VerificationKind::Transaction);
+        assert_eq!(verified.block_height, 100);
+    }
+
+    #[test]
+    fn rejects_init_big_tx_verify() {
+        let ix = relay_ix(
+            btc_relay::instruction::InitBigTxVerify {
+                tx_id: reversed_txid(),
+                tx_size: 204,
+                confirmations: 1,
+                tx_index: 5,
+                reversed_merkle_proof: vec![[2; 32], [3; 32]],
+                commited_header: commited_header(42),
+            }
+            .data(),
+        );
+
+        assert_eq!(
+            verify_tx_ix(&ix, &reversed_txid(), 1).unwrap_err(),
+            TxVerifyError::NotTxVerification.into()
+        );
+    }
+
+    #[test]
+    fn rejects_mismatches() {
+        let ix = small_tx_ix(2);
+
+        assert_eq!(
+            verify_tx_ix(&ix, &[0; 32], 2).unwrap_err(),
+            TxVerifyError::TxIdMismatch.into()
+        );
+        assert_eq!(
+            verify_tx_ix(&ix, &reversed_txid(), 3).unwrap_err(),
+            TxVerifyError::NotEnoughConfirmations.into()
+        );
+
+        let mut other_program = ix.clone();
+        other_program.program_id = Pubkey::new_unique();
+        assert_eq!(
+            decode_tx_ix(&other_program).unwrap_err(),
+            TxVerifyError::InvalidProgram.into()
+        );
+
+        let block_height = relay_ix(
+            btc_relay::instruction::BlockHeight {
+                value: 1,
+                operation: 0,
+            }
+            .data(),
+        );
+        assert_eq!(
+            decode_tx_ix(&block_height).unwrap_err(),
+            TxVerifyError::NotTxVerification.into()
+        );
+
+        let mut truncated = ix;
+        truncated.data.truncate(20);
+        assert_eq!(
+            decode_tx_ix(&truncated).unwrap_err(),
+            TxVerifyError::InvalidInstructionData.into()
+        );
+    }
+
+    #[test]
+    fn finds_verification_in_instructions_sysvar() {
+        let other_program = Pubkey::new_unique();
+        let instructions = [
+            relay_ix(
+                btc_relay::instruction::BlockHeight {
+                    value: 1,
+                    operation: 0,
+                }
+                .data(),
+            ),
+            small_tx_ix(6),
+            Instruction {
+                program_id: other_program,
+                accounts: vec![],
+                data: vec![1, 2, 3],
+            },
+        ];
+
+        let borrowed: Vec<BorrowedInstruction> = instructions
+            .iter()
+            .map(|ix| BorrowedInstruction {
+                program_id: &ix.program_id,
+                accounts: ix
+                    .accounts
+                    .iter()
+                    .map(|meta| BorrowedAccountMeta {
+                        pubkey: &meta.pubkey,
+                        is_signer: meta.is_signer,
+                        is_writable: meta.is_writable,
+                    })
+                    .collect(),
+                data: &ix.data,
+            })
+            .collect();
+
+        let mut data = construct_instructions_data(&borrowed);
+        store_current_index(&mut data, 2);
+
+        let mut lamports = 0;
+        let owner = Pubkey::default();
+        let sysvar = AccountInfo::new(
+            &INSTRUCTIONS_SYSVAR_ID,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+
+        let verified = find_tx_verification(&sysvar, &reversed_txid(), 6).unwrap();
+        assert_eq!(verified.kind, VerificationKind::SmallTx);
+        assert_eq!(
+            verify_tx_at(&sysvar, 1, &reversed_txid(), 6).unwrap(),
+            verified
+        );
+
+        assert_eq!(
+            find_tx_verification(&sysvar, &reversed_txid(), 7).unwrap_err(),
+            TxVerifyError::VerificationNotFound.into()
+        );
+        assert_eq!(
+            find_tx_verification(&sysvar, &[0; 32], 1).unwrap_err(),
+            TxVerifyError::VerificationNotFound.into()
+        );
+    }
+}